@@ -1,13 +1,54 @@
+use std::env;
 use std::fs::{read_dir, File};
 use std::io::{Result, Write};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 fn main() {
     println!("cargo:rerun-if-changed=../user/src/");
+    println!("cargo:rerun-if-changed=../user/services/");
     // println!("cargo:rerun-if-changed={}", TARGET_PATH);
     insert_app_data().unwrap();
+    emit_build_info();
+}
+
+/// Build metadata for `build_info.rs`, read by `env!`
+fn emit_build_info() {
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/index");
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    let is_dirty = Command::new("git")
+        .args(["diff", "--quiet", "HEAD"])
+        .status()
+        .map_or(false, |status| status.code() == Some(1));
+    let build_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase())
+        })
+        .collect();
+    features.sort();
+    println!(
+        "cargo:rustc-env=KERNEL_GIT_HASH={}{}",
+        git_hash,
+        if is_dirty { "-dirty" } else { "" }
+    );
+    println!("cargo:rustc-env=KERNEL_BUILD_TIME={}", build_time);
+    println!("cargo:rustc-env=KERNEL_FEATURES={}", features.join(","));
 }
 
 static TARGET_PATH: &str = "../user/target/riscv64imac-unknown-none-elf/release/";
+static SERVICE_SOURCE_PATH: &str = "../user/services";
+static SERVICE_TARGET_PATH: &str = "../user/target/services/";
 
 fn insert_app_data() -> Result<()> {
     let mut f = File::create("src/link_app.asm").unwrap();
@@ -63,5 +104,59 @@ app_{0}_end:"#,
             idx, app, TARGET_PATH
         )?;
     }
+    insert_service_data(&mut f)
+}
+
+/// Flat binaries of `user/services`, loaded by `service::load`
+fn insert_service_data(f: &mut File) -> Result<()> {
+    let mut services: Vec<_> = read_dir(SERVICE_SOURCE_PATH)
+        .unwrap()
+        .into_iter()
+        .filter_map(|dir_entry| {
+            let name = dir_entry.unwrap().file_name().into_string().unwrap();
+            name.strip_suffix(".rs").map(String::from)
+        })
+        .collect();
+    services.sort();
+
+    writeln!(
+        f,
+        r#"
+    .align 3
+    .section .data
+    .global _num_service
+_num_service:
+    .quad {}"#,
+        services.len()
+    )?;
+    for i in 0..services.len() {
+        writeln!(f, r#"    .quad service_{0}_start, service_{0}_end"#, i)?;
+    }
+
+    writeln!(
+        f,
+        r#"
+    .global _service_names
+_service_names:"#
+    )?;
+    for service in services.iter() {
+        writeln!(f, r#"    .string "{}""#, service)?;
+    }
+
+    for (idx, service) in services.iter().enumerate() {
+        println!("service_{}: {}", idx, service);
+        writeln!(
+            f,
+            r#"
+    .section .data
+    .global service_{0}_start
+    .global service_{0}_end
+    .align 3
+service_{0}_start:
+    .incbin "{2}{1}.bin"
+service_{0}_end:"#,
+            idx, service, SERVICE_TARGET_PATH
+        )?;
+    }
     Ok(())
 }
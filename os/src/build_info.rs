@@ -0,0 +1,70 @@
+//! Metadata of the kernel image, embedded by `build.rs`
+
+use crate::config::{CPU_NUM, DETERMINISTIC};
+use crate::console::ANSICON;
+use alloc::format;
+use alloc::string::String;
+
+const UTSNAME_FIELD_LEN: usize = 65;
+
+/// As the `struct utsname` of Linux, every field is nul-terminated
+#[repr(C)]
+pub struct Utsname {
+    pub sysname: [u8; UTSNAME_FIELD_LEN],
+    pub nodename: [u8; UTSNAME_FIELD_LEN],
+    pub release: [u8; UTSNAME_FIELD_LEN],
+    pub version: [u8; UTSNAME_FIELD_LEN],
+    pub machine: [u8; UTSNAME_FIELD_LEN],
+    pub domainname: [u8; UTSNAME_FIELD_LEN],
+}
+
+pub const SYSNAME: &str = "rCore-N";
+pub const RELEASE: &str = env!("CARGO_PKG_VERSION");
+pub const MACHINE: &str = "riscv64";
+pub const GIT_HASH: &str = env!("KERNEL_GIT_HASH");
+/// Seconds since the Unix epoch
+pub const BUILD_TIME: &str = env!("KERNEL_BUILD_TIME");
+/// Enabled cargo features, comma separated
+pub const FEATURES: &str = env!("KERNEL_FEATURES");
+
+/// Everything needed to tell two kernel images apart
+pub fn version() -> String {
+    format!(
+        "{} built at {} features [{}] cpus {}{}",
+        GIT_HASH,
+        BUILD_TIME,
+        FEATURES,
+        CPU_NUM,
+        if DETERMINISTIC { " deterministic" } else { "" }
+    )
+}
+
+pub fn print_banner() {
+    println_colorized!(
+        "{} {} ({})",
+        ANSICON::FgDefault,
+        ANSICON::BgDefault,
+        SYSNAME,
+        RELEASE,
+        version()
+    );
+}
+
+/// Strings are cut to fit their field
+fn utsname_field(s: &str) -> [u8; UTSNAME_FIELD_LEN] {
+    let mut field = [0; UTSNAME_FIELD_LEN];
+    let len = s.len().min(UTSNAME_FIELD_LEN - 1);
+    field[..len].copy_from_slice(&s.as_bytes()[..len]);
+    field
+}
+
+pub fn utsname() -> Utsname {
+    Utsname {
+        sysname: utsname_field(SYSNAME),
+        nodename: utsname_field("rcore-n"),
+        release: utsname_field(RELEASE),
+        version: utsname_field(&version()),
+        machine: utsname_field(MACHINE),
+        domainname: utsname_field(""),
+    }
+}
@@ -0,0 +1,19 @@
+//! labeled-RISC-V on FPGA
+
+pub const MEMORY_END: usize = 0x100A00000;
+pub const CLOCK_FREQ: usize = 10_000_000;
+
+/// `compatible` of the serial ports to pick up from the DTB
+pub const SERIAL_COMPATIBLE: &str = "xlnx,xps-uart16550-2.00.b";
+/// Ports used when the DTB has none: base of the first one,
+/// then one every `SERIAL_ADDRESS_STRIDE` with these PLIC sources
+pub const SERIAL_BASE_ADDRESS: usize = 0x6000_1000;
+pub const SERIAL_IRQS: [u16; super::SERIAL_NUM] = [4, 5, 6, 7];
+
+/// No RTC on the FPGA board
+pub const RTC_MMIO: Option<(usize, usize)> = None;
+
+/// Flags set on every leaf PTE; the core faults instead of updating A and D
+pub const PTE_PRESET_FLAGS: u8 = (1 << 6) | (1 << 7);
+/// Hand all PLIC sources of a hart back to the kernel before enabling ours
+pub const PLIC_RESET_CONTEXTS: bool = true;
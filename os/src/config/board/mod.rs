@@ -0,0 +1,24 @@
+//! Board profiles, one per `board_*` cargo feature.
+//! Everything here is a compile-time default; what the firmware reports at boot
+//! ends up in `KernelConfig` instead.
+
+#[cfg(feature = "board_qemu")]
+mod qemu;
+#[cfg(feature = "board_qemu")]
+pub use qemu::*;
+
+#[cfg(feature = "board_lrv")]
+mod lrv;
+#[cfg(feature = "board_lrv")]
+pub use lrv::*;
+
+pub const CPU_NUM: usize = 4;
+
+pub const PLIC_BASE: usize = 0xc00_0000;
+pub const PLIC_SIZE: usize = 0x400_0000;
+pub const PLIC_PRIORITY_BIT: usize = 3;
+
+/// Max serial ports driven by the kernel
+pub const SERIAL_NUM: usize = 4;
+/// Distance between the fallback ports, also the MMIO window mapped for each port
+pub const SERIAL_ADDRESS_STRIDE: usize = 0x1000;
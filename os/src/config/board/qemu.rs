@@ -0,0 +1,19 @@
+//! QEMU virt machine
+
+pub const MEMORY_END: usize = 0x80A00000;
+pub const CLOCK_FREQ: usize = 12500000;
+
+/// `compatible` of the serial ports to pick up from the DTB
+pub const SERIAL_COMPATIBLE: &str = "ns16550a";
+/// Ports used when the DTB has none: base of the first one,
+/// then one every `SERIAL_ADDRESS_STRIDE` with these PLIC sources
+pub const SERIAL_BASE_ADDRESS: usize = 0x1000_2000;
+pub const SERIAL_IRQS: [u16; super::SERIAL_NUM] = [12, 13, 14, 15];
+
+/// Goldfish RTC
+pub const RTC_MMIO: Option<(usize, usize)> = Some((0x10_1000, 0x1000));
+
+/// Flags set on every leaf PTE; QEMU updates A and D in hardware
+pub const PTE_PRESET_FLAGS: u8 = 0;
+/// Hand all PLIC sources of a hart back to the kernel before enabling ours
+pub const PLIC_RESET_CONTEXTS: bool = false;
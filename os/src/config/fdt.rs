@@ -0,0 +1,223 @@
+//! Just enough of a flattened device tree walker to read what the kernel
+//! config needs: the RAM range and the reserved regions in it, the number of harts,
+//! the serial ports with their interrupts and the bootargs.
+//! Only the root's `#address-cells` and `#size-cells` are honoured.
+
+use super::{IrqTrigger, SerialPort, MAX_RESERVED_REGIONS, SERIAL_COMPATIBLE, SERIAL_NUM};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+/// Edge bits of the flags cell of `interrupts`, the others being level ones
+const IRQ_TYPE_EDGE_BOTH: u32 = 3;
+
+#[derive(Default)]
+pub struct FdtInfo<'a> {
+    /// (base, size) of the first `/memory` node
+    pub memory: Option<(usize, usize)>,
+    /// Number of `cpu@*` nodes under `/cpus`
+    pub cpu_num: usize,
+    pub bootargs: Option<&'a str>,
+    /// Ports matching `SERIAL_COMPATIBLE` in ascending address order,
+    /// except the one `/chosen/stdout-path` gives to the firmware console
+    pub serial_ports: [SerialPort; SERIAL_NUM],
+    pub serial_num: usize,
+    /// (base, size) of the `/memreserve/` entries and of the `/reserved-memory` nodes
+    /// with a `reg`
+    pub reserved: [(usize, usize); MAX_RESERVED_REGIONS],
+    pub reserved_num: usize,
+}
+
+impl FdtInfo<'_> {
+    fn reserve(&mut self, base: usize, size: usize) {
+        if size == 0 {
+            return;
+        }
+        // handing out reserved memory would corrupt whatever lives there,
+        // and the DTB is read before the heap exists to hold more
+        assert!(
+            self.reserved_num < MAX_RESERVED_REGIONS,
+            "[fdt] more than {} reserved regions, {:#x}+{:#x} does not fit",
+            MAX_RESERVED_REGIONS,
+            base,
+            size
+        );
+        self.reserved[self.reserved_num] = (base, size);
+        self.reserved_num += 1;
+    }
+}
+
+/// Properties of the node being walked, kept until its first child or its end
+#[derive(Default)]
+struct NodeProps<'a> {
+    name: &'a str,
+    reg: Option<usize>,
+    size: Option<usize>,
+    irq: Option<u16>,
+    trigger: IrqTrigger,
+    is_serial: bool,
+    /// A child of `/reserved-memory`
+    is_reserved: bool,
+}
+
+fn be32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let word = bytes.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([word[0], word[1], word[2], word[3]]))
+}
+
+fn read_cells(bytes: &[u8], cells: usize) -> Option<usize> {
+    (0..cells).try_fold(0usize, |acc, i| {
+        Some((acc << 32) | be32(bytes, i * 4)? as usize)
+    })
+}
+
+fn cstr(bytes: &[u8]) -> Option<&str> {
+    let len = bytes.iter().position(|&b| b == 0)?;
+    core::str::from_utf8(&bytes[..len]).ok()
+}
+
+/// Walk the blob at `dtb`, returning `None` if it is not a well-formed DTB.
+///
+/// # Safety
+///
+/// `dtb` must be null or point to readable memory, which must stay valid for `'a`.
+pub unsafe fn parse<'a>(dtb: usize) -> Option<FdtInfo<'a>> {
+    if dtb == 0 || dtb % 4 != 0 {
+        return None;
+    }
+    let header = core::slice::from_raw_parts(dtb as *const u8, 40);
+    if be32(header, 0)? != FDT_MAGIC {
+        return None;
+    }
+    let total_size = be32(header, 4)? as usize;
+    let blob = core::slice::from_raw_parts(dtb as *const u8, total_size);
+    let structs = blob.get(be32(header, 8)? as usize..)?;
+    let strings = blob.get(be32(header, 12)? as usize..)?;
+
+    let mut info = FdtInfo::default();
+    // pairs of 64-bit base and size, up to an empty one
+    let reserve_map = blob.get(be32(header, 16)? as usize..)?;
+    for entry in reserve_map.chunks_exact(16) {
+        let (base, size) = (read_cells(entry, 2)?, read_cells(&entry[8..], 2)?);
+        if base == 0 && size == 0 {
+            break;
+        }
+        info.reserve(base, size);
+    }
+    let (mut address_cells, mut size_cells) = (2, 1);
+    let mut depth = 0;
+    // Which depth-1 node we are in
+    let (mut in_memory, mut in_cpus, mut in_chosen) = (false, false, false);
+    let mut in_reserved = false;
+    let mut node = NodeProps::default();
+    let mut serial_names = [""; SERIAL_NUM];
+    let mut stdout_path = None;
+    let mut offset = 0;
+    loop {
+        let token = be32(structs, offset)?;
+        offset += 4;
+        if token == FDT_BEGIN_NODE || token == FDT_END_NODE {
+            let node = core::mem::take(&mut node);
+            if let (true, Some(base), Some(irq)) = (node.is_serial, node.reg, node.irq) {
+                if info.serial_num < SERIAL_NUM {
+                    info.serial_ports[info.serial_num] = SerialPort {
+                        base,
+                        irq,
+                        trigger: node.trigger,
+                    };
+                    serial_names[info.serial_num] = node.name;
+                    info.serial_num += 1;
+                }
+            }
+            if let (true, Some(base), Some(size)) = (node.is_reserved, node.reg, node.size) {
+                info.reserve(base, size);
+            }
+        }
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = cstr(structs.get(offset..)?)?;
+                node.name = name;
+                offset = (offset + name.len() + 1 + 3) & !3;
+                depth += 1;
+                if depth == 2 {
+                    in_memory = name == "memory" || name.starts_with("memory@");
+                    in_cpus = name == "cpus";
+                    in_chosen = name == "chosen";
+                    in_reserved = name == "reserved-memory";
+                } else if depth == 3 && in_cpus && name.starts_with("cpu@") {
+                    info.cpu_num += 1;
+                }
+                node.is_reserved = depth == 3 && in_reserved;
+            }
+            FDT_END_NODE => {
+                depth -= 1;
+                if depth == 1 {
+                    in_memory = false;
+                    in_cpus = false;
+                    in_chosen = false;
+                    in_reserved = false;
+                }
+            }
+            FDT_PROP => {
+                let len = be32(structs, offset)? as usize;
+                let name = cstr(strings.get(be32(structs, offset + 4)? as usize..)?)?;
+                let value = structs.get(offset + 8..offset + 8 + len)?;
+                offset = (offset + 8 + len + 3) & !3;
+                match (depth, name) {
+                    (1, "#address-cells") => address_cells = be32(value, 0)? as usize,
+                    (1, "#size-cells") => size_cells = be32(value, 0)? as usize,
+                    (2, "reg") if in_memory && info.memory.is_none() => {
+                        info.memory = Some((
+                            read_cells(value, address_cells)?,
+                            read_cells(value.get(address_cells * 4..)?, size_cells)?,
+                        ));
+                    }
+                    (2, "bootargs") if in_chosen => info.bootargs = cstr(value),
+                    (2, "stdout-path") if in_chosen => stdout_path = cstr(value),
+                    (_, "reg") => {
+                        node.reg = read_cells(value, address_cells);
+                        node.size = value
+                            .get(address_cells * 4..)
+                            .and_then(|value| read_cells(value, size_cells));
+                    }
+                    (_, "interrupts") => {
+                        node.irq = be32(value, 0).map(|irq| irq as u16);
+                        // a flags cell only comes with `#interrupt-cells = <2>`, level otherwise
+                        if be32(value, 4).map_or(false, |flags| flags & IRQ_TYPE_EDGE_BOTH != 0) {
+                            node.trigger = IrqTrigger::Edge;
+                        }
+                    }
+                    (_, "compatible") => {
+                        node.is_serial = value
+                            .split(|&b| b == 0)
+                            .any(|compatible| compatible == SERIAL_COMPATIBLE.as_bytes());
+                    }
+                    _ => {}
+                }
+            }
+            FDT_NOP => {}
+            FDT_END => {
+                // stdout-path is a path, optionally followed by `:options`
+                let console = stdout_path
+                    .and_then(|path| path.split(':').next())
+                    .and_then(|path| path.rsplit('/').next());
+                let mut ports = [SerialPort::default(); SERIAL_NUM];
+                let mut num = 0;
+                for i in 0..info.serial_num {
+                    if Some(serial_names[i]) != console {
+                        ports[num] = info.serial_ports[i];
+                        num += 1;
+                    }
+                }
+                ports[..num].sort_unstable_by_key(|port| port.base);
+                info.serial_ports = ports;
+                info.serial_num = num;
+                return Some(info);
+            }
+            _ => return None,
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use super::{
+    fdt, ASLR_ENABLED, CPU_NUM, DMA_REGION_SIZE, IRQ_THREADS, MAX_RESERVED_REGIONS, MEMORY_END,
+    OOM_RESERVE_FRAMES, PAGE_SIZE, SERIAL_ADDRESS_STRIDE, SERIAL_BASE_ADDRESS, SERIAL_IRQS,
+    SERIAL_NUM,
+};
+use log::LevelFilter;
+use spin::Once;
+
+/// What the kernel was told at boot, on top of the board profile.
+///
+/// Later sources override earlier ones: the board profile, then the DTB,
+/// then the bootargs, e.g. `mem=0x88000000 harts=2 aslr=1 log=debug irqthreads=12,13`.
+/// `irqthreads` is `all`, `none` or the PLIC sources to defer.
+#[derive(Debug, Clone)]
+pub struct KernelConfig {
+    /// End of the physical memory managed by the kernel
+    pub memory_end: usize,
+    /// Harts to bring up, at most `CPU_NUM`
+    pub hart_num: usize,
+    /// Randomize the user stack top and mmap base of every new address space
+    pub aslr: bool,
+    pub log_level: LevelFilter,
+    serial_ports: [SerialPort; SERIAL_NUM],
+    serial_num: usize,
+    reserved: [(usize, usize); MAX_RESERVED_REGIONS],
+    reserved_num: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SerialPort {
+    pub base: usize,
+    /// PLIC source
+    pub irq: u16,
+    pub trigger: IrqTrigger,
+    /// Serviced after the trap, by `irq_thread`
+    pub threaded: bool,
+}
+
+/// How a device signals its PLIC source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqTrigger {
+    /// Held until the device is serviced, as 16550 UARTs do
+    Level,
+    /// One pulse per event
+    Edge,
+}
+
+impl Default for IrqTrigger {
+    fn default() -> Self {
+        IrqTrigger::Level
+    }
+}
+
+static KERNEL_CONFIG: Once<KernelConfig> = Once::new();
+
+impl KernelConfig {
+    fn from_board() -> Self {
+        Self {
+            memory_end: MEMORY_END,
+            hart_num: CPU_NUM,
+            aslr: ASLR_ENABLED,
+            log_level: match option_env!("LOG") {
+                Some("ERROR") => LevelFilter::Error,
+                Some("WARN") => LevelFilter::Warn,
+                Some("INFO") => LevelFilter::Info,
+                Some("DEBUG") => LevelFilter::Debug,
+                Some("TRACE") => LevelFilter::Trace,
+                _ => LevelFilter::Off,
+            },
+            serial_ports: array_init::array_init(|i| SerialPort {
+                base: SERIAL_BASE_ADDRESS + i * SERIAL_ADDRESS_STRIDE,
+                irq: SERIAL_IRQS[i],
+                trigger: IrqTrigger::Level,
+                threaded: IRQ_THREADS,
+            }),
+            serial_num: SERIAL_NUM,
+            reserved: [(0, 0); MAX_RESERVED_REGIONS],
+            reserved_num: 0,
+        }
+    }
+
+    /// Serial ports in ttyS order, port 0 being the kernel console
+    pub fn serial_ports(&self) -> &[SerialPort] {
+        &self.serial_ports[..self.serial_num]
+    }
+
+    /// (base, size) of the regions of RAM the firmware keeps for itself or its devices,
+    /// e.g. OpenSBI, which no frame may be allocated from
+    pub fn reserved_regions(&self) -> &[(usize, usize)] {
+        &self.reserved[..self.reserved_num]
+    }
+
+    fn apply_fdt(&mut self, info: &fdt::FdtInfo) {
+        // Never manage more memory than the board profile has room for
+        // unless asked to on the command line
+        if let Some((base, size)) = info.memory {
+            self.memory_end = self.memory_end.min(base + size);
+        }
+        if info.cpu_num > 0 {
+            self.hart_num = self.hart_num.min(info.cpu_num);
+        }
+        if info.serial_num > 0 {
+            self.serial_ports = info.serial_ports;
+            for port in &mut self.serial_ports {
+                port.threaded = IRQ_THREADS;
+            }
+            self.serial_num = info.serial_num;
+        }
+        self.reserved = info.reserved;
+        self.reserved_num = info.reserved_num;
+    }
+
+    /// Unknown or malformed options are ignored, as is a `mem` leaving no frames past the
+    /// kernel image, the DMA region and the OOM reserve
+    fn apply_bootargs(&mut self, bootargs: &str, ram_end: Option<usize>) {
+        for (key, value) in bootargs
+            .split_ascii_whitespace()
+            .filter_map(|arg| arg.split_once('='))
+        {
+            match key {
+                "mem" => {
+                    if let Some(end) = parse_usize(value).filter(|&end| end >= min_memory_end()) {
+                        self.memory_end = ram_end.map_or(end, |ram_end| end.min(ram_end));
+                    }
+                }
+                "harts" => {
+                    if let Some(n) = parse_usize(value).filter(|&n| n > 0) {
+                        self.hart_num = n.min(CPU_NUM);
+                    }
+                }
+                "aslr" => self.aslr = value != "0",
+                "irqthreads" => {
+                    for port in &mut self.serial_ports {
+                        port.threaded = match value {
+                            "all" => true,
+                            "none" => false,
+                            _ => value
+                                .split(',')
+                                .any(|irq| parse_usize(irq) == Some(port.irq as usize)),
+                        };
+                    }
+                }
+                "log" => {
+                    if let Ok(level) = value.parse() {
+                        self.log_level = level;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn min_memory_end() -> usize {
+    extern "C" {
+        fn ekernel();
+    }
+    let kernel_end = (ekernel as usize + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    kernel_end + DMA_REGION_SIZE + OOM_RESERVE_FRAMES * PAGE_SIZE
+}
+
+fn parse_usize(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Build the config from the board profile and the DTB at `dtb`.
+/// Must run on the boot hart before paging, since the DTB may lie
+/// outside the memory mapped by the kernel.
+pub fn init_kernel_config(dtb: usize) -> &'static KernelConfig {
+    KERNEL_CONFIG.call_once(|| {
+        let mut config = KernelConfig::from_board();
+        if let Some(info) = unsafe { fdt::parse(dtb) } {
+            config.apply_fdt(&info);
+            if let Some(bootargs) = info.bootargs {
+                config.apply_bootargs(bootargs, info.memory.map(|(base, size)| base + size));
+            }
+        }
+        config
+    })
+}
+
+pub fn kernel_config() -> &'static KernelConfig {
+    KERNEL_CONFIG
+        .get()
+        .expect("kernel config used before init_kernel_config")
+}
@@ -0,0 +1,67 @@
+//! Kernel configuration in three layers: the generic constants below, the
+//! board profile selected by cargo feature in `board`, and `KernelConfig`,
+//! built at boot from the profile and overridden by the DTB and bootargs.
+
+mod board;
+mod fdt;
+mod kernel_config;
+
+pub use board::*;
+pub use kernel_config::{init_kernel_config, kernel_config, IrqTrigger, KernelConfig, SerialPort};
+
+pub const USER_STACK_SIZE: usize = 0x4000;
+/// Default of `KernelConfig::aslr`: randomize the user stack top and mmap base
+/// of every new address space
+pub const ASLR_ENABLED: bool = false;
+/// Default of `SerialPort::threaded`: service serial ports from the scheduler loop, see
+/// `irq_thread`, rather than in the trap path. Off, as a busy hart may not reach the loop
+/// before the RX FIFO overruns
+pub const IRQ_THREADS: bool = false;
+/// Max random gap in pages between the end of the elf and the user stack
+pub const ASLR_STACK_PAGES: usize = 0x1000;
+/// Max random offset in pages added to `MMAP_BASE`
+pub const ASLR_MMAP_PAGES: usize = 0x1_0000;
+/// Where `mmap` places areas when no address is given
+pub const MMAP_BASE: usize = 0x20_0000_0000;
+/// Run tasks on a single hart against a virtual clock, see `deterministic`
+pub const DETERMINISTIC: bool = false;
+/// Seed of every randomized decision in deterministic mode
+pub const DETERMINISTIC_SEED: usize = 0x2545_f491_4f6c_dd1d;
+/// Allow user areas which are both writable and executable, e.g. for self-modifying code
+pub const ALLOW_WRITABLE_EXEC: bool = false;
+pub const KERNEL_STACK_SIZE: usize = 0x4000;
+/// Stack of each hart for the traps taken in supervisor mode, see `trap::stack`
+pub const TRAP_STACK_SIZE: usize = 0x4000;
+pub const KERNEL_HEAP_SIZE: usize = 0x20_0000;
+/// Physically contiguous memory reserved at the end of RAM for DMA buffers
+pub const DMA_REGION_SIZE: usize = 0x10_0000;
+/// Regions of RAM kept from the kernel by the DTB, booting with more panics
+pub const MAX_RESERVED_REGIONS: usize = 8;
+
+pub const PAGE_SIZE: usize = 0x1000;
+pub const PAGE_SIZE_BITS: usize = 0xc;
+
+/// End of the lower half of Sv39, where every pointer given to a syscall must lie. The user
+/// trap buffer, trap context and trampoline above are only touched by the kernel itself.
+pub const USER_SPACE_END: usize = 0x40_0000_0000;
+pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
+pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
+pub const USER_TRAP_BUFFER: usize = TRAP_CONTEXT - PAGE_SIZE;
+
+/// Kernel space for loadable services, a slot of `SERVICE_SLOT_SIZE` each,
+/// at the bottom of the upper half and far from the kernel stacks under `TRAMPOLINE`
+pub const SERVICE_BASE: usize = 0xffff_ffc0_0000_0000;
+pub const SERVICE_SLOT_SIZE: usize = 0x10_0000;
+pub const MAX_SERVICES: usize = 4;
+
+/// Frames kept back from `mmap` for the kernel, a task asking for them is killed instead
+pub const OOM_RESERVE_FRAMES: usize = 64;
+/// Tag frames and heap objects with the task they were allocated for and
+/// report those outliving it, see `mm::alloc_track`
+pub const TRACK_ALLOCATIONS: bool = false;
+/// With `TRACK_ALLOCATIONS`, panic on the first task found to leak at teardown
+/// instead of only reporting it, for test runs
+pub const PANIC_ON_LEAK: bool = false;
+/// Check that SUM is never set in the kernel
+/// and report kernel page faults as stray user memory accesses
+pub const STRICT_USER_ACCESS: bool = cfg!(debug_assertions);
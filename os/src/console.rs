@@ -1,23 +1,96 @@
-use crate::sbi::console_putchar;
+//! Kernel console output.
+//!
+//! Every hart formats into its own line buffer, and complete lines are
+//! written out whole under `CONSOLE_LOCK`, so that output of different harts
+//! never interleaves within a line. Whatever is left of a line is written at
+//! the end of each print, so partial lines such as prompts are not held back.
+//! Each flush hands the device the whole buffer at once, not a byte at a time.
+
+use crate::config::CPU_NUM;
+use crate::sbi::console_write;
+use crate::task::hart_id;
+use crate::util::SpinNoIrq;
 use core::fmt::{self, Write};
 
-use alloc::sync::Arc;
-use lazy_static::*;
-use spin::Mutex;
+const LINE_BUFFER_SIZE: usize = 256;
 
-struct Stderr;
+pub struct LineBuffer {
+    bytes: [u8; LINE_BUFFER_SIZE],
+    len: usize,
+}
 
-impl Write for Stderr {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.chars() {
-            console_putchar(c as usize);
+impl LineBuffer {
+    pub const fn new() -> Self {
+        Self {
+            bytes: [0; LINE_BUFFER_SIZE],
+            len: 0,
+        }
+    }
+}
+
+pub type LineBuffers = [SpinNoIrq<LineBuffer>; CPU_NUM];
+
+#[allow(clippy::declare_interior_mutable_const)]
+pub const LINE_BUFFERS_INIT: SpinNoIrq<LineBuffer> = SpinNoIrq::new(LineBuffer::new());
+
+/// Held while a line goes out, by all consoles as they may share the same device
+static CONSOLE_LOCK: SpinNoIrq<()> = SpinNoIrq::new(());
+
+static STDERR_LINES: LineBuffers = [LINE_BUFFERS_INIT; CPU_NUM];
+
+struct LineWriter<'a> {
+    buffer: &'a mut LineBuffer,
+    write: fn(&[u8]),
+}
+
+impl LineWriter<'_> {
+    fn flush(&mut self) {
+        if self.buffer.len == 0 {
+            return;
+        }
+        let _console = CONSOLE_LOCK.lock();
+        (self.write)(&self.buffer.bytes[..self.buffer.len]);
+        self.buffer.len = 0;
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for &c in bytes {
+            self.buffer.bytes[self.buffer.len] = c;
+            self.buffer.len += 1;
+            if c == b'\n' || self.buffer.len == LINE_BUFFER_SIZE {
+                self.flush();
+            }
         }
+    }
+}
+
+impl Write for LineWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
         Ok(())
     }
 }
 
-lazy_static! {
-    static ref STDERR: Arc<Mutex<Stderr>> = Arc::new(Mutex::new(Stderr {}));
+/// Format `args` into the line buffer of this hart in `buffers`, writing it out with `write`
+pub fn print_line_buffered(buffers: &LineBuffers, write: fn(&[u8]), args: fmt::Arguments) {
+    let mut buffer = buffers[hart_id()].lock();
+    let mut writer = LineWriter {
+        buffer: &mut buffer,
+        write,
+    };
+    writer.write_fmt(args).unwrap();
+    writer.flush();
+}
+
+/// Like `print_line_buffered` for raw bytes, which need not be text
+pub fn write_line_buffered(buffers: &LineBuffers, write: fn(&[u8]), bytes: &[u8]) {
+    let mut buffer = buffers[hart_id()].lock();
+    let mut writer = LineWriter {
+        buffer: &mut buffer,
+        write,
+    };
+    writer.write_bytes(bytes);
+    writer.flush();
 }
 
 /// Use ANSICON to format colorized string
@@ -36,10 +109,11 @@ macro_rules! colorize {
 
 /// Use colorize! to print with color
 pub fn print_colorized(args: fmt::Arguments, foreground_color: u8, background_color: u8) {
-    STDERR
-        .lock()
-        .write_fmt(colorize!(args, foreground_color, background_color))
-        .unwrap();
+    print_line_buffered(
+        &STDERR_LINES,
+        console_write,
+        colorize!(args, foreground_color, background_color),
+    );
 }
 
 #[macro_export]
@@ -0,0 +1,101 @@
+//! Deterministic run mode for replaying a failing interleaving, enabled by
+//! `config::DETERMINISTIC`.
+//!
+//! Only `DETERMINISTIC_HART` runs tasks and the timer interrupt no longer
+//! preempts them. User programs see a virtual clock which advances by a fixed
+//! amount on every syscall, yield and idle loop, user timers and scripted
+//! events fire against that clock, and randomized decisions draw from a
+//! seeded generator.
+
+use crate::config::DETERMINISTIC_SEED;
+use crate::trap::{push_trap_record, UserTrapRecord};
+use alloc::collections::BTreeSet;
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use lazy_static::*;
+use spin::Mutex;
+
+/// The only hart which runs tasks
+pub const DETERMINISTIC_HART: usize = 0;
+/// Virtual time charged to every syscall
+pub const VIRTUAL_SYSCALL_US: usize = 1;
+/// Extra virtual time charged to `sys_yield`
+pub const VIRTUAL_YIELD_US: usize = 100;
+/// Virtual time charged to each round of the idle loop
+pub const VIRTUAL_IDLE_US: usize = 1000;
+
+/// A user trap record delivered once the virtual clock reaches `at_us`
+pub struct ScriptedEvent {
+    pub at_us: usize,
+    pub pid: usize,
+    pub cause: usize,
+    pub message: usize,
+}
+
+/// External events to replay, sorted by `at_us`
+const EVENT_SCRIPT: &[ScriptedEvent] = &[];
+
+static VIRTUAL_TIME_US: AtomicUsize = AtomicUsize::new(0);
+static NEXT_EVENT: AtomicUsize = AtomicUsize::new(0);
+static RNG_STATE: AtomicUsize = AtomicUsize::new(DETERMINISTIC_SEED | 1);
+
+lazy_static! {
+    /// Pending user timers as (deadline, pid)
+    static ref VIRTUAL_TIMERS: Mutex<BTreeSet<(usize, usize)>> = Mutex::new(BTreeSet::new());
+}
+
+pub fn now_us() -> usize {
+    VIRTUAL_TIME_US.load(Relaxed)
+}
+
+/// Advance the virtual clock, then fire the user timers and scripted events which are due
+pub fn advance(us: usize) {
+    let now = VIRTUAL_TIME_US.fetch_add(us, Relaxed) + us;
+    loop {
+        let mut timers = VIRTUAL_TIMERS.lock();
+        let (deadline, pid) = match timers.iter().next() {
+            Some(&(deadline, pid)) if deadline <= now => (deadline, pid),
+            _ => break,
+        };
+        timers.remove(&(deadline, pid));
+        drop(timers);
+        let _ = push_trap_record(
+            pid,
+            UserTrapRecord {
+                cause: 4,
+                message: now,
+            },
+        );
+    }
+    while let Some(event) = EVENT_SCRIPT.get(NEXT_EVENT.load(Relaxed)) {
+        if event.at_us > now {
+            break;
+        }
+        NEXT_EVENT.fetch_add(1, Relaxed);
+        debug!(
+            "[deterministic] inject cause {} to pid {} at {} us",
+            event.cause, event.pid, now
+        );
+        let _ = push_trap_record(
+            event.pid,
+            UserTrapRecord {
+                cause: event.cause,
+                message: event.message,
+            },
+        );
+    }
+}
+
+/// Arm a user timer at `deadline_us` of the virtual clock
+pub fn set_timer(deadline_us: usize, pid: usize) {
+    VIRTUAL_TIMERS.lock().insert((deadline_us, pid));
+}
+
+/// xorshift over a state seeded by `DETERMINISTIC_SEED`
+pub fn random() -> usize {
+    let mut x = RNG_STATE.load(Relaxed);
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    RNG_STATE.store(x, Relaxed);
+    x
+}
@@ -0,0 +1,5 @@
+pub mod rtc;
+
+pub fn init() {
+    rtc::init();
+}
@@ -0,0 +1,39 @@
+use crate::config::RTC_MMIO;
+use crate::timer::{get_time_ns, NSEC_PER_SEC};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Goldfish RTC registers, reading TIME_LOW latches TIME_HIGH
+const TIME_LOW: usize = 0x00;
+const TIME_HIGH: usize = 0x04;
+
+/// Nanoseconds since the Unix epoch, `None` if the board has no RTC
+pub fn read_hardware_ns() -> Option<usize> {
+    let (base, _) = RTC_MMIO?;
+    unsafe {
+        let low = ((base + TIME_LOW) as *const u32).read_volatile() as usize;
+        let high = ((base + TIME_HIGH) as *const u32).read_volatile() as usize;
+        Some(high << 32 | low)
+    }
+}
+
+/// Wall clock time at the moment the timebase was zero
+static BOOT_REALTIME_NS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn init() {
+    match read_hardware_ns() {
+        Some(now) => {
+            BOOT_REALTIME_NS.store(now.saturating_sub(get_time_ns()), Ordering::Relaxed);
+            debug!("[rtc] wall clock: {} s since epoch", now / NSEC_PER_SEC);
+        }
+        None => warn!("[rtc] no hardware RTC, wall clock starts at the epoch"),
+    }
+}
+
+/// Nanoseconds since the Unix epoch
+pub fn realtime_ns() -> usize {
+    BOOT_REALTIME_NS.load(Ordering::Relaxed) + get_time_ns()
+}
+
+pub fn set_realtime_ns(now: usize) {
+    BOOT_REALTIME_NS.store(now.saturating_sub(get_time_ns()), Ordering::Relaxed);
+}
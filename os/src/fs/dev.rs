@@ -0,0 +1,95 @@
+use super::{DevNull, DevRandom, DevZero, File, Serial};
+use crate::mm::UserBuffer;
+use crate::mm::{alloc_track, dma_report};
+use crate::syscall::EBADF;
+use crate::task::{cpu_group_report, sched_report};
+use crate::{plic, uart};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+use spin::Mutex;
+
+enum Device {
+    /// Every open shares the same file
+    Shared(Arc<dyn File + Send + Sync>),
+    /// Every open gets a file of its own, e.g. a snapshot taken at open
+    PerOpen(fn() -> Arc<dyn File + Send + Sync>),
+}
+
+lazy_static! {
+    /// Device files by path, standing in for `/dev` and `/proc` until there is a file system
+    static ref DEVICES: Mutex<BTreeMap<String, Device>> = Mutex::new(BTreeMap::new());
+}
+
+fn register(path: &str, device: Device) {
+    if DEVICES.lock().insert(path.to_string(), device).is_some() {
+        warn!("[dev] {} registered twice", path);
+    }
+}
+
+pub fn register_device(path: &str, file: Arc<dyn File + Send + Sync>) {
+    register(path, Device::Shared(file));
+}
+
+pub fn register_per_open(path: &str, open: fn() -> Arc<dyn File + Send + Sync>) {
+    register(path, Device::PerOpen(open));
+}
+
+pub fn open_device(path: &str) -> Option<Arc<dyn File + Send + Sync>> {
+    match DEVICES.lock().get(path)? {
+        Device::Shared(file) => Some(file.clone()),
+        Device::PerOpen(open) => Some(open()),
+    }
+}
+
+/// Read-only text fixed when the file is opened
+struct Snapshot {
+    bytes: Vec<u8>,
+    offset: Mutex<usize>,
+}
+
+impl Snapshot {
+    fn new(text: String) -> Self {
+        Self {
+            bytes: text.into_bytes(),
+            offset: Mutex::new(0),
+        }
+    }
+}
+
+impl File for Snapshot {
+    fn read(&self, mut buf: UserBuffer) -> Result<usize, isize> {
+        let mut offset = self.offset.lock();
+        let read_len = buf.write(&self.bytes[*offset..]);
+        *offset += read_len;
+        Ok(read_len)
+    }
+
+    fn write(&self, _buf: UserBuffer) -> Result<usize, isize> {
+        Err(EBADF)
+    }
+}
+
+/// Register every serial port found at boot as `/dev/ttyS<n>`, and the kernel reports under `/proc`
+pub fn init() {
+    for id in 0..uart::serial_num() {
+        let path = format!("/dev/ttyS{}", id);
+        debug!("[dev] {} at {:#x}", path, uart::serial_base_addr(id));
+        register_device(&path, Arc::new(Serial::new(id)));
+    }
+    register_device("/dev/null", Arc::new(DevNull));
+    register_device("/dev/zero", Arc::new(DevZero));
+    register_device("/dev/random", Arc::new(DevRandom));
+    register_per_open("/proc/memleak", || {
+        Arc::new(Snapshot::new(alloc_track::leak_report()))
+    });
+    register_per_open("/proc/dma", || Arc::new(Snapshot::new(dma_report())));
+    register_per_open("/proc/plic", || Arc::new(Snapshot::new(plic::report())));
+    register_per_open("/proc/sched", || Arc::new(Snapshot::new(sched_report())));
+    register_per_open("/proc/cpu_groups", || {
+        Arc::new(Snapshot::new(cpu_group_report()))
+    });
+}
@@ -5,6 +5,7 @@ use alloc::{
 use spin::Mutex;
 
 use crate::mm::UserBuffer;
+use crate::syscall::{EAGAIN, EBADF};
 use crate::task::suspend_current_and_run_next;
 
 use super::File;
@@ -87,12 +88,12 @@ impl File for MailBox {
                     return Ok(read_size);
                 }
             }
-            None => Err(-1),
+            None => Err(EAGAIN),
         }
     }
 
     fn write(&self, _buf: UserBuffer) -> Result<usize, isize> {
-        Err(-1)
+        Err(EBADF)
     }
 }
 
@@ -112,7 +113,7 @@ impl Socket {
 
 impl File for Socket {
     fn read(&self, _buf: UserBuffer) -> Result<usize, isize> {
-        Err(-1)
+        Err(EBADF)
     }
 
     fn write(&self, buf: UserBuffer) -> Result<usize, isize> {
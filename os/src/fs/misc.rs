@@ -0,0 +1,54 @@
+//! Device files backed by no hardware: sinks and sources for scripts and tests
+
+use super::File;
+use crate::mm::UserBuffer;
+use crate::random;
+
+/// `/dev/null`: reads end at once, writes are dropped
+pub struct DevNull;
+
+/// `/dev/zero`: reads give zeros, writes are dropped
+pub struct DevZero;
+
+/// `/dev/random`: reads drain the kernel entropy pool, writes are mixed into it
+pub struct DevRandom;
+
+impl File for DevNull {
+    fn read(&self, _buf: UserBuffer) -> Result<usize, isize> {
+        Ok(0)
+    }
+    fn write(&self, buf: UserBuffer) -> Result<usize, isize> {
+        Ok(buf.len())
+    }
+}
+
+impl File for DevZero {
+    fn read(&self, mut buf: UserBuffer) -> Result<usize, isize> {
+        for buffer in buf.buffers.iter_mut() {
+            buffer.fill(0);
+        }
+        Ok(buf.len())
+    }
+    fn write(&self, buf: UserBuffer) -> Result<usize, isize> {
+        Ok(buf.len())
+    }
+}
+
+impl File for DevRandom {
+    fn read(&self, mut buf: UserBuffer) -> Result<usize, isize> {
+        for buffer in buf.buffers.iter_mut() {
+            random::fill(buffer);
+        }
+        Ok(buf.len())
+    }
+    fn write(&self, buf: UserBuffer) -> Result<usize, isize> {
+        for buffer in buf.buffers.iter() {
+            for word in buffer.chunks(core::mem::size_of::<usize>()) {
+                let mut bytes = [0u8; core::mem::size_of::<usize>()];
+                bytes[..word.len()].copy_from_slice(word);
+                random::add_entropy(usize::from_ne_bytes(bytes));
+            }
+        }
+        Ok(buf.len())
+    }
+}
@@ -1,14 +1,77 @@
+mod dev;
 mod mail;
+mod misc;
 mod pipe;
 mod serial;
 mod stdio;
 
-use crate::mm::UserBuffer;
+use crate::mm::{FrameTracker, UserBuffer};
+use crate::syscall::ENOTTY;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 
+pub use dev::{init, open_device};
 pub use mail::{MailBox, Socket};
+pub use misc::{DevNull, DevRandom, DevZero};
+/// fd flag: closed by a successful `exec`, and not given to spawned tasks
+pub const FD_CLOEXEC: usize = 1;
+/// Open flag setting `FD_CLOEXEC` on the new fds
+pub const O_CLOEXEC: u32 = 1 << 19;
+
 pub trait File: Send + Sync {
     fn read(&self, buf: UserBuffer) -> Result<usize, isize>;
     fn write(&self, buf: UserBuffer) -> Result<usize, isize>;
+    /// Device specific request, `arg` is usually a user pointer
+    fn ioctl(&self, _cmd: usize, _arg: usize) -> Result<usize, isize> {
+        Err(ENOTTY)
+    }
+    /// Read into `bufs` in turn, a short read ends it like for `readv`
+    fn readv(&self, bufs: Vec<UserBuffer>) -> Result<usize, isize> {
+        let mut total = 0;
+        for buf in bufs {
+            let len = buf.len();
+            match self.read(buf) {
+                Ok(read_len) if read_len == len => total += read_len,
+                Ok(read_len) => return Ok(total + read_len),
+                Err(errno) if total == 0 => return Err(errno),
+                Err(_) => break,
+            }
+        }
+        Ok(total)
+    }
+    /// Write `bufs` in turn, a short write ends it like for `writev`
+    fn writev(&self, bufs: Vec<UserBuffer>) -> Result<usize, isize> {
+        let mut total = 0;
+        for buf in bufs {
+            let len = buf.len();
+            match self.write(buf) {
+                Ok(write_len) if write_len == len => total += write_len,
+                Ok(write_len) => return Ok(total + write_len),
+                Err(errno) if total == 0 => return Err(errno),
+                Err(_) => break,
+            }
+        }
+        Ok(total)
+    }
+    /// Write all of `buf` without blocking, or nothing, `None` for files whose writes never
+    /// block
+    fn try_write(&self, _buf: UserBuffer) -> Option<Result<usize, isize>> {
+        None
+    }
+    /// Write whole pages pinned by the caller without copying them, `None` for files which
+    /// copy their data anyway. The pages must not change until this returns.
+    fn write_pinned(&self, _pages: Vec<Arc<FrameTracker>>) -> Option<Result<usize, isize>> {
+        None
+    }
+    /// Pass `file` to the reader after the bytes written so far, `None` for files which
+    /// carry no files
+    fn send_file(&self, _file: Arc<dyn File + Send + Sync>) -> Option<Result<(), isize>> {
+        None
+    }
+    /// Take the file passed next, once the bytes before it are read
+    fn recv_file(&self) -> Option<Result<Arc<dyn File + Send + Sync>, isize>> {
+        None
+    }
 }
 
 pub use pipe::{make_pipe, Pipe};
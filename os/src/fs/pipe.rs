@@ -1,7 +1,11 @@
 use super::File;
-use crate::mm::UserBuffer;
-use crate::task::suspend_current_and_run_next;
+use crate::config::PAGE_SIZE;
+use crate::mm::{FrameTracker, UserBuffer};
+use crate::syscall::{EAGAIN, EBADF, EINVAL, ENOMSG, EPIPE, ERESTART};
+use crate::task::{current_has_pending_user_trap, current_task, suspend_current_and_run_next};
+use alloc::collections::VecDeque;
 use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
 use spin::Mutex;
 
 pub struct Pipe {
@@ -28,6 +32,8 @@ impl Pipe {
 }
 
 const RING_BUFFER_SIZE: usize = 32;
+/// Files passed but not received yet, past which senders get `EAGAIN`
+const MAX_PASSED_FILES: usize = 16;
 
 #[derive(Copy, Clone, PartialEq)]
 enum RingBufferStatus {
@@ -36,12 +42,32 @@ enum RingBufferStatus {
     NORMAL,
 }
 
+/// Pages lent by a writer blocked until they are read, read in place instead of being
+/// copied through the ring buffer
+struct PinnedWrite {
+    pages: Vec<Arc<FrameTracker>>,
+    read: usize,
+}
+
+/// A file in flight, received by the reader after the byte `at` of the stream
+struct PassedFile {
+    at: usize,
+    file: Arc<dyn File + Send + Sync>,
+}
+
 pub struct PipeRingBuffer {
     arr: [u8; RING_BUFFER_SIZE],
     head: usize,
     tail: usize,
     status: RingBufferStatus,
     write_end: Option<Weak<Pipe>>,
+    read_end: Option<Weak<Pipe>>,
+    /// Comes after the bytes in `arr`, other writers wait until it is read
+    pinned: Option<PinnedWrite>,
+    /// Bytes ever written and read, which place the passed files in the stream
+    written: usize,
+    consumed: usize,
+    passed: VecDeque<PassedFile>,
 }
 
 impl PipeRingBuffer {
@@ -52,15 +78,24 @@ impl PipeRingBuffer {
             tail: 0,
             status: RingBufferStatus::EMPTY,
             write_end: None,
+            read_end: None,
+            pinned: None,
+            written: 0,
+            consumed: 0,
+            passed: VecDeque::new(),
         }
     }
     pub fn set_write_end(&mut self, write_end: &Arc<Pipe>) {
         self.write_end = Some(Arc::downgrade(write_end));
     }
+    pub fn set_read_end(&mut self, read_end: &Arc<Pipe>) {
+        self.read_end = Some(Arc::downgrade(read_end));
+    }
     pub fn write_byte(&mut self, byte: u8) {
         self.status = RingBufferStatus::NORMAL;
         self.arr[self.tail] = byte;
         self.tail = (self.tail + 1) % RING_BUFFER_SIZE;
+        self.written += 1;
         if self.tail == self.head {
             self.status = RingBufferStatus::FULL;
         }
@@ -69,6 +104,7 @@ impl PipeRingBuffer {
         self.status = RingBufferStatus::NORMAL;
         let c = self.arr[self.head];
         self.head = (self.head + 1) % RING_BUFFER_SIZE;
+        self.consumed += 1;
         if self.head == self.tail {
             self.status = RingBufferStatus::EMPTY;
         }
@@ -84,15 +120,37 @@ impl PipeRingBuffer {
         }
     }
     pub fn available_write(&self) -> usize {
-        if self.status == RingBufferStatus::FULL {
+        if self.status == RingBufferStatus::FULL || self.pinned.is_some() {
             0
         } else {
             RING_BUFFER_SIZE - self.available_read()
         }
     }
+    fn available_pinned(&self) -> usize {
+        self.pinned
+            .as_ref()
+            .map_or(0, |pinned| pinned.pages.len() * PAGE_SIZE - pinned.read)
+    }
+    fn read_pinned_byte(&mut self) -> u8 {
+        let pinned = self.pinned.as_mut().unwrap();
+        let page = pinned.pages[pinned.read / PAGE_SIZE].ppn.get_bytes_array();
+        let c = page[pinned.read % PAGE_SIZE];
+        pinned.read += 1;
+        self.consumed += 1;
+        c
+    }
+    /// Bytes readable before the next passed file, which ends reads
+    fn until_passed_file(&self) -> usize {
+        self.passed
+            .front()
+            .map_or(usize::MAX, |passed| passed.at - self.consumed)
+    }
     pub fn all_write_ends_closed(&self) -> bool {
         self.write_end.as_ref().unwrap().upgrade().is_none()
     }
+    pub fn all_read_ends_closed(&self) -> bool {
+        self.read_end.as_ref().unwrap().upgrade().is_none()
+    }
 }
 
 /// Return (read_end, write_end)
@@ -101,22 +159,57 @@ pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
     let read_end = Arc::new(Pipe::read_end_with_buffer(buffer.clone()));
     let write_end = Arc::new(Pipe::write_end_with_buffer(buffer.clone()));
     buffer.lock().set_write_end(&write_end);
+    buffer.lock().set_read_end(&read_end);
     (read_end, write_end)
 }
 
-impl File for Pipe {
-    fn read(&self, buf: UserBuffer) -> Result<usize, isize> {
+impl Pipe {
+    fn read_counting_waits(&self, buf: UserBuffer, waits: &mut usize) -> Result<usize, isize> {
         assert!(self.readable);
         let mut buf_iter = buf.into_iter();
         let mut read_size = 0usize;
         loop {
             let mut ring_buffer = self.buffer.lock();
-            let loop_read = ring_buffer.available_read();
+            let until_passed_file = ring_buffer.until_passed_file();
+            if until_passed_file == 0 {
+                if read_size > 0 {
+                    return Ok(read_size);
+                }
+                // not taken by `recv_file`, dropped like by a read of a Unix socket
+                let passed = ring_buffer.passed.pop_front();
+                drop(ring_buffer);
+                drop(passed);
+                continue;
+            }
+            let loop_read = ring_buffer.available_read().min(until_passed_file);
             if loop_read == 0 {
+                let pinned_read = ring_buffer.available_pinned().min(until_passed_file);
+                if pinned_read > 0 {
+                    for _ in 0..pinned_read {
+                        if let Some(byte_ref) = buf_iter.next() {
+                            unsafe {
+                                *byte_ref = ring_buffer.read_pinned_byte();
+                            }
+                            read_size += 1;
+                        } else {
+                            return Ok(read_size);
+                        }
+                    }
+                    continue;
+                }
                 if ring_buffer.all_write_ends_closed() {
                     return Ok(read_size);
                 }
                 drop(ring_buffer);
+                // the user trap is taken first, then the read is restarted or cut short
+                if current_has_pending_user_trap() {
+                    return if read_size == 0 {
+                        Err(ERESTART)
+                    } else {
+                        Ok(read_size)
+                    };
+                }
+                *waits += 1;
                 suspend_current_and_run_next();
                 continue;
             }
@@ -133,7 +226,7 @@ impl File for Pipe {
             }
         }
     }
-    fn write(&self, buf: UserBuffer) -> Result<usize, isize> {
+    fn write_counting_waits(&self, buf: UserBuffer, waits: &mut usize) -> Result<usize, isize> {
         assert!(self.writable);
         let mut buf_iter = buf.into_iter();
         let mut write_size = 0usize;
@@ -142,6 +235,14 @@ impl File for Pipe {
             let loop_write = ring_buffer.available_write();
             if loop_write == 0 {
                 drop(ring_buffer);
+                if current_has_pending_user_trap() {
+                    return if write_size == 0 {
+                        Err(ERESTART)
+                    } else {
+                        Ok(write_size)
+                    };
+                }
+                *waits += 1;
                 suspend_current_and_run_next();
                 continue;
             }
@@ -157,3 +258,173 @@ impl File for Pipe {
         }
     }
 }
+
+impl Pipe {
+    /// Lend `pages` to the readers and wait until they are read. The caller is blocked here,
+    /// so its pages stay as they are. A debugger writing to them gets a copy, see
+    /// `shared_pages::unshare`.
+    fn write_pinned_counting_waits(
+        &self,
+        pages: Vec<Arc<FrameTracker>>,
+        waits: &mut usize,
+    ) -> Result<usize, isize> {
+        assert!(self.writable);
+        let len = pages.len() * PAGE_SIZE;
+        loop {
+            let mut ring_buffer = self.buffer.lock();
+            if ring_buffer.pinned.is_none() {
+                ring_buffer.pinned = Some(PinnedWrite { pages, read: 0 });
+                break;
+            }
+            drop(ring_buffer);
+            if current_has_pending_user_trap() {
+                return Err(ERESTART);
+            }
+            *waits += 1;
+            suspend_current_and_run_next();
+        }
+        loop {
+            let mut ring_buffer = self.buffer.lock();
+            let read = ring_buffer.pinned.as_ref().unwrap().read;
+            // the pages go back to the caller, readers must let go of them
+            if read == len {
+                ring_buffer.pinned = None;
+                ring_buffer.written += len;
+                return Ok(len);
+            }
+            if current_has_pending_user_trap() {
+                ring_buffer.pinned = None;
+                ring_buffer.written += read;
+                return if read == 0 { Err(ERESTART) } else { Ok(read) };
+            }
+            drop(ring_buffer);
+            *waits += 1;
+            suspend_current_and_run_next();
+        }
+    }
+}
+
+impl Pipe {
+    fn try_write(&self, buf: UserBuffer) -> Result<usize, isize> {
+        assert!(self.writable);
+        let mut ring_buffer = self.buffer.lock();
+        if ring_buffer.all_read_ends_closed() {
+            return Err(EPIPE);
+        }
+        if ring_buffer.available_write() < buf.len() {
+            return Err(EAGAIN);
+        }
+        let len = buf.len();
+        for byte_ref in buf.into_iter() {
+            ring_buffer.write_byte(unsafe { *byte_ref });
+        }
+        Ok(len)
+    }
+}
+
+impl Pipe {
+    fn send_file(&self, file: Arc<dyn File + Send + Sync>) -> Result<(), isize> {
+        if !self.writable {
+            return Err(EBADF);
+        }
+        loop {
+            let mut ring_buffer = self.buffer.lock();
+            // an end of this very pipe would hold itself open, its readers would never see
+            // the end and its writers never see it broken
+            let file_ptr = Arc::as_ptr(&file) as *const u8;
+            if [&ring_buffer.write_end, &ring_buffer.read_end]
+                .iter()
+                .any(|end| {
+                    end.as_ref()
+                        .map_or(false, |end| end.as_ptr() as *const u8 == file_ptr)
+                })
+            {
+                return Err(EINVAL);
+            }
+            if ring_buffer.passed.len() >= MAX_PASSED_FILES {
+                return Err(EAGAIN);
+            }
+            // placed after the pinned bytes, which are only counted once read
+            if ring_buffer.pinned.is_none() {
+                let at = ring_buffer.written;
+                ring_buffer.passed.push_back(PassedFile { at, file });
+                return Ok(());
+            }
+            drop(ring_buffer);
+            if current_has_pending_user_trap() {
+                return Err(ERESTART);
+            }
+            suspend_current_and_run_next();
+        }
+    }
+    fn recv_file(&self) -> Result<Arc<dyn File + Send + Sync>, isize> {
+        if !self.readable {
+            return Err(EBADF);
+        }
+        loop {
+            let mut ring_buffer = self.buffer.lock();
+            if ring_buffer.until_passed_file() == 0 {
+                return Ok(ring_buffer.passed.pop_front().unwrap().file);
+            }
+            if !ring_buffer.passed.is_empty()
+                || ring_buffer.available_read() > 0
+                || ring_buffer.available_pinned() > 0
+            {
+                // bytes come first
+                return Err(ENOMSG);
+            }
+            if ring_buffer.all_write_ends_closed() {
+                return Err(EPIPE);
+            }
+            drop(ring_buffer);
+            if current_has_pending_user_trap() {
+                return Err(ERESTART);
+            }
+            suspend_current_and_run_next();
+        }
+    }
+}
+
+/// Charge pipe traffic to the caller, see `Rusage`
+fn account(ret: Result<usize, isize>, waits: usize) -> Result<usize, isize> {
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    inner.usage.pipe_bytes += *ret.as_ref().unwrap_or(&0);
+    inner.usage.pipe_waits += waits;
+    ret
+}
+
+impl File for Pipe {
+    fn read(&self, buf: UserBuffer) -> Result<usize, isize> {
+        let mut waits = 0;
+        let ret = self.read_counting_waits(buf, &mut waits);
+        account(ret, waits)
+    }
+    fn write(&self, buf: UserBuffer) -> Result<usize, isize> {
+        let mut waits = 0;
+        let ret = self.write_counting_waits(buf, &mut waits);
+        account(ret, waits)
+    }
+    /// The fragments are read as one buffer, so a reader blocks until all are filled
+    fn readv(&self, bufs: Vec<UserBuffer>) -> Result<usize, isize> {
+        self.read(UserBuffer::concat(bufs))
+    }
+    fn writev(&self, bufs: Vec<UserBuffer>) -> Result<usize, isize> {
+        self.write(UserBuffer::concat(bufs))
+    }
+    /// Not charged to the caller, which writes on behalf of the kernel
+    fn try_write(&self, buf: UserBuffer) -> Option<Result<usize, isize>> {
+        Some(Pipe::try_write(self, buf))
+    }
+    fn write_pinned(&self, pages: Vec<Arc<FrameTracker>>) -> Option<Result<usize, isize>> {
+        let mut waits = 0;
+        let ret = self.write_pinned_counting_waits(pages, &mut waits);
+        Some(account(ret, waits))
+    }
+    fn send_file(&self, file: Arc<dyn File + Send + Sync>) -> Option<Result<(), isize>> {
+        Some(Pipe::send_file(self, file))
+    }
+    fn recv_file(&self) -> Option<Result<Arc<dyn File + Send + Sync>, isize>> {
+        Some(Pipe::recv_file(self))
+    }
+}
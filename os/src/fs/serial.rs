@@ -1,16 +1,33 @@
 use super::File;
-use crate::mm::UserBuffer;
-use crate::uart::{serial_getchar, serial_putchar};
+use crate::mm::{copy_from_user, copy_to_user, UserBuffer};
+use crate::syscall::{EAGAIN, EINVAL, ENOTTY};
+use crate::task::current_user_token;
+use crate::uart::{serial_config, serial_getchar, serial_putchar, serial_set_config, SerialConfig};
+use alloc::vec::Vec;
+use core::mem::size_of;
 
-pub struct Serial<const N: usize>;
+/// `ioctl` commands of serial ports, the argument points to a `SerialConfig`
+pub const SERIAL_GET_CONFIG: usize = 0x5400;
+pub const SERIAL_SET_CONFIG: usize = 0x5401;
 
-impl<const N: usize> File for Serial<N> {
+/// A serial port found at boot, registered as `/dev/ttyS<id>`
+pub struct Serial {
+    id: usize,
+}
+
+impl Serial {
+    pub fn new(id: usize) -> Self {
+        Self { id }
+    }
+}
+
+impl File for Serial {
     fn read(&self, user_buf: UserBuffer) -> Result<usize, isize> {
         let mut read_cnt = 0;
         let mut buf_iter = user_buf.into_iter();
         while let Some(ptr) = buf_iter.next() {
-            if let Ok(ch) = serial_getchar(N) {
-                // debug!("Serial {} read: {}", N, ch);
+            if let Ok(ch) = serial_getchar(self.id) {
+                // debug!("Serial {} read: {}", self.id, ch);
                 unsafe {
                     ptr.write_volatile(ch);
                 }
@@ -19,11 +36,11 @@ impl<const N: usize> File for Serial<N> {
                 break;
             }
         }
-        // debug!("Serial {} read cnt: {}", N, read_cnt);
+        // debug!("Serial {} read cnt: {}", self.id, read_cnt);
         if read_cnt > 0 {
             Ok(read_cnt)
         } else {
-            Err(-1)
+            Err(EAGAIN)
         }
     }
     fn write(&self, user_buf: UserBuffer) -> Result<usize, isize> {
@@ -31,8 +48,8 @@ impl<const N: usize> File for Serial<N> {
         let mut write_ok = true;
         for buffer in user_buf.buffers.iter() {
             for char in buffer.iter() {
-                // debug!("Serial {} write: {}", N, *char);
-                if let Ok(()) = serial_putchar(N, *char) {
+                // debug!("Serial {} write: {}", self.id, *char);
+                if let Ok(()) = serial_putchar(self.id, *char) {
                     write_cnt += 1;
                 } else {
                     write_ok = false;
@@ -46,7 +63,45 @@ impl<const N: usize> File for Serial<N> {
         if write_cnt > 0 {
             Ok(write_cnt)
         } else {
-            Err(-1)
+            Err(EAGAIN)
+        }
+    }
+    /// The fragments go through the port as one buffer, with no gap between them
+    fn readv(&self, bufs: Vec<UserBuffer>) -> Result<usize, isize> {
+        self.read(UserBuffer::concat(bufs))
+    }
+    fn writev(&self, bufs: Vec<UserBuffer>) -> Result<usize, isize> {
+        self.write(UserBuffer::concat(bufs))
+    }
+    fn ioctl(&self, cmd: usize, arg: usize) -> Result<usize, isize> {
+        let token = current_user_token();
+        match cmd {
+            SERIAL_GET_CONFIG => {
+                let config = serial_config(self.id);
+                let bytes = unsafe {
+                    core::slice::from_raw_parts(
+                        &config as *const _ as *const u8,
+                        size_of::<SerialConfig>(),
+                    )
+                };
+                copy_to_user(token, arg as *mut u8, bytes).map(|_| 0)
+            }
+            SERIAL_SET_CONFIG => {
+                let mut config = SerialConfig::new(0);
+                let bytes = unsafe {
+                    core::slice::from_raw_parts_mut(
+                        &mut config as *mut _ as *mut u8,
+                        size_of::<SerialConfig>(),
+                    )
+                };
+                copy_from_user(token, arg as *const u8, bytes)?;
+                if serial_set_config(self.id, config) {
+                    Ok(0)
+                } else {
+                    Err(EINVAL)
+                }
+            }
+            _ => Err(ENOTTY),
         }
     }
 }
@@ -1,7 +1,9 @@
 use super::File;
+use crate::config::CPU_NUM;
+use crate::console::{print_line_buffered, write_line_buffered, LineBuffers, LINE_BUFFERS_INIT};
 use crate::mm::UserBuffer;
-use crate::print;
-use crate::uart::{serial_getchar, serial_putchar};
+use crate::syscall::{EAGAIN, EBADF};
+use crate::uart::{serial_getchar, serial_putchar, serial_write};
 use core::fmt::{self, Write};
 
 pub struct Stdin;
@@ -9,30 +11,37 @@ pub struct Stdin;
 pub struct Stdout;
 
 impl File for Stdin {
-    fn read(&self, mut user_buf: UserBuffer) -> Result<usize, isize> {
-        assert_eq!(user_buf.len(), 1);
-        // busy loop
-        if let Ok(ch) = serial_getchar(0) {
-            unsafe {
-                user_buf.buffers[0].as_mut_ptr().write_volatile(ch);
+    fn read(&self, user_buf: UserBuffer) -> Result<usize, isize> {
+        let mut read_cnt = 0;
+        for ptr in user_buf.into_iter() {
+            match serial_getchar(0) {
+                Ok(ch) => unsafe {
+                    ptr.write_volatile(ch);
+                },
+                Err(_) => break,
             }
-            Ok(1)
+            read_cnt += 1;
+        }
+        // the caller polls until something arrives
+        if read_cnt > 0 {
+            Ok(read_cnt)
         } else {
-            Err(-1)
+            Err(EAGAIN)
         }
     }
+    /// stdin may be any fd after a redirection, refuse rather than panic
     fn write(&self, _user_buf: UserBuffer) -> Result<usize, isize> {
-        panic!("Cannot write to stdin!");
+        Err(EBADF)
     }
 }
 
 impl File for Stdout {
     fn read(&self, _user_buf: UserBuffer) -> Result<usize, isize> {
-        panic!("Cannot read from stdout!");
+        Err(EBADF)
     }
     fn write(&self, user_buf: UserBuffer) -> Result<usize, isize> {
         for buffer in user_buf.buffers.iter() {
-            print!("{}", core::str::from_utf8(*buffer).unwrap());
+            write_line_buffered(&STDOUT_LINES, stdout_write, buffer);
         }
         Ok(user_buf.len())
     }
@@ -47,9 +56,15 @@ impl Write for Stdout {
     }
 }
 
+static STDOUT_LINES: LineBuffers = [LINE_BUFFERS_INIT; CPU_NUM];
+
+fn stdout_write(bytes: &[u8]) {
+    serial_write(0, bytes);
+}
+
 #[allow(dead_code)]
 pub fn print(args: fmt::Arguments) {
-    Stdout.write_fmt(args).unwrap();
+    print_line_buffered(&STDOUT_LINES, stdout_write, args);
 }
 
 #[macro_export]
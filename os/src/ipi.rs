@@ -0,0 +1,87 @@
+//! Typed inter-hart requests on top of the SBI IPI, which carries no payload.
+//!
+//! Every hart has a mailbox holding one pending bit per message type, so
+//! repeated requests of a type coalesce until the target handles them. The
+//! kernel runs with interrupts off, so the mailbox is drained on the
+//! supervisor software interrupt from user mode and by the idle loop.
+
+use crate::config::{kernel_config, CPU_NUM};
+use crate::sbi::{remote_fence_i, remote_sfence_vma, send_ipi};
+use core::sync::atomic::{AtomicUsize, Ordering::AcqRel};
+use riscv::asm::sfence_vma_all;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(usize)]
+pub enum IpiMessage {
+    /// Kick a hart out of user mode so that it delivers pending user trap records
+    UserTrap = 0,
+    /// Kernel page table entries were removed, flush the TLB
+    TlbShootdown = 1,
+    /// Nothing to do, only makes the hart take a trap, sent to start a hart
+    Wake = 2,
+}
+
+/// A set of harts, bit `i` for hart `i`, as the SBI expects it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HartMask(usize);
+
+impl HartMask {
+    pub fn single(hart_id: usize) -> Self {
+        HartMask(1 << hart_id)
+    }
+    /// The harts brought up, which may be fewer than `CPU_NUM`
+    pub fn all() -> Self {
+        HartMask((1 << kernel_config().hart_num) - 1)
+    }
+    pub fn all_but(hart_id: usize) -> Self {
+        HartMask(Self::all().0 & !(1 << hart_id))
+    }
+    pub fn contains(&self, hart_id: usize) -> bool {
+        self.0 & (1 << hart_id) != 0
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const MAILBOX_INIT: AtomicUsize = AtomicUsize::new(0);
+static MAILBOXES: [AtomicUsize; CPU_NUM] = [MAILBOX_INIT; CPU_NUM];
+
+pub fn send(mask: HartMask, msg: IpiMessage) {
+    if mask.is_empty() {
+        return;
+    }
+    for (hart_id, mailbox) in MAILBOXES.iter().enumerate() {
+        if mask.contains(hart_id) {
+            mailbox.fetch_or(1 << msg as usize, AcqRel);
+        }
+    }
+    send_ipi(&mask.0 as *const _ as usize);
+}
+
+/// Ask every other hart to flush its TLB, without waiting for them
+pub fn tlb_shootdown(hart_id: usize) {
+    send(HartMask::all_but(hart_id), IpiMessage::TlbShootdown);
+}
+
+/// Make code just written to memory visible to the instruction fetch of every hart.
+/// Unlike the mailbox messages, the firmware does it before returning.
+pub fn sync_icache() {
+    remote_fence_i(&HartMask::all().0 as *const _ as usize);
+}
+
+/// Flush kernel mappings of `[start, start + size)` from every TLB before returning,
+/// for memory reused right away, unlike `tlb_shootdown`
+pub fn flush_kernel_range(start: usize, size: usize) {
+    remote_sfence_vma(&HartMask::all().0 as *const _ as usize, start, size);
+}
+
+/// Handle the messages pending for this hart
+pub fn handle_ipis(hart_id: usize) {
+    let pending = MAILBOXES[hart_id].swap(0, AcqRel);
+    if pending & (1 << IpiMessage::TlbShootdown as usize) != 0 {
+        unsafe { sfence_vma_all() }
+    }
+    // `UserTrap` records are delivered by `trap_return`, `Wake` needs nothing
+}
@@ -0,0 +1,90 @@
+//! Deferred handling of device interrupts, so that servicing a device does not add to
+//! the latency of the trap it came in with. For a threaded source the top half in `plic`
+//! only masks and acknowledges it and queues it here. The scheduler loop of the first
+//! hart to get there then services the devices behind it, between two tasks, sources
+//! of a higher PLIC priority first.
+//!
+//! There are no kernel tasks to run this in, so the scheduler loop stands in for them.
+
+use crate::config::IrqTrigger;
+use crate::plic::{self, Plic};
+use crate::timer::get_time_us;
+use crate::trap::USER_EXT_INT_MAP;
+use crate::util::SpinNoIrq;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+struct PendingIrq {
+    /// What to restore once serviced, a level source being masked by priority 0 meanwhile
+    priority: u32,
+    queued_us: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IrqThreadStats {
+    pub serviced: usize,
+    /// Longest wait from the top half to the devices being serviced
+    pub max_delay_us: usize,
+}
+
+lazy_static! {
+    static ref PENDING_IRQS: SpinNoIrq<BTreeMap<u16, PendingIrq>> = SpinNoIrq::new(BTreeMap::new());
+    static ref IRQ_THREAD_STATS: SpinNoIrq<BTreeMap<u16, IrqThreadStats>> =
+        SpinNoIrq::new(BTreeMap::new());
+}
+
+/// Top half of a threaded source claimed in `context`. A level source stays masked until
+/// its devices are serviced, otherwise its line still asserted fires again at once.
+/// Interrupts of a source already queued are serviced together.
+pub fn defer(context: usize, irq: u16, trigger: IrqTrigger) {
+    let mut pending = PENDING_IRQS.lock();
+    if !pending.contains_key(&irq) {
+        pending.insert(
+            irq,
+            PendingIrq {
+                priority: plic::priority(irq),
+                queued_us: get_time_us(),
+            },
+        );
+    }
+    if trigger == IrqTrigger::Level {
+        plic::set_priority(irq, 0);
+    }
+    Plic::complete(context, irq);
+}
+
+/// Bottom half, run by the scheduler loop
+pub fn run_pending() {
+    let mut queued: Vec<(u16, PendingIrq)> = {
+        let mut pending = PENDING_IRQS.lock();
+        if pending.is_empty() {
+            return;
+        }
+        core::mem::take(&mut *pending).into_iter().collect()
+    };
+    queued.sort_by_key(|(_, pending)| core::cmp::Reverse(pending.priority));
+    for (irq, pending) in queued {
+        // claimed by a task since, which services it and got it a priority of its own
+        if USER_EXT_INT_MAP.lock().contains_key(&irq) {
+            continue;
+        }
+        let delay_us = get_time_us() - pending.queued_us;
+        plic::service_devices(irq);
+        if !USER_EXT_INT_MAP.lock().contains_key(&irq) {
+            plic::set_priority(irq, pending.priority);
+        }
+        let mut stats = IRQ_THREAD_STATS.lock();
+        let stats = stats.entry(irq).or_default();
+        stats.serviced += 1;
+        stats.max_delay_us = stats.max_delay_us.max(delay_us);
+    }
+}
+
+pub fn stats(irq: u16) -> IrqThreadStats {
+    IRQ_THREAD_STATS
+        .lock()
+        .get(&irq)
+        .cloned()
+        .unwrap_or_default()
+}
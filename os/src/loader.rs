@@ -26,25 +26,18 @@ pub fn get_app_data(app_id: usize) -> &'static [u8] {
 
 lazy_static! {
     static ref APP_NAMES: Vec<&'static str> = {
-        let num_app = get_num_app();
         extern "C" {
             fn _app_names();
         }
-        let mut start = _app_names as usize as *const u8;
-        let mut v = Vec::new();
-        unsafe {
-            for _ in 0..num_app {
-                let mut end = start;
-                while end.read_volatile() != b'\0' {
-                    end = end.add(1);
-                }
-                let slice = core::slice::from_raw_parts(start, end as usize - start as usize);
-                let str = core::str::from_utf8(slice).unwrap();
-                v.push(str);
-                start = end.add(1);
-            }
+        read_names(_app_names as usize as *const u8, get_num_app())
+    };
+    static ref SERVICE_NAMES: Vec<&'static str> = {
+        extern "C" {
+            fn _num_service();
+            fn _service_names();
         }
-        v
+        let num_service = unsafe { (_num_service as usize as *const usize).read_volatile() };
+        read_names(_service_names as usize as *const u8, num_service)
     };
 }
 
@@ -61,5 +54,42 @@ pub fn list_apps() {
     for app in APP_NAMES.iter() {
         info!("{}", app);
     }
+    for service in SERVICE_NAMES.iter() {
+        info!("{} (service)", service);
+    }
     info!("**************/")
 }
+
+/// Read `num` names from the `.string` list at `start`, as generated by `build.rs`
+fn read_names(mut start: *const u8, num: usize) -> Vec<&'static str> {
+    let mut v = Vec::new();
+    unsafe {
+        for _ in 0..num {
+            let mut end = start;
+            while end.read_volatile() != b'\0' {
+                end = end.add(1);
+            }
+            let slice = core::slice::from_raw_parts(start, end as usize - start as usize);
+            v.push(core::str::from_utf8(slice).unwrap());
+            start = end.add(1);
+        }
+    }
+    v
+}
+
+/// The flat binary of the loadable service `name`
+pub fn get_service_data_by_name(name: &str) -> Option<&'static [u8]> {
+    extern "C" {
+        fn _num_service();
+    }
+    // pairs of start and end follow the count
+    let ranges = (_num_service as usize as *const usize).wrapping_add(1);
+    SERVICE_NAMES
+        .iter()
+        .position(|&n| n == name)
+        .map(|i| unsafe {
+            let start = ranges.add(2 * i).read_volatile();
+            let end = ranges.add(2 * i + 1).read_volatile();
+            core::slice::from_raw_parts(start as *const u8, end - start)
+        })
+}
@@ -1,19 +1,13 @@
+use crate::config::KernelConfig;
 use crate::console::{print_colorized, ANSICON};
 use crate::task::hart_id;
-use log::{Level, LevelFilter, Metadata, Record};
+use log::{Level, Metadata, Record};
 
 static LOGGER: SimpleLogger = SimpleLogger;
 
-pub fn init() {
+pub fn init(config: &KernelConfig) {
     log::set_logger(&LOGGER).unwrap();
-    log::set_max_level(match option_env!("LOG") {
-        Some("ERROR") => LevelFilter::Error,
-        Some("WARN") => LevelFilter::Warn,
-        Some("INFO") => LevelFilter::Info,
-        Some("DEBUG") => LevelFilter::Debug,
-        Some("TRACE") => LevelFilter::Trace,
-        _ => LevelFilter::Off,
-    });
+    log::set_max_level(config.log_level);
 }
 
 struct SimpleLogger;
@@ -13,50 +13,88 @@ extern crate bitflags;
 #[macro_use]
 extern crate log;
 
-use crate::{config::CPU_NUM, mm::init_kernel_space, sbi::send_ipi};
+use crate::{
+    config::init_kernel_config,
+    ipi::{HartMask, IpiMessage},
+    mm::init_kernel_space,
+};
 use core::arch::{asm, global_asm};
 
 #[macro_use]
 mod console;
+mod build_info;
 mod config;
+mod deterministic;
+mod drivers;
 #[macro_use]
 mod fs;
+mod ipi;
+mod irq_thread;
 mod lang_items;
 mod loader;
 mod logger;
 mod mm;
 mod plic;
+mod random;
 mod sbi;
+mod service;
 mod syscall;
 mod task;
 mod timer;
 mod trap;
 #[macro_use]
 mod uart;
+mod util;
+mod watchdog;
 
 global_asm!(include_str!("entry.asm"));
 global_asm!(include_str!("link_app.asm"));
 
+/// Clear with word stores, unrolled to a cache line. The bss is page aligned by the linker
+/// scripts, the byte loops only cover unaligned ends should that change.
 fn clear_bss() {
     extern "C" {
         fn sbss();
         fn ebss();
     }
-    (sbss as usize..ebss as usize).for_each(|a| unsafe { (a as *mut u8).write_volatile(0) });
+    const WORD: usize = core::mem::size_of::<usize>();
+    const LINE: usize = 8 * WORD;
+    let (start, end) = (sbss as usize, ebss as usize);
+    let word_start = ((start + WORD - 1) & !(WORD - 1)).min(end);
+    let word_end = (end & !(WORD - 1)).max(word_start);
+    let line_end = word_start + (word_end - word_start) / LINE * LINE;
+    unsafe {
+        (start..word_start).for_each(|a| (a as *mut u8).write_volatile(0));
+        for line in (word_start..line_end).step_by(LINE) {
+            let p = line as *mut usize;
+            for i in 0..8 {
+                p.add(i).write_volatile(0);
+            }
+        }
+        (line_end..word_end)
+            .step_by(WORD)
+            .for_each(|a| (a as *mut usize).write_volatile(0));
+        (word_end..end).for_each(|a| (a as *mut u8).write_volatile(0));
+    }
 }
 
 #[no_mangle]
-pub fn rust_main(hart_id: usize) -> ! {
+pub fn rust_main(hart_id: usize, dtb: usize) -> ! {
     if hart_id == 0 {
         clear_bss();
-        logger::init();
-        mm::init();
+        let config = init_kernel_config(dtb);
+        logger::init(config);
+        mm::init(config);
+        debug!("[kernel {}] {:x?}", hart_id, config);
         debug!("[kernel {}] Hello, world!", hart_id);
         mm::remap_test();
         trap::init();
         plic::init();
         plic::init_hart(hart_id);
         uart::init();
+        fs::init();
+        drivers::init();
+        build_info::print_banner();
 
         extern "C" {
             fn boot_stack();
@@ -69,6 +107,7 @@ pub fn rust_main(hart_id: usize) -> ! {
         );
 
         debug!("trying to add initproc");
+        timer::mark_booted();
         task::add_initproc();
         debug!("initproc added to task manager!");
 
@@ -80,10 +119,9 @@ pub fn rust_main(hart_id: usize) -> ! {
             println_hart!("satp: {:#x}, sp: {:#x}", hart_id, satp, sp);
         }
 
-        for i in 1..CPU_NUM {
+        for i in 1..config.hart_num {
             debug!("[kernel {}] Start {}", hart_id, i);
-            let mask: usize = 1 << i;
-            send_ipi(&mask as *const _ as usize);
+            ipi::send(HartMask::single(i), IpiMessage::Wake);
         }
     } else {
         let hart_id = task::hart_id();
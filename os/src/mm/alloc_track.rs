@@ -0,0 +1,303 @@
+//! Allocation tracking for leak hunting, on when `TRACK_ALLOCATIONS` is set.
+//!
+//! Every task has an `AllocOwner`, dropped last with its control block.
+//! Frames and heap objects allocated while a task runs on a hart, or inside an
+//! `AllocScope`, are tagged with its owner and a subsystem. Whatever is still
+//! tagged with an owner when it is dropped has survived the task: it is
+//! reported at teardown and kept for the `/proc/memleak` report, or is fatal
+//! with `PANIC_ON_LEAK`.
+
+use crate::config::{CPU_NUM, PANIC_ON_LEAK, TRACK_ALLOCATIONS};
+use crate::task::hart_id;
+use crate::util::{in_irq, SpinNoIrq};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use core::fmt::{self, Debug, Formatter, Write};
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering::Relaxed};
+use lazy_static::*;
+
+pub const SUBSYSTEM_TASK: u8 = 0;
+pub const SUBSYSTEM_EXEC: u8 = 1;
+pub const SUBSYSTEM_FORK: u8 = 2;
+pub const SUBSYSTEM_SPAWN: u8 = 3;
+pub const SUBSYSTEM_USER_TRAP: u8 = 4;
+const SUBSYSTEM_NUM: usize = 5;
+
+fn subsystem_name(subsystem: u8) -> &'static str {
+    match subsystem {
+        SUBSYSTEM_TASK => "task",
+        SUBSYSTEM_EXEC => "exec",
+        SUBSYSTEM_FORK => "fork",
+        SUBSYSTEM_SPAWN => "spawn",
+        SUBSYSTEM_USER_TRAP => "user_trap",
+        _ => "unknown",
+    }
+}
+
+/// Owner 0 is the kernel itself, which is not tracked
+const KERNEL_OWNER: usize = 0;
+
+#[derive(Clone, Copy)]
+struct Tag {
+    owner: usize,
+    subsystem: u8,
+    size: usize,
+}
+
+struct OwnerInfo {
+    pid: usize,
+    alive: bool,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Usage {
+    frames: usize,
+    heap_objects: usize,
+    heap_bytes: usize,
+}
+
+struct Tracker {
+    /// ppn -> tag
+    frames: BTreeMap<usize, Tag>,
+    /// address -> tag
+    heap: BTreeMap<usize, Tag>,
+    owners: BTreeMap<usize, OwnerInfo>,
+}
+
+impl Tracker {
+    fn usage_of(&self, owner: usize) -> [Usage; SUBSYSTEM_NUM] {
+        let mut usage = [Usage::default(); SUBSYSTEM_NUM];
+        for tag in self.frames.values().filter(|tag| tag.owner == owner) {
+            usage[tag.subsystem as usize].frames += 1;
+        }
+        for tag in self.heap.values().filter(|tag| tag.owner == owner) {
+            usage[tag.subsystem as usize].heap_objects += 1;
+            usage[tag.subsystem as usize].heap_bytes += tag.size;
+        }
+        usage
+    }
+}
+
+lazy_static! {
+    static ref TRACKER: SpinNoIrq<Tracker> = SpinNoIrq::new(Tracker {
+        frames: BTreeMap::new(),
+        heap: BTreeMap::new(),
+        owners: BTreeMap::new(),
+    });
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const FALSE_INIT: AtomicBool = AtomicBool::new(false);
+#[allow(clippy::declare_interior_mutable_const)]
+const OWNER_INIT: AtomicUsize = AtomicUsize::new(KERNEL_OWNER);
+#[allow(clippy::declare_interior_mutable_const)]
+const SUBSYSTEM_INIT: AtomicU8 = AtomicU8::new(SUBSYSTEM_TASK);
+
+/// Set while a hart is inside the tracker, whose own heap use must not be tracked
+static IN_TRACKER: [AtomicBool; CPU_NUM] = [FALSE_INIT; CPU_NUM];
+static CURRENT_OWNER: [AtomicUsize; CPU_NUM] = [OWNER_INIT; CPU_NUM];
+static CURRENT_SUBSYSTEM: [AtomicU8; CPU_NUM] = [SUBSYSTEM_INIT; CPU_NUM];
+static NEXT_OWNER: AtomicUsize = AtomicUsize::new(KERNEL_OWNER + 1);
+
+/// Run `f` on the tracker unless tracking is off or this hart is already inside it
+fn with_tracker<R>(f: impl FnOnce(&mut Tracker, usize) -> R) -> Option<R> {
+    if !TRACK_ALLOCATIONS {
+        return None;
+    }
+    let hart = hart_id();
+    if IN_TRACKER[hart].swap(true, Relaxed) {
+        return None;
+    }
+    let ret = f(&mut TRACKER.lock(), hart);
+    IN_TRACKER[hart].store(false, Relaxed);
+    Some(ret)
+}
+
+/// Tag of a new allocation on `hart`, interrupt handlers work for nobody in particular
+fn current_tag(hart: usize, size: usize) -> Option<Tag> {
+    let owner = CURRENT_OWNER[hart].load(Relaxed);
+    if owner == KERNEL_OWNER || in_irq() {
+        return None;
+    }
+    Some(Tag {
+        owner,
+        subsystem: CURRENT_SUBSYSTEM[hart].load(Relaxed),
+        size,
+    })
+}
+
+pub fn record_frame(ppn: usize) {
+    with_tracker(|tracker, hart| {
+        if let Some(tag) = current_tag(hart, 1) {
+            tracker.frames.insert(ppn, tag);
+        }
+    });
+}
+
+pub fn forget_frame(ppn: usize) {
+    with_tracker(|tracker, _| tracker.frames.remove(&ppn));
+}
+
+pub fn record_heap(addr: usize, size: usize) {
+    with_tracker(|tracker, hart| {
+        if let Some(tag) = current_tag(hart, size) {
+            tracker.heap.insert(addr, tag);
+        }
+    });
+}
+
+pub fn forget_heap(addr: usize) {
+    with_tracker(|tracker, _| tracker.heap.remove(&addr));
+}
+
+/// Called by the scheduler around running a task on this hart
+pub fn switch_owner(owner: usize) {
+    CURRENT_OWNER[hart_id()].store(owner, Relaxed);
+}
+
+pub struct AllocOwner {
+    id: usize,
+}
+
+impl AllocOwner {
+    pub fn new() -> Self {
+        let id = NEXT_OWNER.fetch_add(1, Relaxed);
+        with_tracker(|tracker, _| {
+            tracker.owners.insert(
+                id,
+                OwnerInfo {
+                    pid: 0,
+                    alive: true,
+                },
+            )
+        });
+        Self { id }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// The pid is only known after the memory of a new task has been set up
+    pub fn set_pid(&self, pid: usize) {
+        with_tracker(|tracker, _| {
+            if let Some(info) = tracker.owners.get_mut(&self.id) {
+                info.pid = pid;
+            }
+        });
+    }
+
+    /// Tag allocations on this hart with this owner until the scope is dropped
+    pub fn scope(&self, subsystem: u8) -> AllocScope {
+        AllocScope::new(self.id, subsystem)
+    }
+}
+
+impl Debug for AllocOwner {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!("AllocOwner({})", self.id))
+    }
+}
+
+impl Drop for AllocOwner {
+    /// Everything of the task has been freed by now, anything left is a leak
+    fn drop(&mut self) {
+        let id = self.id;
+        with_tracker(|tracker, _| {
+            let usage = tracker.usage_of(id);
+            let leaked = usage
+                .iter()
+                .any(|usage| usage.frames > 0 || usage.heap_objects > 0);
+            if leaked {
+                let pid = tracker.owners.get(&id).map_or(0, |info| info.pid);
+                for (subsystem, usage) in usage.iter().enumerate() {
+                    if usage.frames > 0 || usage.heap_objects > 0 {
+                        warn!(
+                            "[memleak] pid {} exited leaving {} frames, {} heap objects ({} bytes) from {}",
+                            pid,
+                            usage.frames,
+                            usage.heap_objects,
+                            usage.heap_bytes,
+                            subsystem_name(subsystem as u8)
+                        );
+                    }
+                }
+                if PANIC_ON_LEAK {
+                    panic!("[memleak] pid {} leaked", pid);
+                }
+                if let Some(info) = tracker.owners.get_mut(&id) {
+                    info.alive = false;
+                }
+            } else {
+                tracker.owners.remove(&id);
+            }
+        });
+    }
+}
+
+pub struct AllocScope {
+    hart: usize,
+    prev_owner: usize,
+    prev_subsystem: u8,
+}
+
+impl AllocScope {
+    fn new(owner: usize, subsystem: u8) -> Self {
+        let hart = hart_id();
+        Self {
+            hart,
+            prev_owner: CURRENT_OWNER[hart].swap(owner, Relaxed),
+            prev_subsystem: CURRENT_SUBSYSTEM[hart].swap(subsystem, Relaxed),
+        }
+    }
+
+    /// Keep the owner running on this hart, tag with `subsystem`
+    pub fn subsystem(subsystem: u8) -> Self {
+        Self::new(CURRENT_OWNER[hart_id()].load(Relaxed), subsystem)
+    }
+
+    /// Allocations which belong to no task, even if one is running on this hart
+    pub fn kernel() -> Self {
+        Self::new(KERNEL_OWNER, SUBSYSTEM_TASK)
+    }
+}
+
+impl Drop for AllocScope {
+    fn drop(&mut self) {
+        CURRENT_OWNER[self.hart].store(self.prev_owner, Relaxed);
+        CURRENT_SUBSYSTEM[self.hart].store(self.prev_subsystem, Relaxed);
+    }
+}
+
+/// Allocations which survived the exit of their owner, one line per owner and subsystem
+pub fn leak_report() -> String {
+    let mut report = String::new();
+    if !TRACK_ALLOCATIONS {
+        report.push_str("allocation tracking is off, see TRACK_ALLOCATIONS\n");
+        return report;
+    }
+    with_tracker(|tracker, _| {
+        let _ = writeln!(
+            report,
+            "tracked: {} frames, {} heap objects",
+            tracker.frames.len(),
+            tracker.heap.len()
+        );
+        for (&id, info) in tracker.owners.iter().filter(|(_, info)| !info.alive) {
+            for (subsystem, usage) in tracker.usage_of(id).iter().enumerate() {
+                if usage.frames > 0 || usage.heap_objects > 0 {
+                    let _ = writeln!(
+                        report,
+                        "pid {} {}: {} frames, {} heap objects ({} bytes)",
+                        info.pid,
+                        subsystem_name(subsystem as u8),
+                        usage.frames,
+                        usage.heap_objects,
+                        usage.heap_bytes
+                    );
+                }
+            }
+        }
+    });
+    report
+}
@@ -0,0 +1,162 @@
+use super::{PhysAddr, PhysPageNum};
+use crate::config::{DMA_REGION_SIZE, PAGE_SIZE};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use core::fmt::{self, Debug, Formatter, Write};
+use lazy_static::*;
+use spin::Mutex;
+
+/// Physically contiguous pages taken from the DMA region.
+///
+/// Physical memory is identically mapped in kernel space,
+/// so the kernel virtual address equals the physical address.
+pub struct DmaTracker {
+    pub ppn: PhysPageNum,
+    pub pages: usize,
+}
+
+impl DmaTracker {
+    pub fn new(ppn: PhysPageNum, pages: usize) -> Self {
+        for i in 0..pages {
+            let bytes_array = PhysPageNum(ppn.0 + i).get_bytes_array();
+            for b in bytes_array {
+                *b = 0;
+            }
+        }
+        Self { ppn, pages }
+    }
+
+    pub fn paddr(&self) -> usize {
+        PhysAddr::from(self.ppn).0
+    }
+
+    pub fn vaddr(&self) -> usize {
+        self.paddr()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pages * PAGE_SIZE
+    }
+}
+
+impl Debug for DmaTracker {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "DmaTracker:PPN={:#x},pages={}",
+            self.ppn.0, self.pages
+        ))
+    }
+}
+
+impl Drop for DmaTracker {
+    fn drop(&mut self) {
+        DMA_ALLOCATOR.lock().dealloc(self.ppn);
+    }
+}
+
+/// First-fit allocator over the page range reserved for DMA at boot
+pub struct DmaAllocator {
+    start: usize,
+    end: usize,
+    /// start ppn -> page count
+    allocated: BTreeMap<usize, usize>,
+}
+
+impl DmaAllocator {
+    pub fn new() -> Self {
+        Self {
+            start: 0,
+            end: 0,
+            allocated: BTreeMap::new(),
+        }
+    }
+
+    pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        self.start = l.0;
+        self.end = r.0;
+        debug!("last {} DMA Frames.", self.end - self.start);
+    }
+
+    pub fn alloc(&mut self, pages: usize) -> Option<PhysPageNum> {
+        if pages == 0 {
+            return None;
+        }
+        let mut current = self.start;
+        for (start, len) in self.allocated.iter() {
+            if start - current >= pages {
+                break;
+            }
+            current = start + len;
+        }
+        if current + pages > self.end {
+            return None;
+        }
+        self.allocated.insert(current, pages);
+        Some(current.into())
+    }
+
+    pub fn dealloc(&mut self, ppn: PhysPageNum) {
+        if self.allocated.remove(&ppn.0).is_none() {
+            panic!("DMA ppn={:#x} has not been allocated!", ppn.0);
+        }
+    }
+}
+
+lazy_static! {
+    pub static ref DMA_ALLOCATOR: Mutex<DmaAllocator> = Mutex::new(DmaAllocator::new());
+}
+
+/// The highest page aligned window of `DMA_REGION_SIZE` below `memory_end` clear of the
+/// `reserved` regions
+pub fn dma_region_base(memory_end: usize, reserved: &[(usize, usize)]) -> usize {
+    let mut end = memory_end;
+    loop {
+        let base = end
+            .checked_sub(DMA_REGION_SIZE)
+            .expect("no room left for the DMA region")
+            & !(PAGE_SIZE - 1);
+        let overlapped = reserved
+            .iter()
+            .filter(|&&(start, size)| start < base + DMA_REGION_SIZE && base < start + size)
+            .map(|&(start, _)| start)
+            .min();
+        match overlapped {
+            Some(start) => end = start,
+            None => return base,
+        }
+    }
+}
+
+/// The DMA region and the buffers taken from it, for `/proc/dma`
+pub fn dma_report() -> String {
+    let allocator = DMA_ALLOCATOR.lock();
+    let mut report = format!(
+        "region {:#x} {:#x}\n",
+        PhysAddr::from(PhysPageNum(allocator.start)).0,
+        PhysAddr::from(PhysPageNum(allocator.end)).0
+    );
+    for (&ppn, &pages) in allocator.allocated.iter() {
+        let _ = writeln!(
+            report,
+            "buffer {:#x} pages {}",
+            PhysAddr::from(PhysPageNum(ppn)).0,
+            pages
+        );
+    }
+    report
+}
+
+pub fn init_dma_allocator(dma_base: usize) {
+    DMA_ALLOCATOR.lock().init(
+        PhysAddr::from(dma_base).ceil(),
+        PhysAddr::from(dma_base + DMA_REGION_SIZE).floor(),
+    );
+}
+
+pub fn dma_alloc(pages: usize) -> Option<DmaTracker> {
+    DMA_ALLOCATOR
+        .lock()
+        .alloc(pages)
+        .map(|ppn| DmaTracker::new(ppn, pages))
+}
@@ -1,5 +1,6 @@
-use super::{PhysAddr, PhysPageNum};
-use crate::config::MEMORY_END;
+use super::{alloc_track, PhysAddr, PhysPageNum};
+use crate::config::DMA_REGION_SIZE;
+use crate::util::StackIdAllocator;
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
 use lazy_static::*;
@@ -39,44 +40,51 @@ trait FrameAllocator {
 }
 
 pub struct StackFrameAllocator {
-    current: usize,
-    end: usize,
-    recycled: Vec<usize>,
+    /// One per free range of RAM
+    ranges: Vec<StackIdAllocator>,
 }
 
 impl StackFrameAllocator {
-    pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
-        self.current = l.0;
-        self.end = r.0;
-        debug!("last {} Physical Frames.", self.end - self.current);
+    /// Manage `[l, r)` but for the `holes`
+    pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum, mut holes: Vec<(usize, usize)>) {
+        holes.sort_unstable();
+        self.ranges.clear();
+        let mut start = l.0;
+        for (hole_start, hole_end) in holes {
+            if hole_start > start {
+                self.ranges
+                    .push(StackIdAllocator::new(start, hole_start.min(r.0)));
+            }
+            start = start.max(hole_end);
+            if start >= r.0 {
+                break;
+            }
+        }
+        if start < r.0 {
+            self.ranges.push(StackIdAllocator::new(start, r.0));
+        }
+        debug!("last {} Physical Frames.", self.available());
+    }
+
+    fn available(&self) -> usize {
+        self.ranges.iter().map(|ppns| ppns.available()).sum()
     }
 }
 impl FrameAllocator for StackFrameAllocator {
     fn new() -> Self {
-        Self {
-            current: 0,
-            end: 0,
-            recycled: Vec::new(),
-        }
+        Self { ranges: Vec::new() }
     }
     fn alloc(&mut self) -> Option<PhysPageNum> {
-        if let Some(ppn) = self.recycled.pop() {
-            Some(ppn.into())
-        } else if self.current == self.end {
-            None
-        } else {
-            self.current += 1;
-            Some((self.current - 1).into())
-        }
+        self.ranges
+            .iter_mut()
+            .find_map(|ppns| ppns.alloc())
+            .map(PhysPageNum::from)
     }
     fn dealloc(&mut self, ppn: PhysPageNum) {
-        let ppn = ppn.0;
-        // validity check
-        if ppn >= self.current || self.recycled.iter().any(|v| *v == ppn) {
-            panic!("Frame ppn={:#x} has not been allocated!", ppn);
+        let ppns = self.ranges.iter_mut().find(|ppns| ppns.is_allocated(ppn.0));
+        if !ppns.map_or(false, |ppns| ppns.dealloc(ppn.0)) {
+            panic!("Frame ppn={:#x} has not been allocated!", ppn.0);
         }
-        // recycle
-        self.recycled.push(ppn);
     }
 }
 
@@ -87,21 +95,41 @@ lazy_static! {
         Mutex::new(FrameAllocatorImpl::new());
 }
 
-pub fn init_frame_allocator() {
+/// Manage the frames from the end of the kernel image to `memory_end`, but for the DMA
+/// region at `dma_base` and the `reserved` regions
+pub fn init_frame_allocator(memory_end: usize, dma_base: usize, reserved: &[(usize, usize)]) {
     extern "C" {
         fn ekernel();
     }
+    let holes = reserved
+        .iter()
+        .chain(core::iter::once(&(dma_base, DMA_REGION_SIZE)))
+        .map(|&(base, size)| {
+            (
+                PhysAddr::from(base).floor().0,
+                PhysAddr::from(base + size).ceil().0,
+            )
+        })
+        .collect();
     FRAME_ALLOCATOR.lock().init(
         PhysAddr::from(ekernel as usize).ceil(),
-        PhysAddr::from(MEMORY_END).floor(),
+        PhysAddr::from(memory_end).floor(),
+        holes,
     );
 }
 
 pub fn frame_alloc() -> Option<FrameTracker> {
-    FRAME_ALLOCATOR.lock().alloc().map(FrameTracker::new)
+    let ppn = FRAME_ALLOCATOR.lock().alloc()?;
+    alloc_track::record_frame(ppn.0);
+    Some(FrameTracker::new(ppn))
+}
+
+pub fn frames_available() -> usize {
+    FRAME_ALLOCATOR.lock().available()
 }
 
 fn frame_dealloc(ppn: PhysPageNum) {
+    alloc_track::forget_frame(ppn.0);
     FRAME_ALLOCATOR.lock().dealloc(ppn);
 }
 
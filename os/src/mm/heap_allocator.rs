@@ -1,8 +1,30 @@
-use crate::config::KERNEL_HEAP_SIZE;
+use super::alloc_track;
+use crate::config::{KERNEL_HEAP_SIZE, TRACK_ALLOCATIONS};
 use buddy_system_allocator::LockedHeap;
+use core::alloc::{GlobalAlloc, Layout};
+
+/// `LockedHeap` reporting to `alloc_track`
+struct TrackedHeap(LockedHeap);
+
+unsafe impl GlobalAlloc for TrackedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.0.alloc(layout);
+        if TRACK_ALLOCATIONS && !ptr.is_null() {
+            alloc_track::record_heap(ptr as usize, layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if TRACK_ALLOCATIONS {
+            alloc_track::forget_heap(ptr as usize);
+        }
+        self.0.dealloc(ptr, layout)
+    }
+}
 
 #[global_allocator]
-static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+static HEAP_ALLOCATOR: TrackedHeap = TrackedHeap(LockedHeap::empty());
 
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
@@ -14,6 +36,7 @@ static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
 pub fn init_heap() {
     unsafe {
         HEAP_ALLOCATOR
+            .0
             .lock()
             .init(HEAP_SPACE.as_ptr() as usize, KERNEL_HEAP_SIZE);
     }
@@ -1,15 +1,22 @@
-use super::{frame_alloc, FrameTracker};
-use super::{PTEFlags, PageTable, PageTableEntry};
+use super::{frame_alloc, frames_available, shared_pages, FrameTracker};
+use super::{PTEFlags, PageTable, PageTableEntry, PteInfo};
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use super::{StepByOne, VPNRange};
-use crate::config::{MEMORY_END, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE};
+use crate::config::{
+    kernel_config, ALLOW_WRITABLE_EXEC, ASLR_MMAP_PAGES, ASLR_STACK_PAGES, DETERMINISTIC,
+    MMAP_BASE, OOM_RESERVE_FRAMES, PAGE_SIZE, PLIC_BASE, PLIC_SIZE, RTC_MMIO,
+    SERIAL_ADDRESS_STRIDE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE,
+};
+use crate::deterministic;
+use crate::syscall::{EEXIST, EINVAL, ENOMEM, EPERM};
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::arch::asm;
+use core::fmt::{self, Write};
 use lazy_static::*;
 use riscv::asm::sfence_vma_all;
-use riscv::register::satp;
+use riscv::register::{cycle, satp};
 use spin::Mutex;
 
 extern "C" {
@@ -33,6 +40,8 @@ lazy_static! {
 pub struct MemorySet {
     page_table: PageTable,
     areas: Vec<MapArea>,
+    /// Lowest address considered by `mmap` when no address is given
+    mmap_base: usize,
 }
 
 impl MemorySet {
@@ -40,6 +49,7 @@ impl MemorySet {
         Self {
             page_table: PageTable::new(),
             areas: Vec::new(),
+            mmap_base: MMAP_BASE,
         }
     }
     pub fn token(&self) -> usize {
@@ -57,6 +67,19 @@ impl MemorySet {
             None,
         );
     }
+    /// Like `insert_framed_area`, with the frames holding a copy of `data`
+    pub fn insert_framed_area_with_data(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        permission: MapPermission,
+        data: &[u8],
+    ) {
+        self.push(
+            MapArea::new(start_va, end_va, MapType::Framed, permission),
+            Some(data),
+        );
+    }
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
         if let Some((idx, area)) = self
             .areas
@@ -75,6 +98,24 @@ impl MemorySet {
         }
         self.areas.push(map_area);
     }
+    /// Map a read-only area from the frames of `image` at `offset`, loading those not resident
+    fn push_shared(
+        &mut self,
+        mut map_area: MapArea,
+        image: &'static [u8],
+        offset: usize,
+        data: &[u8],
+    ) {
+        for (i, vpn) in map_area.vpn_range.into_iter().enumerate() {
+            let start = (i * PAGE_SIZE).min(data.len());
+            let src = &data[start..data.len().min(start + PAGE_SIZE)];
+            let frame = shared_pages::get_or_insert(image, offset + i * PAGE_SIZE, |frame| {
+                frame.ppn.get_bytes_array()[..src.len()].copy_from_slice(src);
+            });
+            map_area.map_frame(&mut self.page_table, vpn, frame);
+        }
+        self.areas.push(map_area);
+    }
     /// Mention that trampoline is not collected by areas.
     fn map_trampoline(&mut self) {
         self.page_table.map(
@@ -140,7 +181,7 @@ impl MemorySet {
         memory_set.push(
             MapArea::new(
                 (ekernel as usize).into(),
-                MEMORY_END.into(),
+                kernel_config().memory_end.into(),
                 MapType::Identical,
                 MapPermission::R | MapPermission::W,
             ),
@@ -149,32 +190,54 @@ impl MemorySet {
         debug!("mapping plic");
         memory_set.push(
             MapArea::new(
-                (0xc00_0000 as usize).into(),
-                (0x1000_0000 as usize).into(),
-                MapType::Mmio,
-                MapPermission::R | MapPermission::W,
-            ),
-            None,
-        );
-        debug!("mapping uart");
-        use crate::uart;
-        #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
-        memory_set.push(
-            MapArea::new(
-                (uart::SERIAL_BASE_ADDRESS).into(),
-                (uart::SERIAL_BASE_ADDRESS + uart::SERIAL_NUM * uart::SERIAL_ADDRESS_STRIDE).into(),
+                PLIC_BASE.into(),
+                (PLIC_BASE + PLIC_SIZE).into(),
                 MapType::Mmio,
                 MapPermission::R | MapPermission::W,
             ),
             None,
         );
+        for port in kernel_config().serial_ports() {
+            debug!("mapping uart {:#x}", port.base);
+            memory_set.push(
+                MapArea::new(
+                    port.base.into(),
+                    (port.base + SERIAL_ADDRESS_STRIDE).into(),
+                    MapType::Mmio,
+                    MapPermission::R | MapPermission::W,
+                ),
+                None,
+            );
+        }
+        if let Some((rtc_base, rtc_size)) = RTC_MMIO {
+            debug!("mapping rtc");
+            memory_set.push(
+                MapArea::new(
+                    rtc_base.into(),
+                    (rtc_base + rtc_size).into(),
+                    MapType::Mmio,
+                    MapPermission::R | MapPermission::W,
+                ),
+                None,
+            );
+        }
         unsafe { asm!("fence.i") }
         memory_set
     }
     /// Include sections in elf and trampoline and TrapContext and user stack,
     /// also returns user_sp and entry point.
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
+    /// With `randomize`, the user stack and mmap base are moved by random page offsets.
+    /// Fail with `EPERM` if a segment is both writable and executable, and with `ENOMEM` if
+    /// the frames for it would eat into `OOM_RESERVE_FRAMES`.
+    /// Read-only segments share their frames with every other process running the same app.
+    pub fn from_elf(
+        elf_data: &'static [u8],
+        randomize: bool,
+    ) -> Result<(Self, usize, usize), isize> {
         let mut memory_set = Self::new_bare();
+        if randomize {
+            memory_set.mmap_base += aslr_random_pages(ASLR_MMAP_PAGES) * PAGE_SIZE;
+        }
         // map trampoline
         memory_set.map_trampoline();
         // map program headers of elf, with U flag
@@ -183,6 +246,23 @@ impl MemorySet {
         let magic = elf_header.pt1.magic;
         assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
         let ph_count = elf_header.pt2.ph_count();
+        // shared segments are counted too, they may not have been loaded yet
+        let segment_pages: usize = (0..ph_count)
+            .map(|i| elf.program_header(i).unwrap())
+            .filter(|ph| ph.get_type().unwrap() == xmas_elf::program::Type::Load)
+            .map(|ph| {
+                let start_vpn = VirtAddr::from(ph.virtual_addr() as usize).floor();
+                let end_vpn = VirtAddr::from((ph.virtual_addr() + ph.mem_size()) as usize).ceil();
+                end_vpn.0 - start_vpn.0
+            })
+            .sum();
+        let pages =
+            segment_pages + USER_STACK_SIZE / PAGE_SIZE + (TRAMPOLINE - TRAP_CONTEXT) / PAGE_SIZE;
+        // page tables, at worst a leaf and a middle one for each area
+        let tables = pages / 512 + 2 * (ph_count as usize + 2) + 1;
+        if pages + tables + OOM_RESERVE_FRAMES > frames_available() {
+            return Err(ENOMEM);
+        }
         let mut max_end_vpn = VirtPageNum(0);
         for i in 0..ph_count {
             let ph = elf.program_header(i).unwrap();
@@ -200,12 +280,17 @@ impl MemorySet {
                 if ph_flags.is_execute() {
                     map_perm |= MapPermission::X;
                 }
-                let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
+                map_perm.check_wx()?;
+                let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm)
+                    .with_kind(MapKind::Elf);
                 max_end_vpn = map_area.vpn_range.get_end();
-                memory_set.push(
-                    map_area,
-                    Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
-                );
+                let offset = ph.offset() as usize;
+                let data = &elf_data[offset..offset + ph.file_size() as usize];
+                if map_perm.contains(MapPermission::W) {
+                    memory_set.push(map_area, Some(data));
+                } else {
+                    memory_set.push_shared(map_area, elf_data, offset, data);
+                }
             }
         }
         // map user stack with U flags
@@ -213,6 +298,9 @@ impl MemorySet {
         let mut user_stack_bottom: usize = max_end_va.into();
         // guard page
         user_stack_bottom += PAGE_SIZE;
+        if randomize {
+            user_stack_bottom += aslr_random_pages(ASLR_STACK_PAGES) * PAGE_SIZE;
+        }
         let user_stack_top = user_stack_bottom + USER_STACK_SIZE;
         memory_set.push(
             MapArea::new(
@@ -220,7 +308,8 @@ impl MemorySet {
                 user_stack_top.into(),
                 MapType::Framed,
                 MapPermission::R | MapPermission::W | MapPermission::U,
-            ),
+            )
+            .with_kind(MapKind::Stack),
             None,
         );
         // map TrapContext
@@ -230,23 +319,52 @@ impl MemorySet {
                 TRAMPOLINE.into(),
                 MapType::Framed,
                 MapPermission::R | MapPermission::W,
-            ),
+            )
+            .with_kind(MapKind::TrapContext),
             None,
         );
         unsafe { asm!("fence.i") }
-        (
+        Ok((
             memory_set,
             user_stack_top,
             elf.header.pt2.entry_point() as usize,
-        )
+        ))
     }
-    pub fn from_existed_user(user_space: &MemorySet) -> MemorySet {
+    /// A copy of `user_space`, or `ENOMEM` if the frames for it would eat into
+    /// `OOM_RESERVE_FRAMES`
+    pub fn from_existed_user(user_space: &MemorySet) -> Result<MemorySet, isize> {
+        let pages: usize = user_space
+            .areas
+            .iter()
+            .filter(|area| {
+                area.map_type == MapType::Framed
+                    && area.map_perm.contains(MapPermission::W)
+                    && area.kind != MapKind::Shared
+            })
+            .map(|area| area.vpn_range.get_end().0 - area.vpn_range.get_start().0)
+            .sum();
+        // page tables, at worst a leaf and a middle one for each area
+        let tables = pages / 512 + 2 * user_space.areas.len() + 1;
+        if pages + tables + OOM_RESERVE_FRAMES > frames_available() {
+            return Err(ENOMEM);
+        }
         let mut memory_set = Self::new_bare();
+        memory_set.mmap_base = user_space.mmap_base;
         // map trampoline
         memory_set.map_trampoline();
         // copy data sections/trap_context/user_stack
         for area in user_space.areas.iter() {
-            let new_area = MapArea::from_another(area);
+            let mut new_area = MapArea::from_another(area);
+            if area.map_type == MapType::Framed
+                && (!area.map_perm.contains(MapPermission::W) || area.kind == MapKind::Shared)
+            {
+                // read-only or shared with the child, nothing to copy
+                for (&vpn, frame) in area.data_frames.iter() {
+                    new_area.map_frame(&mut memory_set.page_table, vpn, frame.clone());
+                }
+                memory_set.areas.push(new_area);
+                continue;
+            }
             memory_set.push(new_area, None);
             // copy data from another space
             for vpn in area.vpn_range {
@@ -258,7 +376,7 @@ impl MemorySet {
             }
         }
         unsafe { asm!("fence.i") }
-        memory_set
+        Ok(memory_set)
     }
     pub fn activate(&self) {
         let satp = self.page_table.token();
@@ -270,6 +388,81 @@ impl MemorySet {
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.page_table.translate(vpn)
     }
+    /// Give the frames of `start..start + len` which are shared with other address spaces
+    /// a private copy, before the kernel writes to read-only memory like a breakpoint
+    pub fn unshare(&mut self, start: usize, len: usize) {
+        let range = VPNRange::new(
+            VirtAddr::from(start).floor(),
+            VirtAddr::from(start + len).ceil(),
+        );
+        for area in self.areas.iter_mut() {
+            // a debugger writes shared memory in place, as its users would
+            if area.map_type != MapType::Framed
+                || area.kind == MapKind::Shared
+                || !area.vpn_range.is_overlapped(&range)
+            {
+                continue;
+            }
+            for vpn in range {
+                area.unshare_one(&mut self.page_table, vpn);
+            }
+        }
+    }
+    /// The frames of `pages` pages from `start`, kept for the kernel to read them without
+    /// copying. `None` if one of them is not plain user memory: MMIO registers or pages
+    /// shared with other processes may change while the kernel reads them.
+    pub fn pin_user_pages(&self, start: usize, pages: usize) -> Option<Vec<Arc<FrameTracker>>> {
+        let mut vpn = VirtAddr::from(start).floor();
+        let mut frames = Vec::with_capacity(pages);
+        for _ in 0..pages {
+            let area = self
+                .areas
+                .iter()
+                .find(|area| area.vpn_range.get_start() <= vpn && vpn < area.vpn_range.get_end())?;
+            if area.map_type != MapType::Framed
+                || area.kind == MapKind::Shared
+                || !area.map_perm.contains(MapPermission::U | MapPermission::R)
+            {
+                return None;
+            }
+            frames.push(area.data_frames.get(&vpn)?.clone());
+            vpn.step();
+        }
+        Some(frames)
+    }
+    pub fn walk(&self, va: VirtAddr) -> PteInfo {
+        self.page_table.walk(va)
+    }
+    pub fn dump_page_table(&self, out: &mut impl Write) -> fmt::Result {
+        self.page_table.dump(out)
+    }
+    /// Whether user mode may write to `va`
+    pub fn is_user_writable(&self, va: VirtAddr) -> bool {
+        self.translate(va.floor()).map_or(false, |pte| {
+            pte.is_valid() && pte.writable() && pte.user_accessible()
+        })
+    }
+    /// Whether user mode may fetch instructions at `va`
+    pub fn is_user_executable(&self, va: VirtAddr) -> bool {
+        self.translate(va.floor()).map_or(false, |pte| {
+            pte.is_valid() && pte.executable() && pte.user_accessible()
+        })
+    }
+
+    fn find_free_area(&self, len: usize) -> usize {
+        let mut start = self.mmap_base;
+        while self.is_mapped_area(start.into(), VirtAddr::from(start + len).ceil().into()) {
+            // skip past every area in the way
+            start = self
+                .areas
+                .iter()
+                .map(|area| VirtAddr::from(area.vpn_range.get_end()).into())
+                .filter(|end: &usize| *end > start)
+                .min()
+                .unwrap();
+        }
+        start
+    }
 
     fn is_mapped_area(&self, start_va: VirtAddr, end_va: VirtAddr) -> bool {
         for area in &self.areas {
@@ -283,23 +476,39 @@ impl MemorySet {
         false
     }
 
+    /// If `start` is 0, the area is placed at the first free address above the mmap base
+    /// and its start address is returned instead of the length.
     pub fn mmap(&mut self, start: usize, len: usize, port: usize) -> Result<isize, isize> {
-        if port & !7 != 0 || port & 7 == 0 || len > 1 << 30 {
-            Err(-1)
+        if port & !(7 | MAP_SHARED) != 0 || port & 7 == 0 || len > 1 << 30 {
+            Err(EINVAL)
+        } else if start == 0 {
+            let start = self.find_free_area(len);
+            self.mmap(start, len, port).map(|_| start as isize)
         } else {
             let start_va: VirtAddr = VirtAddr::from(start);
             if start_va != start_va.floor().into() {
-                return Err(-1);
+                return Err(EINVAL);
             }
             let end_va: VirtAddr = VirtAddr::from(start + len).ceil().into();
 
+            let map_perm = MapPermission::from_bits(((port & 7) << 1 | 0b10000) as u8).unwrap();
+            map_perm.check_wx()?;
             if self.is_mapped_area(start_va, end_va) {
-                return Err(-1);
+                return Err(EEXIST);
             }
-            self.insert_framed_area(
-                start_va,
-                end_va,
-                MapPermission::from_bits((port << 1 | 0b10000) as u8).unwrap(),
+            // frames for the data and the page tables, which the kernel must never run out of
+            let pages = (usize::from(end_va) - usize::from(start_va)) / PAGE_SIZE;
+            if pages + pages / 512 + 2 + OOM_RESERVE_FRAMES > frames_available() {
+                return Err(ENOMEM);
+            }
+            let kind = if port & MAP_SHARED != 0 {
+                MapKind::Shared
+            } else {
+                MapKind::Mmap
+            };
+            self.push(
+                MapArea::new(start_va, end_va, MapType::Framed, map_perm).with_kind(kind),
+                None,
             );
 
             Ok((usize::from(end_va) - usize::from(start_va)) as isize)
@@ -309,7 +518,7 @@ impl MemorySet {
     pub fn munmap(&mut self, start: usize, len: usize) -> Result<isize, isize> {
         let mut start_va: VirtAddr = VirtAddr::from(start);
         if start_va != start_va.floor().into() {
-            return Err(-1);
+            return Err(EINVAL);
         }
         let end_va: VirtAddr = VirtAddr::from(start + len).ceil().into();
 
@@ -330,11 +539,11 @@ impl MemorySet {
             if start_va == self.areas[*i].vpn_range.get_start().into() {
                 start_va = self.areas[*i].vpn_range.get_end().into();
             } else {
-                return Err(-1);
+                return Err(EINVAL);
             }
         }
         if start_va != end_va {
-            return Err(-1);
+            return Err(EINVAL);
         }
 
         to_unmap.sort_by(|l, r| r.cmp(l));
@@ -347,74 +556,91 @@ impl MemorySet {
         Ok(len as isize)
     }
 
-    pub fn mmio_map(&mut self, start: usize, len: usize, port: usize) -> Result<isize, isize> {
-        if port & !7 != 0 || port & 7 == 0 || len > 1 << 30 {
-            Err(-1)
-        } else {
-            let start_va: VirtAddr = VirtAddr::from(start);
-            if start_va != start_va.floor().into() {
-                return Err(-1);
-            }
-            let end_va: VirtAddr = VirtAddr::from(start + len).ceil().into();
-
-            if self.is_mapped_area(start_va, end_va) {
-                return Err(-1);
-            }
-            self.push(
-                MapArea::new(
-                    start_va,
-                    end_va,
-                    MapType::Mmio,
-                    MapPermission::from_bits((port << 1 | 0b10000) as u8).unwrap(),
-                ),
-                None,
-            );
-            Ok((usize::from(end_va) - usize::from(start_va)) as isize)
-        }
+    /// The MMIO area covering exactly `start_vpn..end_vpn`, if any
+    fn find_mmio_area(&self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> Option<usize> {
+        self.areas.iter().position(|area| {
+            area.map_type == MapType::Mmio
+                && area.vpn_range.get_start() == start_vpn
+                && area.vpn_range.get_end() == end_vpn
+        })
     }
 
-    #[allow(unused)]
-    pub fn mmio_unmap(&mut self, start: usize, len: usize) -> Result<isize, isize> {
-        let mut start_va: VirtAddr = VirtAddr::from(start);
+    /// Mapping a region which is already mapped with the same permission shares it,
+    /// it stays mapped until every `mmio_map` of it is undone by `mmio_unmap`.
+    pub fn mmio_map(&mut self, start: usize, len: usize, port: usize) -> Result<isize, MmioError> {
+        if port & !7 != 0 || port & 7 == 0 || len > 1 << 30 {
+            return Err(MmioError::InvalidArgument);
+        }
+        let start_va: VirtAddr = VirtAddr::from(start);
         if start_va != start_va.floor().into() {
-            return Err(-1);
+            return Err(MmioError::InvalidArgument);
         }
         let end_va: VirtAddr = VirtAddr::from(start + len).ceil().into();
 
-        let mut to_unmap: Vec<usize> = Vec::new();
-
-        for (i, area) in self.areas.iter().enumerate() {
-            if area
-                .vpn_range
-                .is_overlapped(&VPNRange::new(start_va.into(), end_va.into()))
-            {
-                to_unmap.push(i);
-            }
+        let map_perm = MapPermission::from_bits((port << 1 | 0b10000) as u8).unwrap();
+        // device registers are never executable
+        if map_perm.contains(MapPermission::X) {
+            return Err(MmioError::Executable);
         }
-
-        to_unmap.sort_by_key(|i| self.areas[*i].vpn_range.get_start());
-
-        for i in &to_unmap {
-            if start_va == self.areas[*i].vpn_range.get_start().into() {
-                start_va = self.areas[*i].vpn_range.get_end().into();
-            } else {
-                return Err(-1);
+        let mapped_len = (usize::from(end_va) - usize::from(start_va)) as isize;
+        if let Some(i) = self.find_mmio_area(start_va.into(), end_va.into()) {
+            let area = &mut self.areas[i];
+            if area.map_perm != map_perm {
+                return Err(MmioError::Overlap);
             }
+            area.refs += 1;
+            return Ok(mapped_len);
         }
-        if start_va != end_va {
-            return Err(-1);
+        if self.is_mapped_area(start_va, end_va) {
+            return Err(MmioError::Overlap);
         }
+        self.push(
+            MapArea::new(start_va, end_va, MapType::Mmio, map_perm),
+            None,
+        );
+        Ok(mapped_len)
+    }
 
-        to_unmap.sort_by(|l, r| r.cmp(l));
-
-        for i in to_unmap {
+    /// Undo one `mmio_map` of exactly the same region
+    pub fn mmio_unmap(&mut self, start: usize, len: usize) -> Result<isize, MmioError> {
+        let start_va: VirtAddr = VirtAddr::from(start);
+        if start_va != start_va.floor().into() {
+            return Err(MmioError::InvalidArgument);
+        }
+        let end_va: VirtAddr = VirtAddr::from(start + len).ceil().into();
+        let i = self
+            .find_mmio_area(start_va.into(), end_va.into())
+            .ok_or(MmioError::NotMapped)?;
+        self.areas[i].refs -= 1;
+        if self.areas[i].refs == 0 {
             self.areas[i].unmap(&mut self.page_table);
             self.areas.remove(i);
         }
-
         Ok(len as isize)
     }
 
+    /// Areas sorted by start address, the trampoline is not included
+    pub fn vm_info(&self) -> Vec<VmAreaInfo> {
+        let mut info: Vec<VmAreaInfo> = self
+            .areas
+            .iter()
+            .map(|area| VmAreaInfo {
+                start: VirtAddr::from(area.vpn_range.get_start()).into(),
+                end: VirtAddr::from(area.vpn_range.get_end()).into(),
+                perm: area.map_perm.bits() as usize,
+                kind: area.kind as usize,
+                refs: area.refs,
+            })
+            .collect();
+        info.sort_by_key(|area| area.start);
+        info
+    }
+
+    /// Frames mapped by the areas, shared ones included
+    pub fn resident_pages(&self) -> usize {
+        self.areas.iter().map(|area| area.data_frames.len()).sum()
+    }
+
     pub fn recycle_data_pages(&mut self) {
         //*self = Self::new_bare();
         self.areas.clear();
@@ -423,9 +649,13 @@ impl MemorySet {
 
 pub struct MapArea {
     vpn_range: VPNRange,
-    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    /// Frames of read-only and `MapKind::Shared` areas may be shared with other address spaces
+    data_frames: BTreeMap<VirtPageNum, Arc<FrameTracker>>,
     map_type: MapType,
     map_perm: MapPermission,
+    kind: MapKind,
+    /// How many `mmio_map` share an MMIO area, 1 for any other area
+    refs: usize,
 }
 
 impl MapArea {
@@ -442,14 +672,28 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type,
             map_perm,
+            kind: match map_type {
+                MapType::Identical => MapKind::Kernel,
+                MapType::Framed => MapKind::Mmap,
+                MapType::Mmio => MapKind::Mmio,
+            },
+            refs: 1,
         }
     }
+    /// Override the kind guessed from the map type, only `MapKind::Shared` changes
+    /// how the area is handled, by fork
+    pub fn with_kind(mut self, kind: MapKind) -> Self {
+        self.kind = kind;
+        self
+    }
     pub fn from_another(another: &MapArea) -> Self {
         Self {
             vpn_range: VPNRange::new(another.vpn_range.get_start(), another.vpn_range.get_end()),
             data_frames: BTreeMap::new(),
             map_type: another.map_type,
             map_perm: another.map_perm,
+            kind: another.kind,
+            refs: another.refs,
         }
     }
     pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
@@ -464,13 +708,34 @@ impl MapArea {
             MapType::Framed => {
                 let frame = frame_alloc().unwrap();
                 ppn = frame.ppn;
-                self.data_frames.insert(vpn, frame);
+                self.data_frames.insert(vpn, Arc::new(frame));
                 trace!("map_one: vpn {:?} ppn {:?}", vpn, ppn);
             }
         }
         let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
         page_table.map(vpn, ppn, pte_flags);
     }
+    /// Map `vpn` to a frame which may be shared with other areas
+    fn map_frame(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+        frame: Arc<FrameTracker>,
+    ) {
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        page_table.map(vpn, frame.ppn, pte_flags);
+        self.data_frames.insert(vpn, frame);
+    }
+    fn unshare_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+        if let Some(frame) = self.data_frames.get_mut(&vpn) {
+            if shared_pages::unshare(frame) {
+                let ppn = frame.ppn;
+                let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+                page_table.unmap(vpn);
+                page_table.map(vpn, ppn, pte_flags);
+            }
+        }
+    }
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
         if let MapType::Framed = self.map_type {
             self.data_frames.remove(&vpn);
@@ -518,6 +783,62 @@ pub enum MapType {
     Mmio,
 }
 
+/// What a `MapArea` is used for, reported by `sys_vm_info`
+#[repr(usize)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MapKind {
+    Kernel = 0,
+    Elf = 1,
+    Stack = 2,
+    TrapContext = 3,
+    Mmap = 4,
+    Mmio = 5,
+    /// Mapped with `MAP_SHARED`, the frames are shared with children instead of copied
+    Shared = 6,
+}
+
+/// `mmap` port bit, the area is shared by fork instead of copied
+pub const MAP_SHARED: usize = 1 << 3;
+
+/// One entry of the buffer filled by `sys_vm_info`
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct VmAreaInfo {
+    pub start: usize,
+    pub end: usize,
+    /// `MapPermission` bits
+    pub perm: usize,
+    /// `MapKind` as usize
+    pub kind: usize,
+    /// Number of `mmio_map` sharing an MMIO area, 1 for other areas
+    pub refs: usize,
+}
+
+/// Why `mmio_map` or `mmio_unmap` refused a region
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MmioError {
+    /// Unaligned, too long or without any permission
+    InvalidArgument,
+    /// Device registers are never executable
+    Executable,
+    /// Overlaps an area which is not the same MMIO region with the same permission
+    Overlap,
+    /// No MMIO area covers exactly the region
+    NotMapped,
+}
+
+impl MmioError {
+    /// Error number returned to user space
+    pub fn errno(&self) -> isize {
+        match self {
+            MmioError::InvalidArgument => EINVAL,
+            MmioError::Executable => EPERM,
+            MmioError::Overlap => EEXIST,
+            MmioError::NotMapped => EINVAL,
+        }
+    }
+}
+
 bitflags! {
     pub struct MapPermission: u8 {
         const R = 1 << 1;
@@ -527,6 +848,31 @@ bitflags! {
     }
 }
 
+impl MapPermission {
+    /// W^X policy, relaxed by `ALLOW_WRITABLE_EXEC`
+    pub fn check_wx(&self) -> Result<(), isize> {
+        if !ALLOW_WRITABLE_EXEC && self.contains(MapPermission::W | MapPermission::X) {
+            warn!("[W^X] refuse to map a writable and executable area");
+            Err(EPERM)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A random page count in `[0, max_pages)` seeded from the cycle counter,
+/// enough to perturb layouts but not for anything cryptographic
+fn aslr_random_pages(max_pages: usize) -> usize {
+    if DETERMINISTIC {
+        return deterministic::random() % max_pages;
+    }
+    let mut x = cycle::read() | 1;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    x % max_pages
+}
+
 #[allow(unused)]
 pub fn remap_test() {
     let mut kernel_space = KERNEL_SPACE.lock();
@@ -1,23 +1,39 @@
+use crate::config::KernelConfig;
+
 mod address;
+pub mod alloc_track;
+mod dma;
 mod frame_allocator;
 mod heap_allocator;
 mod memory_set;
 mod page_table;
+pub mod pressure;
+mod shared_pages;
+mod user_access;
 
 pub use address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use address::{StepByOne, VPNRange};
-pub use frame_allocator::{frame_alloc, FrameTracker};
+pub use dma::{dma_alloc, dma_report, DmaTracker};
+pub use frame_allocator::{frame_alloc, frames_available, FrameTracker};
 pub use memory_set::remap_test;
-pub use memory_set::{MapPermission, MemorySet, KERNEL_SPACE};
+pub use memory_set::{MapPermission, MemorySet, VmAreaInfo, KERNEL_SPACE};
 pub use page_table::{
-    translate_writable_va, translated_byte_buffer, translated_refmut, translated_str,
-    PageTableEntry, UserBuffer, UserBufferIterator,
+    copy_from_user, copy_to_user, copy_value_from_user, copy_value_to_user, translate_writable_va,
+    translated_byte_buffer, translated_byte_buffer_mut, translated_byte_buffer_prefix,
+    translated_refmut, translated_str, PageTableEntry, PteInfo, UserBuffer, UserBufferIterator,
 };
 use page_table::{PTEFlags, PageTable};
+pub use user_access::{check_user_range, clear_sum_on_trap_entry};
 
-pub fn init() {
+pub fn init(config: &KernelConfig) {
     heap_allocator::init_heap();
-    frame_allocator::init_frame_allocator();
+    let reserved = config.reserved_regions();
+    for &(base, size) in reserved {
+        debug!("[mm] reserved {:#x}..{:#x}", base, base + size);
+    }
+    let dma_base = dma::dma_region_base(config.memory_end, reserved);
+    frame_allocator::init_frame_allocator(config.memory_end, dma_base, reserved);
+    dma::init_dma_allocator(dma_base);
     KERNEL_SPACE.lock().activate();
 }
 
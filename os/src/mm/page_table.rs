@@ -1,8 +1,13 @@
+use super::user_access::check_user_range;
 use super::{frame_alloc, FrameTracker, PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
+use crate::config::{PAGE_SIZE, PTE_PRESET_FLAGS, USER_SPACE_END};
+use crate::syscall::EFAULT;
 use alloc::string::String;
 use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
+use core::fmt::{self, Display, Formatter, Write};
+use core::mem::size_of;
 
 bitflags! {
     pub struct PTEFlags: u8 {
@@ -17,6 +22,80 @@ bitflags! {
     }
 }
 
+impl PTEFlags {
+    /// A valid entry with any of R/W/X maps memory, otherwise it points to the next level
+    fn is_leaf(&self) -> bool {
+        self.contains(PTEFlags::V) && self.intersects(PTEFlags::R | PTEFlags::W | PTEFlags::X)
+    }
+}
+
+impl Display for PTEFlags {
+    /// `VRWXUGAD` with `-` for the bits which are clear
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, name) in "VRWXUGAD".chars().enumerate() {
+            let set = self.bits & (1 << i) != 0;
+            f.write_char(if set { name } else { '-' })?;
+        }
+        Ok(())
+    }
+}
+
+/// What the walk of a page table found for one address, filled by `sys_debug_translate`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PteInfo {
+    /// The physical address, 0 if the address is not mapped
+    pub paddr: usize,
+    /// `PTEFlags` bits of the last entry reached
+    pub flags: usize,
+    /// Level of that entry, 0 for the root table and 2 for a 4 KiB page
+    pub level: usize,
+}
+
+impl PteInfo {
+    pub fn is_mapped(&self) -> bool {
+        PTEFlags::from_bits_truncate(self.flags as u8).is_leaf()
+    }
+}
+
+/// Size mapped by a leaf at `level`
+fn level_size(level: usize) -> usize {
+    PAGE_SIZE << (9 * (2 - level))
+}
+
+/// Sv39 addresses sign-extend bit 38
+fn canonical_va(va: usize) -> usize {
+    if va & (1 << 38) != 0 {
+        va | !((1 << 39) - 1)
+    } else {
+        va
+    }
+}
+
+/// Consecutive leaves mapping contiguous memory with the same flags, printed as one line
+struct LeafRun {
+    va: usize,
+    pa: usize,
+    size: usize,
+    flags: PTEFlags,
+}
+
+impl LeafRun {
+    fn dump(&self, out: &mut impl Write, level: usize) -> fmt::Result {
+        writeln!(
+            out,
+            "{:indent$}L{} {:#x}..{:#x} -> {:#x} {}",
+            "",
+            level,
+            canonical_va(self.va),
+            canonical_va(self.va + self.size),
+            self.pa,
+            self.flags,
+            indent = level * 2
+        )
+    }
+}
+
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub struct PageTableEntry {
@@ -50,6 +129,9 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+    pub fn user_accessible(&self) -> bool {
+        (self.flags() & PTEFlags::U) != PTEFlags::empty()
+    }
 }
 
 pub struct PageTable {
@@ -113,8 +195,7 @@ impl PageTable {
     pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
         let pte = self.find_pte_create(vpn).unwrap();
         assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
-        #[cfg(feature = "board_lrv")]
-        let flags = flags | PTEFlags::A | PTEFlags::D;
+        let flags = flags | PTEFlags::from_bits_truncate(PTE_PRESET_FLAGS);
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
     }
     #[allow(unused)]
@@ -138,15 +219,98 @@ impl PageTable {
     pub fn token(&self) -> usize {
         8usize << 60 | self.root_ppn.0
     }
+    /// Walk down to the leaf of `va`, stopping at the first invalid entry
+    pub fn walk(&self, va: VirtAddr) -> PteInfo {
+        let idxs = va.floor().indexes();
+        let mut ppn = self.root_ppn;
+        let mut level = 0;
+        loop {
+            let pte = ppn.get_pte_array()[idxs[level]];
+            let flags = pte.flags();
+            if !pte.is_valid() || flags.is_leaf() || level == 2 {
+                let paddr = if flags.is_leaf() {
+                    usize::from(PhysAddr::from(pte.ppn())) + usize::from(va) % level_size(level)
+                } else {
+                    0
+                };
+                return PteInfo {
+                    paddr,
+                    flags: flags.bits() as usize,
+                    level,
+                };
+            }
+            ppn = pte.ppn();
+            level += 1;
+        }
+    }
+    /// Print every valid entry, a line per table and per run of leaves
+    pub fn dump(&self, out: &mut impl Write) -> fmt::Result {
+        writeln!(out, "satp {:#x}", self.token())?;
+        Self::dump_table(out, self.root_ppn, 0, 0)
+    }
+    fn dump_table(
+        out: &mut impl Write,
+        ppn: PhysPageNum,
+        level: usize,
+        base: usize,
+    ) -> fmt::Result {
+        let size = level_size(level);
+        let mut run: Option<LeafRun> = None;
+        for (idx, pte) in ppn.get_pte_array().iter().enumerate() {
+            let flags = pte.flags();
+            let va = base + idx * size;
+            let pa = usize::from(PhysAddr::from(pte.ppn()));
+            if let Some(leaves) = &mut run {
+                if flags.is_leaf()
+                    && flags == leaves.flags
+                    && va == leaves.va + leaves.size
+                    && pa == leaves.pa + leaves.size
+                {
+                    leaves.size += size;
+                    continue;
+                }
+                leaves.dump(out, level)?;
+                run = None;
+            }
+            if !pte.is_valid() {
+                continue;
+            }
+            if flags.is_leaf() {
+                run = Some(LeafRun {
+                    va,
+                    pa,
+                    size,
+                    flags,
+                });
+            } else if level < 2 {
+                writeln!(
+                    out,
+                    "{:indent$}L{} [{}] {:#x} -> table {:#x}",
+                    "",
+                    level,
+                    idx,
+                    canonical_va(va),
+                    pa,
+                    indent = level * 2
+                )?;
+                Self::dump_table(out, pte.ppn(), level + 1, va)?;
+            }
+        }
+        if let Some(leaves) = run {
+            leaves.dump(out, level)?;
+        }
+        Ok(())
+    }
 }
 
+/// Also for the kernel's own pages in user space, callers check user pointers first
 pub fn translate_writable_va(token: usize, va: usize) -> Result<usize, isize> {
     let va = VirtAddr::from(va);
     let vpn = va.floor();
     let page_table = PageTable::from_token(token);
-    let pte = page_table.translate(vpn).unwrap();
+    let pte = page_table.translate(vpn).ok_or(EFAULT)?;
     if !pte.writable() || !pte.is_valid() {
-        return Err(-1);
+        return Err(EFAULT);
     }
     let ppn = pte.ppn();
     let mut pa: PhysAddr = ppn.into();
@@ -159,20 +323,59 @@ pub fn translated_byte_buffer(
     ptr: *const u8,
     len: usize,
 ) -> Result<Vec<&'static mut [u8]>, isize> {
+    user_byte_buffer(token, ptr, len, false)
+}
+
+/// Like `translated_byte_buffer`, for buffers the kernel writes to.
+/// Read-only pages may be shared by several processes, so they are refused.
+pub fn translated_byte_buffer_mut(
+    token: usize,
+    ptr: *mut u8,
+    len: usize,
+) -> Result<Vec<&'static mut [u8]>, isize> {
+    user_byte_buffer(token, ptr, len, true)
+}
+
+fn user_byte_buffer(
+    token: usize,
+    ptr: *const u8,
+    len: usize,
+    writable: bool,
+) -> Result<Vec<&'static mut [u8]>, isize> {
+    check_user_range(ptr as usize, len)?;
+    let v = translated_byte_buffer_prefix(token, ptr, len, writable);
+    if v.iter().map(|buffer| buffer.len()).sum::<usize>() < len {
+        return Err(EFAULT);
+    }
+    Ok(v)
+}
+
+/// The pages of `[ptr, ptr + len)` up to the first unmapped one, or read-only one
+/// when `writable`, so that a copy can stop there and report how far it got.
+/// It stops at the end of user space too.
+pub fn translated_byte_buffer_prefix(
+    token: usize,
+    ptr: *const u8,
+    len: usize,
+    writable: bool,
+) -> Vec<&'static mut [u8]> {
     let page_table = PageTable::from_token(token);
     let mut start = ptr as usize;
-    let end = start + len;
+    let end = start.saturating_add(len).min(USER_SPACE_END);
     let mut v = Vec::new();
     while start < end {
         let start_va = VirtAddr::from(start);
         let mut vpn = start_va.floor();
-        let pte = page_table.translate(vpn);
-        if pte.is_none() {
-            return Err(-1);
-        }
-        let pte = pte.unwrap();
-        if !pte.readable() || !pte.is_valid() {
-            return Err(-1);
+        let pte = match page_table.translate(vpn) {
+            Some(pte) => pte,
+            None => break,
+        };
+        if !pte.readable()
+            || !pte.is_valid()
+            || !pte.user_accessible()
+            || (writable && !pte.writable())
+        {
+            break;
         }
         let ppn = pte.ppn();
         vpn.step();
@@ -185,26 +388,60 @@ pub fn translated_byte_buffer(
         }
         start = end_va.into();
     }
-    Ok(v)
+    v
 }
 
-pub fn translated_str(token: usize, ptr: *const u8) -> String {
-    let page_table = PageTable::from_token(token);
+pub fn copy_from_user(token: usize, src: *const u8, dst: &mut [u8]) -> Result<(), isize> {
+    let mut start = 0;
+    for buffer in translated_byte_buffer(token, src, dst.len())? {
+        dst[start..start + buffer.len()].copy_from_slice(buffer);
+        start += buffer.len();
+    }
+    Ok(())
+}
+
+pub fn copy_to_user(token: usize, dst: *mut u8, src: &[u8]) -> Result<(), isize> {
+    let mut start = 0;
+    for buffer in translated_byte_buffer_mut(token, dst, src.len())? {
+        buffer.copy_from_slice(&src[start..start + buffer.len()]);
+        start += buffer.len();
+    }
+    Ok(())
+}
+
+/// `copy_to_user` of the bytes of `value`, which may straddle pages unlike `translated_refmut`
+pub fn copy_value_to_user<T>(token: usize, dst: *mut T, value: &T) -> Result<(), isize> {
+    let bytes =
+        unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+    copy_to_user(token, dst as *mut u8, bytes)
+}
+
+/// `copy_from_user` into `value`, which must be plain data
+pub fn copy_value_from_user<T>(token: usize, src: *const T, value: &mut T) -> Result<(), isize> {
+    let bytes =
+        unsafe { core::slice::from_raw_parts_mut(value as *mut T as *mut u8, size_of::<T>()) };
+    copy_from_user(token, src as *const u8, bytes)
+}
+
+/// The null-terminated string at `ptr`, `EFAULT` if it runs into memory the user cannot read
+pub fn translated_str(token: usize, ptr: *const u8) -> Result<String, isize> {
     let mut string = String::new();
     let mut va = ptr as usize;
+    // a page at a time, up to the null byte
     loop {
-        let ch: u8 = *(page_table
-            .translate_va(VirtAddr::from(va))
-            .unwrap()
-            .get_mut());
-        if ch == 0 {
-            break;
-        } else {
-            string.push(ch as char);
-            va += 1;
+        let page_end = (va / PAGE_SIZE + 1) * PAGE_SIZE;
+        check_user_range(va, page_end - va)?;
+        let buffers = translated_byte_buffer_prefix(token, va as *const u8, page_end - va, false);
+        let page = buffers.first().ok_or(-14isize)?; // EFAULT
+        match page.iter().position(|&ch| ch == 0) {
+            Some(len) => {
+                string.extend(page[..len].iter().map(|&ch| ch as char));
+                return Ok(string);
+            }
+            None => string.extend(page.iter().map(|&ch| ch as char)),
         }
+        va = page_end;
     }
-    string
 }
 
 pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
@@ -224,6 +461,15 @@ impl UserBuffer {
     pub fn new(buffers: Vec<&'static mut [u8]>) -> Self {
         Self { buffers }
     }
+    /// One buffer of all of `buffers` in turn, for files which take an iovec in one go
+    pub fn concat(buffers: Vec<UserBuffer>) -> Self {
+        Self {
+            buffers: buffers
+                .into_iter()
+                .flat_map(|buffer| buffer.buffers)
+                .collect(),
+        }
+    }
     pub fn len(&self) -> usize {
         let mut total: usize = 0;
         for b in self.buffers.iter() {
@@ -231,6 +477,32 @@ impl UserBuffer {
         }
         total
     }
+    /// Copy `src` to the start of the buffer, return how much of it fitted
+    pub fn write(&mut self, src: &[u8]) -> usize {
+        let mut copied = 0;
+        for buffer in self.buffers.iter_mut() {
+            let len = buffer.len().min(src.len() - copied);
+            buffer[..len].copy_from_slice(&src[copied..copied + len]);
+            copied += len;
+            if copied == src.len() {
+                break;
+            }
+        }
+        copied
+    }
+    /// Copy the start of the buffer to `dst`, return how much of it was filled
+    pub fn read(&self, dst: &mut [u8]) -> usize {
+        let mut copied = 0;
+        for buffer in self.buffers.iter() {
+            let len = buffer.len().min(dst.len() - copied);
+            dst[copied..copied + len].copy_from_slice(&buffer[..len]);
+            copied += len;
+            if copied == dst.len() {
+                break;
+            }
+        }
+        copied
+    }
 }
 
 impl IntoIterator for UserBuffer {
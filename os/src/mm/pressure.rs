@@ -0,0 +1,154 @@
+//! Memory pressure notifications, so that user caches can shed memory before the kernel
+//! runs out of frames. A task registers a watermark of free frames and is told once they
+//! fall below it, by a user interrupt, by an event file it reads, or both. It is told
+//! again only after the free frames rose `REARM_MARGIN` frames above the watermark, so
+//! that hovering around it does not flood the task.
+//!
+//! Frames are allocated with all sorts of locks held, so the free frames are checked by
+//! the scheduler loop rather than by the allocator.
+
+use super::{frames_available, UserBuffer};
+use crate::fs::File;
+use crate::syscall::{EBADF, EINVAL, ENOTTY, ERESTART};
+use crate::task::{current_has_pending_user_trap, suspend_current_and_run_next};
+use crate::trap::{push_trap_record, UserTrapRecord};
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use lazy_static::*;
+use spin::Mutex;
+
+/// Registration flag: notify by a user interrupt
+pub const MEM_PRESSURE_UIPI: usize = 1;
+/// Registration flag: notify through an event file
+pub const MEM_PRESSURE_FD: usize = 2;
+/// Cause of the trap records of notifications, the message being the free frames
+pub const MEM_PRESSURE_CAUSE: usize = 0xf;
+/// `ioctl` command of the event file: the notifications not read yet, without blocking
+pub const MEM_PRESSURE_PENDING: usize = 0x5600;
+
+const REARM_MARGIN: usize = 32;
+
+/// Counts the notifications not read yet, a read takes them all as a `u64`
+pub struct MemPressureEvent {
+    pending: AtomicUsize,
+}
+
+impl File for MemPressureEvent {
+    fn read(&self, mut buf: UserBuffer) -> Result<usize, isize> {
+        if buf.len() < size_of::<u64>() {
+            return Err(EINVAL);
+        }
+        loop {
+            let pending = self.pending.swap(0, Relaxed);
+            if pending > 0 {
+                return Ok(buf.write(&(pending as u64).to_ne_bytes()));
+            }
+            if current_has_pending_user_trap() {
+                return Err(ERESTART);
+            }
+            suspend_current_and_run_next();
+        }
+    }
+
+    fn write(&self, _buf: UserBuffer) -> Result<usize, isize> {
+        Err(EBADF)
+    }
+
+    fn ioctl(&self, cmd: usize, _arg: usize) -> Result<usize, isize> {
+        match cmd {
+            MEM_PRESSURE_PENDING => Ok(self.pending.load(Relaxed)),
+            _ => Err(ENOTTY),
+        }
+    }
+}
+
+struct Watcher {
+    watermark: usize,
+    uipi: bool,
+    event: Option<Weak<MemPressureEvent>>,
+    /// Below the watermark and already told
+    notified: bool,
+}
+
+lazy_static! {
+    /// Keyed by pid, one registration per task
+    static ref WATCHERS: Mutex<BTreeMap<usize, Watcher>> = Mutex::new(BTreeMap::new());
+}
+
+/// Lets the scheduler loop skip the check while nobody watches
+static WATCHER_NUM: AtomicUsize = AtomicUsize::new(0);
+
+/// Watch the free frames for task `pid`, replacing its previous registration. Return the
+/// event file for `MEM_PRESSURE_FD`.
+pub fn register(pid: usize, watermark: usize, flags: usize) -> Option<Arc<MemPressureEvent>> {
+    let event = if flags & MEM_PRESSURE_FD != 0 {
+        Some(Arc::new(MemPressureEvent {
+            pending: AtomicUsize::new(0),
+        }))
+    } else {
+        None
+    };
+    let mut watchers = WATCHERS.lock();
+    watchers.insert(
+        pid,
+        Watcher {
+            watermark,
+            uipi: flags & MEM_PRESSURE_UIPI != 0,
+            event: event.as_ref().map(Arc::downgrade),
+            notified: false,
+        },
+    );
+    WATCHER_NUM.store(watchers.len(), Relaxed);
+    event
+}
+
+pub fn unregister(pid: usize) {
+    let mut watchers = WATCHERS.lock();
+    if watchers.remove(&pid).is_some() {
+        WATCHER_NUM.store(watchers.len(), Relaxed);
+    }
+}
+
+/// Notify the tasks whose watermark the free frames fell below, run by the scheduler loop
+pub fn check() {
+    if WATCHER_NUM.load(Relaxed) == 0 {
+        return;
+    }
+    let free = frames_available();
+    let mut uipi_pids = Vec::new();
+    let mut watchers = WATCHERS.lock();
+    for (&pid, watcher) in watchers.iter_mut() {
+        if free >= watcher.watermark.saturating_add(REARM_MARGIN) {
+            watcher.notified = false;
+        }
+        if free >= watcher.watermark || watcher.notified {
+            continue;
+        }
+        watcher.notified = true;
+        if let Some(event) = watcher.event.as_ref().and_then(Weak::upgrade) {
+            event.pending.fetch_add(1, Relaxed);
+        }
+        if watcher.uipi {
+            uipi_pids.push(pid);
+        }
+    }
+    // a task which closed its event file and takes no interrupts is told nothing anymore
+    watchers.retain(|_, watcher| {
+        watcher.uipi || watcher.event.iter().any(|event| event.strong_count() > 0)
+    });
+    WATCHER_NUM.store(watchers.len(), Relaxed);
+    drop(watchers);
+    // pushing takes the lock of every receiver
+    for pid in uipi_pids {
+        let _ = push_trap_record(
+            pid,
+            UserTrapRecord {
+                cause: MEM_PRESSURE_CAUSE,
+                message: free,
+            },
+        );
+    }
+}
@@ -0,0 +1,58 @@
+//! Frames of read-only ELF segments, shared by every process running the same app.
+//!
+//! App images are linked into the kernel, so a page is identified by the address of its
+//! image and its offset in the image. The cache only holds weak references, a frame is
+//! freed with the last address space mapping it. Stale entries are replaced when the page
+//! is loaded again, so the cache never outgrows the images.
+
+use super::{alloc_track::AllocScope, frame_alloc, FrameTracker};
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use lazy_static::*;
+use spin::Mutex;
+
+lazy_static! {
+    /// (image address, offset in the image) -> frame
+    static ref SHARED_PAGES: Mutex<BTreeMap<(usize, usize), Weak<FrameTracker>>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// The resident frame of the page at `offset` of `image`, or a new one filled by `fill`
+pub fn get_or_insert(
+    image: &'static [u8],
+    offset: usize,
+    fill: impl FnOnce(&FrameTracker),
+) -> Arc<FrameTracker> {
+    let key = (image.as_ptr() as usize, offset);
+    let mut pages = SHARED_PAGES.lock();
+    if let Some(frame) = pages.get(&key).and_then(Weak::upgrade) {
+        return frame;
+    }
+    // shared frames outlive the task which loaded them first
+    let _scope = AllocScope::kernel();
+    let frame = Arc::new(frame_alloc().unwrap());
+    fill(&frame);
+    pages.insert(key, Arc::downgrade(&frame));
+    frame
+}
+
+/// Copy-on-write: make `frame` private before the kernel writes to it on behalf of a
+/// debugger, return whether it was replaced by a copy
+pub fn unshare(frame: &mut Arc<FrameTracker>) -> bool {
+    // a frame which is not mapped elsewhere is modified in place, but must not be
+    // handed out again, new mappings are only made under the lock
+    let mut pages = SHARED_PAGES.lock();
+    if Arc::strong_count(frame) == 1 {
+        if Arc::weak_count(frame) > 0 {
+            pages.retain(|_, cached| cached.as_ptr() != Arc::as_ptr(frame));
+        }
+        return false;
+    }
+    drop(pages);
+    let copy = frame_alloc().unwrap();
+    copy.ppn
+        .get_bytes_array()
+        .copy_from_slice(frame.ppn.get_bytes_array());
+    *frame = Arc::new(copy);
+    true
+}
@@ -0,0 +1,29 @@
+//! Access to user memory from the kernel.
+//!
+//! The kernel runs in its own address space, so user pages are reached by translating
+//! them to physical frames (`translated_byte_buffer`, `translated_refmut`, `UserBuffer`),
+//! which never needs `sstatus.SUM`. SUM stays clear in the kernel.
+
+use crate::config::{STRICT_USER_ACCESS, USER_SPACE_END};
+use crate::syscall::EFAULT;
+use riscv::register::sstatus;
+
+/// Called on trap entry from user, nothing in the kernel sets SUM
+pub fn clear_sum_on_trap_entry() {
+    if sstatus::read().sum() {
+        if STRICT_USER_ACCESS {
+            panic!("sstatus.SUM was set in the kernel!");
+        }
+        unsafe { sstatus::clear_sum() }
+    }
+}
+
+/// Fail with `EFAULT` unless `[ptr, ptr + len)` lies in user space, checked before a user
+/// pointer is translated: the user page table also maps the trap context, which the kernel
+/// must not read or write on behalf of the user
+pub fn check_user_range(ptr: usize, len: usize) -> Result<(), isize> {
+    match ptr.checked_add(len) {
+        Some(end) if end <= USER_SPACE_END => Ok(()),
+        _ => Err(EFAULT),
+    }
+}
@@ -1,11 +1,19 @@
+use crate::config::{
+    kernel_config, IrqTrigger, CPU_NUM, PLIC_BASE, PLIC_PRIORITY_BIT, PLIC_RESET_CONTEXTS,
+    SERIAL_ADDRESS_STRIDE,
+};
+use crate::irq_thread;
+use crate::syscall::{EBUSY, ENODEV};
 use crate::trap::{push_trap_record, UserTrapRecord, USER_EXT_INT_MAP};
 use crate::uart;
-use rv_plic::{Priority, PLIC};
-
-#[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
-pub const PLIC_BASE: usize = 0xc00_0000;
-#[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
-pub const PLIC_PRIORITY_BIT: usize = 3;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::ptr::{read_volatile, write_volatile};
+use rv_plic::PLIC;
+use spin::Once;
 
 pub type Plic = PLIC<{ PLIC_BASE }, { PLIC_PRIORITY_BIT }>;
 
@@ -20,49 +28,165 @@ pub fn get_context(hart_id: usize, mode: char) -> usize {
         }
 }
 
-#[cfg(feature = "board_qemu")]
-pub fn init() {
-    Plic::set_priority(12, Priority::lowest());
-    Plic::set_priority(13, Priority::lowest());
-    Plic::set_priority(14, Priority::lowest());
-    Plic::set_priority(15, Priority::lowest());
+/// Highest priority of a source, a context with this threshold takes no interrupt
+pub const MAX_PRIORITY: u32 = (1 << PLIC_PRIORITY_BIT) - 1;
+/// Priority of the sources the kernel handles
+const KERNEL_IRQ_PRIORITY: u32 = 1;
+/// Sources routed to user space are boosted above the kernel ones, so that a busy
+/// console cannot hold back the devices tasks wait on
+const USER_IRQ_PRIORITY: u32 = MAX_PRIORITY;
+
+const PLIC_CONTEXT_BASE: usize = PLIC_BASE + 0x20_0000;
+const PLIC_CONTEXT_STRIDE: usize = 0x1000;
+
+fn priority_register(irq: u16) -> *mut u32 {
+    (PLIC_BASE + 4 * irq as usize) as *mut u32
+}
+
+fn threshold_register(context: usize) -> *mut u32 {
+    (PLIC_CONTEXT_BASE + context * PLIC_CONTEXT_STRIDE) as *mut u32
+}
+
+/// Priority 0 never interrupts, higher ones are capped at `MAX_PRIORITY`
+pub fn set_priority(irq: u16, priority: u32) {
+    unsafe { write_volatile(priority_register(irq), priority.min(MAX_PRIORITY)) }
+}
+
+pub fn priority(irq: u16) -> u32 {
+    unsafe { read_volatile(priority_register(irq)) }
+}
+
+/// A context only takes the sources with a priority above its threshold
+pub fn set_threshold(context: usize, threshold: u32) {
+    unsafe { write_volatile(threshold_register(context), threshold.min(MAX_PRIORITY)) }
+}
+
+pub fn threshold(context: usize) -> u32 {
+    unsafe { read_volatile(threshold_register(context)) }
 }
 
-#[cfg(feature = "board_lrv")]
+/// Called once `irq` is claimed by a task
+pub fn route_to_user(irq: u16) {
+    set_priority(irq, USER_IRQ_PRIORITY);
+}
+
+/// Called once `irq` is given back to the kernel
+pub fn route_to_kernel(irq: u16) {
+    set_priority(irq, KERNEL_IRQ_PRIORITY);
+}
+
+#[derive(Default)]
+struct IrqRoute {
+    serial_ids: Vec<usize>,
+    trigger: IrqTrigger,
+    /// Serviced by `irq_thread`, only if every device behind the source is
+    threaded: bool,
+}
+
+/// Serial ports behind each PLIC source, built at boot. On some bitstreams several
+/// devices share a source, which then cannot be routed to one task alone.
+static IRQ_ROUTES: Once<BTreeMap<u16, IrqRoute>> = Once::new();
+
 pub fn init() {
-    Plic::set_priority(4, Priority::lowest());
-    Plic::set_priority(5, Priority::lowest());
-    Plic::set_priority(6, Priority::lowest());
-    Plic::set_priority(7, Priority::lowest());
+    let mut routes: BTreeMap<u16, IrqRoute> = BTreeMap::new();
+    for (serial_id, port) in kernel_config().serial_ports().iter().enumerate() {
+        set_priority(port.irq, KERNEL_IRQ_PRIORITY);
+        let route = routes.entry(port.irq).or_default();
+        if route.serial_ids.is_empty() {
+            route.trigger = port.trigger;
+            route.threaded = port.threaded;
+        } else {
+            route.threaded &= port.threaded;
+            warn!(
+                "[PLIC] irq {} shared by serial {:?} and {}, it stays with the kernel",
+                port.irq, route.serial_ids, serial_id
+            );
+            // handling an edge source as a level one only costs a late completion
+            if port.trigger != route.trigger {
+                route.trigger = IrqTrigger::Level;
+            }
+        }
+        route.serial_ids.push(serial_id);
+    }
+    IRQ_ROUTES.call_once(|| routes);
 }
 
-#[cfg(feature = "board_qemu")]
-pub fn init_hart(hart_id: usize) {
-    let context = get_context(hart_id, 'S');
-    Plic::enable(context, 12);
-    Plic::enable(context, 13);
-    Plic::enable(context, 14);
-    Plic::enable(context, 15);
-    Plic::set_threshold(context, Priority::any());
+fn irq_route(irq: u16) -> Option<&'static IrqRoute> {
+    IRQ_ROUTES.get()?.get(&irq)
+}
+
+/// Serial ports behind `irq`
+fn irq_serial_ids(irq: u16) -> &'static [usize] {
+    irq_route(irq).map_or(&[], |route| route.serial_ids.as_slice())
+}
+
+/// Service every device behind `irq`, any of them on a shared source may be the one asking
+pub fn service_devices(irq: u16) {
+    let serial_ids = irq_serial_ids(irq);
+    for &serial_id in serial_ids {
+        uart::handle_interrupt(serial_id);
+    }
+    if serial_ids.is_empty() {
+        warn!("[PLIC]: irq {:?} not supported!", irq);
+    } else {
+        trace!("[PLIC] irq {:?} handled by kenel", irq);
+    }
+}
+
+/// Sources without a device behind them are taken as level ones
+pub fn irq_trigger(irq: u16) -> IrqTrigger {
+    irq_route(irq).map_or(IrqTrigger::Level, |route| route.trigger)
+}
+
+/// Check that `irq` can be taken over by a user task: it must have a single device behind
+/// it, which the kernel does not use itself
+pub fn check_user_route(irq: u16) -> Result<(), isize> {
+    match irq_serial_ids(irq) {
+        [] => Err(ENODEV),
+        // Serial 0 is the kernel console
+        [0] => Err(ENODEV),
+        [_] => Ok(()),
+        serial_ids => {
+            warn!(
+                "[PLIC] irq {} is shared by serial {:?}, not routed to user",
+                irq, serial_ids
+            );
+            Err(EBUSY)
+        }
+    }
 }
 
-#[cfg(feature = "board_lrv")]
 pub fn init_hart(hart_id: usize) {
     let context = get_context(hart_id, 'S');
-    Plic::clear_enable(context, 0);
-    Plic::clear_enable(get_context(hart_id, 'U'), 0);
-    Plic::enable(context, 4);
-    Plic::enable(context, 5);
-    Plic::enable(context, 6);
-    Plic::enable(context, 7);
-    Plic::set_threshold(context, Priority::any());
-    Plic::set_threshold(get_context(hart_id, 'U'), Priority::any());
-    Plic::set_threshold(get_context(hart_id, 'M'), Priority::never());
+    if PLIC_RESET_CONTEXTS {
+        Plic::clear_enable(context, 0);
+        Plic::clear_enable(get_context(hart_id, 'U'), 0);
+    }
+    for port in kernel_config().serial_ports() {
+        Plic::enable(context, port.irq);
+    }
+    set_threshold(context, 0);
+    if PLIC_RESET_CONTEXTS {
+        set_threshold(get_context(hart_id, 'U'), 0);
+        set_threshold(get_context(hart_id, 'M'), MAX_PRIORITY);
+    }
+}
+
+/// Return (base, len) of the MMIO registers of a device which can be claimed by user
+pub fn device_mmio_range(irq: u16) -> Option<(usize, usize)> {
+    check_user_route(irq).ok()?;
+    let serial_id = irq_serial_ids(irq)[0];
+    Some((uart::serial_base_addr(serial_id), SERIAL_ADDRESS_STRIDE))
 }
 
+/// A claimed source is held back by its gateway until completed. An edge source is
+/// completed as soon as it is claimed, so that no edge coming meanwhile is lost. A level
+/// source is completed once its device was serviced, otherwise it fires again at once.
 pub fn handle_external_interrupt(hart_id: usize) {
     let context = get_context(hart_id, 'S');
     while let Some(irq) = Plic::claim(context) {
+        crate::random::add_entropy(irq as usize);
+        let trigger = irq_trigger(irq);
         let mut can_user_handle = false;
         let uei_map = USER_EXT_INT_MAP.lock();
         if let Some(pid) = uei_map.get(&irq).cloned() {
@@ -79,26 +203,75 @@ pub fn handle_external_interrupt(hart_id: usize) {
             .is_ok()
             {
                 can_user_handle = true;
+                match trigger {
+                    // mask the source until its owner is switched in and takes it over in
+                    // U mode. Left claimed, the line still asserted neither refills the trap
+                    // buffer nor reaches the owner a second time in U mode: the owner
+                    // completes it once it serviced the device.
+                    IrqTrigger::Level => Plic::disable(context, irq),
+                    IrqTrigger::Edge => Plic::complete(context, irq),
+                }
             }
             // prioritize_task(*pid);
         }
         if !can_user_handle {
-            match irq {
-                #[cfg(feature = "board_qemu")]
-                12 | 13 | 14 | 15 => {
-                    uart::handle_interrupt(irq);
-                    trace!("[PLIC] irq {:?} handled by kenel", irq);
-                }
-                #[cfg(feature = "board_lrv")]
-                4 | 5 | 6 | 7 => {
-                    uart::handle_interrupt(irq);
-                    // trace!("[PLIC] irq {:?} handled by kenel", irq);
-                }
-                _ => {
-                    warn!("[PLIC]: irq {:?} not supported!", irq);
-                }
+            if irq_route(irq).map_or(false, |route| route.threaded) {
+                irq_thread::defer(context, irq, trigger);
+                continue;
+            }
+            if trigger == IrqTrigger::Edge {
+                Plic::complete(context, irq);
+            }
+            service_devices(irq);
+            if trigger == IrqTrigger::Level {
+                Plic::complete(context, irq);
             }
-            Plic::complete(context, irq);
         }
     }
 }
+
+/// Current programming of the PLIC, for `/proc/plic`
+pub fn report() -> String {
+    let mut report = String::new();
+    if let Some(routes) = IRQ_ROUTES.get() {
+        let owners = USER_EXT_INT_MAP.lock().clone();
+        for (&irq, route) in routes {
+            let owner = match owners.get(&irq) {
+                Some(pid) => format!("pid {}", pid),
+                None => String::from("kernel"),
+            };
+            let _ = write!(
+                report,
+                "irq {} priority {} {:?} serial {:?} {}",
+                irq,
+                priority(irq),
+                route.trigger,
+                route.serial_ids,
+                owner
+            );
+            if route.threaded {
+                let stats = irq_thread::stats(irq);
+                let _ = write!(
+                    report,
+                    " threaded serviced {} max_delay_us {}",
+                    stats.serviced, stats.max_delay_us
+                );
+            }
+            report.push('\n');
+        }
+    }
+    for hart_id in 0..CPU_NUM {
+        for mode in ['S', 'U'] {
+            let context = get_context(hart_id, mode);
+            let _ = writeln!(
+                report,
+                "context {} hart {} {} threshold {}",
+                context,
+                hart_id,
+                mode,
+                threshold(context)
+            );
+        }
+    }
+    report
+}
@@ -0,0 +1,42 @@
+//! The kernel entropy pool, fed with the timing of interrupts and drained through
+//! `/dev/random`. The cycle counter at an interrupt is about all the entropy a board
+//! without a hardware generator has: good enough to seed user programs, not for
+//! cryptography. Deterministic runs take `deterministic::random` instead, so that they
+//! repeat.
+
+use crate::config::DETERMINISTIC;
+use crate::deterministic;
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use riscv::register::cycle;
+
+/// Increment of splitmix64
+const GAMMA: usize = 0x9e37_79b9_7f4a_7c15;
+
+static POOL: AtomicUsize = AtomicUsize::new(GAMMA);
+
+/// Mix `event` and the time it happened into the pool, lock-free for interrupt handlers
+pub fn add_entropy(event: usize) {
+    POOL.fetch_add(mix(cycle::read() ^ event.rotate_left(32)), Relaxed);
+}
+
+/// Every caller gets a different state of the pool
+pub fn random() -> usize {
+    if DETERMINISTIC {
+        return deterministic::random();
+    }
+    let state = POOL.fetch_add(GAMMA, Relaxed).wrapping_add(GAMMA);
+    mix(state ^ cycle::read())
+}
+
+pub fn fill(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(core::mem::size_of::<usize>()) {
+        chunk.copy_from_slice(&random().to_ne_bytes()[..chunk.len()]);
+    }
+}
+
+/// Finalizer of splitmix64
+fn mix(mut x: usize) -> usize {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
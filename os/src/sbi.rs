@@ -9,7 +9,15 @@ const SBI_REMOTE_FENCE_I: usize = 5;
 const SBI_REMOTE_SFENCE_VMA: usize = 6;
 const SBI_REMOTE_SFENCE_VMA_ASID: usize = 7;
 const SBI_SHUTDOWN: usize = 8;
+
+const SBI_EXT_BASE: usize = 0x10;
+const SBI_BASE_PROBE_EXTENSION: usize = 3;
+/// Debug console extension, SBI v2.0
+const SBI_EXT_DBCN: usize = 0x4442_434e;
+const SBI_DBCN_CONSOLE_WRITE: usize = 0;
+
 use core::arch::asm;
+use core::sync::atomic::{AtomicU8, Ordering};
 
 #[inline(always)]
 fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
@@ -21,6 +29,17 @@ fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
     ret
 }
 
+/// Call of the v0.2+ calling convention, returning (error, value)
+#[inline(always)]
+fn sbi_call_ext(eid: usize, fid: usize, arg0: usize, arg1: usize, arg2: usize) -> (isize, usize) {
+    let (error, value);
+    unsafe {
+        asm!("ecall", inout("a0") arg0 => error, inout("a1") arg1 => value,
+             in("a2") arg2, in("a6") fid, in("a7") eid)
+    }
+    (error, value)
+}
+
 pub fn set_timer(timer: usize) {
     sbi_call(SBI_SET_TIMER, timer, 0, 0);
 }
@@ -29,6 +48,52 @@ pub fn console_putchar(c: usize) {
     sbi_call(SBI_CONSOLE_PUTCHAR, c, 0, 0);
 }
 
+const DBCN_UNKNOWN: u8 = 0;
+const DBCN_PRESENT: u8 = 1;
+const DBCN_ABSENT: u8 = 2;
+static DBCN_STATE: AtomicU8 = AtomicU8::new(DBCN_UNKNOWN);
+
+fn has_dbcn() -> bool {
+    match DBCN_STATE.load(Ordering::Relaxed) {
+        DBCN_PRESENT => true,
+        DBCN_ABSENT => false,
+        _ => {
+            // legacy firmware fails the base extension call with a negative error
+            let (error, value) =
+                sbi_call_ext(SBI_EXT_BASE, SBI_BASE_PROBE_EXTENSION, SBI_EXT_DBCN, 0, 0);
+            let present = error == 0 && value != 0;
+            DBCN_STATE.store(
+                if present { DBCN_PRESENT } else { DBCN_ABSENT },
+                Ordering::Relaxed,
+            );
+            present
+        }
+    }
+}
+
+/// Write `bytes` with one call per chunk the firmware accepts if it has the debug console
+/// extension, a call per byte otherwise. `bytes` must be identity mapped, as is all kernel memory.
+pub fn console_write(mut bytes: &[u8]) {
+    if has_dbcn() {
+        while !bytes.is_empty() {
+            let (error, written) = sbi_call_ext(
+                SBI_EXT_DBCN,
+                SBI_DBCN_CONSOLE_WRITE,
+                bytes.len(),
+                bytes.as_ptr() as usize,
+                0,
+            );
+            if error != 0 || written == 0 {
+                break;
+            }
+            bytes = &bytes[written.min(bytes.len())..];
+        }
+    }
+    for &c in bytes {
+        console_putchar(c as usize);
+    }
+}
+
 pub fn console_getchar() -> usize {
     sbi_call(SBI_CONSOLE_GETCHAR, 0, 0, 0)
 }
@@ -41,3 +106,11 @@ pub fn shutdown() -> ! {
 pub fn send_ipi(ptr: usize) {
     sbi_call(SBI_SEND_IPI, ptr, 0, 0);
 }
+
+pub fn remote_fence_i(ptr: usize) {
+    sbi_call(SBI_REMOTE_FENCE_I, ptr, 0, 0);
+}
+
+pub fn remote_sfence_vma(ptr: usize, start: usize, size: usize) {
+    sbi_call(SBI_REMOTE_SFENCE_VMA, ptr, start, size);
+}
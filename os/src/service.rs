@@ -0,0 +1,359 @@
+//! Loadable services: flat binaries from `user/services`, embedded in the
+//! kernel image by `build.rs` and loaded into kernel space at run time, so
+//! that UIPI and scheduling policies can be tried without a rebuild.
+//!
+//! A service starts with a `ServiceHeader` giving the offsets of its entry
+//! points. It only reaches the kernel through the `ServiceApi` table handed
+//! to its init function, and is reached through the hooks below, which run
+//! under a read lock so that a service is never unloaded while in use.
+//!
+//! A service which aborts cannot be resumed. The task it was called for is
+//! killed in place of the kernel, and the service is left loaded but never
+//! called again, until it is unloaded.
+
+use crate::config::{CPU_NUM, MAX_SERVICES, PAGE_SIZE, SERVICE_BASE, SERVICE_SLOT_SIZE};
+use crate::ipi;
+use crate::loader::get_service_data_by_name;
+use crate::mm::{frames_available, MapPermission, VirtAddr, KERNEL_SPACE};
+use crate::syscall::{EBUSY, EEXIST, EIO, ENOENT, ENOEXEC, ENOMEM};
+use crate::task::{exit_current_and_run_next, hart_id, ExitReason, ExitStatus};
+use crate::timer::get_time_us;
+use crate::trap::{push_trap_record, UserTrapRecord};
+use alloc::string::String;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use spin::RwLock;
+
+const SERVICE_MAGIC: [u8; 4] = *b"RSVC";
+const SERVICE_API_VERSION: u32 = 1;
+
+/// At offset 0 of every service, offsets are from there and 0 for no entry
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct ServiceHeader {
+    magic: [u8; 4],
+    api_version: u32,
+    /// Code and read-only data, mapped executable
+    text_size: u32,
+    /// Including the bss, which is not part of the binary
+    mem_size: u32,
+    init: u32,
+    exit: u32,
+    on_uipi_send: u32,
+    on_sched_tick: u32,
+}
+
+/// Kernel functions callable by services, see `user/services/svc`
+#[repr(C)]
+#[allow(dead_code)] // only read by services
+struct ServiceApi {
+    version: u32,
+    log: extern "C" fn(level: usize, msg: *const u8, len: usize, value: usize),
+    hart_id: extern "C" fn() -> usize,
+    time_us: extern "C" fn() -> usize,
+    send_msg: extern "C" fn(pid: usize, msg: usize) -> isize,
+    abort: extern "C" fn(msg: *const u8, len: usize) -> !,
+}
+
+static API: ServiceApi = ServiceApi {
+    version: SERVICE_API_VERSION,
+    log: api_log,
+    hart_id: api_hart_id,
+    time_us: api_time_us,
+    send_msg: api_send_msg,
+    abort: api_abort,
+};
+
+unsafe fn service_str<'a>(msg: *const u8, len: usize) -> &'a str {
+    core::str::from_utf8(core::slice::from_raw_parts(msg, len)).unwrap_or("<invalid utf-8>")
+}
+
+extern "C" fn api_log(level: usize, msg: *const u8, len: usize, value: usize) {
+    let level = match level {
+        1 => log::Level::Error,
+        2 => log::Level::Warn,
+        3 => log::Level::Info,
+        _ => log::Level::Debug,
+    };
+    log!(
+        level,
+        "[service] {} {:#x}",
+        unsafe { service_str(msg, len) },
+        value
+    );
+}
+
+extern "C" fn api_hart_id() -> usize {
+    hart_id()
+}
+
+extern "C" fn api_time_us() -> usize {
+    get_time_us()
+}
+
+extern "C" fn api_send_msg(pid: usize, msg: usize) -> isize {
+    match push_trap_record(
+        pid,
+        UserTrapRecord {
+            cause: 0,
+            message: msg,
+        },
+    ) {
+        Ok(()) => 0,
+        Err(e) => e.errno(),
+    }
+}
+
+extern "C" fn api_abort(msg: *const u8, len: usize) -> ! {
+    let calling = CALLING[hart_id()].swap(0, Relaxed);
+    error!("[service] aborted: {}", unsafe { service_str(msg, len) });
+    if calling != 0 {
+        let slot = (calling & !CALL_HOLDS_WRITE) - 1;
+        ABORTED.fetch_or(1 << slot, Relaxed);
+        // the guard is on the stack of the abandoned call and never dropped
+        unsafe {
+            if calling & CALL_HOLDS_WRITE != 0 {
+                SERVICES.force_write_unlock();
+            } else {
+                SERVICES.force_read_decrement();
+            }
+        }
+    }
+    exit_current_and_run_next(ExitStatus::killed(ExitReason::ServiceAbort));
+    unreachable!("a killed task is never resumed");
+}
+
+#[derive(Debug)]
+pub enum ServiceError {
+    NotFound,
+    BadImage,
+    AlreadyLoaded,
+    NoSlot,
+    NoMemory,
+    InitFailed,
+}
+
+impl ServiceError {
+    /// Error number returned to user space
+    pub fn errno(&self) -> isize {
+        match self {
+            ServiceError::NotFound => ENOENT,
+            ServiceError::BadImage => ENOEXEC,
+            ServiceError::AlreadyLoaded => EEXIST,
+            ServiceError::NoSlot => EBUSY,
+            ServiceError::NoMemory => ENOMEM,
+            ServiceError::InitFailed => EIO,
+        }
+    }
+}
+
+struct Service {
+    name: String,
+    base: usize,
+    header: ServiceHeader,
+}
+
+/// The entry at `offset` of the service at `base`, `None` for 0
+fn entry(base: usize, offset: u32) -> Option<usize> {
+    (offset != 0).then(|| base + offset as usize)
+}
+
+/// Set in `CALLING` when the call is made under the write lock of `SERVICES`
+const CALL_HOLDS_WRITE: usize = 1 << 63;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NOT_CALLING: AtomicUsize = AtomicUsize::new(0);
+/// The slot plus one of the service each hart is calling into, 0 for none, so that
+/// `api_abort` knows what to back out of
+static CALLING: [AtomicUsize; CPU_NUM] = [NOT_CALLING; CPU_NUM];
+/// One bit per slot whose service aborted
+static ABORTED: AtomicUsize = AtomicUsize::new(0);
+
+fn is_aborted(slot: usize) -> bool {
+    ABORTED.load(Relaxed) & (1 << slot) != 0
+}
+
+/// Run `f`, which calls into the service in `slot`, with `SERVICES` locked for writing if
+/// `holds_write`, or else for reading
+fn call<R>(slot: usize, holds_write: bool, f: impl FnOnce() -> R) -> R {
+    let calling = (slot + 1) | if holds_write { CALL_HOLDS_WRITE } else { 0 };
+    CALLING[hart_id()].store(calling, Relaxed);
+    let ret = f();
+    CALLING[hart_id()].store(0, Relaxed);
+    ret
+}
+
+impl Service {
+    fn slot(&self) -> usize {
+        (self.base - SERVICE_BASE) / SERVICE_SLOT_SIZE
+    }
+    fn on_uipi_send(&self, sender_pid: usize, receiver_pid: usize, msg: usize) -> isize {
+        match entry(self.base, self.header.on_uipi_send) {
+            Some(entry) if !is_aborted(self.slot()) => call(self.slot(), false, || unsafe {
+                let f: extern "C" fn(usize, usize, usize) -> isize = core::mem::transmute(entry);
+                f(sender_pid, receiver_pid, msg)
+            }),
+            _ => 0,
+        }
+    }
+    fn on_sched_tick(&self, hart_id: usize, pid: usize) {
+        match entry(self.base, self.header.on_sched_tick) {
+            Some(entry) if !is_aborted(self.slot()) => call(self.slot(), false, || unsafe {
+                let f: extern "C" fn(usize, usize) = core::mem::transmute(entry);
+                f(hart_id, pid)
+            }),
+            _ => {}
+        }
+    }
+}
+
+impl Drop for Service {
+    fn drop(&mut self) {
+        // an aborted service is not asked to exit, nothing of it is running any more
+        match entry(self.base, self.header.exit) {
+            Some(entry) if !is_aborted(self.slot()) => call(self.slot(), true, || unsafe {
+                let f: extern "C" fn() = core::mem::transmute(entry);
+                f()
+            }),
+            _ => {}
+        }
+        unmap(self.base, &self.header);
+        ABORTED.fetch_and(!(1 << self.slot()), Relaxed);
+        info!("[service] {} unloaded", self.name);
+    }
+}
+
+fn unmap(base: usize, header: &ServiceHeader) {
+    let text_end = base + header.text_size as usize;
+    let mut kernel_space = KERNEL_SPACE.lock();
+    kernel_space.remove_area_with_start_vpn(VirtAddr::from(base).into());
+    kernel_space.remove_area_with_start_vpn(VirtAddr::from(text_end).into());
+    drop(kernel_space);
+    // the slot may be loaded again right away
+    ipi::flush_kernel_range(base, SERVICE_SLOT_SIZE);
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_SERVICE: Option<Service> = None;
+static SERVICES: RwLock<[Option<Service>; MAX_SERVICES]> = RwLock::new([NO_SERVICE; MAX_SERVICES]);
+/// Loaded services, so that the hooks skip the lock in the common case of none
+static LOADED: AtomicUsize = AtomicUsize::new(0);
+
+fn parse_header(data: &[u8]) -> Result<ServiceHeader, ServiceError> {
+    if data.len() < size_of::<ServiceHeader>() {
+        return Err(ServiceError::BadImage);
+    }
+    let header = unsafe { (data.as_ptr() as *const ServiceHeader).read_unaligned() };
+    let (text_size, mem_size) = (header.text_size as usize, header.mem_size as usize);
+    let entries = [
+        header.init,
+        header.exit,
+        header.on_uipi_send,
+        header.on_sched_tick,
+    ];
+    if header.magic != SERVICE_MAGIC
+        || header.api_version != SERVICE_API_VERSION
+        || text_size % PAGE_SIZE != 0
+        || text_size > data.len()
+        || data.len() > mem_size
+        || mem_size > SERVICE_SLOT_SIZE
+        || header.init == 0
+        || entries.iter().any(|&entry| entry as usize >= text_size)
+    {
+        return Err(ServiceError::BadImage);
+    }
+    Ok(header)
+}
+
+/// Load the service `name` into a free slot and run its init, return the slot
+pub fn load(name: &str) -> Result<usize, ServiceError> {
+    let data = get_service_data_by_name(name).ok_or(ServiceError::NotFound)?;
+    let header = parse_header(data)?;
+    let (text_size, mem_size) = (header.text_size as usize, header.mem_size as usize);
+    let mut services = SERVICES.write();
+    if services
+        .iter()
+        .flatten()
+        .any(|service| service.name == name)
+    {
+        return Err(ServiceError::AlreadyLoaded);
+    }
+    // a service which aborted in its init is left mapped in its slot for good
+    let slot = (0..MAX_SERVICES)
+        .find(|&slot| services[slot].is_none() && !is_aborted(slot))
+        .ok_or(ServiceError::NoSlot)?;
+    // frames for the service and the page tables
+    if mem_size / PAGE_SIZE + 4 > frames_available() {
+        return Err(ServiceError::NoMemory);
+    }
+    let base = SERVICE_BASE + slot * SERVICE_SLOT_SIZE;
+    let mut kernel_space = KERNEL_SPACE.lock();
+    kernel_space.insert_framed_area_with_data(
+        base.into(),
+        (base + text_size).into(),
+        MapPermission::R | MapPermission::X,
+        &data[..text_size],
+    );
+    if mem_size > text_size {
+        kernel_space.insert_framed_area_with_data(
+            (base + text_size).into(),
+            (base + mem_size).into(),
+            MapPermission::R | MapPermission::W,
+            &data[text_size..],
+        );
+    }
+    drop(kernel_space);
+    ipi::sync_icache();
+    let init: extern "C" fn(&'static ServiceApi) -> isize =
+        unsafe { core::mem::transmute(entry(base, header.init).unwrap()) };
+    let ret = call(slot, true, || init(&API));
+    if ret != 0 {
+        // only a service which came up is asked to exit
+        unmap(base, &header);
+        warn!("[service] {} failed to init: {}", name, ret);
+        return Err(ServiceError::InitFailed);
+    }
+    info!("[service] {} loaded at {:#x}", name, base);
+    services[slot] = Some(Service {
+        name: String::from(name),
+        base,
+        header,
+    });
+    LOADED.fetch_add(1, Relaxed);
+    Ok(slot)
+}
+
+/// Run the exit of the service in `slot` and unload it
+pub fn unload(slot: usize) -> Result<(), ServiceError> {
+    let mut services = SERVICES.write();
+    let service = services
+        .get_mut(slot)
+        .and_then(Option::take)
+        .ok_or(ServiceError::NotFound)?;
+    LOADED.fetch_sub(1, Relaxed);
+    // no hook is running, and the slot is not reused before it is unmapped
+    drop(service);
+    Ok(())
+}
+
+/// Whether every loaded service lets the message through
+pub fn uipi_send_allowed(sender_pid: usize, receiver_pid: usize, msg: usize) -> bool {
+    if LOADED.load(Relaxed) == 0 {
+        return true;
+    }
+    SERVICES
+        .read()
+        .iter()
+        .flatten()
+        .all(|service| service.on_uipi_send(sender_pid, receiver_pid, msg) == 0)
+}
+
+/// Called on every scheduler tick, with `pid` running on the hart
+pub fn sched_tick(hart_id: usize, pid: usize) {
+    if LOADED.load(Relaxed) == 0 {
+        return;
+    }
+    for service in SERVICES.read().iter().flatten() {
+        service.on_sched_tick(hart_id, pid);
+    }
+}
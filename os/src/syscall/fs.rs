@@ -1,99 +1,410 @@
 use core::cmp::min;
 
-use crate::fs::{make_pipe, File};
-use crate::task::{current_task, current_user_token};
-use crate::{
-    mm::{translated_byte_buffer, translated_refmut, UserBuffer},
-    task::find_task,
+use crate::config::PAGE_SIZE;
+use crate::fs::{make_pipe, open_device, File, FD_CLOEXEC, O_CLOEXEC};
+use crate::mm::{
+    check_user_range, copy_from_user, copy_to_user, translated_byte_buffer,
+    translated_byte_buffer_mut, translated_byte_buffer_prefix, translated_str, UserBuffer,
 };
+use crate::syscall::{EAGAIN, EBADF, EFAULT, EINVAL, EIO, ENOENT, ESPIPE, ESRCH};
+use crate::task::{current_has_pending_user_trap, current_task, current_user_token};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+/// Writes of at least this much from a page-aligned buffer lend its whole pages to files
+/// which read them in place, see `File::write_pinned`
+const ZERO_COPY_MIN_LEN: usize = 4 * PAGE_SIZE;
 
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
-    if fd == 3 || fd == 4 || fd == 0 || fd == 1 {
-        // debug!("sys_write {} {}", fd, len);
+    if let Err(errno) = check_user_range(buf as usize, len) {
+        return errno;
     }
     let token = current_user_token();
     let task = current_task().unwrap();
     let inner = task.acquire_inner_lock();
-    if fd >= inner.fd_table.len() {
-        return -1;
+    if fd >= inner.fd_table.files.len() {
+        return EBADF;
     }
-    if let Some(file) = &inner.fd_table[fd] {
+    if let Some(file) = inner.fd_table.get(fd) {
         let file = file.clone();
-        // release Task lock manually to avoid deadlock
         drop(inner);
-        if let Ok(buffers) = translated_byte_buffer(token, buf, len) {
-            match file.write(UserBuffer::new(buffers)) {
-                Ok(write_len) => write_len as isize,
-                Err(_) => -2,
-            }
+        let pinned_len = len / PAGE_SIZE * PAGE_SIZE;
+        let pinned = if buf as usize % PAGE_SIZE == 0 && len >= ZERO_COPY_MIN_LEN {
+            task.acquire_inner_lock()
+                .memory_set
+                .pin_user_pages(buf as usize, pinned_len / PAGE_SIZE)
         } else {
-            -3
+            None
+        };
+        let mut written = 0;
+        if let Some(Some(ret)) = pinned.map(|pages| file.write_pinned(pages)) {
+            match ret {
+                Ok(write_len) if write_len == pinned_len && pinned_len < len => written = write_len,
+                Ok(write_len) => return write_len as isize,
+                Err(errno) => return errno,
+            }
+        }
+        // the unaligned tail is copied, like everything written to other files
+        match write_copied(&file, token, buf as usize + written, len - written) {
+            Ok(write_len) => (written + write_len) as isize,
+            Err(_) if written > 0 => written as isize,
+            Err(errno) => errno,
         }
     } else {
-        -4
+        EBADF
     }
 }
 
+fn write_copied(
+    file: &Arc<dyn File + Send + Sync>,
+    token: usize,
+    buf: usize,
+    len: usize,
+) -> Result<usize, isize> {
+    // like Linux, a buffer running into an unmapped page is written up to it
+    let buffers = translated_byte_buffer_prefix(token, buf as *const u8, len, false);
+    if buffers.is_empty() && len > 0 {
+        return Err(EFAULT);
+    }
+    file.write(UserBuffer::new(buffers))
+}
+
 pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
-    if fd == 3 || fd == 4 || fd == 0 || fd == 1 {
-        // debug!("sys_read {} {}", fd, len);
+    if let Err(errno) = check_user_range(buf as usize, len) {
+        return errno;
     }
     let token = current_user_token();
     let task = current_task().unwrap();
     let inner = task.acquire_inner_lock();
-    if fd >= inner.fd_table.len() {
-        return -1;
+    if fd >= inner.fd_table.files.len() {
+        return EBADF;
     }
-    if let Some(file) = &inner.fd_table[fd] {
+    if let Some(file) = inner.fd_table.get(fd) {
         let file = file.clone();
         // release Task lock manually to avoid deadlock
         drop(inner);
-        if let Ok(buffers) = translated_byte_buffer(token, buf, len) {
-            match file.read(UserBuffer::new(buffers)) {
-                Ok(read_len) => read_len as isize,
-                Err(_) => -2,
+        let buffers = translated_byte_buffer_prefix(token, buf, len, true);
+        if buffers.is_empty() && len > 0 {
+            return EFAULT;
+        }
+        match file.read(UserBuffer::new(buffers)) {
+            Ok(read_len) => read_len as isize,
+            Err(errno) => errno,
+        }
+    } else {
+        EBADF
+    }
+}
+
+/// `struct iovec` of Linux
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct IoVec {
+    base: usize,
+    len: usize,
+}
+
+/// Like Linux, more fragments are refused
+const IOV_MAX: usize = 1024;
+
+/// The buffers of the `iovcnt` fragments at `iov`, up to the first unmapped page, or
+/// read-only one when `writable`, so that a transfer can stop there
+fn translated_iovec(
+    token: usize,
+    iov: *const IoVec,
+    iovcnt: usize,
+    writable: bool,
+) -> Result<Vec<UserBuffer>, isize> {
+    if iovcnt > IOV_MAX {
+        return Err(EINVAL);
+    }
+    let mut iovecs = vec![IoVec::default(); iovcnt];
+    let bytes = unsafe {
+        core::slice::from_raw_parts_mut(iovecs.as_mut_ptr() as *mut u8, iovcnt * size_of::<IoVec>())
+    };
+    copy_from_user(token, iov as *const u8, bytes)?;
+    // the total must fit the return value
+    let total = iovecs
+        .iter()
+        .try_fold(0usize, |total, iovec| total.checked_add(iovec.len))
+        .filter(|&total| total <= isize::MAX as usize)
+        .ok_or(EINVAL)?;
+    let mut bufs = Vec::with_capacity(iovcnt);
+    let mut translated = 0;
+    for iovec in iovecs {
+        let buffers =
+            translated_byte_buffer_prefix(token, iovec.base as *const u8, iovec.len, writable);
+        let buf = UserBuffer::new(buffers);
+        let len = buf.len();
+        translated += len;
+        bufs.push(buf);
+        if len < iovec.len {
+            break;
+        }
+    }
+    if translated == 0 && total > 0 {
+        return Err(EFAULT);
+    }
+    Ok(bufs)
+}
+
+/// Read into the `iovcnt` fragments at `iov` in turn
+pub fn sys_readv(fd: usize, iov: *const IoVec, iovcnt: usize) -> isize {
+    let token = current_user_token();
+    let task = current_task().unwrap();
+    let file = match task.acquire_inner_lock().fd_table.get(fd) {
+        Some(file) => file.clone(),
+        None => return EBADF,
+    };
+    let bufs = match translated_iovec(token, iov, iovcnt, true) {
+        Ok(bufs) => bufs,
+        Err(errno) => return errno,
+    };
+    match file.readv(bufs) {
+        Ok(read_len) => read_len as isize,
+        Err(errno) => errno,
+    }
+}
+
+/// Write the `iovcnt` fragments at `iov` in turn
+pub fn sys_writev(fd: usize, iov: *const IoVec, iovcnt: usize) -> isize {
+    let token = current_user_token();
+    let task = current_task().unwrap();
+    let file = match task.acquire_inner_lock().fd_table.get(fd) {
+        Some(file) => file.clone(),
+        None => return EBADF,
+    };
+    let bufs = match translated_iovec(token, iov, iovcnt, false) {
+        Ok(bufs) => bufs,
+        Err(errno) => return errno,
+    };
+    match file.writev(bufs) {
+        Ok(write_len) => write_len as isize,
+        Err(errno) => errno,
+    }
+}
+
+/// Bytes moved at a time by `sys_sendfile` and `sys_splice`
+const TRANSFER_CHUNK: usize = PAGE_SIZE;
+
+/// Move up to `count` bytes from `in_fd` to `out_fd` inside the kernel, instead of reading
+/// them into user space and writing them back. Files have no offsets, `offset` must be null.
+pub fn sys_sendfile(out_fd: usize, in_fd: usize, offset: *mut usize, count: usize) -> isize {
+    if !offset.is_null() {
+        return ESPIPE;
+    }
+    transfer(in_fd, out_fd, count)
+}
+
+/// `splice` of Linux, the same as `sys_sendfile` here, since no file has offsets
+pub fn sys_splice(
+    fd_in: usize,
+    off_in: *mut usize,
+    fd_out: usize,
+    off_out: *mut usize,
+    len: usize,
+    _flags: usize,
+) -> isize {
+    if !off_in.is_null() || !off_out.is_null() {
+        return ESPIPE;
+    }
+    transfer(fd_in, fd_out, len)
+}
+
+/// A kernel buffer passed to `File::read` and `File::write`, which take user buffers
+fn kernel_buffer(buf: &mut [u8]) -> UserBuffer {
+    let buf = unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr(), buf.len()) };
+    UserBuffer::new(vec![buf])
+}
+
+/// Copy from `in_fd` to `out_fd` through a kernel buffer, until `count` bytes or a short
+/// read. A pending user trap ends the transfer, with the bytes written so far, or with
+/// `ERESTART` if none were. The bytes of the chunk read but not written are then lost, as
+/// when the output refuses them.
+fn transfer(in_fd: usize, out_fd: usize, count: usize) -> isize {
+    let task = current_task().unwrap();
+    let inner = task.acquire_inner_lock();
+    let (input, output) = match (inner.fd_table.get(in_fd), inner.fd_table.get(out_fd)) {
+        (Some(input), Some(output)) => (input.clone(), output.clone()),
+        _ => return EBADF,
+    };
+    drop(inner);
+    let mut chunk = vec![0u8; TRANSFER_CHUNK.min(count)];
+    let mut transferred = 0;
+    while transferred < count {
+        let len = chunk.len().min(count - transferred);
+        let read_len = match input.read(kernel_buffer(&mut chunk[..len])) {
+            Ok(read_len) => read_len,
+            Err(errno) if transferred == 0 => return errno,
+            Err(_) => break,
+        };
+        let mut written = 0;
+        while written < read_len {
+            match output.write(kernel_buffer(&mut chunk[written..read_len])) {
+                Ok(write_len) if write_len > 0 => written += write_len,
+                Err(errno) if transferred + written == 0 => return errno,
+                _ if transferred + written == 0 => return EIO,
+                _ => return (transferred + written) as isize,
             }
-        } else {
-            -3
         }
+        transferred += read_len;
+        if read_len < len || current_has_pending_user_trap() {
+            break;
+        }
+    }
+    transferred as isize
+}
+
+pub fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
+    let task = current_task().unwrap();
+    let inner = task.acquire_inner_lock();
+    if let Some(file) = inner.fd_table.get(fd) {
+        let file = file.clone();
+        drop(inner);
+        match file.ioctl(cmd, arg) {
+            Ok(ret) => ret as isize,
+            Err(e) => e,
+        }
+    } else {
+        EBADF
+    }
+}
+
+/// Only device files exist, of `flags` only `O_CLOEXEC` is taken
+pub fn sys_open(path: *const u8, flags: u32) -> isize {
+    let token = current_user_token();
+    let path = match translated_str(token, path) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    if let Some(file) = open_device(path.as_str()) {
+        let task = current_task().unwrap();
+        let fd = task
+            .acquire_inner_lock()
+            .fd_table
+            .insert(file, fd_flags_of(flags));
+        fd as isize
+    } else {
+        ENOENT
+    }
+}
+
+fn fd_flags_of(open_flags: u32) -> usize {
+    if open_flags & O_CLOEXEC != 0 {
+        FD_CLOEXEC
+    } else {
+        0
+    }
+}
+
+const F_GETFD: usize = 1;
+const F_SETFD: usize = 2;
+
+/// Only the fd flags can be read and set, `F_GETFD` and `F_SETFD`
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    if inner.fd_table.get(fd).is_none() {
+        return EBADF;
+    }
+    match cmd {
+        F_GETFD => inner.fd_table.fd_flags(fd) as isize,
+        F_SETFD => {
+            inner.fd_table.set_fd_flags(fd, arg & FD_CLOEXEC);
+            0
+        }
+        _ => EINVAL,
+    }
+}
+
+/// Duplicate `fd` into the lowest free fd, which is how redirections
+/// replace 0, 1 or 2 after closing them
+pub fn sys_dup(fd: usize) -> isize {
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    if let Some(file) = inner.fd_table.get(fd) {
+        let file = file.clone();
+        let new_fd = inner.fd_table.insert(file, 0);
+        new_fd as isize
     } else {
-        -4
+        EBADF
     }
 }
 
 pub fn sys_close(fd: usize) -> isize {
     let task = current_task().unwrap();
     let mut inner = task.acquire_inner_lock();
-    if fd >= inner.fd_table.len() {
-        return -1;
+    if fd >= inner.fd_table.files.len() {
+        return EBADF;
     }
-    if inner.fd_table[fd].is_none() {
-        return -1;
+    if inner.fd_table.files[fd].is_none() {
+        return EBADF;
     }
-    inner.fd_table[fd].take();
+    inner.fd_table.files[fd].take();
     0
 }
 
-pub fn sys_pipe(pipe: *mut usize) -> isize {
+/// `pipe2` of Linux, of `flags` only `O_CLOEXEC` is taken
+pub fn sys_pipe(pipe: *mut usize, flags: u32) -> isize {
     let task = current_task().unwrap();
     let token = current_user_token();
     let mut inner = task.acquire_inner_lock();
     let (pipe_read, pipe_write) = make_pipe();
-    let read_fd = inner.alloc_fd();
-    inner.fd_table[read_fd] = Some(pipe_read);
-    let write_fd = inner.alloc_fd();
-    inner.fd_table[write_fd] = Some(pipe_write);
-    *translated_refmut(token, pipe) = read_fd;
-    *translated_refmut(token, unsafe { pipe.add(1) }) = write_fd;
+    let read_fd = inner.fd_table.insert(pipe_read, fd_flags_of(flags));
+    let write_fd = inner.fd_table.insert(pipe_write, fd_flags_of(flags));
+    let fds = [read_fd, write_fd];
+    let bytes = unsafe {
+        core::slice::from_raw_parts(fds.as_ptr() as *const u8, core::mem::size_of_val(&fds))
+    };
+    if copy_to_user(token, pipe as *mut u8, bytes).is_err() {
+        // nobody has seen the fds, close them again
+        inner.fd_table.files[read_fd] = None;
+        inner.fd_table.files[write_fd] = None;
+        return EFAULT;
+    }
     0
 }
 
+/// Pass the file of `fd` through the pipe `pipe_fd`, like `SCM_RIGHTS` over a Unix socket.
+/// It travels after the bytes written before, and is taken by `sys_recv_fd`.
+pub fn sys_send_fd(pipe_fd: usize, fd: usize) -> isize {
+    let task = current_task().unwrap();
+    let inner = task.acquire_inner_lock();
+    let (pipe, file) = match (inner.fd_table.get(pipe_fd), inner.fd_table.get(fd)) {
+        (Some(pipe), Some(file)) => (pipe.clone(), file.clone()),
+        _ => return EBADF,
+    };
+    drop(inner);
+    match pipe.send_file(file) {
+        Some(Ok(())) => 0,
+        Some(Err(errno)) => errno,
+        None => EINVAL,
+    }
+}
+
+/// Take the file passed next through the pipe `pipe_fd` into a new fd, blocking until it
+/// comes. Fails with `ENOMSG` while bytes written before it are not read.
+pub fn sys_recv_fd(pipe_fd: usize) -> isize {
+    let task = current_task().unwrap();
+    let pipe = match task.acquire_inner_lock().fd_table.get(pipe_fd) {
+        Some(pipe) => pipe.clone(),
+        None => return EBADF,
+    };
+    match pipe.recv_file() {
+        Some(Ok(file)) => task.acquire_inner_lock().fd_table.insert(file, 0) as isize,
+        Some(Err(errno)) => errno,
+        None => EINVAL,
+    }
+}
+
 pub fn sys_mailwrite(pid: usize, buf: *mut u8, len: usize) -> isize {
     let token = current_user_token();
-    if let Some(receive_task) = find_task(pid) {
+    if let Some(receive_task) = current_task().unwrap().find_visible_task(pid) {
         debug!("find task");
         if receive_task.acquire_inner_lock().is_mailbox_full() {
-            return -1;
+            return EAGAIN;
         } else if len == 0 {
             return 0;
         }
@@ -102,14 +413,14 @@ pub fn sys_mailwrite(pid: usize, buf: *mut u8, len: usize) -> isize {
             let socket = receive_task.create_socket();
             match socket.write(UserBuffer::new(buffers)) {
                 Ok(write_len) => write_len as isize,
-                Err(_) => -1,
+                Err(errno) => errno,
             }
         } else {
-            -1
+            EFAULT
         }
     } else {
         debug!("not find task");
-        -1
+        ESRCH
     }
 }
 
@@ -121,20 +432,20 @@ pub fn sys_mailread(buf: *mut u8, len: usize) -> isize {
         task.acquire_inner_lock().is_mailbox_empty()
     );
     if task.acquire_inner_lock().is_mailbox_empty() {
-        return -1;
+        return EAGAIN;
     } else if len == 0 {
         return 0;
     }
     let mail_box = task.acquire_inner_lock().mail_box.clone();
-    if let Ok(buffers) = translated_byte_buffer(token, buf, min(len, 256)) {
+    if let Ok(buffers) = translated_byte_buffer_mut(token, buf, min(len, 256)) {
         match mail_box.read(UserBuffer::new(buffers)) {
             Ok(read_len) => {
                 debug!("mail read {} len", read_len);
                 read_len as isize
             }
-            Err(_) => -1,
+            Err(errno) => errno,
         }
     } else {
-        -1
+        EFAULT
     }
 }
@@ -0,0 +1,76 @@
+//! Linux syscalls which rCore-N has no own counterpart for, so that programs written
+//! against the Linux ABI run unchanged. Anything they need but cannot have fails with
+//! `ENOSYS`, which such programs are prepared for.
+
+use core::mem::size_of;
+
+use crate::mm;
+use crate::syscall::{EFAULT, EINVAL, ENOMEM, ENOSYS};
+use crate::task::{current_task, current_user_token, mmap, suspend_current_and_run_next};
+use crate::timer::{get_user_time_ns, TimeSpec, NSEC_PER_SEC};
+
+pub const MAP_SHARED: usize = 0x01;
+pub const MAP_PRIVATE: usize = 0x02;
+pub const MAP_FIXED: usize = 0x10;
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+/// Threads are not supported, the only thread of a process is the process itself
+pub fn sys_set_tid_address(_tidptr: usize) -> isize {
+    current_task().unwrap().getpid() as isize
+}
+
+/// Sleep by yielding until the deadline, which is never interrupted, so `rem` is always zero
+pub fn sys_nanosleep(req: *const TimeSpec, rem: *mut TimeSpec) -> isize {
+    let token = current_user_token();
+    let mut time = TimeSpec { sec: 0, nsec: 0 };
+    let bytes = unsafe {
+        core::slice::from_raw_parts_mut(&mut time as *mut _ as *mut u8, size_of::<TimeSpec>())
+    };
+    if mm::copy_from_user(token, req as *const u8, bytes).is_err() {
+        return EFAULT;
+    }
+    if time.nsec >= NSEC_PER_SEC || time.sec > isize::MAX as usize / NSEC_PER_SEC {
+        return EINVAL;
+    }
+    let deadline = get_user_time_ns() + time.sec * NSEC_PER_SEC + time.nsec;
+    while get_user_time_ns() < deadline {
+        suspend_current_and_run_next();
+    }
+    let zero = TimeSpec { sec: 0, nsec: 0 };
+    if !rem.is_null() && mm::copy_value_to_user(token, rem, &zero).is_err() {
+        return EFAULT;
+    }
+    0
+}
+
+/// There is no program break, allocators fall back to `mmap`
+pub fn sys_brk(_addr: usize) -> isize {
+    ENOSYS
+}
+
+/// Linux `mmap`, the native one has its own number. Only private anonymous mappings are
+/// supported, `addr` is a hint unless `MAP_FIXED`, which fails instead of replacing what
+/// is mapped there.
+pub fn sys_linux_mmap(addr: usize, len: usize, prot: usize, flags: usize) -> isize {
+    if flags & MAP_ANONYMOUS == 0 || flags & (MAP_SHARED | MAP_PRIVATE) != MAP_PRIVATE {
+        return ENOSYS;
+    }
+    if len == 0 {
+        return EINVAL;
+    }
+    let ret = if addr == 0 {
+        mmap(0, len, prot)
+    } else {
+        // the native `mmap` returns the length for a given address
+        match mmap(addr, len, prot) {
+            Ok(_) => Ok(addr as isize),
+            Err(err) if flags & MAP_FIXED != 0 || err == ENOMEM => Err(err),
+            Err(_) => mmap(0, len, prot),
+        }
+    };
+    match ret {
+        Ok(start) => start,
+        Err(err) if err == ENOMEM => err,
+        Err(_) => EINVAL,
+    }
+}
@@ -1,57 +1,310 @@
-const SYSCALL_CLOSE: usize = 57;
-const SYSCALL_PIPE: usize = 59;
-const SYSCALL_READ: usize = 63;
-const SYSCALL_WRITE: usize = 64;
-const SYSCALL_EXIT: usize = 93;
-const SYSCALL_YIELD: usize = 124;
-const SYSCALL_SET_PRIORITY: usize = 140;
-const SYSCALL_GET_TIME: usize = 169;
-const SYSCALL_GETPID: usize = 172;
-const SYSCALL_MUNMAP: usize = 215;
-const SYSCALL_FORK: usize = 220;
-const SYSCALL_EXEC: usize = 221;
-const SYSCALL_MMAP: usize = 222;
-const SYSCALL_WAITPID: usize = 260;
-const SYSCALL_SPAWN: usize = 400;
-const SYSCALL_MAILREAD: usize = 401;
-const SYSCALL_MAILWRITE: usize = 402;
-const SYSCALL_INIT_USER_TRAP: usize = 600;
-const SYSCALL_SEND_MSG: usize = 601;
-const SYSCALL_SET_TIMER: usize = 602;
-const SYSCALL_CLAIM_EXT_INT: usize = 603;
-const SYSCALL_SET_EXT_INT_ENABLE: usize = 604;
-
 mod fs;
+mod linux;
 mod process;
+mod trace;
 
+use crate::config::DETERMINISTIC;
+use crate::deterministic::{self, VIRTUAL_SYSCALL_US};
+use crate::task::{Rusage, SchedAttr, Tms};
+use crate::timer::{TimeSpec, TimeVal};
+use crate::trap::UserTrapDescriptor;
 use fs::*;
+use linux::*;
+pub use process::SIGSTOP;
 use process::*;
+pub use trace::SyscallTrace;
+
+/// Returned by a blocking syscall given up for a pending user trap, never seen by user space:
+/// the syscall is issued again after the handler if restartable, or fails with `EINTR`
+pub const ERESTART: isize = -512;
+
+/// Error numbers of Linux, which every syscall fails with as `-errno`
+pub const EPERM: isize = -1;
+pub const ENOENT: isize = -2;
+pub const ESRCH: isize = -3;
+pub const EINTR: isize = -4;
+pub const EIO: isize = -5;
+pub const E2BIG: isize = -7;
+pub const ENOEXEC: isize = -8;
+pub const EBADF: isize = -9;
+pub const ECHILD: isize = -10;
+pub const EAGAIN: isize = -11;
+pub const ENOMEM: isize = -12;
+pub const EFAULT: isize = -14;
+pub const EBUSY: isize = -16;
+pub const EEXIST: isize = -17;
+pub const ENODEV: isize = -19;
+pub const EINVAL: isize = -22;
+pub const ENOTTY: isize = -25;
+pub const ENOSPC: isize = -28;
+pub const ESPIPE: isize = -29;
+pub const EPIPE: isize = -32;
+pub const ENOSYS: isize = -38;
+pub const ENOMSG: isize = -42;
+pub const ETIME: isize = -62;
+pub const ENOBUFS: isize = -105;
+pub const ENOTCONN: isize = -107;
+
+/// Errors are returned as `-errno` from `-MAX_ERRNO` to -1 like on Linux, any other return
+/// is a value, which may be an address not fitting in an `isize`
+pub const MAX_ERRNO: isize = 4095;
+
+pub fn is_error(ret: isize) -> bool {
+    (-MAX_ERRNO..0).contains(&ret)
+}
+
+/// Syscalls which made no progress when interrupted, and can be issued again as they were
+pub fn is_restartable(syscall_id: usize) -> bool {
+    matches!(
+        syscall_id,
+        SYSCALL_READ
+            | SYSCALL_WRITE
+            | SYSCALL_READV
+            | SYSCALL_WRITEV
+            | SYSCALL_SENDFILE
+            | SYSCALL_SPLICE
+            | SYSCALL_SEND_FD
+            | SYSCALL_RECV_FD
+    )
+}
+
+/// Every syscall, with `SYSCALL_*` the numbers of the table shared with the user crate
+macro_rules! syscall_table {
+    ($($constant:ident = $id:expr, $name:expr, $args:expr;)*) => {
+        $(pub const $constant: usize = $id;)*
+
+        pub const SYSCALLS: &[SyscallDesc] = &[
+            $(SyscallDesc { id: $id, name: $name, args: $args },)*
+        ];
+    };
+}
+
+include!("../../../user/src/syscall_table.rs");
+
+pub struct SyscallDesc {
+    pub id: usize,
+    pub name: &'static str,
+    /// How many of a0 to a5 it takes
+    pub args: usize,
+}
+
+/// Takes a0 to a5, only Linux syscalls take more than three
+type Handler = fn([usize; 6]) -> isize;
+
+/// One past the largest syscall number
+const SYSCALL_NUM: usize = {
+    let mut num = 0;
+    let mut i = 0;
+    while i < SYSCALLS.len() {
+        if SYSCALLS[i].id >= num {
+            num = SYSCALLS[i].id + 1;
+        }
+        i += 1;
+    }
+    num
+};
+
+/// Of every syscall the kernel serves, in no particular order
+const HANDLER_LIST: &[(usize, Handler)] = &[
+    (SYSCALL_DUP, |args| sys_dup(args[0])),
+    (SYSCALL_IOCTL, |args| sys_ioctl(args[0], args[1], args[2])),
+    (SYSCALL_OPEN, |args| {
+        sys_open(args[0] as *const u8, args[1] as u32)
+    }),
+    (SYSCALL_CLOSE, |args| sys_close(args[0])),
+    (SYSCALL_FCNTL, |args| sys_fcntl(args[0], args[1], args[2])),
+    (SYSCALL_PIPE, |args| {
+        sys_pipe(args[0] as *mut usize, args[1] as u32)
+    }),
+    (SYSCALL_READ, |args| {
+        sys_read(args[0], args[1] as *const u8, args[2])
+    }),
+    (SYSCALL_WRITE, |args| {
+        sys_write(args[0], args[1] as *const u8, args[2])
+    }),
+    (SYSCALL_READV, |args| {
+        sys_readv(args[0], args[1] as *const IoVec, args[2])
+    }),
+    (SYSCALL_WRITEV, |args| {
+        sys_writev(args[0], args[1] as *const IoVec, args[2])
+    }),
+    (SYSCALL_SENDFILE, |args| {
+        sys_sendfile(args[0], args[1], args[2] as *mut usize, args[3])
+    }),
+    (SYSCALL_SPLICE, |args| {
+        sys_splice(
+            args[0],
+            args[1] as *mut usize,
+            args[2],
+            args[3] as *mut usize,
+            args[4],
+            args[5],
+        )
+    }),
+    (SYSCALL_EXIT, |args| {
+        trace::trace_syscall(SYSCALL_EXIT, args, None);
+        sys_exit(args[0] as i32)
+    }),
+    (SYSCALL_SET_TID_ADDRESS, |args| sys_set_tid_address(args[0])),
+    (SYSCALL_NANOSLEEP, |args| {
+        sys_nanosleep(args[0] as *const TimeSpec, args[1] as *mut TimeSpec)
+    }),
+    (SYSCALL_PTRACE, |args| sys_ptrace(args[0], args[1], args[2])),
+    (SYSCALL_SCHED_SETAFFINITY, |args| {
+        sys_sched_setaffinity(args[0], args[1], args[2] as *const usize)
+    }),
+    (SYSCALL_SCHED_GETAFFINITY, |args| {
+        sys_sched_getaffinity(args[0], args[1], args[2] as *mut usize)
+    }),
+    (SYSCALL_YIELD, |_| sys_yield()),
+    (SYSCALL_KILL, |args| sys_kill(args[0], args[1])),
+    (SYSCALL_GET_TIME, |args| sys_get_time(args[0], args[1])),
+    (SYSCALL_CLOCK_GETTIME, |args| {
+        sys_clock_gettime(args[0], args[1] as *mut TimeSpec)
+    }),
+    (SYSCALL_SETTIMEOFDAY, |args| {
+        sys_settimeofday(args[0] as *const TimeVal, args[1])
+    }),
+    (SYSCALL_SET_PRIORITY, |args| {
+        sys_set_priority(args[0] as isize)
+    }),
+    (SYSCALL_TIMES, |args| sys_times(args[0] as *mut Tms)),
+    (SYSCALL_UNAME, |args| sys_uname(args[0] as *mut u8)),
+    (SYSCALL_BRK, |args| sys_brk(args[0])),
+    (SYSCALL_MMAP, |args| {
+        sys_linux_mmap(args[0], args[1], args[2], args[3])
+    }),
+    (SYSCALL_MUNMAP, |args| sys_munmap(args[0], args[1])),
+    (SYSCALL_GETPID, |_| sys_getpid()),
+    (SYSCALL_FORK, |_| sys_fork()),
+    (SYSCALL_EXEC, |args| {
+        sys_exec(
+            args[0] as *const u8,
+            args[1] as *const usize,
+            args[2] as *const usize,
+        )
+    }),
+    (SYSCALL_WAITPID, |args| {
+        sys_waitpid(
+            args[0] as isize,
+            args[1] as *mut i32,
+            args[2],
+            args[3] as *mut Rusage,
+        )
+    }),
+    (SYSCALL_SCHED_SETATTR, |args| {
+        sys_sched_setattr(args[0], args[1] as *const SchedAttr)
+    }),
+    (SYSCALL_SPAWN, |args| {
+        sys_spawn(args[0] as *const u8, args[1], args[2])
+    }),
+    (SYSCALL_MAILREAD, |args| {
+        sys_mailread(args[0] as *mut u8, args[1])
+    }),
+    (SYSCALL_MAILWRITE, |args| {
+        sys_mailwrite(args[0], args[1] as *mut u8, args[2])
+    }),
+    (SYSCALL_INIT_USER_TRAP, |args| {
+        sys_init_user_trap(args[0] as *const UserTrapDescriptor)
+    }),
+    (SYSCALL_SEND_MSG, |args| sys_send_msg(args[0], args[1])),
+    (SYSCALL_SET_TIMER, |args| sys_set_timer(args[0])),
+    (SYSCALL_CLAIM_EXT_INT, |args| sys_claim_ext_int(args[0])),
+    (SYSCALL_SET_EXT_INT_ENABLE, |args| {
+        sys_set_ext_int_enable(args[0], args[1])
+    }),
+    (SYSCALL_MMIO_MAP, |args| {
+        sys_mmio_map(args[0], args[1], args[2])
+    }),
+    (SYSCALL_DMA_ALLOC, |args| {
+        sys_dma_alloc(args[0], args[1] as *mut usize)
+    }),
+    (SYSCALL_USER_TRAP_CTL, |args| {
+        sys_user_trap_ctl(args[0], args[1], args[2])
+    }),
+    (SYSCALL_MSG_GROUP_CTL, |args| {
+        sys_msg_group_ctl(args[0], args[1], args[2])
+    }),
+    (SYSCALL_SEND_GROUP_MSG, |args| {
+        sys_send_group_msg(args[0], args[1])
+    }),
+    (SYSCALL_VM_INFO, |args| {
+        sys_vm_info(args[0] as *mut u8, args[1])
+    }),
+    (SYSCALL_TRACE_CTL, |args| {
+        sys_trace_ctl(args[0], args[1], args[2])
+    }),
+    (SYSCALL_SCHED_STATS, |args| {
+        sys_sched_stats(args[0], args[1] as *mut u8)
+    }),
+    (SYSCALL_TASK_INFO, |args| {
+        sys_task_info(args[0], args[1] as *mut u8)
+    }),
+    (SYSCALL_UIPI_INJECT, |args| {
+        sys_uipi_inject(args[0], args[1])
+    }),
+    (SYSCALL_CPU_GROUP_CTL, |args| {
+        sys_cpu_group_ctl(args[0], args[1], args[2])
+    }),
+    (SYSCALL_YIELD_TO, |args| sys_yield_to(args[0])),
+    (SYSCALL_UINTR_MASK, |args| sys_uintr_mask(args[0] != 0)),
+    (SYSCALL_UINTR_WAIT, |_| sys_uintr_wait()),
+    (SYSCALL_NATIVE_MMAP, |args| {
+        sys_mmap(args[0], args[1], args[2])
+    }),
+    (SYSCALL_DEBUG_TRANSLATE, |args| {
+        sys_debug_translate(args[0], args[1] as *mut u8, args[2])
+    }),
+    (SYSCALL_SERVICE_CTL, |args| {
+        sys_service_ctl(args[0], args[1])
+    }),
+    (SYSCALL_SCHED_POLICY, |args| sys_sched_policy(args[0])),
+    (SYSCALL_UIPI_BIND, |args| {
+        sys_uipi_bind(args[0] as *const u8, args[1])
+    }),
+    (SYSCALL_UIPI_UNBIND, |args| {
+        sys_uipi_unbind(args[0] as *const u8, args[1])
+    }),
+    (SYSCALL_UIPI_RESOLVE, |args| {
+        sys_uipi_resolve(args[0] as *const u8, args[1])
+    }),
+    (SYSCALL_CHECKPOINT, |args| sys_checkpoint(args[0], args[1])),
+    (SYSCALL_MEM_PRESSURE, |args| {
+        sys_mem_pressure(args[0], args[1])
+    }),
+    (SYSCALL_SEND_FD, |args| sys_send_fd(args[0], args[1])),
+    (SYSCALL_RECV_FD, |args| sys_recv_fd(args[0])),
+    (SYSCALL_CHILD_NOTIFY, |args| {
+        sys_child_notify(args[0], args[1])
+    }),
+];
+
+/// Indexed by syscall number, built at compile time
+static HANDLERS: [Option<Handler>; SYSCALL_NUM] = {
+    let mut handlers: [Option<Handler>; SYSCALL_NUM] = [None; SYSCALL_NUM];
+    let mut i = 0;
+    while i < HANDLER_LIST.len() {
+        let (id, handler) = HANDLER_LIST[i];
+        // numbers out of the table fail the build too, by indexing out of bounds
+        assert!(handlers[id].is_none(), "syscall with two handlers");
+        handlers[id] = Some(handler);
+        i += 1;
+    }
+    handlers
+};
 
-pub fn syscall(syscall_id: usize, args: [usize; 3]) -> isize {
+pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
     trace!("syscall {}, args {:x?}", syscall_id, args);
-    match syscall_id {
-        SYSCALL_CLOSE => sys_close(args[0]),
-        SYSCALL_PIPE => sys_pipe(args[0] as *mut usize),
-        SYSCALL_READ => sys_read(args[0], args[1] as *const u8, args[2]),
-        SYSCALL_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
-        SYSCALL_EXIT => sys_exit(args[0] as i32),
-        SYSCALL_YIELD => sys_yield(),
-        SYSCALL_GET_TIME => sys_get_time(args[0], args[1]),
-        SYSCALL_SET_PRIORITY => sys_set_priority(args[0] as isize),
-        SYSCALL_MMAP => sys_mmap(args[0], args[1], args[2]),
-        SYSCALL_MUNMAP => sys_munmap(args[0], args[1]),
-        SYSCALL_GETPID => sys_getpid(),
-        SYSCALL_FORK => sys_fork(),
-        SYSCALL_EXEC => sys_exec(args[0] as *const u8),
-        SYSCALL_WAITPID => sys_waitpid(args[0] as isize, args[1] as *mut i32),
-        SYSCALL_SPAWN => sys_spawn(args[0] as *const u8),
-        SYSCALL_MAILREAD => sys_mailread(args[0] as *mut u8, args[1]),
-        SYSCALL_MAILWRITE => sys_mailwrite(args[0], args[1] as *mut u8, args[2]),
-        SYSCALL_INIT_USER_TRAP => sys_init_user_trap(),
-        SYSCALL_SEND_MSG => sys_send_msg(args[0], args[1]),
-        SYSCALL_SET_TIMER => sys_set_timer(args[0]),
-        SYSCALL_CLAIM_EXT_INT => sys_claim_ext_int(args[0]),
-        SYSCALL_SET_EXT_INT_ENABLE => sys_set_ext_int_enable(args[0], args[1]),
-        _ => panic!("Unsupported syscall_id: {}", syscall_id),
+    if DETERMINISTIC {
+        deterministic::advance(VIRTUAL_SYSCALL_US);
     }
+    let ret = match HANDLERS.get(syscall_id).copied().flatten() {
+        Some(handler) => handler(args),
+        None => {
+            warn!("Unsupported syscall_id: {}", syscall_id);
+            ENOSYS
+        }
+    };
+    trace::trace_syscall(syscall_id, args, Some(ret));
+    ret
 }
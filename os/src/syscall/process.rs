@@ -1,30 +1,136 @@
 use core::mem::size_of;
 
-use crate::config::CPU_NUM;
+use crate::build_info::{self, Utsname};
+use crate::config::{kernel_config, CPU_NUM, DETERMINISTIC};
+use crate::console::ANSICON;
+use crate::deterministic::{self, VIRTUAL_YIELD_US};
 use crate::loader::get_app_data_by_name;
-use crate::mm;
+use crate::mm::alloc_track::{AllocScope, SUBSYSTEM_USER_TRAP};
+use crate::mm::pressure::{self, MEM_PRESSURE_FD, MEM_PRESSURE_UIPI};
+use crate::mm::{self, PteInfo, VirtAddr, VmAreaInfo};
 use crate::plic::{get_context, Plic};
+use crate::service;
 use crate::task::{
-    add_task, current_task, current_user_token, exit_current_and_run_next, hart_id, mmap, munmap,
-    set_current_priority, suspend_current_and_run_next, WAIT_LOCK,
+    add_task, continue_task, cpu_group_stats, create_group, current_task, current_user_token,
+    drop_checkpoint, exit_current_and_run_next, find_task, hart_id, mmap, munmap, post_uipi_event,
+    prioritize_task, ptrace, restore_checkpoint, save_checkpoint, sched_policy, sched_stats,
+    set_current_priority, set_deadline_params, set_period, set_quota, set_sched_policy, stop_task,
+    suspend_current_and_run_next, CpuGroupStats, DeadlineParams, ExitReason, ExitStatus, Rusage,
+    SchedAttr, SchedPolicy, SchedStats, TaskControlBlock, TaskInfo, Tms, UipiEvent, UipiEventSink,
+    SCHED_DEADLINE, SCHED_NORMAL, SPAWN_NOTIFY_PARENT, WAIT_LOCK,
+};
+use crate::trap::{
+    bind_uipi_name, join_msg_group, leave_all_msg_groups, leave_msg_group, push_group_trap_record,
+    push_trap_record, resolve_uipi_name, unbind_all_uipi_names, unbind_uipi_name,
+    UserTrapDescriptor, UserTrapError, UserTrapRecord, MAX_UIPI_NAME_LEN, USER_TRAP_CTL_RELEASE,
+    USER_TRAP_CTL_SET_COALESCE, USER_TRAP_CTL_SET_HANDLER_BUDGET, USER_TRAP_CTL_SET_SEND_RATE,
 };
-use crate::trap::{push_trap_record, UserTrapRecord};
 
-use crate::timer::get_time;
+use super::trace::{SyscallTrace, TRACE_TO_LOG};
+use super::{
+    EAGAIN, EBADF, EBUSY, ECHILD, EFAULT, EINVAL, ENODEV, ENOENT, ENOMEM, ENOTCONN, EPERM, ESRCH,
+};
+use crate::drivers::rtc;
+use crate::timer::{
+    get_time, get_time_us, get_user_time_ns, ticks_to_us, TimeSpec, TimeVal, CLOCK_MONOTONIC,
+    CLOCK_REALTIME, NSEC_PER_SEC,
+};
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use riscv::register::{uip, uscratch};
+
+/// `sys_debug_translate` flag: print the whole page table of the caller to the console
+pub const DEBUG_TRANSLATE_DUMP: usize = 1;
+
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+/// `sys_waitpid` option: also report children which have stopped
+pub const WUNTRACED: usize = 2;
+/// `sys_waitpid` option: the status is an `ExitStatus`, the code followed by the reason
+pub const WEXITSTATUS_EXT: usize = 4;
 
 pub fn sys_exit(exit_code: i32) -> ! {
-    exit_current_and_run_next(exit_code);
+    exit_current_and_run_next(ExitStatus::exited(exit_code));
     panic!("Unreachable in sys_exit!");
 }
 
 pub fn sys_yield() -> isize {
     trace!("sys_yield");
+    if DETERMINISTIC {
+        deterministic::advance(VIRTUAL_YIELD_US);
+    }
     suspend_current_and_run_next();
     0
 }
 
+/// Yield with a hint to run task `pid` next, by putting it at the front of its ready queue.
+/// Only a hint: another hart or a task of a higher class may still come first.
+/// Return 0 if the target was dispatched before the caller ran again, 1 if it is not ready
+/// and this was a plain yield, 2 if it was ready but did not run in the meantime.
+pub fn sys_yield_to(pid: usize) -> isize {
+    trace!("sys_yield_to {}", pid);
+    let target = match current_task().unwrap().find_visible_task(pid) {
+        Some(target) => target,
+        None => return ESRCH,
+    };
+    let is_ready = prioritize_task(target.getpid());
+    let yielded_us = get_time_us();
+    if DETERMINISTIC {
+        deterministic::advance(VIRTUAL_YIELD_US);
+    }
+    suspend_current_and_run_next();
+    if !is_ready {
+        1
+    } else if target.acquire_inner_lock().dispatched_us >= yielded_us {
+        0
+    } else {
+        2
+    }
+}
+
+/// Only SIGSTOP and SIGCONT are supported
+pub fn sys_kill(pid: usize, signal: usize) -> isize {
+    trace!("sys_kill pid: {}, signal: {}", pid, signal);
+    let current_task = current_task().unwrap();
+    let task = match current_task.find_visible_task(pid) {
+        Some(task) => task,
+        None => return ESRCH,
+    };
+    let is_current = Arc::ptr_eq(&task, &current_task);
+    if !may_control(&current_task, &task) {
+        return EPERM;
+    }
+    drop(current_task);
+    let res = match signal {
+        SIGSTOP => stop_task(task),
+        SIGCONT => continue_task(task),
+        _ => Err(EINVAL),
+    };
+    match res {
+        Ok(()) => {
+            if is_current && signal == SIGSTOP {
+                suspend_current_and_run_next();
+            }
+            0
+        }
+        Err(err) => err,
+    }
+}
+
+pub fn sys_ptrace(request: usize, pid: usize, arg: usize) -> isize {
+    trace!(
+        "sys_ptrace request: {}, pid: {}, arg: {:#x}",
+        request,
+        pid,
+        arg
+    );
+    match ptrace(request, pid, arg) {
+        Ok(res) => res,
+        Err(err) => err,
+    }
+}
+
 pub fn sys_set_priority(prio: isize) -> isize {
     match set_current_priority(prio) {
         Ok(prio) => prio,
@@ -32,37 +138,554 @@ pub fn sys_set_priority(prio: isize) -> isize {
     }
 }
 
+/// Move task `pid`, 0 for the caller, into or out of the deadline class.
+/// Only `SCHED_NORMAL` and `SCHED_DEADLINE` are supported. A task may only change itself
+/// and its descendants, and only a privileged one may raise a reservation.
+pub fn sys_sched_setattr(pid: usize, attr: *const SchedAttr) -> isize {
+    let current_task = current_task().unwrap();
+    let task = match pid {
+        0 => current_task.clone(),
+        pid => match current_task.find_visible_task(pid) {
+            Some(task) => task,
+            None => return ESRCH,
+        },
+    };
+    if !may_control(&current_task, &task) {
+        return EPERM;
+    }
+    let may_raise = is_privileged(&current_task);
+    drop(current_task);
+    let mut sched_attr = SchedAttr::default();
+    let bytes = unsafe {
+        core::slice::from_raw_parts_mut(
+            &mut sched_attr as *mut _ as *mut u8,
+            size_of::<SchedAttr>(),
+        )
+    };
+    if mm::copy_from_user(current_user_token(), attr as *const u8, bytes).is_err() {
+        return EFAULT;
+    }
+    let params = match sched_attr.sched_policy {
+        SCHED_NORMAL => None,
+        SCHED_DEADLINE => match DeadlineParams::from_attr(&sched_attr) {
+            Ok(params) => Some(params),
+            Err(e) => return e,
+        },
+        _ => return EINVAL,
+    };
+    match set_deadline_params(&task, params, may_raise) {
+        Ok(()) => 0,
+        Err(e) => e,
+    }
+}
+
+/// Harts which run tasks
+fn online_harts() -> usize {
+    if DETERMINISTIC {
+        1 << deterministic::DETERMINISTIC_HART
+    } else {
+        (1 << kernel_config().hart_num) - 1
+    }
+}
+
+/// Let task `pid`, 0 for the caller, only run on the harts of the mask at `mask`,
+/// one bit per hart id. Only the first `usize` of a longer mask is read.
+/// A task may only pin itself and its descendants.
+pub fn sys_sched_setaffinity(pid: usize, len: usize, mask: *const usize) -> isize {
+    let current_task = current_task().unwrap();
+    let task = match pid {
+        0 => current_task.clone(),
+        pid => match current_task.find_visible_task(pid) {
+            Some(task) => task,
+            None => return ESRCH,
+        },
+    };
+    if !may_control(&current_task, &task) {
+        return EPERM;
+    }
+    let mut bytes = [0u8; size_of::<usize>()];
+    if len < bytes.len() {
+        return EINVAL;
+    }
+    if mm::copy_from_user(current_user_token(), mask as *const u8, &mut bytes).is_err() {
+        return EFAULT;
+    }
+    let affinity = usize::from_ne_bytes(bytes) & online_harts();
+    if affinity == 0 {
+        return EINVAL;
+    }
+    task.acquire_inner_lock().cpu_affinity = affinity;
+    // leave a hart the caller may no longer run on, other tasks move at their next switch
+    if Arc::ptr_eq(&task, &current_task) && affinity & 1 << hart_id() == 0 {
+        drop(task);
+        drop(current_task);
+        suspend_current_and_run_next();
+    }
+    0
+}
+
+/// Write the affinity mask of task `pid`, 0 for the caller, return its size in bytes
+pub fn sys_sched_getaffinity(pid: usize, len: usize, mask: *mut usize) -> isize {
+    let current_task = current_task().unwrap();
+    let task = match pid {
+        0 => current_task,
+        pid => match current_task.find_visible_task(pid) {
+            Some(task) => task,
+            None => return ESRCH,
+        },
+    };
+    let bytes = (task.acquire_inner_lock().cpu_affinity & online_harts()).to_ne_bytes();
+    if len < bytes.len() {
+        return EINVAL;
+    }
+    if mm::copy_to_user(current_user_token(), mask as *mut u8, &bytes).is_err() {
+        return EFAULT;
+    }
+    bytes.len() as isize
+}
+
 pub fn sys_get_time(time: usize, tz: usize) -> isize {
+    if mm::check_user_range(time, 2 * size_of::<usize>()).is_err() {
+        return EFAULT;
+    }
     let token = current_user_token();
     let mut pas: Vec<*mut usize> = Vec::new();
     match mm::translate_writable_va(token, time) {
-        Err(_) => return -1,
+        Err(errno) => return errno,
         Ok(pa) => pas.push(pa as *mut usize),
     }
     match mm::translate_writable_va(token, time + size_of::<usize>()) {
-        Err(_) => return -1,
+        Err(errno) => return errno,
         Ok(pa) => pas.push(pa as *mut usize),
     }
     get_time(pas, tz)
 }
 
+pub fn sys_clock_gettime(clock_id: usize, tp: *mut TimeSpec) -> isize {
+    let now = match clock_id {
+        CLOCK_REALTIME => rtc::realtime_ns(),
+        CLOCK_MONOTONIC => get_user_time_ns(),
+        _ => return EINVAL,
+    };
+    let time = TimeSpec {
+        sec: now / NSEC_PER_SEC,
+        nsec: now % NSEC_PER_SEC,
+    };
+    match mm::copy_value_to_user(current_user_token(), tp, &time) {
+        Ok(()) => 0,
+        Err(_) => EFAULT,
+    }
+}
+
+/// Only a privileged task may set the wall clock, see `is_privileged`. The timezone is ignored.
+/// `EINVAL` if `usec` is not below a second or the time does not fit in nanoseconds.
+pub fn sys_settimeofday(tv: *const TimeVal, _tz: usize) -> isize {
+    if !is_privileged(&current_task().unwrap()) {
+        return EPERM;
+    }
+    let mut time = TimeVal::new();
+    if mm::copy_value_from_user(current_user_token(), tv, &mut time).is_err() {
+        return EFAULT;
+    }
+    if time.usec >= 1_000_000 {
+        return EINVAL;
+    }
+    match time
+        .sec
+        .checked_mul(NSEC_PER_SEC)
+        .and_then(|ns| ns.checked_add(time.usec * 1000))
+    {
+        Some(ns) => {
+            rtc::set_realtime_ns(ns);
+            0
+        }
+        None => EINVAL,
+    }
+}
+
+/// A task asking for the frames the kernel keeps for itself gets `ENOMEM`, the frames
+/// of `OOM_RESERVE_FRAMES` are never handed out to user space
 pub fn sys_mmap(start: usize, len: usize, port: usize) -> isize {
-    mmap(start, len, port).unwrap_or(-1)
+    match mmap(start, len, port) {
+        Ok(ret) => ret,
+        Err(ENOMEM) => {
+            debug!(
+                "[syscall mmap] pid {} asked for {:#x} bytes, {} frames left",
+                current_task().unwrap().getpid(),
+                len,
+                mm::frames_available()
+            );
+            ENOMEM
+        }
+        Err(errno) => errno,
+    }
 }
 
 pub fn sys_munmap(start: usize, len: usize) -> isize {
-    munmap(start, len).unwrap_or(-1)
+    match munmap(start, len) {
+        Ok(ret) => ret,
+        Err(errno) => errno,
+    }
+}
+
+/// Ask to be told once fewer than `watermark` frames are free, see `mm::pressure`.
+/// Return the fd of the event file with `MEM_PRESSURE_FD`, 0 otherwise. A watermark of 0
+/// stops the notifications.
+pub fn sys_mem_pressure(watermark: usize, flags: usize) -> isize {
+    let current_task = current_task().unwrap();
+    if watermark == 0 {
+        pressure::unregister(current_task.getpid());
+        return 0;
+    }
+    if flags == 0 || flags & !(MEM_PRESSURE_UIPI | MEM_PRESSURE_FD) != 0 {
+        return EINVAL;
+    }
+    if flags & MEM_PRESSURE_UIPI != 0 && current_task.acquire_inner_lock().user_trap_info.is_none()
+    {
+        return ENOTCONN;
+    }
+    match pressure::register(current_task.getpid(), watermark, flags) {
+        Some(event) => current_task.acquire_inner_lock().fd_table.insert(event, 0) as isize,
+        None => 0,
+    }
+}
+
+/// Tell the task `receiver`, usually the caller itself, of the exits of the children of the
+/// caller by a user interrupt, see `CHILD_EXIT_CAUSE`. A receiver of 0 stops the
+/// notifications. Another task must have accepted them first, by a call with
+/// `CHILD_NOTIFY_ACCEPT` naming the caller, 0 to accept from nobody.
+pub fn sys_child_notify(pid: usize, flags: usize) -> isize {
+    const CHILD_NOTIFY_ACCEPT: usize = 1;
+    let current_task = current_task().unwrap();
+    if flags & !CHILD_NOTIFY_ACCEPT != 0 {
+        return EINVAL;
+    }
+    if pid == 0 {
+        let mut inner = current_task.acquire_inner_lock();
+        if flags & CHILD_NOTIFY_ACCEPT != 0 {
+            inner.child_notify_accept = None;
+        } else {
+            inner.child_notify = None;
+        }
+        return 0;
+    }
+    let task = match current_task.find_visible_task(pid) {
+        Some(task) => task,
+        None => return ESRCH,
+    };
+    if flags & CHILD_NOTIFY_ACCEPT != 0 {
+        current_task.acquire_inner_lock().child_notify_accept = Some(Arc::downgrade(&task));
+        return 0;
+    }
+    // the inner locks one after the other, the receiver may be the caller itself
+    let receiver_inner = task.acquire_inner_lock();
+    let accepted = Arc::ptr_eq(&task, &current_task)
+        || receiver_inner
+            .child_notify_accept
+            .as_ref()
+            .and_then(|accept| accept.upgrade())
+            .map_or(false, |accept| Arc::ptr_eq(&accept, &current_task));
+    if !accepted {
+        return EPERM;
+    }
+    if receiver_inner.user_trap_info.is_none() {
+        return ENOTCONN;
+    }
+    drop(receiver_inner);
+    current_task.acquire_inner_lock().child_notify = Some(Arc::downgrade(&task));
+    0
+}
+
+/// Fill `buf` with as many `VmAreaInfo` of the current process as fit in `len` bytes,
+/// return the total number of areas.
+pub fn sys_vm_info(buf: *mut u8, len: usize) -> isize {
+    let current_task = current_task().unwrap();
+    let inner = current_task.acquire_inner_lock();
+    let info = inner.memory_set.vm_info();
+    let count = info.len().min(len / size_of::<VmAreaInfo>());
+    let bytes = unsafe {
+        core::slice::from_raw_parts(info.as_ptr() as *const u8, count * size_of::<VmAreaInfo>())
+    };
+    match mm::copy_to_user(inner.get_user_token(), buf, bytes) {
+        Ok(()) => info.len() as isize,
+        Err(errno) => errno,
+    }
+}
+
+/// Walk the page table of the current process for `vaddr` and write what was found as a
+/// `PteInfo` to `info`, with `DEBUG_TRANSLATE_DUMP` in `flags` also print the whole table.
+/// Return 0 if `vaddr` is mapped, `ENOENT` if not.
+pub fn sys_debug_translate(vaddr: usize, info: *mut u8, flags: usize) -> isize {
+    let current_task = current_task().unwrap();
+    let inner = current_task.acquire_inner_lock();
+    let pte_info = inner.memory_set.walk(VirtAddr::from(vaddr));
+    if flags & DEBUG_TRANSLATE_DUMP != 0 {
+        let mut dump = String::new();
+        let _ = inner.memory_set.dump_page_table(&mut dump);
+        print_colorized!(
+            "[pid {}] {}",
+            ANSICON::FgDefault,
+            ANSICON::BgDefault,
+            current_task.getpid(),
+            dump
+        );
+    }
+    let bytes = unsafe {
+        core::slice::from_raw_parts(&pte_info as *const _ as *const u8, size_of::<PteInfo>())
+    };
+    if let Err(errno) = mm::copy_to_user(inner.get_user_token(), info, bytes) {
+        return errno;
+    }
+    if pte_info.is_mapped() {
+        0
+    } else {
+        ENOENT
+    }
+}
+
+/// Trace the syscalls of the current task or one of its children whose class is in `mask`,
+/// writing a line per syscall to `fd` or the kernel log if `fd` is `TRACE_TO_LOG`.
+/// A zero `mask` stops tracing.
+pub fn sys_trace_ctl(pid: usize, mask: usize, fd: usize) -> isize {
+    let current_task = current_task().unwrap();
+    let inner = current_task.acquire_inner_lock();
+    let target = if pid == current_task.ns_pid() {
+        current_task.clone()
+    } else if let Some(child) = inner
+        .children
+        .iter()
+        .find(|child| current_task.vpid_of(child) == Some(pid))
+    {
+        child.clone()
+    } else {
+        return ESRCH;
+    };
+    let output = if mask == 0 || fd == TRACE_TO_LOG {
+        None
+    } else if let Some(file) = inner.fd_table.get(fd) {
+        Some(file.clone())
+    } else {
+        return EBADF;
+    };
+    drop(inner);
+    let mut target_inner = target.acquire_inner_lock();
+    target_inner.syscall_trace = if mask == 0 {
+        None
+    } else {
+        Some(SyscallTrace::new(mask, output))
+    };
+    0
+}
+
+/// Copy the scheduler statistics of a hart into `buf`, which must hold a `SchedStats`
+pub fn sys_sched_stats(hart_id: usize, buf: *mut u8) -> isize {
+    let stats = match sched_stats(hart_id) {
+        Some(stats) => stats,
+        None => return EINVAL,
+    };
+    let bytes = unsafe {
+        core::slice::from_raw_parts(&stats as *const _ as *const u8, size_of::<SchedStats>())
+    };
+    match mm::copy_to_user(current_user_token(), buf, bytes) {
+        Ok(()) => 0,
+        Err(errno) => errno,
+    }
+}
+
+/// Copy the CPU time accounting of task `pid` (0 for the caller) into `buf`,
+/// which must hold a `TaskInfo`
+pub fn sys_task_info(pid: usize, buf: *mut u8) -> isize {
+    let task = if pid == 0 {
+        current_task().unwrap()
+    } else {
+        match current_task().unwrap().find_visible_task(pid) {
+            Some(task) => task,
+            None => return ESRCH,
+        }
+    };
+    let info = task.task_info();
+    let bytes = unsafe {
+        core::slice::from_raw_parts(&info as *const _ as *const u8, size_of::<TaskInfo>())
+    };
+    match mm::copy_to_user(current_user_token(), buf, bytes) {
+        Ok(()) => 0,
+        Err(errno) => errno,
+    }
+}
+
+/// Control CPU bandwidth groups, group 0 is the unlimited default one.
+/// `arg` is the quota or period in us, the pid to attach, or where to copy `CpuGroupStats`.
+/// Only a privileged task may set the bandwidth of a group, or move tasks out of their group
+/// and into new ones. Others may only take their descendants into their own group, see
+/// `may_control`. Every group is listed in `/proc/cpu_groups`.
+pub fn sys_cpu_group_ctl(cmd: usize, group: usize, arg: usize) -> isize {
+    const CPU_GROUP_SET_QUOTA: usize = 0;
+    const CPU_GROUP_SET_PERIOD: usize = 1;
+    const CPU_GROUP_ATTACH: usize = 2;
+    const CPU_GROUP_STATS: usize = 3;
+    let current_task = current_task().unwrap();
+    if (cmd == CPU_GROUP_SET_QUOTA || cmd == CPU_GROUP_SET_PERIOD) && !is_privileged(&current_task)
+    {
+        return EPERM;
+    }
+    let res = match cmd {
+        CPU_GROUP_SET_QUOTA => set_quota(group, arg),
+        CPU_GROUP_SET_PERIOD => set_period(group, arg),
+        CPU_GROUP_ATTACH => match current_task.find_visible_task(arg) {
+            Some(task) if !may_control(&current_task, &task) => Err(EPERM),
+            Some(_)
+                if !is_privileged(&current_task)
+                    && group != current_task.acquire_inner_lock().cpu_group =>
+            {
+                Err(EPERM)
+            }
+            Some(task) => {
+                create_group(group);
+                task.acquire_inner_lock().cpu_group = group;
+                Ok(())
+            }
+            None => Err(ESRCH),
+        },
+        CPU_GROUP_STATS => match cpu_group_stats(group) {
+            Some(stats) => {
+                let bytes = unsafe {
+                    core::slice::from_raw_parts(
+                        &stats as *const _ as *const u8,
+                        size_of::<CpuGroupStats>(),
+                    )
+                };
+                mm::copy_to_user(current_user_token(), arg as *mut u8, bytes)
+            }
+            None => Err(ENOENT),
+        },
+        _ => Err(EINVAL),
+    };
+    match res {
+        Ok(()) => 0,
+        Err(e) => e,
+    }
+}
+
+/// Only initproc and the commands it runs, outside any pid namespace, may change the kernel,
+/// see `TaskControlBlock::privileged`
+fn is_privileged(task: &Arc<TaskControlBlock>) -> bool {
+    task.privileged
+}
+
+/// A task may stop, reschedule or move itself and its descendants, a privileged one any task
+fn may_control(current: &Arc<TaskControlBlock>, task: &Arc<TaskControlBlock>) -> bool {
+    if is_privileged(current) {
+        return true;
+    }
+    // one inner lock at a time, up the parent chain of `task`
+    let mut ancestor = Some(task.clone());
+    while let Some(task) = ancestor {
+        if Arc::ptr_eq(&task, current) {
+            return true;
+        }
+        ancestor = task
+            .acquire_inner_lock()
+            .parent
+            .as_ref()
+            .and_then(|parent| parent.upgrade());
+    }
+    false
+}
+
+/// Load the service named by the string at `arg` and return its slot, or unload the one in
+/// slot `arg`
+pub fn sys_service_ctl(cmd: usize, arg: usize) -> isize {
+    const SERVICE_LOAD: usize = 0;
+    const SERVICE_UNLOAD: usize = 1;
+    if !is_privileged(&current_task().unwrap()) {
+        return EPERM;
+    }
+    let res = match cmd {
+        SERVICE_LOAD => match mm::translated_str(current_user_token(), arg as *const u8) {
+            Ok(name) => service::load(name.as_str()),
+            Err(errno) => return errno,
+        },
+        SERVICE_UNLOAD => service::unload(arg).map(|()| 0),
+        _ => return EINVAL,
+    };
+    match res {
+        Ok(slot) => slot as isize,
+        Err(e) => e.errno(),
+    }
+}
+
+/// Switch the scheduling policy, 0 for round-robin, 1 for priority and 2 for EDF, while no
+/// other task is ready. Return the previous policy, or just the current one for `usize::MAX`.
+pub fn sys_sched_policy(policy: usize) -> isize {
+    if policy == usize::MAX {
+        return sched_policy() as isize;
+    }
+    if !is_privileged(&current_task().unwrap()) {
+        return EPERM;
+    }
+    let policy = match SchedPolicy::from_usize(policy) {
+        Some(policy) => policy,
+        None => return EINVAL,
+    };
+    match set_sched_policy(policy) {
+        Ok(old) => old as isize,
+        Err(errno) => errno,
+    }
+}
+
+/// Checkpoint the task `arg` and return the id of the checkpoint, restore checkpoint `arg`
+/// as a new child and return its pid, or drop checkpoint `arg`
+pub fn sys_checkpoint(cmd: usize, arg: usize) -> isize {
+    const CHECKPOINT_SAVE: usize = 0;
+    const CHECKPOINT_RESTORE: usize = 1;
+    const CHECKPOINT_DROP: usize = 2;
+    let current_task = current_task().unwrap();
+    if !is_privileged(&current_task) {
+        return EPERM;
+    }
+    let res = match cmd {
+        CHECKPOINT_SAVE => match current_task.find_visible_task(arg) {
+            Some(task) if Arc::ptr_eq(&task, &current_task) => return EINVAL,
+            Some(task) => save_checkpoint(task),
+            None => return ESRCH,
+        },
+        CHECKPOINT_RESTORE => restore_checkpoint(arg, &current_task).map(|task| {
+            let pid = current_task.vpid_of(&task).unwrap();
+            add_task(task);
+            pid
+        }),
+        CHECKPOINT_DROP => drop_checkpoint(arg).map(|()| 0),
+        _ => return EINVAL,
+    };
+    match res {
+        Ok(ret) => ret as isize,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_uname(buf: *mut u8) -> isize {
+    let utsname = build_info::utsname();
+    let bytes = unsafe {
+        core::slice::from_raw_parts(&utsname as *const _ as *const u8, size_of::<Utsname>())
+    };
+    match mm::copy_to_user(current_user_token(), buf, bytes) {
+        Ok(()) => 0,
+        Err(errno) => errno,
+    }
 }
 
 pub fn sys_getpid() -> isize {
-    current_task().unwrap().pid.0 as isize
+    current_task().unwrap().ns_pid() as isize
 }
 
 pub fn sys_fork() -> isize {
     debug!("Fork start");
     let current_task = current_task().unwrap();
-    let new_task = current_task.fork();
-    let new_pid = new_task.pid.0;
+    let new_task = match current_task.fork() {
+        Ok(new_task) => new_task,
+        Err(errno) => return errno,
+    };
+    let new_pid = current_task.vpid_of(&new_task).unwrap();
     // modify trap context of new_task, because it returns immediately after switching
     let trap_cx = new_task.acquire_inner_lock().get_trap_cx();
     // we do not have to move to next instruction since we have done it before
@@ -74,23 +697,65 @@ pub fn sys_fork() -> isize {
     new_pid as isize
 }
 
-pub fn sys_exec(path: *const u8) -> isize {
+/// Strings of a null-terminated array of pointers, a null array is empty
+fn translated_str_array(token: usize, mut ptr: *const usize) -> Result<Vec<String>, isize> {
+    let mut strings = Vec::new();
+    if ptr.is_null() {
+        return Ok(strings);
+    }
+    loop {
+        let mut str_ptr = 0;
+        mm::copy_value_from_user(token, ptr, &mut str_ptr)?;
+        if str_ptr == 0 {
+            break;
+        }
+        strings.push(mm::translated_str(token, str_ptr as *const u8)?);
+        ptr = unsafe { ptr.add(1) };
+    }
+    Ok(strings)
+}
+
+/// `args` and `envs` are null-terminated arrays of strings, either may be null
+pub fn sys_exec(path: *const u8, args: *const usize, envs: *const usize) -> isize {
     let token = current_user_token();
-    let path = mm::translated_str(token, path);
-    debug!("EXEC {}", &path);
+    let path = match mm::translated_str(token, path) {
+        Ok(path) => path,
+        Err(errno) => return errno,
+    };
+    let (args, envs) = match (
+        translated_str_array(token, args),
+        translated_str_array(token, envs),
+    ) {
+        (Ok(args), Ok(envs)) => (args, envs),
+        _ => return EFAULT,
+    };
+    debug!("EXEC {} {:?}", &path, &args);
     if let Some(data) = get_app_data_by_name(path.as_str()) {
         let task = current_task().unwrap();
-        task.exec(data);
-        0
+        match task.exec(data, &args, &envs) {
+            Ok(()) => 0,
+            Err(err) => {
+                warn!("exec failed!");
+                err
+            }
+        }
     } else {
         warn!("exec failed!");
-        -1
+        ENOENT
     }
 }
 
-/// If there is not a child process whose pid is same as given, return -1.
-/// Else if there is a child process but it is still running, return -2.
-pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
+/// If there is not a child process whose pid is same as given, return `ECHILD`.
+/// Else if there is a child process but it is still running, return `EAGAIN`.
+/// With `WUNTRACED`, a newly stopped child is reported once with status `(SIGSTOP << 8) | 0x7f`.
+/// With `WEXITSTATUS_EXT`, `exit_code_ptr` points to an `ExitStatus` instead of an `i32`.
+/// Like wait4, `rusage` is filled with the usage of the child found unless it is null
+pub fn sys_waitpid(
+    pid: isize,
+    exit_code_ptr: *mut i32,
+    options: usize,
+    rusage: *mut Rusage,
+) -> isize {
     trace!("sys_waitpid {}", pid);
     let task = current_task().unwrap();
     // find a child process
@@ -101,83 +766,484 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
     if inner
         .children
         .iter()
-        .find(|p| pid == -1 || pid as usize == p.getpid())
+        .find(|p| pid == -1 || Some(pid as usize) == task.vpid_of(p))
         .is_none()
     {
-        return -1;
+        return ECHILD;
         // ---- release current PCB lock
     }
     let pair = inner.children.iter().enumerate().find(|(_, p)| {
         // ++++ temporarily hold child PCB lock
-        p.acquire_inner_lock().is_zombie() && (pid == -1 || pid as usize == p.getpid())
+        p.acquire_inner_lock().is_zombie() && (pid == -1 || Some(pid as usize) == task.vpid_of(p))
         // ++++ release child PCB lock
     });
     if let Some((idx, _)) = pair {
-        let child = inner.children.remove(idx);
-        // confirm that child will be deallocated after removing from children list
-        // assert_eq!(Arc::strong_count(&child), 1);
-        let found_pid = child.getpid();
+        let child = inner.children[idx].clone();
+        let found_pid = task.vpid_of(&child).unwrap();
         // ++++ temporarily hold child lock
-        let exit_code = child.acquire_inner_lock().exit_code;
+        let child_inner = child.acquire_inner_lock();
+        // the child is only reaped once the caller got its status
+        let token = inner.memory_set.token();
+        if write_exit_status(token, exit_code_ptr, options, child_inner.exit_status).is_err()
+            || write_rusage(token, rusage, &child_inner.rusage()).is_err()
+        {
+            return EFAULT;
+        }
+        inner.children.remove(idx);
+        // like rusage(RUSAGE_CHILDREN), reaped children are charged to the parent
+        inner.children_cpu_times.add(&child_inner.cpu_times);
+        inner
+            .children_cpu_times
+            .add(&child_inner.children_cpu_times);
+        inner.children_usage.add(&child_inner.usage);
+        inner.children_usage.add(&child_inner.children_usage);
+        drop(child_inner);
         // ++++ release child PCB lock
-        *mm::translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
         found_pid as isize
+    } else if options & WUNTRACED != 0 {
+        let stopped_child = inner.children.iter().find(|p| {
+            if pid != -1 && Some(pid as usize) != task.vpid_of(p) {
+                return false;
+            }
+            let child_inner = p.acquire_inner_lock();
+            child_inner.is_stopped() && !child_inner.is_stop_reported
+        });
+        if let Some(child) = stopped_child {
+            let found_pid = task.vpid_of(child).unwrap();
+            let mut child_inner = child.acquire_inner_lock();
+            let token = inner.memory_set.token();
+            let status = ExitStatus::killed(ExitReason::Stopped);
+            if write_exit_status(token, exit_code_ptr, options, status).is_err()
+                || write_rusage(token, rusage, &child_inner.rusage()).is_err()
+            {
+                return EFAULT;
+            }
+            // reported once the caller got it
+            child_inner.is_stop_reported = true;
+            found_pid as isize
+        } else {
+            EAGAIN
+        }
     } else {
-        -2
+        EAGAIN
     }
     // ---- release current PCB lock automatically
 }
 
-pub fn sys_spawn(file: *const u8) -> isize {
+fn write_exit_status(
+    token: usize,
+    ptr: *mut i32,
+    options: usize,
+    status: ExitStatus,
+) -> Result<(), ()> {
+    if ptr.is_null() {
+        return Ok(());
+    }
+    if options & WEXITSTATUS_EXT != 0 {
+        mm::copy_value_to_user(token, ptr as *mut ExitStatus, &status)
+    } else {
+        mm::copy_value_to_user(token, ptr, &status.code)
+    }
+    .map_err(|_| ())
+}
+
+fn write_rusage(token: usize, ptr: *mut Rusage, rusage: &Rusage) -> Result<(), ()> {
+    if ptr.is_null() {
+        return Ok(());
+    }
+    let bytes = unsafe {
+        core::slice::from_raw_parts(rusage as *const _ as *const u8, size_of::<Rusage>())
+    };
+    mm::copy_to_user(token, ptr as *mut u8, bytes).map_err(|_| ())
+}
+
+/// Fill `tms` with the CPU time of the caller and of its reaped children,
+/// return the time since boot, all in microseconds
+pub fn sys_times(tms: *mut Tms) -> isize {
+    let task = current_task().unwrap();
+    let inner = task.acquire_inner_lock();
+    let info = Tms {
+        utime_us: ticks_to_us(inner.cpu_times.utime),
+        stime_us: ticks_to_us(inner.cpu_times.stime + inner.cpu_times.irqtime),
+        children_utime_us: ticks_to_us(inner.children_cpu_times.utime),
+        children_stime_us: ticks_to_us(
+            inner.children_cpu_times.stime + inner.children_cpu_times.irqtime,
+        ),
+    };
+    let token = inner.get_user_token();
+    drop(inner);
+    let bytes =
+        unsafe { core::slice::from_raw_parts(&info as *const _ as *const u8, size_of::<Tms>()) };
+    match mm::copy_to_user(token, tms as *mut u8, bytes) {
+        Ok(()) => get_time_us() as isize,
+        Err(errno) => errno,
+    }
+}
+
+/// With `SPAWN_NOTIFY_PARENT`, the UIPI events of the child are written to `events_fd`,
+/// usually the write end of a pipe
+pub fn sys_spawn(file: *const u8, flags: usize, events_fd: usize) -> isize {
     trace!("SPAWN start");
     let current_task = current_task().unwrap();
-    match current_task.spawn(file) {
+    let events = if flags & SPAWN_NOTIFY_PARENT != 0 {
+        match current_task.acquire_inner_lock().fd_table.get(events_fd) {
+            Some(file) => Some(file.clone()),
+            _ => return EBADF,
+        }
+    } else {
+        None
+    };
+    match current_task.spawn(file, flags) {
         Ok(new_task) => {
-            let new_pid = new_task.pid.0;
+            let new_pid = current_task.vpid_of(&new_task).unwrap();
+            if let Some(output) = events {
+                new_task.acquire_inner_lock().uipi_events = Some(UipiEventSink {
+                    vpid: new_pid,
+                    output,
+                    dropped: 0,
+                });
+            }
             add_task(new_task);
             debug!("new_task via spawn {:?}", new_pid);
             new_pid as isize
         }
-        Err(_) => {
+        Err(errno) => {
             warn!("spawn failed!");
-            -1
+            errno
         }
     }
 }
 
-pub fn sys_init_user_trap() -> isize {
+/// `descriptor` points to a `UserTrapDescriptor`, or is null to keep the entry in utvec
+pub fn sys_init_user_trap(descriptor: *const UserTrapDescriptor) -> isize {
     trace!("init user trap!");
+    let descriptor = if descriptor.is_null() {
+        None
+    } else {
+        let mut buf = UserTrapDescriptor::default();
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(
+                &mut buf as *mut _ as *mut u8,
+                size_of::<UserTrapDescriptor>(),
+            )
+        };
+        if let Err(errno) = mm::copy_from_user(current_user_token(), descriptor as *const u8, bytes)
+        {
+            return errno;
+        }
+        Some(buf)
+    };
+    let _scope = AllocScope::subsystem(SUBSYSTEM_USER_TRAP);
     match current_task()
         .unwrap()
         .acquire_inner_lock()
-        .init_user_trap()
+        .init_user_trap(descriptor)
     {
         Ok(addr) => {
             trace!("init ok, addr: {:#x}", addr);
+            post_uipi_event(&current_task().unwrap(), UipiEvent::TrapInit);
             addr
         }
         Err(errno) => errno,
     }
 }
 
+/// Leave the hart until a user trap record is queued for the caller, at once if there is
+/// one. The caller is skipped by the scheduler meanwhile, see `TaskPool::fetch`.
+pub fn sys_uintr_wait() -> isize {
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    let trap_info = match &mut inner.user_trap_info {
+        Some(trap_info) => trap_info,
+        None => return UserTrapError::TrapUninitialized.errno(),
+    };
+    if trap_info.user_trap_record_num() > 0 {
+        return 0;
+    }
+    trap_info.is_waiting = true;
+    drop(inner);
+    drop(task);
+    suspend_current_and_run_next();
+    0
+}
+
+/// Hold back the user interrupts injected by the kernel while `mask` is set,
+/// records are buffered in the trap queue and delivered once unmasked.
+/// Return the previous mask.
+pub fn sys_uintr_mask(mask: bool) -> isize {
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    let trap_info = match &mut inner.user_trap_info {
+        Some(trap_info) => trap_info,
+        None => return UserTrapError::TrapUninitialized.errno(),
+    };
+    let was_masked = core::mem::replace(&mut trap_info.is_masked, mask);
+    if mask {
+        // a notification already raised is taken back, the records stay queued
+        unsafe {
+            uip::clear_usoft();
+        }
+    } else if was_masked && trap_info.should_notify() {
+        // records queued while masked raised nothing, the task would not hear of them
+        // until the next one arrives
+        uscratch::write(trap_info.user_trap_record_num());
+        unsafe {
+            uip::set_usoft();
+        }
+    }
+    was_masked as isize
+}
+
 pub fn sys_send_msg(pid: usize, msg: usize) -> isize {
-    if push_trap_record(
-        pid,
+    let current_task = current_task().unwrap();
+    let receiver = match current_task.find_visible_task(pid) {
+        Some(receiver) => receiver,
+        None => return UserTrapError::TaskNotFound.errno(),
+    };
+    // a loaded service may drop the message, as if the sender ran out of quota
+    if !service::uipi_send_allowed(current_task.getpid(), receiver.getpid(), msg) {
+        return EAGAIN;
+    }
+    // the lock must be released before pushing, the receiver may be the sender itself
+    if let Some(info) = &mut current_task.acquire_inner_lock().user_trap_info {
+        if !info.send_quota.try_acquire() {
+            trace!(
+                "[syscall send_msg] pid {} ran out of send quota",
+                current_task.pid.0
+            );
+            return EAGAIN;
+        }
+    }
+    // the receiver can tell where the message comes from, by the pid in its own namespace
+    let sender_pid = receiver.vpid_of(&current_task).unwrap_or(0);
+    match push_trap_record(
+        receiver.getpid(),
         UserTrapRecord {
-            cause: pid << 4,
+            cause: sender_pid << 4,
             message: msg,
         },
-    )
-    .is_ok()
+    ) {
+        Ok(()) => {
+            current_task.acquire_inner_lock().usage.uipi_sent += 1;
+            0
+        }
+        Err(e) => {
+            if let Some(info) = &mut current_task.acquire_inner_lock().user_trap_info {
+                info.send_quota.refund();
+            }
+            e.errno()
+        }
+    }
+}
+
+/// Forge a user soft interrupt from task `sender_pid` to task `receiver_pid` without
+/// involving the sender, so that tests can drive the receiving side alone.
+/// Both are pids of the caller's namespace, and the caller must control the receiver.
+#[cfg(feature = "uipi_inject")]
+pub fn sys_uipi_inject(receiver_pid: usize, sender_pid: usize) -> isize {
+    let current_task = current_task().unwrap();
+    let (receiver, sender) = match (
+        current_task.find_visible_task(receiver_pid),
+        current_task.find_visible_task(sender_pid),
+    ) {
+        (Some(receiver), Some(sender)) => (receiver, sender),
+        _ => return ESRCH,
+    };
+    if !may_control(&current_task, &receiver) {
+        return EPERM;
+    }
+    debug!(
+        "[syscall uipi_inject] pid {} forges {} -> {}",
+        current_task.pid.0, sender.pid.0, receiver.pid.0
+    );
+    // like sys_send_msg, the receiver sees the sender by its pid in its own namespace
+    let sender_pid = receiver.vpid_of(&sender).unwrap_or(0);
+    match push_trap_record(
+        receiver.getpid(),
+        UserTrapRecord {
+            cause: sender_pid << 4,
+            message: 0,
+        },
+    ) {
+        Ok(()) => 0,
+        Err(e) => e.errno(),
+    }
+}
+
+#[cfg(not(feature = "uipi_inject"))]
+pub fn sys_uipi_inject(_receiver_pid: usize, _sender_pid: usize) -> isize {
+    super::ENOSYS
+}
+
+/// Add task `pid` to the message group `group_id` of the caller's namespace, or remove it.
+/// A task may only move itself and the tasks it controls, see `may_control`, and only a
+/// task with user traps can be a member.
+pub fn sys_msg_group_ctl(cmd: usize, group_id: usize, pid: usize) -> isize {
+    const MSG_GROUP_JOIN: usize = 0;
+    const MSG_GROUP_LEAVE: usize = 1;
+    if cmd != MSG_GROUP_JOIN && cmd != MSG_GROUP_LEAVE {
+        return EINVAL;
+    }
+    let current_task = current_task().unwrap();
+    let task = match current_task.find_visible_task(pid) {
+        Some(task) => task,
+        None => return ESRCH,
+    };
+    if !may_control(&current_task, &task) {
+        return EPERM;
+    }
+    if task.acquire_inner_lock().user_trap_info.is_none() {
+        return EINVAL;
+    }
+    if cmd == MSG_GROUP_JOIN {
+        join_msg_group(current_task.ns_id(), group_id, task.getpid());
+        post_uipi_event(&task, UipiEvent::GroupJoin(group_id));
+        0
+    } else if leave_msg_group(current_task.ns_id(), group_id, task.getpid()) {
+        post_uipi_event(&task, UipiEvent::GroupLeave(group_id));
+        0
+    } else {
+        ENOENT
+    }
+}
+
+/// Names are 1 to `MAX_UIPI_NAME_LEN` letters, digits, `.`, `_` or `-`, like `service.name`
+fn uipi_name(name: *const u8, len: usize) -> Result<String, isize> {
+    if len == 0 || len > MAX_UIPI_NAME_LEN {
+        return Err(EINVAL);
+    }
+    let mut buf = [0u8; MAX_UIPI_NAME_LEN];
+    if mm::copy_from_user(current_user_token(), name, &mut buf[..len]).is_err() {
+        return Err(EFAULT);
+    }
+    let name = &buf[..len];
+    if !name
+        .iter()
+        .all(|&c| c.is_ascii_alphanumeric() || c == b'.' || c == b'_' || c == b'-')
     {
+        return Err(EINVAL);
+    }
+    Ok(name.iter().map(|&c| c as char).collect())
+}
+
+/// Bind the trap queue of the caller to `name` in its pid namespace, until it unbinds it,
+/// releases its user traps or exits
+pub fn sys_uipi_bind(name: *const u8, len: usize) -> isize {
+    let name = match uipi_name(name, len) {
+        Ok(name) => name,
+        Err(errno) => return errno,
+    };
+    let current_task = current_task().unwrap();
+    if current_task.acquire_inner_lock().user_trap_info.is_none() {
+        return UserTrapError::TrapUninitialized.errno();
+    }
+    match bind_uipi_name(current_task.ns_id(), name, current_task.getpid()) {
+        Ok(()) => 0,
+        Err(errno) => errno,
+    }
+}
+
+pub fn sys_uipi_unbind(name: *const u8, len: usize) -> isize {
+    let name = match uipi_name(name, len) {
+        Ok(name) => name,
+        Err(errno) => return errno,
+    };
+    let current_task = current_task().unwrap();
+    if unbind_uipi_name(current_task.ns_id(), name, current_task.getpid()) {
         0
     } else {
-        -1
+        ENOENT
+    }
+}
+
+/// Return the pid to send to for `name`, as seen by the caller. Only names of its own
+/// pid namespace are found, whose receivers it can see anyway.
+pub fn sys_uipi_resolve(name: *const u8, len: usize) -> isize {
+    let name = match uipi_name(name, len) {
+        Ok(name) => name,
+        Err(errno) => return errno,
+    };
+    let current_task = current_task().unwrap();
+    let receiver = match resolve_uipi_name(current_task.ns_id(), name).and_then(find_task) {
+        Some(receiver) => receiver,
+        None => return ENOENT,
+    };
+    match current_task.vpid_of(&receiver) {
+        Some(pid) => pid as isize,
+        None => ENOENT,
+    }
+}
+
+/// Return the number of group members the message is delivered to
+pub fn sys_send_group_msg(group_id: usize, msg: usize) -> isize {
+    let current_task = current_task().unwrap();
+    // one broadcast costs one token of the send quota
+    if let Some(info) = &mut current_task.acquire_inner_lock().user_trap_info {
+        if !info.send_quota.try_acquire() {
+            return EAGAIN;
+        }
+    }
+    let delivered =
+        push_group_trap_record(current_task.ns_pid(), current_task.ns_id(), group_id, msg);
+    current_task.acquire_inner_lock().usage.uipi_sent += delivered;
+    delivered as isize
+}
+
+pub fn sys_user_trap_ctl(cmd: usize, arg0: usize, arg1: usize) -> isize {
+    let current_task = current_task().unwrap();
+    if cmd == USER_TRAP_CTL_RELEASE {
+        let pid = current_task.getpid();
+        let res = current_task.acquire_inner_lock().release_user_trap(pid);
+        return match res {
+            Ok(()) => {
+                // no longer able to receive
+                leave_all_msg_groups(pid);
+                unbind_all_uipi_names(pid);
+                post_uipi_event(&current_task, UipiEvent::TrapRelease);
+                0
+            }
+            Err(e) => e.errno(),
+        };
+    }
+    let mut inner = current_task.acquire_inner_lock();
+    if cmd == USER_TRAP_CTL_SET_HANDLER_BUDGET
+        && arg1 != 0
+        && !inner.memory_set.is_user_executable(arg1.into())
+    {
+        return EINVAL;
+    }
+    match &mut inner.user_trap_info {
+        Some(info) => match cmd {
+            USER_TRAP_CTL_SET_COALESCE => {
+                info.max_notify_per_slice = arg0;
+                info.poll_threshold = arg1;
+                0
+            }
+            USER_TRAP_CTL_SET_SEND_RATE => {
+                info.send_quota.set_rate(arg0, arg1);
+                0
+            }
+            USER_TRAP_CTL_SET_HANDLER_BUDGET => {
+                info.handler_budget_us = arg0;
+                info.handler_abort_entry = arg1;
+                info.handler_seen = None;
+                0
+            }
+            _ => EINVAL,
+        },
+        None => {
+            warn!("[syscall user_trap_ctl] user trap info is None!");
+            ENOTCONN
+        }
     }
 }
 
 pub fn sys_set_timer(time_us: usize) -> isize {
     let pid = current_task().unwrap().pid.0;
+    if DETERMINISTIC {
+        deterministic::set_timer(time_us, pid);
+        return 0;
+    }
     use crate::config::CLOCK_FREQ;
     use crate::timer::{set_virtual_timer, USEC_PER_SEC};
     let time = time_us * CLOCK_FREQ / USEC_PER_SEC;
@@ -186,70 +1252,161 @@ pub fn sys_set_timer(time_us: usize) -> isize {
 }
 
 pub fn sys_claim_ext_int(device_id: usize) -> isize {
-    let device_id = device_id as u16;
+    let ret = claim_ext_int(device_id as u16);
+    if ret >= 0 {
+        post_uipi_event(&current_task().unwrap(), UipiEvent::DeviceClaim(device_id));
+    }
+    ret
+}
+
+fn claim_ext_int(device_id: u16) -> isize {
     let current_task = current_task().unwrap();
     let mut inner = current_task.acquire_inner_lock();
     if !inner.is_user_trap_enabled() {
-        return -1;
+        return ENOTCONN;
     }
     use crate::plic;
     use crate::trap::USER_EXT_INT_MAP;
+    // nothing is claimed for an interrupt without a device of its own behind it
+    if let Err(errno) = plic::check_user_route(device_id) {
+        return errno;
+    }
+    let base_address = match plic::device_mmio_range(device_id) {
+        Some((base_address, _)) => base_address,
+        None => return ENODEV,
+    };
+    let pid = current_task.getpid();
     let user_trap_info = &mut inner.user_trap_info;
     match user_trap_info {
         Some(info) => {
             let mut map = USER_EXT_INT_MAP.lock();
-            if !map.contains_key(&device_id) {
-                let pid = current_task.getpid();
-                debug!(
-                    "[syscall claim] mapping device {} to pid {}",
-                    device_id, pid
-                );
-                map.insert(device_id, pid);
-                info.devices.push((device_id, false));
-                for hart_id in 0..CPU_NUM {
-                    let claim_addr = Plic::context_address(plic::get_context(hart_id, 'U'));
-                    if inner
-                        .memory_set
-                        .mmio_map(claim_addr, crate::config::PAGE_SIZE, 0b11)
-                        .is_err()
-                    {
-                        warn!("[syscall claim] map plic claim reg failed!");
-                        return -6;
-                    }
-                }
-            }
-            use crate::uart;
-            match device_id {
-                #[cfg(feature = "board_qemu")]
-                13 | 14 | 15 => {
-                    let base_address = uart::get_base_addr_from_irq(device_id);
-                    match inner
-                        .memory_set
-                        .mmio_map(base_address, uart::SERIAL_ADDRESS_STRIDE, 0x3)
-                    {
-                        Ok(_) => base_address as isize,
-                        Err(_) => -2,
-                    }
+            match map.get(&device_id) {
+                Some(&owner) if owner != pid => {
+                    warn!(
+                        "[syscall claim] device {} already claimed by pid {}",
+                        device_id, owner
+                    );
+                    return EBUSY;
                 }
-                #[cfg(feature = "board_lrv")]
-                5 | 6 | 7 => {
-                    let base_address = uart::get_base_addr_from_irq(device_id);
-                    match inner
-                        .memory_set
-                        .mmio_map(base_address, uart::SERIAL_ADDRESS_STRIDE, 0x3)
-                    {
-                        Ok(_) => base_address as isize,
-                        Err(_) => -2,
+                Some(_) => {}
+                None => {
+                    debug!(
+                        "[syscall claim] mapping device {} to pid {}",
+                        device_id, pid
+                    );
+                    map.insert(device_id, pid);
+                    info.devices.push((device_id, false));
+                    plic::route_to_user(device_id);
+                    for hart_id in 0..CPU_NUM {
+                        let claim_addr = Plic::context_address(plic::get_context(hart_id, 'U'));
+                        if inner
+                            .memory_set
+                            .mmio_map(claim_addr, crate::config::PAGE_SIZE, 0b11)
+                            .is_err()
+                        {
+                            warn!("[syscall claim] map plic claim reg failed!");
+                            return ENOMEM;
+                        }
                     }
                 }
-                _ => -4,
             }
+            // device registers are mapped by sys_mmio_map on demand
+            base_address as isize
         }
         None => {
             warn!("[syscall claim] user trap info is None!");
-            -5
+            ENOTCONN
+        }
+    }
+}
+
+pub fn sys_mmio_map(start: usize, len: usize, port: usize) -> isize {
+    let current_task = current_task().unwrap();
+    let mut inner = current_task.acquire_inner_lock();
+    if !inner.is_user_trap_enabled() {
+        return ENOTCONN;
+    }
+    // device registers can never be executable
+    if port & !0x3 != 0 || port & 0x3 == 0 {
+        return EINVAL;
+    }
+    let is_allowed = match &inner.user_trap_info {
+        Some(info) => info.is_mmio_allowed(start, len),
+        None => {
+            warn!("[syscall mmio_map] user trap info is None!");
+            return ENOTCONN;
         }
+    };
+    if !is_allowed {
+        warn!(
+            "[syscall mmio_map] {:#x}..{:#x} not owned by pid {}",
+            start,
+            start + len,
+            current_task.getpid()
+        );
+        return EPERM;
+    }
+    match inner.memory_set.mmio_map(start, len, port) {
+        Ok(len) => {
+            if let Some(info) = &mut inner.user_trap_info {
+                info.mmio_regions.push((start, len as usize));
+            }
+            len
+        }
+        Err(err) => {
+            warn!(
+                "[syscall mmio_map] {:#x}..{:#x} refused: {:?}",
+                start,
+                start + len,
+                err
+            );
+            err.errno()
+        }
+    }
+}
+
+/// Allocate physically contiguous memory for devices, return its address in user space
+/// and write the physical address to `paddr`
+pub fn sys_dma_alloc(len: usize, paddr: *mut usize) -> isize {
+    use crate::config::PAGE_SIZE;
+    let current_task = current_task().unwrap();
+    let mut inner = current_task.acquire_inner_lock();
+    if !inner.is_user_trap_enabled() {
+        return ENOTCONN;
+    }
+    match &inner.user_trap_info {
+        Some(info) => {
+            if info.devices.is_empty() {
+                warn!("[syscall dma_alloc] no device claimed!");
+                return ENODEV;
+            }
+        }
+        None => {
+            warn!("[syscall dma_alloc] user trap info is None!");
+            return ENOTCONN;
+        }
+    }
+    let buffer = match mm::dma_alloc((len + PAGE_SIZE - 1) / PAGE_SIZE) {
+        Some(buffer) => Arc::new(buffer),
+        None => return ENOMEM,
+    };
+    // before mapping it, a bad `paddr` only frees the buffer again
+    let token = inner.get_user_token();
+    if mm::copy_value_to_user(token, paddr, &buffer.paddr()).is_err() {
+        return EFAULT;
+    }
+    if inner
+        .memory_set
+        .mmio_map(buffer.vaddr(), buffer.len(), 0b11)
+        .is_err()
+    {
+        return ENOMEM;
+    }
+    let vaddr = buffer.vaddr();
+    if let Some(info) = &mut inner.user_trap_info {
+        info.dma_buffers.push(buffer);
     }
+    vaddr as isize
 }
 
 pub fn sys_set_ext_int_enable(device_id: usize, enable: usize) -> isize {
@@ -259,7 +1416,7 @@ pub fn sys_set_ext_int_enable(device_id: usize, enable: usize) -> isize {
     let current_task = current_task().unwrap();
     let mut inner = current_task.acquire_inner_lock();
     if !inner.is_user_trap_enabled() {
-        return -1;
+        return ENOTCONN;
     }
     use crate::trap::USER_EXT_INT_MAP;
     let user_trap_info = &mut inner.user_trap_info;
@@ -288,16 +1445,16 @@ pub fn sys_set_ext_int_enable(device_id: usize, enable: usize) -> isize {
                         device_id,
                         current_task.getpid()
                     );
-                    return -1;
+                    return EPERM;
                 }
             } else {
                 warn!("[sys set ext] device not claimed!");
-                return -2;
+                return ENODEV;
             }
         }
         None => {
             warn!("[syscall claim] user trap info is None!");
-            -5
+            ENOTCONN
         }
     }
 }
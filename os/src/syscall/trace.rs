@@ -0,0 +1,128 @@
+use super::*;
+use crate::fs::File;
+use crate::mm::UserBuffer;
+use crate::task::current_task;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+/// Syscall classes for the mask of `sys_trace_ctl`
+pub const TRACE_CLASS_PROCESS: usize = 1 << 0;
+pub const TRACE_CLASS_FS: usize = 1 << 1;
+pub const TRACE_CLASS_MEMORY: usize = 1 << 2;
+pub const TRACE_CLASS_TIME: usize = 1 << 3;
+pub const TRACE_CLASS_USER_TRAP: usize = 1 << 4;
+/// `sys_trace_ctl` fd which sends the trace to the kernel log instead of a pipe
+pub const TRACE_TO_LOG: usize = usize::MAX;
+
+/// Number of tasks being traced, so that untraced syscalls skip the task lock
+static TRACED_TASK_NUM: AtomicUsize = AtomicUsize::new(0);
+
+/// Syscall tracing state of a task, set by `sys_trace_ctl`
+pub struct SyscallTrace {
+    /// `TRACE_CLASS_*` bits
+    pub mask: usize,
+    /// Where trace lines are written, the kernel log if `None`
+    pub output: Option<Arc<dyn File + Send + Sync>>,
+}
+
+impl SyscallTrace {
+    pub fn new(mask: usize, output: Option<Arc<dyn File + Send + Sync>>) -> Self {
+        TRACED_TASK_NUM.fetch_add(1, Relaxed);
+        Self { mask, output }
+    }
+}
+
+impl Drop for SyscallTrace {
+    fn drop(&mut self) {
+        TRACED_TASK_NUM.fetch_sub(1, Relaxed);
+    }
+}
+
+fn syscall_desc(syscall_id: usize) -> Option<&'static SyscallDesc> {
+    SYSCALLS.iter().find(|desc| desc.id == syscall_id)
+}
+
+pub fn syscall_class(syscall_id: usize) -> usize {
+    match syscall_id {
+        SYSCALL_DUP | SYSCALL_FCNTL | SYSCALL_IOCTL | SYSCALL_OPEN | SYSCALL_CLOSE
+        | SYSCALL_PIPE | SYSCALL_READ | SYSCALL_WRITE | SYSCALL_READV | SYSCALL_WRITEV
+        | SYSCALL_SENDFILE | SYSCALL_SPLICE | SYSCALL_SEND_FD | SYSCALL_RECV_FD
+        | SYSCALL_MAILREAD | SYSCALL_MAILWRITE => TRACE_CLASS_FS,
+        SYSCALL_BRK
+        | SYSCALL_MMAP
+        | SYSCALL_NATIVE_MMAP
+        | SYSCALL_MUNMAP
+        | SYSCALL_MMIO_MAP
+        | SYSCALL_DMA_ALLOC
+        | SYSCALL_VM_INFO
+        | SYSCALL_MEM_PRESSURE
+        | SYSCALL_DEBUG_TRANSLATE => TRACE_CLASS_MEMORY,
+        SYSCALL_CLOCK_GETTIME
+        | SYSCALL_NANOSLEEP
+        | SYSCALL_GET_TIME
+        | SYSCALL_SETTIMEOFDAY
+        | SYSCALL_TIMES
+        | SYSCALL_SET_TIMER => TRACE_CLASS_TIME,
+        SYSCALL_INIT_USER_TRAP
+        | SYSCALL_SEND_MSG
+        | SYSCALL_CLAIM_EXT_INT
+        | SYSCALL_SET_EXT_INT_ENABLE
+        | SYSCALL_USER_TRAP_CTL
+        | SYSCALL_MSG_GROUP_CTL
+        | SYSCALL_SEND_GROUP_MSG
+        | SYSCALL_UIPI_BIND
+        | SYSCALL_UIPI_UNBIND
+        | SYSCALL_UIPI_RESOLVE => TRACE_CLASS_USER_TRAP,
+        _ => TRACE_CLASS_PROCESS,
+    }
+}
+
+/// Report a syscall of the current task if it is traced,
+/// `ret` is `None` for syscalls which do not return
+pub fn trace_syscall(syscall_id: usize, args: [usize; 6], ret: Option<isize>) {
+    if TRACED_TASK_NUM.load(Relaxed) == 0 {
+        return;
+    }
+    let task = match current_task() {
+        Some(task) => task,
+        None => return,
+    };
+    let inner = task.acquire_inner_lock();
+    let output = match &inner.syscall_trace {
+        Some(trace) if trace.mask & syscall_class(syscall_id) != 0 => trace.output.clone(),
+        _ => return,
+    };
+    // writing to a full pipe may switch tasks
+    drop(inner);
+    let ret = match ret {
+        Some(ret) if is_error(ret) || ret >= 0 => format!("{}", ret),
+        Some(ret) => format!("{:#x}", ret as usize),
+        None => format!("?"),
+    };
+    // unknown syscalls show the three arguments most take
+    let (name, arg_num) =
+        syscall_desc(syscall_id).map_or(("unknown", 3), |desc| (desc.name, desc.args));
+    let args: Vec<String> = args[..arg_num]
+        .iter()
+        .map(|arg| format!("{:#x}", arg))
+        .collect();
+    let line = format!(
+        "[{}] {}({}) = {}\n",
+        task.getpid(),
+        name,
+        args.join(", "),
+        ret
+    );
+    match output {
+        Some(file) => {
+            let buf =
+                unsafe { core::slice::from_raw_parts_mut(line.as_ptr() as *mut u8, line.len()) };
+            let _ = file.write(UserBuffer::new(vec![buf]));
+        }
+        None => info!("[strace] {}", line.trim_end()),
+    }
+}
@@ -0,0 +1,157 @@
+use crate::syscall::EINVAL;
+use crate::timer::get_time_us;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use core::fmt::Write;
+use lazy_static::*;
+use spin::Mutex;
+
+/// Tasks in group 0 are never throttled
+pub const DEFAULT_CPU_GROUP: usize = 0;
+pub const DEFAULT_PERIOD_US: usize = 100_000;
+/// Longest period, a group throttled for longer would look hung
+const MAX_PERIOD_US: usize = 10_000_000;
+
+/// A group of tasks sharing `quota_us` of CPU time in every `period_us`,
+/// a zero quota means no limit
+struct CpuGroup {
+    quota_us: usize,
+    period_us: usize,
+    period_start_us: usize,
+    used_us: usize,
+    total_us: usize,
+    throttled_periods: usize,
+}
+
+impl CpuGroup {
+    fn new() -> Self {
+        Self {
+            quota_us: 0,
+            period_us: DEFAULT_PERIOD_US,
+            period_start_us: get_time_us(),
+            used_us: 0,
+            total_us: 0,
+            throttled_periods: 0,
+        }
+    }
+    fn refill(&mut self, now: usize) {
+        if now >= self.period_start_us.saturating_add(self.period_us) {
+            self.period_start_us = now;
+            self.used_us = 0;
+        }
+    }
+    fn is_throttled(&self) -> bool {
+        self.quota_us != 0 && self.used_us >= self.quota_us
+    }
+}
+
+/// Snapshot returned by `sys_cpu_group_ctl`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct CpuGroupStats {
+    pub quota_us: usize,
+    pub period_us: usize,
+    /// Used in the current period
+    pub used_us: usize,
+    pub total_us: usize,
+    /// Periods in which the group ran out of its quota
+    pub throttled_periods: usize,
+}
+
+lazy_static! {
+    static ref CPU_GROUPS: Mutex<BTreeMap<usize, CpuGroup>> = Mutex::new(BTreeMap::new());
+}
+
+/// Whether the tasks of `group` must wait for the next period
+pub fn is_throttled(group: usize) -> bool {
+    if group == DEFAULT_CPU_GROUP {
+        return false;
+    }
+    let mut groups = CPU_GROUPS.lock();
+    match groups.get_mut(&group) {
+        Some(cpu_group) => {
+            cpu_group.refill(get_time_us());
+            cpu_group.is_throttled()
+        }
+        None => false,
+    }
+}
+
+/// Charge `us` of CPU time to `group` when a task of it is switched out
+pub fn charge(group: usize, us: usize) {
+    if group == DEFAULT_CPU_GROUP {
+        return;
+    }
+    let mut groups = CPU_GROUPS.lock();
+    if let Some(cpu_group) = groups.get_mut(&group) {
+        cpu_group.refill(get_time_us());
+        let was_throttled = cpu_group.is_throttled();
+        cpu_group.used_us += us;
+        cpu_group.total_us += us;
+        if !was_throttled && cpu_group.is_throttled() {
+            cpu_group.throttled_periods += 1;
+        }
+    }
+}
+
+/// Create `group` if needed, so that tasks can be attached to it
+pub fn create_group(group: usize) {
+    CPU_GROUPS.lock().entry(group).or_insert_with(CpuGroup::new);
+}
+
+pub fn set_quota(group: usize, quota_us: usize) -> Result<(), isize> {
+    if group == DEFAULT_CPU_GROUP {
+        return Err(EINVAL);
+    }
+    CPU_GROUPS
+        .lock()
+        .entry(group)
+        .or_insert_with(CpuGroup::new)
+        .quota_us = quota_us;
+    Ok(())
+}
+
+pub fn set_period(group: usize, period_us: usize) -> Result<(), isize> {
+    if group == DEFAULT_CPU_GROUP || period_us == 0 || period_us > MAX_PERIOD_US {
+        return Err(EINVAL);
+    }
+    CPU_GROUPS
+        .lock()
+        .entry(group)
+        .or_insert_with(CpuGroup::new)
+        .period_us = period_us;
+    Ok(())
+}
+
+/// Every group and its use, for `/proc/cpu_groups`
+pub fn report() -> String {
+    let mut report = String::new();
+    let now = get_time_us();
+    for (group, cpu_group) in CPU_GROUPS.lock().iter_mut() {
+        cpu_group.refill(now);
+        let _ = writeln!(
+            report,
+            "group {} quota_us {} period_us {} used_us {} total_us {} throttled_periods {}",
+            group,
+            cpu_group.quota_us,
+            cpu_group.period_us,
+            cpu_group.used_us,
+            cpu_group.total_us,
+            cpu_group.throttled_periods
+        );
+    }
+    report
+}
+
+pub fn cpu_group_stats(group: usize) -> Option<CpuGroupStats> {
+    let mut groups = CPU_GROUPS.lock();
+    let cpu_group = groups.get_mut(&group)?;
+    cpu_group.refill(get_time_us());
+    Some(CpuGroupStats {
+        quota_us: cpu_group.quota_us,
+        period_us: cpu_group.period_us,
+        used_us: cpu_group.used_us,
+        total_us: cpu_group.total_us,
+        throttled_periods: cpu_group.throttled_periods,
+    })
+}
@@ -0,0 +1,145 @@
+//! Checkpoints of a task kept in kernel memory, so that a long benchmark setup can be
+//! run once and restored as often as needed.
+//!
+//! A checkpoint is taken while the task is parked by the SIGSTOP machinery. It holds a
+//! copy of its user space, trap context included, and shares its open files. UIPI
+//! objects are not part of it: the trap buffer, device registers and DMA buffers are
+//! left out, and a restored task has to set up its user traps again. A task parked
+//! inside a blocking syscall is restored at the `ecall` if the syscall can be restarted,
+//! or else right after it with `EINTR`, as if it had been interrupted by a user trap.
+
+use super::fd_table::FdTable;
+use super::task::TaskControlBlockInner;
+use super::{continue_task, stop_task, suspend_current_and_run_next, TaskControlBlock};
+use super::{TaskStatus, TASK_POOL};
+use crate::config::{PAGE_SIZE, TRAP_CONTEXT, USER_TRAP_BUFFER};
+use crate::mm::alloc_track::AllocScope;
+use crate::mm::{MemorySet, VirtAddr};
+use crate::syscall::{is_restartable, EBUSY, EINTR, ENOENT, ENOSPC, ESRCH};
+use crate::trap::TrapContext;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use lazy_static::*;
+use spin::Mutex;
+
+/// Each checkpoint holds a full copy of the user space of its task
+pub const MAX_CHECKPOINTS: usize = 8;
+
+pub struct Checkpoint {
+    pub memory_set: MemorySet,
+    pub base_size: usize,
+    pub fd_table: FdTable,
+    pub priority: isize,
+    pub cpu_group: usize,
+    pub cpu_affinity: usize,
+}
+
+impl Checkpoint {
+    /// `in_syscall` as in `TaskControlBlock`
+    fn take(inner: &TaskControlBlockInner, in_syscall: usize) -> Result<Self, isize> {
+        // kept past the exit of the task, owned by no task
+        let _scope = AllocScope::kernel();
+        let mut memory_set = MemorySet::from_existed_user(&inner.memory_set)?;
+        if in_syscall != 0 {
+            let trap_cx: &mut TrapContext = memory_set
+                .translate(VirtAddr::from(TRAP_CONTEXT).into())
+                .unwrap()
+                .ppn()
+                .get_mut();
+            // sepc is already past the ecall and a0 still holds the first argument
+            if is_restartable(in_syscall - 1) {
+                trap_cx.sepc -= 4;
+            } else {
+                trap_cx.x[10] = EINTR as usize;
+            }
+        }
+        if let Some(info) = &inner.user_trap_info {
+            for &(start, len) in &info.mmio_regions {
+                let _ = memory_set.mmio_unmap(start, len);
+            }
+            for buffer in &info.dma_buffers {
+                let _ = memory_set.mmio_unmap(buffer.paddr(), buffer.len());
+            }
+            let _ = memory_set.munmap(USER_TRAP_BUFFER, PAGE_SIZE);
+        }
+        Ok(Self {
+            memory_set,
+            base_size: inner.base_size,
+            fd_table: inner.fd_table.clone(),
+            priority: inner.priority,
+            cpu_group: inner.cpu_group,
+            cpu_affinity: inner.cpu_affinity,
+        })
+    }
+}
+
+lazy_static! {
+    static ref CHECKPOINTS: Mutex<BTreeMap<usize, Arc<Checkpoint>>> = Mutex::new(BTreeMap::new());
+}
+
+static NEXT_CHECKPOINT_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Take `task` out of the pool if it is parked there, so that it stays off every hart,
+/// even if continued meanwhile
+fn unpark(task: &Arc<TaskControlBlock>) -> bool {
+    TASK_POOL.lock().sleeping_tasks.remove(task)
+}
+
+/// Put an unparked task back, into the ready queue if it was continued meanwhile
+fn repark(task: Arc<TaskControlBlock>) {
+    let mut pool = TASK_POOL.lock();
+    let is_stopped = task.acquire_inner_lock().is_stopped();
+    if is_stopped {
+        pool.sleep(task);
+    } else {
+        pool.add(task);
+    }
+}
+
+/// Freeze `task`, which is not the current one, until it is parked and copy it. It goes on
+/// afterwards unless it was stopped before. Return the id of the checkpoint.
+pub fn save_checkpoint(task: Arc<TaskControlBlock>) -> Result<usize, isize> {
+    if CHECKPOINTS.lock().len() >= MAX_CHECKPOINTS {
+        return Err(ENOSPC);
+    }
+    let was_stopped = task.acquire_inner_lock().is_stopped();
+    if stop_task(task.clone()).is_err() {
+        return Err(ESRCH);
+    }
+    // a stopped task runs on until it is switched out, and may exit meanwhile
+    while !unpark(&task) {
+        match task.acquire_inner_lock().task_status {
+            TaskStatus::Stopped => {}
+            TaskStatus::Zombie => return Err(ESRCH),
+            // continued by another task before it was parked
+            _ => return Err(EBUSY),
+        }
+        suspend_current_and_run_next();
+    }
+    let checkpoint = Checkpoint::take(&task.acquire_inner_lock(), task.in_syscall.load(Relaxed));
+    repark(task.clone());
+    if !was_stopped {
+        let _ = continue_task(task);
+    }
+    let checkpoint = checkpoint?;
+    let id = NEXT_CHECKPOINT_ID.fetch_add(1, Relaxed);
+    CHECKPOINTS.lock().insert(id, Arc::new(checkpoint));
+    Ok(id)
+}
+
+/// Make a new child of `parent` out of checkpoint `id`, which can be restored again
+pub fn restore_checkpoint(
+    id: usize,
+    parent: &Arc<TaskControlBlock>,
+) -> Result<Arc<TaskControlBlock>, isize> {
+    let checkpoint = CHECKPOINTS.lock().get(&id).cloned().ok_or(ENOENT)?;
+    parent.restore(&checkpoint)
+}
+
+pub fn drop_checkpoint(id: usize) -> Result<(), isize> {
+    match CHECKPOINTS.lock().remove(&id) {
+        Some(_) => Ok(()),
+        None => Err(ENOENT),
+    }
+}
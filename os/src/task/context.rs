@@ -1,5 +1,8 @@
 use crate::trap::trap_return;
-use riscv::register::{uie, uip};
+use riscv::register::{ucause, uepc, uie, utval, utvec};
+
+/// USIP bit in uip, pending iff the task has undelivered user trap records
+const USIP: usize = 1 << 0;
 
 #[repr(C)]
 #[derive(Debug, Clone)]
@@ -16,12 +19,13 @@ pub struct TaskContext {
 }
 
 impl TaskContext {
+    /// A new program starts without any user interrupt enabled or pending
     pub fn goto_trap_return(kernel_stack_top: usize) -> Self {
         Self {
             ra: trap_return as usize,
             s: [0; 12],
-            uie: uie::read().bits(),
-            uip: uip::read().bits(),
+            uie: 0,
+            uip: 0,
             uepc: 0,
             utvec: 0,
             utval: 0,
@@ -29,6 +33,32 @@ impl TaskContext {
             sp: kernel_stack_top,
         }
     }
+
+    /// A forked task inherits the live user trap CSRs of its parent,
+    /// but not the interrupts pending for the parent
+    pub fn fork_trap_return(kernel_stack_top: usize) -> Self {
+        Self {
+            ra: trap_return as usize,
+            s: [0; 12],
+            uie: uie::read().bits(),
+            uip: 0,
+            uepc: uepc::read(),
+            utvec: utvec::read().bits(),
+            utval: utval::read(),
+            ucause: ucause::read().bits(),
+            sp: kernel_stack_top,
+        }
+    }
+
+    /// Merge user soft interrupts raised while the task was off-CPU into the saved uip,
+    /// so that they are neither lost nor delivered twice after switching in
+    pub fn merge_user_soft_pending(&mut self, has_pending_record: bool) {
+        if has_pending_record {
+            self.uip |= USIP;
+        } else {
+            self.uip &= !USIP;
+        }
+    }
 }
 
 impl Default for TaskContext {
@@ -0,0 +1,166 @@
+//! Deadline scheduling class: a task declares the CPU time it needs in every
+//! period and the deadline, relative to the period start, by which it needs
+//! it. Ready deadline tasks run before all others, earliest deadline first,
+//! and are held back once they have used up their budget for the period.
+//!
+//! Tasks are only admitted while the bandwidth of all deadline tasks fits
+//! into `MAX_BANDWIDTH_PERCENT` of the harts, so that each can be given its
+//! budget. The budget is enforced by a timer armed when the task is switched in.
+
+use crate::config::kernel_config;
+use crate::syscall::{EBUSY, EINVAL};
+use crate::timer::get_time_us;
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+/// Policies of `sys_sched_setattr`, as in Linux
+pub const SCHED_NORMAL: u32 = 0;
+pub const SCHED_DEADLINE: u32 = 6;
+/// Share of every hart which deadline tasks may take together
+const MAX_BANDWIDTH_PERCENT: usize = 95;
+/// Bandwidth of a task running all the time
+const FULL_BANDWIDTH: usize = 1_000_000;
+/// Shortest budget, less could not be enforced against the cost of switching
+const MIN_BUDGET_US: usize = 100;
+/// Longest period, so that the bandwidth computation cannot overflow
+const MAX_PERIOD_US: usize = 10_000_000;
+/// `pid` of the timer which ends the budget of the current deadline task,
+/// 0 being the scheduler tick
+pub const DEADLINE_TIMER: usize = usize::MAX;
+
+/// Bandwidth taken by admitted tasks, in millionths of a hart
+static TOTAL_BANDWIDTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Argument of `sys_sched_setattr`, laid out as `struct sched_attr` of Linux,
+/// times are in nanoseconds
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SchedAttr {
+    pub size: u32,
+    pub sched_policy: u32,
+    pub sched_flags: u64,
+    pub sched_nice: i32,
+    pub sched_priority: u32,
+    pub sched_runtime: u64,
+    pub sched_deadline: u64,
+    pub sched_period: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DeadlineParams {
+    pub budget_us: usize,
+    pub deadline_us: usize,
+    pub period_us: usize,
+}
+
+impl DeadlineParams {
+    /// A deadline of 0 is the period, as in Linux
+    pub fn from_attr(attr: &SchedAttr) -> Result<Self, isize> {
+        let budget_us = (attr.sched_runtime / 1000) as usize;
+        let period_us = (attr.sched_period / 1000) as usize;
+        let deadline_us = match (attr.sched_deadline / 1000) as usize {
+            0 => period_us,
+            deadline_us => deadline_us,
+        };
+        if budget_us < MIN_BUDGET_US
+            || budget_us > deadline_us
+            || deadline_us > period_us
+            || period_us > MAX_PERIOD_US
+        {
+            return Err(EINVAL);
+        }
+        Ok(Self {
+            budget_us,
+            deadline_us,
+            period_us,
+        })
+    }
+    /// Share of a hart, in millionths
+    pub fn bandwidth(&self) -> usize {
+        self.budget_us * FULL_BANDWIDTH / self.period_us
+    }
+}
+
+/// Take `new` and give back `old` bandwidth, if all tasks still fit
+fn reserve(new: usize, old: usize) -> Result<(), isize> {
+    let limit = kernel_config().hart_num * FULL_BANDWIDTH / 100 * MAX_BANDWIDTH_PERCENT;
+    TOTAL_BANDWIDTH
+        .fetch_update(Relaxed, Relaxed, |total| {
+            let total = total - old + new;
+            (total <= limit).then(|| total)
+        })
+        .map(|_| ())
+        .map_err(|_| EBUSY)
+}
+
+/// State of a task in the deadline class, which holds its bandwidth until dropped
+#[derive(Debug)]
+pub struct DeadlineTask {
+    params: DeadlineParams,
+    period_start_us: usize,
+    /// CPU time used in the current period
+    used_us: usize,
+    /// Periods in which the task ran out of its budget
+    pub throttled_periods: usize,
+}
+
+impl DeadlineTask {
+    pub fn new(params: DeadlineParams) -> Result<Self, isize> {
+        reserve(params.bandwidth(), 0)?;
+        Ok(Self {
+            params,
+            period_start_us: get_time_us(),
+            used_us: 0,
+            throttled_periods: 0,
+        })
+    }
+    pub fn bandwidth(&self) -> usize {
+        self.params.bandwidth()
+    }
+    /// Change the parameters, starting a new period
+    pub fn update(&mut self, params: DeadlineParams) -> Result<(), isize> {
+        reserve(params.bandwidth(), self.params.bandwidth())?;
+        self.params = params;
+        self.period_start_us = get_time_us();
+        self.used_us = 0;
+        Ok(())
+    }
+    /// Start a new period once the current one is over. A task which slept
+    /// through several periods starts afresh now instead of catching up.
+    pub fn refill(&mut self, now: usize) {
+        let elapsed = now.saturating_sub(self.period_start_us);
+        if elapsed >= self.params.period_us {
+            self.period_start_us = if elapsed < 2 * self.params.period_us {
+                self.period_start_us + self.params.period_us
+            } else {
+                now
+            };
+            self.used_us = 0;
+        }
+    }
+    pub fn abs_deadline_us(&self) -> usize {
+        self.period_start_us + self.params.deadline_us
+    }
+    pub fn is_throttled(&self) -> bool {
+        self.used_us >= self.params.budget_us
+    }
+    /// Budget left in the current period after running for `running_us` since the last charge
+    pub fn remaining_us(&self, running_us: usize) -> usize {
+        self.params
+            .budget_us
+            .saturating_sub(self.used_us + running_us)
+    }
+    /// Charge `us` of CPU time when the task is switched out
+    pub fn charge(&mut self, us: usize) {
+        let was_throttled = self.is_throttled();
+        self.used_us += us;
+        if !was_throttled && self.is_throttled() {
+            self.throttled_periods += 1;
+        }
+    }
+}
+
+impl Drop for DeadlineTask {
+    fn drop(&mut self) {
+        TOTAL_BANDWIDTH.fetch_sub(self.params.bandwidth(), Relaxed);
+    }
+}
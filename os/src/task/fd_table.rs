@@ -0,0 +1,100 @@
+//! Open files of a task and their fd flags, kept in `TaskControlBlockInner`.
+//!
+//! Files are cloned out and the inner lock dropped before they are read or written, as
+//! these may block.
+
+use crate::fs::{open_device, File, Stdin, Stdout, FD_CLOEXEC};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Debug, Formatter};
+
+#[derive(Clone)]
+pub struct FdTable {
+    pub files: Vec<Option<Arc<dyn File + Send + Sync>>>,
+    /// `FD_CLOEXEC` of each fd, missing ones are 0
+    flags: Vec<usize>,
+}
+
+impl Debug for FdTable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let open = self.files.iter().filter(|file| file.is_some()).count();
+        f.debug_struct("FdTable").field("open", &open).finish()
+    }
+}
+
+impl FdTable {
+    /// fds of the first process, everything else inherits its parent's
+    pub fn initial() -> Self {
+        Self {
+            files: vec![
+                // 0 -> stdin
+                Some(Arc::new(Stdin)),
+                // 1 -> stdout
+                Some(Arc::new(Stdout)),
+                // 2 -> stderr
+                Some(Arc::new(Stdout)),
+                // 3 -> serial 2
+                open_device("/dev/ttyS2"),
+                // 4 -> serial 3
+                open_device("/dev/ttyS3"),
+            ],
+            flags: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, fd: usize) -> Option<&Arc<dyn File + Send + Sync>> {
+        self.files.get(fd)?.as_ref()
+    }
+
+    /// The lowest free fd, its flags cleared
+    pub fn alloc_fd(&mut self) -> usize {
+        let fd = if let Some(fd) = (0..self.files.len()).find(|fd| self.files[*fd].is_none()) {
+            fd
+        } else {
+            self.files.push(None);
+            self.files.len() - 1
+        };
+        self.set_fd_flags(fd, 0);
+        fd
+    }
+
+    /// Put `file` at the lowest free fd
+    pub fn insert(&mut self, file: Arc<dyn File + Send + Sync>, flags: usize) -> usize {
+        let fd = self.alloc_fd();
+        self.files[fd] = Some(file);
+        self.set_fd_flags(fd, flags);
+        fd
+    }
+
+    pub fn fd_flags(&self, fd: usize) -> usize {
+        self.flags.get(fd).copied().unwrap_or(0)
+    }
+
+    pub fn set_fd_flags(&mut self, fd: usize, flags: usize) {
+        if fd >= self.flags.len() {
+            self.flags.resize(fd + 1, 0);
+        }
+        self.flags[fd] = flags;
+    }
+
+    /// The fds a new program keeps, all but those marked `FD_CLOEXEC`
+    pub fn on_exec(&self) -> Self {
+        let files = self
+            .files
+            .iter()
+            .enumerate()
+            .map(|(fd, file)| {
+                if self.fd_flags(fd) & FD_CLOEXEC != 0 {
+                    None
+                } else {
+                    file.clone()
+                }
+            })
+            .collect();
+        Self {
+            files,
+            flags: Vec::new(),
+        }
+    }
+}
@@ -1,57 +1,54 @@
+use super::scheduler::{SchedPolicy, Scheduler};
 use super::TaskControlBlock;
-use alloc::collections::VecDeque;
+use crate::syscall::EBUSY;
+use alloc::boxed::Box;
 use alloc::sync::Arc;
 
+/// The ready queue, ordered by the current scheduling policy
 pub struct TaskManager {
-    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+    policy: SchedPolicy,
+    scheduler: Box<dyn Scheduler>,
 }
 
-/// A simple FIFO scheduler.
 impl TaskManager {
     pub fn new() -> Self {
+        let policy = SchedPolicy::boot_default();
         Self {
-            ready_queue: VecDeque::new(),
+            policy,
+            scheduler: policy.new_scheduler(),
         }
     }
     pub fn add(&mut self, task: Arc<TaskControlBlock>) {
-        self.ready_queue.push_back(task);
+        self.scheduler.add(task);
     }
-    #[allow(unused)]
-    pub fn remove(&mut self, task: &Arc<TaskControlBlock>) {
-        for (idx, task_item) in self.ready_queue.iter().enumerate() {
-            if *task_item == *task {
-                self.ready_queue.remove(idx);
-                break;
-            }
-        }
+    /// Return false if the task is not queued
+    pub fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        self.scheduler.remove(task)
+    }
+    pub fn len(&self) -> usize {
+        self.scheduler.len()
     }
     pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        // May need to concern affinity
-        self.ready_queue.pop_front()
+        self.scheduler.fetch()
     }
-
-    #[allow(unused)]
-    pub fn prioritize(&mut self, pid: usize) {
-        let q = &mut self.ready_queue;
-        if q.is_empty() || q.len() == 1 {
-            return;
-        }
-        let front_pid = q.front().unwrap().pid.0;
-        if front_pid == pid {
-            debug!("[Taskmgr] Task {} already at front", pid);
-
-            return;
-        }
-        q.rotate_left(1);
-        while {
-            let f_pid = q.front().unwrap().pid.0;
-            f_pid != pid && f_pid != front_pid
-        } {
-            q.rotate_left(1);
+    /// Move task `pid` to the front, return false if it is not in the queue
+    pub fn prioritize(&mut self, pid: usize) -> bool {
+        self.scheduler.prioritize(pid)
+    }
+    pub fn policy(&self) -> SchedPolicy {
+        self.policy
+    }
+    /// Switch to `policy`, only while no task is ready, so that none is
+    /// delayed by the switch. Return the previous policy.
+    pub fn set_policy(&mut self, policy: SchedPolicy) -> Result<SchedPolicy, isize> {
+        if self.len() != 0 {
+            return Err(EBUSY);
         }
-        if q.front().unwrap().pid.0 == pid {
-            debug!("[Taskmgr] Prioritized task {}", pid);
+        if policy != self.policy {
+            info!("[Taskmgr] policy {:?} -> {:?}", self.policy, policy);
+            self.scheduler = policy.new_scheduler();
         }
+        Ok(core::mem::replace(&mut self.policy, policy))
     }
 }
 
@@ -1,36 +1,75 @@
+mod bandwidth;
+mod checkpoint;
 mod context;
+mod deadline;
+mod fd_table;
 mod manager;
 mod pid;
 mod pool;
 mod processor;
+mod ptrace;
+mod sched_stats;
+mod scheduler;
 mod switch;
 mod task;
+mod uipi_events;
 
+use crate::config::OOM_RESERVE_FRAMES;
+use crate::console::ANSICON;
 use crate::loader::get_app_data_by_name;
+use crate::mm::frames_available;
+use crate::syscall::ESRCH;
+use crate::timer::{get_time_us, ticks_to_us};
 use alloc::sync::Arc;
 use lazy_static::*;
 
+use pool::TASK_POOL;
 use spin::Mutex;
 use switch::__switch2;
 
+pub use bandwidth::{
+    cpu_group_stats, create_group, report as cpu_group_report, set_period, set_quota, CpuGroupStats,
+};
+pub use checkpoint::{drop_checkpoint, restore_checkpoint, save_checkpoint};
 pub use context::TaskContext;
-pub use pid::{find_task, pid_alloc, KernelStack, PidHandle};
-pub use pool::{add_task, fetch_task, prioritize_task};
+pub use deadline::{DeadlineParams, SchedAttr, DEADLINE_TIMER, SCHED_DEADLINE, SCHED_NORMAL};
+pub use pid::{find_task, pid_alloc, KernelStack, PidHandle, PidNamespace};
+pub use pool::{
+    add_task, fetch_task, prioritize_task, sched_policy, set_deadline_params, set_sched_policy,
+};
 pub use processor::{
     current_task, current_trap_cx, current_user_token, hart_id, mmap, munmap, run_tasks, schedule,
-    set_current_priority, take_current_task,
+    set_current_priority, set_hart_state, take_current_task, HartState,
+};
+pub use ptrace::{handle_ptrace_breakpoint, ptrace, PtraceState};
+pub use sched_stats::{report as sched_report, sched_stats, SchedStats};
+pub use scheduler::SchedPolicy;
+pub use task::{
+    ExitReason, ExitStatus, Rusage, TaskControlBlock, TaskInfo, TaskStatus, Tms,
+    SPAWN_NOTIFY_PARENT,
 };
-pub use task::{TaskControlBlock, TaskStatus};
+pub use uipi_events::{post_uipi_event, UipiEvent, UipiEventSink};
 
 lazy_static! {
     pub static ref WAIT_LOCK: Mutex<()> = Mutex::new(());
 }
 
+/// Cause of the trap records telling of the exit of a child, the message being the pid of
+/// the child as its parent sees it in the upper 32 bits and its exit code in the lower ones
+pub const CHILD_EXIT_CAUSE: usize = 0xe;
+
 pub fn suspend_current_and_run_next() {
     // There must be an application running.
     let task = current_task().unwrap();
     let mut task_inner = task.acquire_inner_lock();
     task_inner.time_intr_count += 1;
+    // only the timer interrupt switches a task out from an interrupt
+    if task_inner.is_in_irq {
+        task_inner.usage.nivcsw += 1;
+    } else {
+        task_inner.usage.nvcsw += 1;
+    }
+    task_inner.update_max_rss();
     let task_cx_ptr = task_inner.get_task_cx_ptr();
     drop(task_inner);
 
@@ -38,7 +77,83 @@ pub fn suspend_current_and_run_next() {
     schedule(task_cx_ptr);
 }
 
-pub fn exit_current_and_run_next(exit_code: i32) {
+/// Whether the current task is in the deadline class and has used up its budget,
+/// counting the time since it was switched in
+pub fn current_deadline_exhausted() -> bool {
+    let task = current_task().unwrap();
+    let inner = task.acquire_inner_lock();
+    let running_us = get_time_us() - inner.dispatched_us;
+    inner
+        .deadline
+        .as_ref()
+        .map_or(false, |deadline| deadline.remaining_us(running_us) == 0)
+}
+
+/// Blocking syscalls stop waiting once the current task has a user trap to take,
+/// so that they can be restarted after the handler
+pub fn current_has_pending_user_trap() -> bool {
+    let task = current_task().unwrap();
+    let inner = task.acquire_inner_lock();
+    inner.has_pending_user_trap()
+}
+
+/// Tell the receiver `parent` registered by `sys_child_notify` that `child` exited,
+/// with no task locked as pushing the record takes the lock of the receiver
+fn notify_child_exit(parent: &TaskControlBlock, child: &TaskControlBlock, status: ExitStatus) {
+    let receiver = match &parent.acquire_inner_lock().child_notify {
+        Some(receiver) => receiver.upgrade(),
+        None => return,
+    };
+    let receiver = match receiver {
+        Some(receiver) => receiver,
+        None => return,
+    };
+    let vpid = parent.vpid_of(child).unwrap_or(0);
+    let _ = crate::trap::push_trap_record(
+        receiver.getpid(),
+        crate::trap::UserTrapRecord {
+            cause: CHILD_EXIT_CAUSE,
+            message: vpid << 32 | status.code as u32 as usize,
+        },
+    );
+}
+
+/// SIGSTOP: the task is parked in the pool the next time it is fetched
+/// or switched out, see `Processor::run_next` and `Processor::suspend_current`
+pub fn stop_task(task: Arc<TaskControlBlock>) -> Result<(), isize> {
+    let mut inner = task.acquire_inner_lock();
+    match inner.task_status {
+        TaskStatus::Ready | TaskStatus::Running(_) => {
+            inner.task_status = TaskStatus::Stopped;
+            inner.is_stop_reported = false;
+            Ok(())
+        }
+        TaskStatus::Stopped => Ok(()),
+        TaskStatus::Zombie => Err(ESRCH),
+    }
+}
+
+/// SIGCONT
+pub fn continue_task(task: Arc<TaskControlBlock>) -> Result<(), isize> {
+    let mut pool = TASK_POOL.lock();
+    let mut inner = task.acquire_inner_lock();
+    match inner.task_status {
+        TaskStatus::Stopped => {
+            inner.task_status = TaskStatus::Ready;
+            drop(inner);
+            // a stopped task which has not been parked yet is still queued or running
+            if pool.sleeping_tasks.contains(&task) {
+                pool.wake(task);
+            }
+            Ok(())
+        }
+        TaskStatus::Zombie => Err(ESRCH),
+        _ => Ok(()),
+    }
+}
+
+pub fn exit_current_and_run_next(exit_status: ExitStatus) {
+    report_uipi_leaks();
     // ++++++ hold initproc PCB lock here
     let mut initproc_inner = INITPROC.acquire_inner_lock();
 
@@ -47,24 +162,48 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     // **** hold current PCB lock
     let wl = WAIT_LOCK.lock();
     let mut inner = task.acquire_inner_lock();
+    inner.account_kernel_time();
+    bandwidth::charge(inner.cpu_group, get_time_us() - inner.dispatched_us);
     info!(
-        "pid: {} exited with code {}, time intr: {}, cycle count: {}",
-        task.pid.0, exit_code, inner.time_intr_count, inner.total_cpu_cycle_count
+        "pid: {} exited with code {}, time intr: {}, cycle count: {}, utime: {}us, stime: {}us, irqtime: {}us",
+        task.pid.0,
+        exit_status.code,
+        inner.time_intr_count,
+        inner.total_cpu_cycle_count,
+        ticks_to_us(inner.cpu_times.utime),
+        ticks_to_us(inner.cpu_times.stime),
+        ticks_to_us(inner.cpu_times.irqtime)
     );
-    if let Some(trap_info) = &inner.user_trap_info {
-        trap_info.remove_user_ext_int_map();
-        use riscv::register::sie;
-        unsafe {
-            sie::clear_uext();
-            sie::clear_usoft();
-            sie::clear_utimer();
-        }
+    crate::trap::leave_all_msg_groups(task.pid.0);
+    crate::trap::unbind_all_uipi_names(task.pid.0);
+    crate::mm::pressure::unregister(task.pid.0);
+    // release the trace and event pipes, so that the tracer and the parent see their end
+    inner.syscall_trace = None;
+    inner.uipi_events = None;
+    // give the bandwidth of a deadline task back
+    if let Some(deadline) = inner.deadline.take() {
+        debug!(
+            "pid {} ran out of its deadline budget in {} periods",
+            task.pid.0, deadline.throttled_periods
+        );
     }
+    let _ = inner.release_user_trap(task.pid.0);
 
     // Change status to Zombie
     inner.task_status = TaskStatus::Zombie;
     // Record exit code
-    inner.exit_code = exit_code;
+    inner.exit_status = exit_status;
+    // one line per process for test harnesses to scrape
+    println_colorized!(
+        "[verdict] pid={} reason={} code={} utime_us={} stime_us={}",
+        ANSICON::FgDefault,
+        ANSICON::BgDefault,
+        task.pid.0,
+        exit_status.reason.name(),
+        exit_status.code,
+        ticks_to_us(inner.cpu_times.utime),
+        ticks_to_us(inner.cpu_times.stime)
+    );
     // do not move to its parent but under initproc
 
     for child in inner.children.iter() {
@@ -75,10 +214,19 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     // ++++++ release parent PCB lock here
 
     inner.children.clear();
+    inner.update_max_rss();
     // deallocate user space
     inner.memory_set.recycle_data_pages();
+    let parent = inner.parent.as_ref().and_then(|parent| parent.upgrade());
     drop(inner);
     // **** release current PCB lock
+    if let Some(parent) = parent {
+        notify_child_exit(&parent, &task, exit_status);
+    }
+    if exit_status.reason == ExitReason::UipiFault {
+        // its claims and memberships may not be all its own, as its state was broken
+        crate::trap::repair_uipi_state();
+    }
     // drop task manually to maintain rc correctly
     drop(task);
     drop(wl);
@@ -93,11 +241,48 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     // }
 }
 
+/// Tell a parent which asked for UIPI events what its exiting child still holds,
+/// before any lock is taken as the event pipe may be full
+fn report_uipi_leaks() {
+    let task = current_task().unwrap();
+    let leaked = match &task.acquire_inner_lock().user_trap_info {
+        Some(info) => {
+            1 + info.devices.len()
+                + info.dma_buffers.len()
+                + crate::trap::msg_group_count(task.pid.0)
+        }
+        None => 0,
+    };
+    post_uipi_event(&task, UipiEvent::Exit(leaked));
+}
+
 lazy_static! {
     pub static ref INITPROC: Arc<TaskControlBlock> =
         TaskControlBlock::new(get_app_data_by_name("initproc").unwrap());
 }
 
+/// User allocations fail with `ENOMEM` before they reach `OOM_RESERVE_FRAMES`, so the
+/// kernel itself ate into it. The task with the most frames other than initproc is killed
+/// at its next return to user mode, run by the scheduler loop.
+pub fn oom_kill() {
+    if frames_available() >= OOM_RESERVE_FRAMES / 2 {
+        return;
+    }
+    let victim = match pid::largest_task(&INITPROC) {
+        Some(victim) => victim,
+        None => return,
+    };
+    let mut inner = victim.acquire_inner_lock();
+    if inner.pending_kill.is_none() {
+        warn!(
+            "[kernel] {} frames left, pid {} killed",
+            frames_available(),
+            victim.getpid()
+        );
+        inner.pending_kill = Some(ExitReason::OutOfMemory);
+    }
+}
+
 pub fn add_initproc() {
     debug!("add_initproc");
     add_task(INITPROC.clone());
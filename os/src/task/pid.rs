@@ -3,59 +3,38 @@ use crate::mm::{MapPermission, VirtAddr, KERNEL_SPACE};
 use alloc::collections::BTreeMap;
 use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
 use lazy_static::*;
 use spin::Mutex;
 
 use super::task::TaskControlBlock;
+use crate::util::{Rcu, StackIdAllocator};
 
 struct PidAllocator {
-    current: usize,
-    recycled: Vec<usize>,
-    task_table: BTreeMap<usize, Weak<TaskControlBlock>>,
+    pids: StackIdAllocator,
 }
 
 impl PidAllocator {
     pub fn new() -> Self {
         PidAllocator {
-            current: 0,
-            recycled: Vec::new(),
-            task_table: BTreeMap::new(),
+            // a pid may still be named by pending trap records after exit
+            pids: StackIdAllocator::new(0, usize::MAX).without_reuse(),
         }
     }
     pub fn alloc(&mut self) -> PidHandle {
-        let pid = match self.recycled.pop() {
-            Some(pid) => pid,
-            None => {
-                self.current += 1;
-                self.current - 1
-            }
-        };
-        PidHandle(pid)
-    }
-    pub fn add_task(&mut self, pid: usize, task: Arc<TaskControlBlock>) -> Result<(), usize> {
-        match self.task_table.try_insert(pid, Arc::downgrade(&task)) {
-            Ok(_) => Ok(()),
-            Err(err) => Err(*err.entry.key()),
-        }
+        PidHandle(self.pids.alloc().unwrap())
     }
     pub fn dealloc(&mut self, pid: usize) {
-        assert!(pid < self.current);
-        // assert!(
-        //     self.recycled.iter().find(|ppid| **ppid == pid).is_none(),
-        //     "pid {} has been deallocated!",
-        //     pid
-        // );
-        assert!(
-            self.task_table.remove(&pid).is_some(),
-            "pid {} has been deallocated!",
-            pid
-        );
-        // self.recycled.push(pid);
+        assert!(self.pids.dealloc(pid), "pid {} has been deallocated!", pid);
+        TASK_TABLE.update(|table| table.remove(&pid));
     }
 }
 
 lazy_static! {
     static ref PID_ALLOCATOR: Mutex<PidAllocator> = Mutex::new(PidAllocator::new());
+    /// Looked up by pid on every message and signal, but only changed on fork and exit
+    static ref TASK_TABLE: Rcu<BTreeMap<usize, Weak<TaskControlBlock>>> =
+        Rcu::new(BTreeMap::new());
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
@@ -78,16 +57,34 @@ pub fn pid_alloc() -> PidHandle {
     PID_ALLOCATOR.lock().alloc()
 }
 
+/// The live task other than `except` with the most resident pages
+pub fn largest_task(except: &Arc<TaskControlBlock>) -> Option<Arc<TaskControlBlock>> {
+    let tasks: Vec<Arc<TaskControlBlock>> =
+        TASK_TABLE.read(|table| table.values().filter_map(Weak::upgrade).collect());
+    tasks
+        .into_iter()
+        .filter(|task| !Arc::ptr_eq(task, except))
+        .filter_map(|task| {
+            let inner = task.acquire_inner_lock();
+            if inner.is_zombie() {
+                return None;
+            }
+            let pages = inner.memory_set.resident_pages();
+            drop(inner);
+            Some((pages, task))
+        })
+        .max_by_key(|(pages, _)| *pages)
+        .map(|(_, task)| task)
+}
+
 pub fn add_task_2_map(pid: usize, task: Arc<TaskControlBlock>) {
-    PID_ALLOCATOR.lock().add_task(pid, task).unwrap();
+    let inserted = TASK_TABLE.update(|table| table.try_insert(pid, Arc::downgrade(&task)).is_ok());
+    assert!(inserted, "pid {} is already in the task table", pid);
 }
 
 pub fn find_task(pid: usize) -> Option<Arc<TaskControlBlock>> {
-    PID_ALLOCATOR
-        .lock()
-        .task_table
-        .get(&pid)
-        .and_then(|weak| weak.upgrade())
+    TASK_TABLE
+        .read(|table| table.get(&pid).and_then(|weak| weak.upgrade()))
         .and_then(|strong| {
             if strong.acquire_inner_lock().is_zombie() {
                 None
@@ -97,6 +94,67 @@ pub fn find_task(pid: usize) -> Option<Arc<TaskControlBlock>> {
         })
 }
 
+/// Ids of pid namespaces, 0 is the root namespace which has no `PidNamespace`
+static NEXT_PID_NS_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// A task in a namespace has a pid in it and in every ancestor, and only sees
+/// the tasks which have a pid in its namespace
+#[derive(Debug)]
+pub struct PidNamespace {
+    pub id: usize,
+    parent: Option<Arc<PidNamespace>>,
+    pids: Mutex<NsPids>,
+}
+
+#[derive(Debug)]
+struct NsPids {
+    next: usize,
+    to_global: BTreeMap<usize, usize>,
+    to_local: BTreeMap<usize, usize>,
+}
+
+impl PidNamespace {
+    pub fn new(parent: Option<Arc<PidNamespace>>) -> Arc<Self> {
+        Arc::new(PidNamespace {
+            id: NEXT_PID_NS_ID.fetch_add(1, Relaxed),
+            parent,
+            pids: Mutex::new(NsPids {
+                // the first task of a namespace is its init
+                next: 1,
+                to_global: BTreeMap::new(),
+                to_local: BTreeMap::new(),
+            }),
+        })
+    }
+    fn ancestors(&self) -> impl Iterator<Item = &PidNamespace> {
+        core::iter::successors(Some(self), |ns| ns.parent.as_deref())
+    }
+    /// Give the task with global pid `pid` a pid in this namespace and its ancestors
+    pub fn register(&self, pid: usize) {
+        for ns in self.ancestors() {
+            let mut pids = ns.pids.lock();
+            let local = pids.next;
+            pids.next += 1;
+            pids.to_global.insert(local, pid);
+            pids.to_local.insert(pid, local);
+        }
+    }
+    pub fn unregister(&self, pid: usize) {
+        for ns in self.ancestors() {
+            let mut pids = ns.pids.lock();
+            if let Some(local) = pids.to_local.remove(&pid) {
+                pids.to_global.remove(&local);
+            }
+        }
+    }
+    pub fn to_global(&self, local: usize) -> Option<usize> {
+        self.pids.lock().to_global.get(&local).cloned()
+    }
+    pub fn to_local(&self, pid: usize) -> Option<usize> {
+        self.pids.lock().to_local.get(&pid).cloned()
+    }
+}
+
 /// Return (bottom, top) of a kernel stack in kernel space.
 pub fn kernel_stack_position(app_id: usize) -> (usize, usize) {
     let top = TRAMPOLINE - app_id * (KERNEL_STACK_SIZE + PAGE_SIZE);
@@ -144,5 +202,7 @@ impl Drop for KernelStack {
         KERNEL_SPACE
             .lock()
             .remove_area_with_start_vpn(kernel_stack_bottom_va.into());
+        // other harts may still cache the mappings of this stack
+        crate::ipi::tlb_shootdown(super::hart_id());
     }
 }
@@ -1,8 +1,12 @@
 use alloc::{collections::BTreeSet, sync::Arc};
 use lazy_static::*;
-use spin::Mutex;
 
-use super::{manager::TaskManager, task::TaskControlBlock};
+use super::deadline::{DeadlineParams, DeadlineTask};
+use super::scheduler::SchedPolicy;
+use super::{bandwidth, hart_id, manager::TaskManager, sched_stats, task::TaskControlBlock};
+use crate::syscall::EPERM;
+use crate::timer::get_time_us;
+use crate::util::SpinNoIrq;
 
 pub struct TaskPool {
     pub scheduler: TaskManager,
@@ -10,7 +14,7 @@ pub struct TaskPool {
 }
 
 lazy_static! {
-    pub static ref TASK_POOL: Mutex<TaskPool> = Mutex::new(TaskPool::new());
+    pub static ref TASK_POOL: SpinNoIrq<TaskPool> = SpinNoIrq::new(TaskPool::new());
 }
 
 impl TaskPool {
@@ -22,6 +26,7 @@ impl TaskPool {
     }
 
     pub fn add(&mut self, task: Arc<TaskControlBlock>) {
+        task.acquire_inner_lock().ready_since_us = get_time_us();
         self.scheduler.add(task);
     }
 
@@ -30,28 +35,96 @@ impl TaskPool {
         self.scheduler.remove(&task);
     }
 
-    #[allow(unused)]
     pub fn wake(&mut self, task: Arc<TaskControlBlock>) {
         self.sleeping_tasks.remove(&task);
-        self.scheduler.add(task);
+        self.add(task);
     }
 
-    #[allow(unused)]
     pub fn sleep(&mut self, task: Arc<TaskControlBlock>) {
         self.scheduler.remove(&task);
         self.sleeping_tasks.insert(task);
     }
 
+    /// Tasks of throttled CPU bandwidth groups, tasks not allowed on this hart and tasks
+    /// waiting for a user trap record are skipped and requeued, deadline tasks are only
+    /// limited by their own budget
     pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        self.scheduler.fetch()
+        let now = get_time_us();
+        let hart_bit = 1 << hart_id();
+        for _ in 0..self.scheduler.len() {
+            let task = self.scheduler.fetch()?;
+            let mut inner = task.acquire_inner_lock();
+            let cpu_group = inner.cpu_group;
+            let is_waiting = match inner.user_trap_info.as_mut() {
+                Some(trap_info) if trap_info.is_waiting => {
+                    trap_info.is_waiting = trap_info.user_trap_record_num() == 0;
+                    trap_info.is_waiting
+                }
+                _ => false,
+            };
+            let is_limited = is_waiting
+                || inner.cpu_affinity & hart_bit == 0
+                || match inner.deadline.as_mut() {
+                    // only EDF leaves them out by itself
+                    Some(deadline) => {
+                        deadline.refill(now);
+                        deadline.is_throttled()
+                    }
+                    None => bandwidth::is_throttled(cpu_group),
+                };
+            drop(inner);
+            if !is_limited {
+                return Some(task);
+            }
+            self.scheduler.add(task);
+        }
+        None
     }
 
-    #[allow(unused)]
-    pub fn prioritize(&mut self, pid: usize) {
-        self.scheduler.prioritize(pid);
+    pub fn prioritize(&mut self, pid: usize) -> bool {
+        self.scheduler.prioritize(pid)
     }
 }
 
+/// Switch the scheduling policy while no task is ready, return the previous one
+pub fn set_sched_policy(policy: SchedPolicy) -> Result<SchedPolicy, isize> {
+    TASK_POOL.lock().scheduler.set_policy(policy)
+}
+
+pub fn sched_policy() -> SchedPolicy {
+    TASK_POOL.lock().scheduler.policy()
+}
+
+/// Move `task` into the deadline class with `params`, or out of it with `None`,
+/// requeueing it if it is ready. Without `may_raise`, the task may not reserve more
+/// bandwidth than it holds.
+pub fn set_deadline_params(
+    task: &Arc<TaskControlBlock>,
+    params: Option<DeadlineParams>,
+    may_raise: bool,
+) -> Result<(), isize> {
+    let mut pool = TASK_POOL.lock();
+    let mut inner = task.acquire_inner_lock();
+    let held = inner
+        .deadline
+        .as_ref()
+        .map_or(0, |deadline| deadline.bandwidth());
+    if !may_raise && params.map_or(0, |params| params.bandwidth()) > held {
+        return Err(EPERM);
+    }
+    match (params, inner.deadline.as_mut()) {
+        (Some(params), Some(deadline)) => deadline.update(params)?,
+        (Some(params), None) => inner.deadline = Some(DeadlineTask::new(params)?),
+        (None, _) => inner.deadline = None,
+    }
+    drop(inner);
+    if pool.scheduler.remove(task) {
+        pool.scheduler.add(task.clone());
+    }
+    Ok(())
+}
+
+/// Idle harts poll the pool and never wait for an interrupt, so none is kicked
 pub fn add_task(task: Arc<TaskControlBlock>) {
     // let token = task.acquire_inner_lock().memory_set.token();
     // trace!("task pid: {}, satp: {:#x} added to pool", task.pid.0, token);
@@ -59,10 +132,16 @@ pub fn add_task(task: Arc<TaskControlBlock>) {
 }
 
 pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
-    TASK_POOL.lock().fetch()
+    let mut pool = TASK_POOL.lock();
+    let run_queue_len = pool.scheduler.len();
+    let task = pool.fetch();
+    if task.is_some() {
+        sched_stats::record_dispatch(hart_id(), run_queue_len);
+    }
+    task
 }
 
-#[allow(unused)]
-pub fn prioritize_task(pid: usize) {
-    TASK_POOL.lock().prioritize(pid);
+/// Return false if task `pid` is not ready
+pub fn prioritize_task(pid: usize) -> bool {
+    TASK_POOL.lock().prioritize(pid)
 }
@@ -1,21 +1,69 @@
 use super::TaskContext;
 use super::TaskControlBlock;
 use super::__switch2;
-use super::add_task;
+use super::bandwidth;
+use super::deadline::DEADLINE_TIMER;
+use super::pool::TASK_POOL;
+use super::sched_stats;
 use super::{fetch_task, TaskStatus};
-use crate::config::CPU_NUM;
+use crate::config::{CPU_NUM, DETERMINISTIC};
+use crate::deterministic::{self, DETERMINISTIC_HART, VIRTUAL_IDLE_US};
+use crate::mm::alloc_track;
+use crate::syscall::ESRCH;
+use crate::timer::{get_time_us, get_user_time_us, set_virtual_timer, us_to_ticks};
 use crate::trap::TrapContext;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::arch::asm;
 use core::cell::RefCell;
-use riscv::register::cycle;
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use riscv::register::{cycle, sip, time};
 
 use lazy_static::*;
 lazy_static! {
     pub static ref PROCESSORS: [Processor; CPU_NUM] = Default::default();
 }
 
+/// What a hart is doing, as seen by the other harts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(usize)]
+pub enum HartState {
+    /// In the scheduler loop with nothing to run
+    Idle = 0,
+    InUser = 1,
+    /// Running a task in the kernel, from a syscall or an exception or before returning to it
+    InKernel = 2,
+    /// Handling an interrupt
+    InTrap = 3,
+}
+
+impl HartState {
+    fn from_usize(state: usize) -> Self {
+        match state {
+            0 => HartState::Idle,
+            1 => HartState::InUser,
+            3 => HartState::InTrap,
+            _ => HartState::InKernel,
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const HART_STATE_INIT: AtomicUsize = AtomicUsize::new(HartState::InKernel as usize);
+/// Harts still booting or not started are never seen idle
+static HART_STATES: [AtomicUsize; CPU_NUM] = [HART_STATE_INIT; CPU_NUM];
+
+/// Record the state this hart enters, return the one it leaves
+pub fn set_hart_state(state: HartState) -> HartState {
+    HartState::from_usize(HART_STATES[hart_id()].swap(state as usize, Relaxed))
+}
+
+pub fn hart_state(hart_id: usize) -> Option<HartState> {
+    HART_STATES
+        .get(hart_id)
+        .map(|state| HartState::from_usize(state.load(Relaxed)))
+}
+
 pub struct Processor {
     inner: RefCell<ProcessorInner>,
 }
@@ -62,19 +110,62 @@ impl Processor {
         &inner.idle_task_cx_ptr as *const usize
     }
 
-    fn run_next(&self, task: Arc<TaskControlBlock>) {
+    /// Return false if the task is stopped and parked instead
+    fn run_next(&self, task: Arc<TaskControlBlock>) -> bool {
         let idle_task_cx_ptr = self.get_idle_task_cx_ptr();
         trace!(
             "[run next] idle task cx ptr: {:x?}, task cx: {:#x?}",
             idle_task_cx_ptr,
             unsafe { &*idle_task_cx_ptr }
         );
+        // the pool lock orders this with SIGCONT, see `suspend_current`
+        let mut pool = TASK_POOL.lock();
         // acquire
         let mut task_inner = task.acquire_inner_lock();
+        if task_inner.is_stopped() {
+            drop(task_inner);
+            pool.sleep(task);
+            return false;
+        }
         let next_task_cx_ptr = task_inner.get_task_cx_ptr();
+        // utvec is writable from user mode, never restore one which leaves user memory
+        let utvec = task_inner.task_cx.utvec;
+        if utvec != 0 && utvec != task_inner.checked_utvec {
+            if task_inner.check_user_trap_vector(utvec).is_ok() {
+                task_inner.checked_utvec = utvec;
+            } else {
+                warn!(
+                    "[run next] pid {} has invalid utvec {:#x}, user interrupts disabled",
+                    task.pid.0, utvec
+                );
+                task_inner.task_cx.utvec = 0;
+                task_inner.task_cx.uie = 0;
+            }
+        }
         task_inner.task_status = TaskStatus::Running(hart_id());
-        if let Some(trap_info) = &task_inner.user_trap_info {
+        drop(pool);
+        let now = get_time_us();
+        sched_stats::record_switch(hart_id(), now - task_inner.ready_since_us);
+        task_inner.dispatched_us = now;
+        if task_inner.first_run_us.is_none() {
+            task_inner.first_run_us = Some(get_user_time_us());
+        }
+        if let Some(deadline) = &task_inner.deadline {
+            // preempt it when its budget runs out, unless it yields before
+            set_virtual_timer(
+                time::read() + us_to_ticks(deadline.remaining_us(0)),
+                DEADLINE_TIMER,
+            );
+        }
+        if let Some(trap_info) = &mut task_inner.user_trap_info {
             trap_info.enable_user_ext_int();
+            trap_info.start_time_slice();
+            let task_cx = unsafe { &mut *next_task_cx_ptr };
+            task_cx.merge_user_soft_pending(
+                !trap_info.get_trap_queue().is_empty()
+                    && !trap_info.is_polling
+                    && !trap_info.is_masked,
+            );
         }
         let task_cx = unsafe { &*next_task_cx_ptr };
         trace!(
@@ -83,42 +174,80 @@ impl Processor {
             task_cx
         );
         task_inner.last_cpu_cycle = cycle::read();
+        task_inner.time_mark = time::read();
         // release
         drop(task_inner);
+        set_hart_state(HartState::InKernel);
+        alloc_track::switch_owner(task.alloc_owner.id());
         self.inner.borrow_mut().current = Some(task);
 
         unsafe {
             __switch2(idle_task_cx_ptr, next_task_cx_ptr);
         }
+        alloc_track::switch_owner(0);
+        true
     }
 
     fn suspend_current(&self) {
         trace!("[suspend current]");
         if let Some(task) = take_current_task() {
+            // the pool is locked first, so that a racing SIGCONT sees the task either
+            // running or parked in the pool
+            let mut pool = TASK_POOL.lock();
             // ---- hold current PCB lock
             let mut task_inner = task.acquire_inner_lock();
-            // Change status to Ready
-            task_inner.task_status = TaskStatus::Ready;
+            let is_stopped = task_inner.is_stopped();
+            if !is_stopped {
+                // Change status to Ready
+                task_inner.task_status = TaskStatus::Ready;
+            }
             if let Some(trap_info) = &task_inner.user_trap_info {
                 trap_info.disable_user_ext_int();
             }
             task_inner.total_cpu_cycle_count += cycle::read() - task_inner.last_cpu_cycle;
+            task_inner.account_kernel_time();
+            let running_us = get_time_us() - task_inner.dispatched_us;
+            bandwidth::charge(task_inner.cpu_group, running_us);
+            if let Some(deadline) = &mut task_inner.deadline {
+                deadline.charge(running_us);
+            }
             drop(task_inner);
             // ---- release current PCB lock
 
-            // push back to ready queue.
-            add_task(task);
+            if is_stopped {
+                // parked until SIGCONT
+                pool.sleep(task);
+            } else {
+                // push back to ready queue.
+                pool.add(task);
+            }
         }
     }
 
     pub fn run(&self) {
         loop {
+            set_hart_state(HartState::Idle);
+            crate::util::rcu::quiescent(hart_id());
+            crate::watchdog::heartbeat(hart_id());
+            // the mailbox is drained here, do not take the interrupt for it later
+            unsafe { sip::clear_ssoft() }
+            crate::ipi::handle_ipis(hart_id());
+            crate::irq_thread::run_pending();
+            crate::mm::pressure::check();
+            super::oom_kill();
+            if DETERMINISTIC && hart_id() != DETERMINISTIC_HART {
+                continue;
+            }
             if let Some(task) = fetch_task() {
                 // unsafe { riscv::asm::sfence_vma_all() }
-                self.run_next(task);
                 // __switch inside run_next
-                // debug!("idle");
-                self.suspend_current();
+                if self.run_next(task) {
+                    // debug!("idle");
+                    self.suspend_current();
+                }
+            } else if DETERMINISTIC {
+                // nothing is ready, let sleeping timers expire
+                deterministic::advance(VIRTUAL_IDLE_US);
             }
         }
     }
@@ -199,7 +328,7 @@ pub fn set_current_priority(priority: isize) -> Result<isize, isize> {
         let mut current = current.acquire_inner_lock();
         current.set_priority(priority)
     } else {
-        Err(-1)
+        Err(ESRCH)
     }
 }
 
@@ -208,7 +337,7 @@ pub fn mmap(start: usize, len: usize, port: usize) -> Result<isize, isize> {
         let mut current = current.acquire_inner_lock();
         current.mmap(start, len, port)
     } else {
-        Err(-1)
+        Err(ESRCH)
     }
 }
 
@@ -217,6 +346,6 @@ pub fn munmap(start: usize, len: usize) -> Result<isize, isize> {
         let mut current = current.acquire_inner_lock();
         current.munmap(start, len)
     } else {
-        Err(-1)
+        Err(ESRCH)
     }
 }
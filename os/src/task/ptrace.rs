@@ -0,0 +1,301 @@
+//! A minimal ptrace for user space debuggers.
+//!
+//! A parent attaches to a child, which is then stopped through the SIGSTOP machinery.
+//! While the child is stopped, the parent can read and write its registers and memory.
+//! RISC-V has no single step in S mode. Stepping puts `c.ebreak` on every possible next
+//! instruction, and the breakpoint trap stops the child again. A breakpoint nobody put
+//! there kills the task, as an unhandled SIGTRAP would.
+
+use super::{continue_task, current_task, stop_task, suspend_current_and_run_next};
+use super::{TaskControlBlock, TaskStatus};
+use crate::ipi;
+use crate::mm::alloc_track::{AllocOwner, SUBSYSTEM_TASK};
+use crate::mm::{copy_from_user, copy_to_user, translated_byte_buffer, MemorySet};
+use crate::syscall::{EBUSY, EINVAL, EPERM, ESRCH};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+pub const PTRACE_PEEKDATA: usize = 2;
+pub const PTRACE_POKEDATA: usize = 5;
+pub const PTRACE_CONT: usize = 7;
+pub const PTRACE_SINGLESTEP: usize = 9;
+pub const PTRACE_GETREGS: usize = 12;
+pub const PTRACE_SETREGS: usize = 13;
+pub const PTRACE_ATTACH: usize = 16;
+pub const PTRACE_DETACH: usize = 17;
+
+/// `c.ebreak`
+const C_EBREAK: u16 = 0x9002;
+
+/// Registers exchanged by `PTRACE_GETREGS` and `PTRACE_SETREGS`
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PtraceRegs {
+    pub x: [usize; 32],
+    pub pc: usize,
+}
+
+/// Argument of `PTRACE_PEEKDATA` and `PTRACE_POKEDATA`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct PtraceWord {
+    pub addr: usize,
+    pub data: usize,
+}
+
+/// Attached to a traced task
+pub struct PtraceState {
+    pub tracer_pid: usize,
+    /// (address, original halfword) of the breakpoints put by a single step
+    pub breakpoints: Vec<(usize, u16)>,
+}
+
+fn as_bytes<T>(value: &T) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+fn as_bytes_mut<T>(value: &mut T) -> &mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(value as *mut T as *mut u8, size_of::<T>()) }
+}
+
+fn read_u16(token: usize, addr: usize) -> Result<u16, isize> {
+    let mut halfword = 0u16;
+    copy_from_user(token, addr as *const u8, as_bytes_mut(&mut halfword))?;
+    Ok(halfword)
+}
+
+fn sign_extend(value: usize, bits: usize) -> usize {
+    let shift = 64 - bits;
+    (((value << shift) as isize) >> shift) as usize
+}
+
+fn bit(inst: usize, from: usize, to: usize) -> usize {
+    ((inst >> from) & 1) << to
+}
+
+/// Every address the instruction at `pc` may go to
+fn next_pcs(inst: usize, pc: usize, x: &[usize; 32]) -> Vec<usize> {
+    if inst & 0b11 != 0b11 {
+        let op = inst & 0b11;
+        let funct3 = (inst >> 13) & 0b111;
+        let rs1 = (inst >> 7) & 0x1f;
+        let rs2 = (inst >> 2) & 0x1f;
+        match (op, funct3) {
+            // c.j
+            (0b01, 0b101) => {
+                let imm = bit(inst, 12, 11)
+                    | bit(inst, 11, 4)
+                    | bit(inst, 10, 9)
+                    | bit(inst, 9, 8)
+                    | bit(inst, 8, 10)
+                    | bit(inst, 7, 6)
+                    | bit(inst, 6, 7)
+                    | bit(inst, 5, 3)
+                    | bit(inst, 4, 2)
+                    | bit(inst, 3, 1)
+                    | bit(inst, 2, 5);
+                vec![pc.wrapping_add(sign_extend(imm, 12))]
+            }
+            // c.beqz, c.bnez
+            (0b01, 0b110) | (0b01, 0b111) => {
+                let imm = bit(inst, 12, 8)
+                    | bit(inst, 11, 4)
+                    | bit(inst, 10, 3)
+                    | bit(inst, 6, 7)
+                    | bit(inst, 5, 6)
+                    | bit(inst, 4, 2)
+                    | bit(inst, 3, 1)
+                    | bit(inst, 2, 5);
+                vec![pc + 2, pc.wrapping_add(sign_extend(imm, 9))]
+            }
+            // c.jr, c.jalr
+            (0b10, 0b100) if rs2 == 0 && rs1 != 0 => vec![x[rs1] & !1],
+            _ => vec![pc + 2],
+        }
+    } else {
+        let rs1 = (inst >> 15) & 0x1f;
+        match inst & 0x7f {
+            // jal
+            0x6f => {
+                let imm = (((inst >> 31) & 1) << 20)
+                    | (((inst >> 21) & 0x3ff) << 1)
+                    | (((inst >> 20) & 1) << 11)
+                    | (((inst >> 12) & 0xff) << 12);
+                vec![pc.wrapping_add(sign_extend(imm, 21))]
+            }
+            // jalr
+            0x67 => vec![x[rs1].wrapping_add(sign_extend(inst >> 20, 12)) & !1],
+            // branch
+            0x63 => {
+                let imm = (((inst >> 31) & 1) << 12)
+                    | (((inst >> 25) & 0x3f) << 5)
+                    | (((inst >> 8) & 0xf) << 1)
+                    | (((inst >> 7) & 1) << 11);
+                vec![pc + 4, pc.wrapping_add(sign_extend(imm, 13))]
+            }
+            _ => vec![pc + 4],
+        }
+    }
+}
+
+/// Write to the memory of `owner`, which may be read-only text shared with other
+/// processes, those pages get a private copy first. The write may be to code, so every
+/// hart fetches instructions anew before the tracee runs again, wherever it is scheduled.
+fn poke(
+    owner: &AllocOwner,
+    memory_set: &mut MemorySet,
+    addr: usize,
+    bytes: &[u8],
+) -> Result<(), isize> {
+    let _scope = owner.scope(SUBSYSTEM_TASK);
+    memory_set.unshare(addr, bytes.len());
+    let mut start = 0;
+    for buffer in translated_byte_buffer(memory_set.token(), addr as *const u8, bytes.len())? {
+        buffer.copy_from_slice(&bytes[start..start + buffer.len()]);
+        start += buffer.len();
+    }
+    ipi::sync_icache();
+    Ok(())
+}
+
+fn remove_breakpoints(owner: &AllocOwner, memory_set: &mut MemorySet, state: &mut PtraceState) {
+    for (addr, halfword) in state.breakpoints.drain(..) {
+        let _ = poke(owner, memory_set, addr, as_bytes(&halfword));
+    }
+}
+
+fn find_tracee(pid: usize) -> Result<Arc<TaskControlBlock>, isize> {
+    let current_task = current_task().unwrap();
+    let inner = current_task.acquire_inner_lock();
+    inner
+        .children
+        .iter()
+        .find(|child| current_task.vpid_of(child) == Some(pid))
+        .cloned()
+        .ok_or(ESRCH)
+}
+
+/// Return the result of the request, `Err(ESRCH)` if `pid` is not a child traced by the caller
+/// and `Err(EBUSY)` if the request needs a stopped tracee but it is still running
+pub fn ptrace(request: usize, pid: usize, arg: usize) -> Result<isize, isize> {
+    let tracer = current_task().unwrap();
+    let tracer_pid = tracer.getpid();
+    let tracer_token = tracer.acquire_inner_lock().get_user_token();
+    drop(tracer);
+    let tracee = find_tracee(pid)?;
+    if request == PTRACE_ATTACH {
+        let mut inner = tracee.acquire_inner_lock();
+        if inner.ptrace.is_some() {
+            return Err(EPERM);
+        }
+        inner.ptrace = Some(PtraceState {
+            tracer_pid,
+            breakpoints: Vec::new(),
+        });
+        drop(inner);
+        stop_task(tracee)?;
+        return Ok(0);
+    }
+    let mut inner = tracee.acquire_inner_lock();
+    match &inner.ptrace {
+        Some(state) if state.tracer_pid == tracer_pid => {}
+        _ => return Err(ESRCH),
+    }
+    if request == PTRACE_DETACH {
+        let mut state = inner.ptrace.take().unwrap();
+        remove_breakpoints(&tracee.alloc_owner, &mut inner.memory_set, &mut state);
+        drop(inner);
+        continue_task(tracee)?;
+        return Ok(0);
+    }
+    // registers and memory are only stable while the tracee is parked
+    if inner.task_status != TaskStatus::Stopped {
+        return Err(EBUSY);
+    }
+    let token = inner.get_user_token();
+    match request {
+        PTRACE_PEEKDATA | PTRACE_POKEDATA => {
+            let mut word = PtraceWord::default();
+            copy_from_user(tracer_token, arg as *const u8, as_bytes_mut(&mut word))?;
+            if request == PTRACE_PEEKDATA {
+                copy_from_user(token, word.addr as *const u8, as_bytes_mut(&mut word.data))?;
+                copy_to_user(tracer_token, arg as *mut u8, as_bytes(&word))?;
+            } else {
+                poke(
+                    &tracee.alloc_owner,
+                    &mut inner.memory_set,
+                    word.addr,
+                    as_bytes(&word.data),
+                )?;
+            }
+            Ok(0)
+        }
+        PTRACE_GETREGS => {
+            let trap_cx = inner.get_trap_cx();
+            let regs = PtraceRegs {
+                x: trap_cx.x,
+                pc: trap_cx.sepc,
+            };
+            copy_to_user(tracer_token, arg as *mut u8, as_bytes(&regs))?;
+            Ok(0)
+        }
+        PTRACE_SETREGS => {
+            let mut regs = PtraceRegs { x: [0; 32], pc: 0 };
+            copy_from_user(tracer_token, arg as *const u8, as_bytes_mut(&mut regs))?;
+            let trap_cx = inner.get_trap_cx();
+            trap_cx.x[1..].copy_from_slice(&regs.x[1..]);
+            trap_cx.sepc = regs.pc;
+            Ok(0)
+        }
+        PTRACE_CONT | PTRACE_SINGLESTEP => {
+            if request == PTRACE_SINGLESTEP {
+                let trap_cx = inner.get_trap_cx();
+                let (pc, x) = (trap_cx.sepc, trap_cx.x);
+                let mut inst = read_u16(token, pc)? as usize;
+                if inst & 0b11 == 0b11 {
+                    inst |= (read_u16(token, pc + 2)? as usize) << 16;
+                }
+                let inner = &mut *inner;
+                let state = inner.ptrace.as_mut().unwrap();
+                for addr in next_pcs(inst, pc, &x) {
+                    if state.breakpoints.iter().any(|(bp, _)| *bp == addr) {
+                        continue;
+                    }
+                    let halfword = read_u16(token, addr)?;
+                    poke(
+                        &tracee.alloc_owner,
+                        &mut inner.memory_set,
+                        addr,
+                        as_bytes(&C_EBREAK),
+                    )?;
+                    state.breakpoints.push((addr, halfword));
+                }
+            }
+            drop(inner);
+            continue_task(tracee)?;
+            Ok(0)
+        }
+        _ => Err(EINVAL),
+    }
+}
+
+/// Called on a breakpoint trap, return false if it was not put by a single step
+pub fn handle_ptrace_breakpoint() -> bool {
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    let sepc = inner.get_trap_cx().sepc;
+    let inner_ref = &mut *inner;
+    match inner_ref.ptrace.as_mut() {
+        Some(state) if state.breakpoints.iter().any(|(addr, _)| *addr == sepc) => {
+            // the original instruction runs when the tracee is continued
+            remove_breakpoints(&task.alloc_owner, &mut inner_ref.memory_set, state);
+        }
+        _ => return false,
+    }
+    drop(inner);
+    let _ = stop_task(task);
+    suspend_current_and_run_next();
+    true
+}
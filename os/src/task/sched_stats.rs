@@ -0,0 +1,113 @@
+use super::processor::hart_state;
+use crate::config::{CPU_NUM, TRAP_STACK_SIZE};
+use crate::trap::trap_stack_high_water;
+use alloc::string::String;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+/// Upper bounds (us) of the dispatch latency buckets, the last bucket takes the rest
+const LATENCY_BUCKET_BOUNDS_US: [usize; LATENCY_BUCKET_NUM - 1] = [10, 100, 1000, 10_000, 100_000];
+pub const LATENCY_BUCKET_NUM: usize = 6;
+
+struct HartSchedStats {
+    switch_count: AtomicUsize,
+    /// Run queue length sampled on every dispatch
+    run_queue_samples: AtomicUsize,
+    run_queue_len_sum: AtomicUsize,
+    run_queue_len_max: AtomicUsize,
+    /// Time from becoming ready to running
+    latency_hist: [AtomicUsize; LATENCY_BUCKET_NUM],
+    latency_sum_us: AtomicUsize,
+}
+
+impl HartSchedStats {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicUsize = AtomicUsize::new(0);
+
+    const fn new() -> Self {
+        Self {
+            switch_count: AtomicUsize::new(0),
+            run_queue_samples: AtomicUsize::new(0),
+            run_queue_len_sum: AtomicUsize::new(0),
+            run_queue_len_max: AtomicUsize::new(0),
+            latency_hist: [Self::ZERO; LATENCY_BUCKET_NUM],
+            latency_sum_us: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const HART_SCHED_STATS_INIT: HartSchedStats = HartSchedStats::new();
+static HART_SCHED_STATS: [HartSchedStats; CPU_NUM] = [HART_SCHED_STATS_INIT; CPU_NUM];
+
+/// Snapshot returned by `sys_sched_stats`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct SchedStats {
+    pub switch_count: usize,
+    pub run_queue_samples: usize,
+    pub run_queue_len_sum: usize,
+    pub run_queue_len_max: usize,
+    pub latency_hist: [usize; LATENCY_BUCKET_NUM],
+    pub latency_sum_us: usize,
+    /// `HartState` at the time of the snapshot
+    pub state: usize,
+}
+
+/// Called when a task is fetched, with the length of the run queue before fetching
+pub fn record_dispatch(hart_id: usize, run_queue_len: usize) {
+    let stats = &HART_SCHED_STATS[hart_id];
+    stats.run_queue_samples.fetch_add(1, Relaxed);
+    stats.run_queue_len_sum.fetch_add(run_queue_len, Relaxed);
+    stats.run_queue_len_max.fetch_max(run_queue_len, Relaxed);
+}
+
+/// Called when a task is switched in after waiting `latency_us` in the run queue
+pub fn record_switch(hart_id: usize, latency_us: usize) {
+    let stats = &HART_SCHED_STATS[hart_id];
+    stats.switch_count.fetch_add(1, Relaxed);
+    let bucket = LATENCY_BUCKET_BOUNDS_US
+        .iter()
+        .position(|bound| latency_us < *bound)
+        .unwrap_or(LATENCY_BUCKET_NUM - 1);
+    stats.latency_hist[bucket].fetch_add(1, Relaxed);
+    stats.latency_sum_us.fetch_add(latency_us, Relaxed);
+}
+
+pub fn sched_stats(hart_id: usize) -> Option<SchedStats> {
+    let stats = HART_SCHED_STATS.get(hart_id)?;
+    let mut latency_hist = [0; LATENCY_BUCKET_NUM];
+    for (count, bucket) in latency_hist.iter_mut().zip(stats.latency_hist.iter()) {
+        *count = bucket.load(Relaxed);
+    }
+    Some(SchedStats {
+        switch_count: stats.switch_count.load(Relaxed),
+        run_queue_samples: stats.run_queue_samples.load(Relaxed),
+        run_queue_len_sum: stats.run_queue_len_sum.load(Relaxed),
+        run_queue_len_max: stats.run_queue_len_max.load(Relaxed),
+        latency_hist,
+        latency_sum_us: stats.latency_sum_us.load(Relaxed),
+        state: hart_state(hart_id)? as usize,
+    })
+}
+
+/// Scheduling of every hart and how deep its trap stack went, for `/proc/sched`
+pub fn report() -> String {
+    let mut report = String::new();
+    for hart_id in 0..CPU_NUM {
+        let stats = match sched_stats(hart_id) {
+            Some(stats) => stats,
+            None => continue,
+        };
+        let _ = writeln!(
+            report,
+            "hart {} switches {} run_queue_max {} trap_stack {}/{}",
+            hart_id,
+            stats.switch_count,
+            stats.run_queue_len_max,
+            trap_stack_high_water(hart_id),
+            TRAP_STACK_SIZE
+        );
+    }
+    report
+}
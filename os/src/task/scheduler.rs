@@ -0,0 +1,221 @@
+//! Scheduling policies of the ready queue, chosen at build time by the
+//! `sched_rr` and `sched_priority` features, EDF without either, and
+//! switchable while no task is ready with `sys_sched_policy`.
+//!
+//! Whatever the policy, a task of the deadline class is not run past its
+//! budget, but only EDF runs such tasks first, ordered by their deadlines.
+
+use super::TaskControlBlock;
+use crate::timer::get_time_us;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+pub trait Scheduler: Send {
+    fn add(&mut self, task: Arc<TaskControlBlock>);
+    /// Return false if the task is not queued
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool;
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>>;
+    fn len(&self) -> usize;
+    /// Make task `pid` the next fetched, as far as the policy allows,
+    /// return false if it is not queued
+    fn prioritize(&mut self, pid: usize) -> bool;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedPolicy {
+    RoundRobin = 0,
+    Priority = 1,
+    Edf = 2,
+}
+
+impl SchedPolicy {
+    pub fn from_usize(policy: usize) -> Option<Self> {
+        match policy {
+            0 => Some(SchedPolicy::RoundRobin),
+            1 => Some(SchedPolicy::Priority),
+            2 => Some(SchedPolicy::Edf),
+            _ => None,
+        }
+    }
+
+    /// The policy the kernel boots with
+    pub fn boot_default() -> Self {
+        if cfg!(feature = "sched_rr") {
+            SchedPolicy::RoundRobin
+        } else if cfg!(feature = "sched_priority") {
+            SchedPolicy::Priority
+        } else {
+            SchedPolicy::Edf
+        }
+    }
+
+    pub fn new_scheduler(self) -> Box<dyn Scheduler> {
+        match self {
+            SchedPolicy::RoundRobin => Box::new(RoundRobin::default()),
+            SchedPolicy::Priority => Box::new(PriorityScheduler::default()),
+            SchedPolicy::Edf => Box::new(EdfScheduler::default()),
+        }
+    }
+}
+
+fn remove_from(q: &mut VecDeque<Arc<TaskControlBlock>>, task: &Arc<TaskControlBlock>) -> bool {
+    match q.iter().position(|t| t == task) {
+        Some(idx) => {
+            q.remove(idx);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Move task `pid` to the front of `q`, return false if it is not there
+fn move_to_front(q: &mut VecDeque<Arc<TaskControlBlock>>, pid: usize) -> bool {
+    if q.is_empty() {
+        return false;
+    }
+    let front_pid = q.front().unwrap().pid.0;
+    if front_pid == pid {
+        debug!("[Taskmgr] Task {} already at front", pid);
+
+        return true;
+    }
+    q.rotate_left(1);
+    while {
+        let f_pid = q.front().unwrap().pid.0;
+        f_pid != pid && f_pid != front_pid
+    } {
+        q.rotate_left(1);
+    }
+    if q.front().unwrap().pid.0 == pid {
+        debug!("[Taskmgr] Prioritized task {}", pid);
+        true
+    } else {
+        false
+    }
+}
+
+/// A simple FIFO scheduler
+#[derive(Default)]
+pub struct RoundRobin {
+    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl Scheduler for RoundRobin {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.ready_queue.push_back(task);
+    }
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        remove_from(&mut self.ready_queue, task)
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        // May need to concern affinity
+        self.ready_queue.pop_front()
+    }
+    fn len(&self) -> usize {
+        self.ready_queue.len()
+    }
+    fn prioritize(&mut self, pid: usize) -> bool {
+        move_to_front(&mut self.ready_queue, pid)
+    }
+}
+
+/// Highest `priority` first, FIFO among equals. Tasks of lower priority
+/// starve while higher ones are ready.
+#[derive(Default)]
+pub struct PriorityScheduler {
+    /// Queues by descending priority
+    levels: Vec<(isize, VecDeque<Arc<TaskControlBlock>>)>,
+    len: usize,
+}
+
+impl Scheduler for PriorityScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let priority = task.acquire_inner_lock().priority;
+        let idx = match self.levels.binary_search_by(|(p, _)| priority.cmp(p)) {
+            Ok(idx) => idx,
+            Err(idx) => {
+                self.levels.insert(idx, (priority, VecDeque::new()));
+                idx
+            }
+        };
+        self.levels[idx].1.push_back(task);
+        self.len += 1;
+    }
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        // the priority may have changed since the task was added
+        let found = self.levels.iter_mut().any(|(_, q)| remove_from(q, task));
+        if found {
+            self.len -= 1;
+        }
+        found
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let task = self.levels.iter_mut().find_map(|(_, q)| q.pop_front())?;
+        self.len -= 1;
+        Some(task)
+    }
+    fn len(&self) -> usize {
+        self.len
+    }
+    /// Only ahead of the tasks of the same priority
+    fn prioritize(&mut self, pid: usize) -> bool {
+        self.levels.iter_mut().any(|(_, q)| move_to_front(q, pid))
+    }
+}
+
+/// Earliest deadline first among the tasks of the deadline class with budget
+/// left, then FIFO
+#[derive(Default)]
+pub struct EdfScheduler {
+    ready_queue: RoundRobin,
+    /// Tasks of the deadline class, few enough to be searched on every fetch
+    deadline_tasks: Vec<Arc<TaskControlBlock>>,
+}
+
+impl EdfScheduler {
+    /// The deadline task with the earliest deadline among those with budget left
+    fn fetch_deadline(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let now = get_time_us();
+        let (idx, _) = self
+            .deadline_tasks
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, task)| {
+                let mut inner = task.acquire_inner_lock();
+                let deadline = inner.deadline.as_mut()?;
+                deadline.refill(now);
+                (!deadline.is_throttled()).then(|| (idx, deadline.abs_deadline_us()))
+            })
+            .min_by_key(|&(_, abs_deadline_us)| abs_deadline_us)?;
+        Some(self.deadline_tasks.swap_remove(idx))
+    }
+}
+
+impl Scheduler for EdfScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        if task.acquire_inner_lock().deadline.is_some() {
+            self.deadline_tasks.push(task);
+        } else {
+            self.ready_queue.add(task);
+        }
+    }
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        if let Some(idx) = self.deadline_tasks.iter().position(|t| t == task) {
+            self.deadline_tasks.swap_remove(idx);
+            return true;
+        }
+        self.ready_queue.remove(task)
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.fetch_deadline().or_else(|| self.ready_queue.fetch())
+    }
+    fn len(&self) -> usize {
+        self.ready_queue.len() + self.deadline_tasks.len()
+    }
+    /// Deadline tasks are only ordered by their deadlines
+    fn prioritize(&mut self, pid: usize) -> bool {
+        self.ready_queue.prioritize(pid)
+    }
+}
@@ -1,27 +1,65 @@
+use super::bandwidth::DEFAULT_CPU_GROUP;
+use super::checkpoint::Checkpoint;
+use super::deadline::DeadlineTask;
+use super::fd_table::FdTable;
+use super::uipi_events::UipiEventSink;
 use super::TaskContext;
-use super::{pid_alloc, KernelStack, PidHandle};
-use crate::fs::{File, MailBox, Serial, Socket, Stdin, Stdout};
-use crate::mm::{translate_writable_va, MemorySet, PhysAddr, PhysPageNum, VirtAddr, KERNEL_SPACE};
+use super::{find_task, pid_alloc, KernelStack, PidHandle, PidNamespace, PtraceState, INITPROC};
+use crate::fs::{MailBox, Socket};
+use crate::mm::alloc_track::{
+    AllocOwner, AllocScope, SUBSYSTEM_EXEC, SUBSYSTEM_FORK, SUBSYSTEM_SPAWN, SUBSYSTEM_TASK,
+};
+use crate::mm::{
+    copy_to_user, translate_writable_va, MemorySet, PhysAddr, PhysPageNum, VirtAddr, KERNEL_SPACE,
+};
+use crate::syscall::{
+    SyscallTrace, E2BIG, EEXIST, EFAULT, EINVAL, EIO, ENOENT, ENOMEM, ETIME, SIGSTOP,
+};
 use crate::task::pid::add_task_2_map;
-use crate::trap::{trap_handler, TrapContext, UserTrapInfo, UserTrapQueue};
+use crate::timer::{boot_us, get_user_time_us, ticks_to_us};
+use crate::trap::{
+    trap_handler, TrapContext, UserTrapDescriptor, UserTrapError, UserTrapInfo, UserTrapQueue,
+    DEFAULT_HANDLER_BUDGET_US, USER_TRAP_REENTRANT, USER_TRAP_STACK_SLOT,
+};
+use crate::util::{SpinNoIrq, SpinNoIrqGuard};
 use crate::{
-    config::{PAGE_SIZE, TRAP_CONTEXT, USER_TRAP_BUFFER},
+    config::{kernel_config, PAGE_SIZE, TRAP_CONTEXT, USER_STACK_SIZE, USER_TRAP_BUFFER},
     loader::get_app_data_by_name,
-    mm::translated_str,
+    mm::{translated_refmut, translated_str},
 };
+use alloc::string::String;
 use alloc::sync::{Arc, Weak};
 use alloc::vec;
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
-use spin::{Mutex, MutexGuard};
+use core::mem::size_of;
+use core::sync::atomic::AtomicUsize;
+use riscv::register::time;
+
+/// `spawn` flag: randomize the layout of the new address space
+pub const SPAWN_RANDOMIZE: usize = 1;
+/// `spawn` flag: put the child into a new pid namespace nested in the one of the caller,
+/// with `FdTable::initial` for fds
+pub const SPAWN_NEW_PID_NS: usize = 2;
+/// `spawn` flag: report the UIPI activity of the child to a pipe of the caller
+pub const SPAWN_NOTIFY_PARENT: usize = 4;
 
 #[derive(Debug)]
 pub struct TaskControlBlock {
     // immutable
     pub pid: PidHandle,
+    /// `None` for the root namespace
+    pub pid_ns: Option<Arc<PidNamespace>>,
+    /// May change the kernel, see `sys_service_ctl`. Only initproc and the tasks it creates
+    /// outside a new pid namespace have it, it is neither inherited nor gained by reparenting.
+    pub privileged: bool,
     pub kernel_stack: KernelStack,
     // mutable
-    inner: Mutex<TaskControlBlockInner>,
+    /// Number plus one of the syscall the task is in, 0 outside of one, see `Checkpoint`
+    pub in_syscall: AtomicUsize,
+    inner: SpinNoIrq<TaskControlBlockInner>,
+    /// Last so that it drops after everything else of the task
+    pub alloc_owner: AllocOwner,
 }
 
 pub struct TaskControlBlockInner {
@@ -35,12 +73,192 @@ pub struct TaskControlBlockInner {
     pub memory_set: MemorySet,
     pub parent: Option<Weak<TaskControlBlock>>,
     pub children: Vec<Arc<TaskControlBlock>>,
-    pub exit_code: i32,
-    pub fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
+    pub exit_status: ExitStatus,
+    /// Whether the parent has been told about the latest stop by waitpid
+    pub is_stop_reported: bool,
+    pub syscall_trace: Option<SyscallTrace>,
+    /// Set for a child spawned with `SPAWN_NOTIFY_PARENT`, not inherited by fork
+    pub uipi_events: Option<UipiEventSink>,
+    /// The task told of the exits of the children, see `notify_child_exit`, not inherited
+    pub child_notify: Option<Weak<TaskControlBlock>>,
+    /// The task whose `child_notify` may name this one, see `sys_child_notify`
+    pub child_notify_accept: Option<Weak<TaskControlBlock>>,
+    pub ptrace: Option<PtraceState>,
+    /// When the task was last put into the ready queue
+    pub ready_since_us: usize,
+    /// When the task was last switched in
+    pub dispatched_us: usize,
+    /// User clock when the task was created, see `TaskInfo`
+    pub start_us: usize,
+    /// User clock when the task first ran, `None` until then
+    pub first_run_us: Option<usize>,
+    /// CPU bandwidth group, see `bandwidth`
+    pub cpu_group: usize,
+    /// Harts the task may run on, one bit per hart id, inherited
+    pub cpu_affinity: usize,
+    /// `None` unless the task is in the deadline class, never inherited
+    pub deadline: Option<DeadlineTask>,
+    /// Set when another task found the task beyond repair, see `poison_user_trap`,
+    /// it is killed with this reason the next time it would return to user mode
+    pub pending_kill: Option<ExitReason>,
+    pub fd_table: FdTable,
     pub mail_box: Arc<MailBox>,
     pub time_intr_count: usize,
     pub total_cpu_cycle_count: usize,
     pub last_cpu_cycle: usize,
+    /// CPU time consumed by this task, in `time` CSR ticks
+    pub cpu_times: CpuTimes,
+    /// CPU time consumed by reaped children, in `time` CSR ticks
+    pub children_cpu_times: CpuTimes,
+    pub usage: UsageCounters,
+    /// Of the children reaped by `waitpid`
+    pub children_usage: UsageCounters,
+    /// `time` CSR value at the last accounting point
+    pub time_mark: usize,
+    /// Whether the kernel is currently handling an interrupt for this task
+    pub is_in_irq: bool,
+    /// Last utvec which passed `check_user_trap_vector`, so that it is checked once
+    pub checked_utvec: usize,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CpuTimes {
+    pub utime: usize,
+    pub stime: usize,
+    pub irqtime: usize,
+}
+
+/// Per-task accounting exported by `sys_task_info`, times are in microseconds
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TaskInfo {
+    pub pid: usize,
+    pub utime_us: usize,
+    pub stime_us: usize,
+    pub irqtime_us: usize,
+    /// Sums over the children reaped by `waitpid`
+    pub children_utime_us: usize,
+    pub children_stime_us: usize,
+    pub children_irqtime_us: usize,
+    pub time_intr_count: usize,
+    pub cpu_cycle_count: usize,
+    /// On the clock of `CLOCK_MONOTONIC`: when the task was created by fork, spawn or
+    /// restore, when it first ran (0 until then), and when the kernel started its first task
+    pub start_us: usize,
+    pub first_run_us: usize,
+    pub boot_us: usize,
+}
+
+impl CpuTimes {
+    pub fn add(&mut self, other: &CpuTimes) {
+        self.utime += other.utime;
+        self.stime += other.stime;
+        self.irqtime += other.irqtime;
+    }
+}
+
+/// Resource usage besides CPU time, reported by `wait4`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UsageCounters {
+    /// Most frames the address space has had at a time
+    pub max_rss_pages: usize,
+    /// Switches out in a syscall, by yielding or blocking
+    pub nvcsw: usize,
+    /// Switches out on a timer interrupt
+    pub nivcsw: usize,
+    /// Messages delivered by `send_msg` and `send_group_msg`
+    pub uipi_sent: usize,
+    /// Records pushed into the trap queue, from any source
+    pub uipi_received: usize,
+    /// Bytes read from and written to pipes
+    pub pipe_bytes: usize,
+    /// Times a pipe read or write had to wait for the other end
+    pub pipe_waits: usize,
+}
+
+impl UsageCounters {
+    /// Like getrusage(RUSAGE_CHILDREN), the RSS is the largest among the children
+    pub fn add(&mut self, other: &UsageCounters) {
+        self.max_rss_pages = self.max_rss_pages.max(other.max_rss_pages);
+        self.nvcsw += other.nvcsw;
+        self.nivcsw += other.nivcsw;
+        self.uipi_sent += other.uipi_sent;
+        self.uipi_received += other.uipi_received;
+        self.pipe_bytes += other.pipe_bytes;
+        self.pipe_waits += other.pipe_waits;
+    }
+}
+
+/// Filled by `wait4`, times are in microseconds
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Rusage {
+    pub utime_us: usize,
+    pub stime_us: usize,
+    pub irqtime_us: usize,
+    pub max_rss_kb: usize,
+    pub nvcsw: usize,
+    pub nivcsw: usize,
+    pub uipi_sent: usize,
+    pub uipi_received: usize,
+    pub pipe_bytes: usize,
+    pub pipe_waits: usize,
+}
+
+/// Filled by `times`, times are in microseconds
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Tms {
+    pub utime_us: usize,
+    pub stime_us: usize,
+    /// Sums over the children reaped by `waitpid`
+    pub children_utime_us: usize,
+    pub children_stime_us: usize,
+}
+
+/// Most bytes the arguments and the environment may take on the user stack
+const MAX_ARGS_SIZE: usize = USER_STACK_SIZE / 4;
+
+/// Lay out `args` and `envs` on the user stack of a new address space like
+/// the System V ABI does: the returned stack pointer points to argc, followed
+/// by the null-terminated argv and envp arrays, the strings are above them.
+fn push_args(
+    memory_set: &MemorySet,
+    user_sp: usize,
+    args: &[String],
+    envs: &[String],
+) -> Result<usize, isize> {
+    let size: usize = args.iter().chain(envs).map(|s| s.len() + 1).sum::<usize>()
+        + (args.len() + envs.len() + 3) * size_of::<usize>();
+    if size > MAX_ARGS_SIZE {
+        return Err(E2BIG);
+    }
+    let token = memory_set.token();
+    let mut sp = user_sp;
+    let mut push_str = |s: &String| -> Result<usize, isize> {
+        sp -= s.len() + 1;
+        copy_to_user(token, sp as *mut u8, s.as_bytes())?;
+        copy_to_user(token, (sp + s.len()) as *mut u8, &[0])?;
+        Ok(sp)
+    };
+    let env_ptrs = envs
+        .iter()
+        .map(&mut push_str)
+        .collect::<Result<Vec<_>, isize>>()?;
+    let arg_ptrs = args
+        .iter()
+        .map(&mut push_str)
+        .collect::<Result<Vec<_>, isize>>()?;
+    let mut words = vec![args.len()];
+    words.extend(arg_ptrs);
+    words.push(0);
+    words.extend(env_ptrs);
+    words.push(0);
+    let words_size = words.len() * size_of::<usize>();
+    sp = (sp - words_size) & !0xf;
+    let bytes = unsafe { core::slice::from_raw_parts(words.as_ptr() as *const u8, words_size) };
+    copy_to_user(token, sp as *mut u8, bytes)?;
+    Ok(sp)
 }
 
 impl Debug for TaskControlBlockInner {
@@ -73,10 +291,59 @@ impl TaskControlBlockInner {
     pub fn is_zombie(&self) -> bool {
         self.get_status() == TaskStatus::Zombie
     }
+    pub fn is_stopped(&self) -> bool {
+        self.get_status() == TaskStatus::Stopped
+    }
+
+    pub fn update_max_rss(&mut self) {
+        let pages = self.memory_set.resident_pages();
+        self.usage.max_rss_pages = self.usage.max_rss_pages.max(pages);
+    }
+
+    /// Usage of the task itself and of its reaped children, as `wait4` reports a child
+    pub fn rusage(&self) -> Rusage {
+        let mut times = self.cpu_times;
+        times.add(&self.children_cpu_times);
+        let mut usage = self.usage;
+        usage.add(&self.children_usage);
+        Rusage {
+            utime_us: ticks_to_us(times.utime),
+            stime_us: ticks_to_us(times.stime),
+            irqtime_us: ticks_to_us(times.irqtime),
+            max_rss_kb: usage.max_rss_pages * PAGE_SIZE / 1024,
+            nvcsw: usage.nvcsw,
+            nivcsw: usage.nivcsw,
+            uipi_sent: usage.uipi_sent,
+            uipi_received: usage.uipi_received,
+            pipe_bytes: usage.pipe_bytes,
+            pipe_waits: usage.pipe_waits,
+        }
+    }
+
+    /// Charges the time since the last mark to user mode, on trap entry.
+    pub fn account_user_time(&mut self, is_interrupt: bool) {
+        let now = time::read();
+        self.cpu_times.utime += now.wrapping_sub(self.time_mark);
+        self.time_mark = now;
+        self.is_in_irq = is_interrupt;
+    }
+
+    /// Charges the time since the last mark to the kernel, either as
+    /// interrupt time or as system time.
+    pub fn account_kernel_time(&mut self) {
+        let now = time::read();
+        let delta = now.wrapping_sub(self.time_mark);
+        if self.is_in_irq {
+            self.cpu_times.irqtime += delta;
+        } else {
+            self.cpu_times.stime += delta;
+        }
+        self.time_mark = now;
+    }
 
     pub fn set_priority(&mut self, priority: isize) -> Result<isize, isize> {
         if priority < 2 {
-            return Err(-1);
+            return Err(EINVAL);
         }
         self.priority = priority;
         Ok(priority)
@@ -90,15 +357,6 @@ impl TaskControlBlockInner {
         self.memory_set.munmap(start, len)
     }
 
-    pub fn alloc_fd(&mut self) -> usize {
-        if let Some(fd) = (0..self.fd_table.len()).find(|fd| self.fd_table[*fd].is_none()) {
-            fd
-        } else {
-            self.fd_table.push(None);
-            self.fd_table.len() - 1
-        }
-    }
-
     pub fn is_mailbox_full(&self) -> bool {
         self.mail_box.is_full()
     }
@@ -111,8 +369,107 @@ impl TaskControlBlockInner {
         self.get_trap_cx().sstatus.uie()
     }
 
-    pub fn init_user_trap(&mut self) -> Result<isize, isize> {
-        use riscv::register::sstatus;
+    /// The handler depth in the trap buffer, kept by the trap entry of user_lib, is non-zero
+    /// from the handler's entry to its uret, whatever it does with UIE in between
+    pub fn is_in_user_trap_handler(&self) -> bool {
+        self.user_trap_info
+            .as_ref()
+            .map_or(false, |trap_info| trap_info.handler_state().0 > 0)
+    }
+
+    /// A user soft interrupt would be raised on trap return and taken by the task,
+    /// the live uie is the one of the task as this is only asked in its syscalls
+    pub fn has_pending_user_trap(&self) -> bool {
+        use riscv::register::uie;
+        self.is_user_trap_enabled()
+            && uie::read().usoft()
+            && self
+                .user_trap_info
+                .as_ref()
+                .map_or(false, |trap_info| trap_info.will_notify())
+    }
+
+    /// Masked by `sys_uintr_mask`, interrupts for the task go to its trap queue
+    pub fn is_user_trap_masked(&self) -> bool {
+        self.user_trap_info
+            .as_ref()
+            .map_or(false, |trap_info| trap_info.is_masked)
+    }
+
+    /// A user trap vector must be in direct or vectored mode,
+    /// with its base in executable user memory
+    pub fn check_user_trap_vector(&self, utvec: usize) -> Result<(), isize> {
+        const UTVEC_MODE_MASK: usize = 0b11;
+        const UTVEC_MODE_VECTORED: usize = 1;
+        let base = utvec & !UTVEC_MODE_MASK;
+        if utvec & UTVEC_MODE_MASK > UTVEC_MODE_VECTORED
+            || base >= USER_TRAP_BUFFER
+            || !self.memory_set.is_user_executable(base.into())
+        {
+            return Err(EINVAL);
+        }
+        Ok(())
+    }
+
+    /// What trap return is about to restore, sepc from the trap context and the live
+    /// uepc and utvec which user code can write. A bad one is given back as
+    /// `(register, value)`, restoring it would only fault again and again.
+    pub fn check_return_context(&mut self) -> Result<(), (&'static str, usize)> {
+        use riscv::register::{uepc, utvec};
+        let sepc = self.get_trap_cx().sepc;
+        if !self.memory_set.is_user_executable(sepc.into()) {
+            return Err(("sepc", sepc));
+        }
+        let utvec = utvec::read().bits();
+        if utvec != 0 && utvec != self.checked_utvec {
+            self.check_user_trap_vector(utvec)
+                .map_err(|_| ("utvec", utvec))?;
+            self.checked_utvec = utvec;
+        }
+        // uepc is only restored by the uret of a running handler, 0 before the first trap
+        let uepc = uepc::read();
+        if self.is_in_user_trap_handler()
+            && uepc != 0
+            && !self.memory_set.is_user_executable(uepc.into())
+        {
+            return Err(("uepc", uepc));
+        }
+        Ok(())
+    }
+
+    fn check_user_trap_descriptor(&self, descriptor: &UserTrapDescriptor) -> Result<(), isize> {
+        self.check_user_trap_vector(descriptor.entry)?;
+        if descriptor.flags & !USER_TRAP_REENTRANT != 0 {
+            return Err(EINVAL);
+        }
+        let stack_top = descriptor.stack_top;
+        if stack_top != 0 {
+            // a single trap stack cannot hold nested handlers
+            if stack_top % 16 != 0
+                || stack_top >= USER_TRAP_BUFFER
+                || !self.memory_set.is_user_writable((stack_top - 16).into())
+                || descriptor.flags & USER_TRAP_REENTRANT != 0
+            {
+                return Err(EINVAL);
+            }
+        }
+        Ok(())
+    }
+
+    /// Without a descriptor the trap entry is the one already in utvec
+    pub fn init_user_trap(
+        &mut self,
+        descriptor: Option<UserTrapDescriptor>,
+    ) -> Result<isize, isize> {
+        use riscv::register::{mtvec::TrapMode, sstatus, utvec};
+        let descriptor = descriptor.unwrap_or(UserTrapDescriptor {
+            entry: utvec::read().bits(),
+            ..Default::default()
+        });
+        if self.check_user_trap_descriptor(&descriptor).is_err() {
+            warn!("[init user trap] invalid descriptor {:x?}", descriptor);
+            return Err(EINVAL);
+        }
         if self.user_trap_info.is_none() {
             // R | W
             if self.mmap(USER_TRAP_BUFFER, PAGE_SIZE, 0b11).is_ok() {
@@ -121,20 +478,82 @@ impl TaskControlBlockInner {
                 self.user_trap_info = Some(UserTrapInfo {
                     user_trap_buffer_ppn: PhysPageNum::from(PhysAddr::from(phys_addr)),
                     devices: Vec::new(),
+                    mmio_regions: Vec::new(),
+                    dma_buffers: Vec::new(),
+                    max_notify_per_slice: 0,
+                    poll_threshold: 0,
+                    slice_notify_count: 0,
+                    is_polling: false,
+                    is_masked: false,
+                    is_waiting: false,
+                    poisoned: false,
+                    send_quota: Default::default(),
+                    handler_budget_us: DEFAULT_HANDLER_BUDGET_US,
+                    handler_abort_entry: 0,
+                    handler_seen: None,
                 });
                 let trap_queue = self.user_trap_info.as_mut().unwrap().get_trap_queue_mut();
                 *trap_queue = UserTrapQueue::new();
+                *translated_refmut(self.get_user_token(), USER_TRAP_STACK_SLOT as *mut usize) =
+                    descriptor.stack_top;
+                let mode = if descriptor.entry & 1 != 0 {
+                    TrapMode::Vectored
+                } else {
+                    TrapMode::Direct
+                };
                 unsafe {
+                    utvec::write(descriptor.entry & !0b11, mode);
                     sstatus::set_uie();
                 }
                 return Ok(USER_TRAP_BUFFER as isize);
             } else {
                 warn!("[init user trap] mmap failed!");
+                return Err(ENOMEM);
             }
         } else {
             warn!("[init user trap] self user trap info is not None!");
         }
-        Err(-1)
+        Err(EEXIST)
+    }
+
+    /// The user trap state of the task cannot be trusted any more, as the trap buffer was
+    /// unmapped or its queue corrupted: nothing is delivered to it from now on, and it is
+    /// killed on its way back to user mode. Return false if it was poisoned already.
+    pub fn poison_user_trap(&mut self) -> bool {
+        match &mut self.user_trap_info {
+            Some(trap_info) if !trap_info.poisoned => {
+                trap_info.poisoned = true;
+                self.pending_kill = Some(ExitReason::UipiFault);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Give claimed devices back to the kernel, unmap device registers, DMA buffers and the
+    /// trap buffer, dropping the queued records. `pid` is the global pid of the task.
+    pub fn release_user_trap(&mut self, pid: usize) -> Result<(), UserTrapError> {
+        use riscv::register::{sie, uip};
+        let trap_info = self
+            .user_trap_info
+            .take()
+            .ok_or(UserTrapError::TrapUninitialized)?;
+        trap_info.remove_user_ext_int_map(pid);
+        for (start, len) in trap_info.mmio_regions {
+            let _ = self.memory_set.mmio_unmap(start, len);
+        }
+        for buffer in &trap_info.dma_buffers {
+            let _ = self.memory_set.mmio_unmap(buffer.paddr(), buffer.len());
+        }
+        let _ = self.munmap(USER_TRAP_BUFFER, PAGE_SIZE);
+        // the handler reads the trap buffer, it must not run for an interrupt raised before
+        unsafe {
+            sie::clear_uext();
+            sie::clear_usoft();
+            sie::clear_utimer();
+            uip::clear_usoft();
+        }
+        Ok(())
     }
 
     pub fn restore_user_trap_info(&mut self) {
@@ -148,7 +567,7 @@ impl TaskControlBlockInner {
                 //         uip::set_usoft();
                 //     }
                 // }
-                if !trap_info.get_trap_queue().is_empty() {
+                if trap_info.should_notify() {
                     trace!("restore {} user trap", trap_info.user_trap_record_num());
                     uscratch::write(trap_info.user_trap_record_num());
                     unsafe {
@@ -161,28 +580,43 @@ impl TaskControlBlockInner {
 }
 
 impl TaskControlBlock {
-    pub fn acquire_inner_lock(&self) -> MutexGuard<TaskControlBlockInner> {
+    /// Held with supervisor interrupts off, as the trap path takes it too
+    pub fn acquire_inner_lock(&self) -> SpinNoIrqGuard<TaskControlBlockInner> {
         self.inner.lock()
     }
-    pub fn new(elf_data: &[u8]) -> Arc<TaskControlBlock> {
+    pub fn new(elf_data: &'static [u8]) -> Arc<TaskControlBlock> {
+        let alloc_owner = AllocOwner::new();
+        let scope = alloc_owner.scope(SUBSYSTEM_EXEC);
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let (memory_set, user_sp, entry_point) =
+            MemorySet::from_elf(elf_data, kernel_config().aslr).unwrap();
+        let stack_sp = push_args(&memory_set, user_sp, &[], &[]).unwrap();
         let trap_cx_ppn = memory_set
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
             .unwrap()
             .ppn();
+        drop(scope);
         // alloc a pid and a kernel stack in kernel space
         let pid_handle = pid_alloc();
-        let kernel_stack = KernelStack::new(&pid_handle);
+        alloc_owner.set_pid(pid_handle.0);
+        let kernel_stack = {
+            let _scope = alloc_owner.scope(SUBSYSTEM_TASK);
+            KernelStack::new(&pid_handle)
+        };
         let kernel_stack_top = kernel_stack.get_top();
         // push a task context which goes to trap_return to the top of kernel stack
         let task_cx = TaskContext::goto_trap_return(kernel_stack_top);
         let task_cx_ptr = kernel_stack.push_on_top(task_cx.clone());
         trace!("new task cx ptr: {:#x?}", task_cx_ptr as usize);
+        // the control block is freed after the owner inside it, it belongs to the kernel
+        let scope = AllocScope::kernel();
         let task_control_block = Arc::new(TaskControlBlock {
             pid: pid_handle,
+            pid_ns: None,
+            privileged: true,
             kernel_stack,
-            inner: Mutex::new(TaskControlBlockInner {
+            in_syscall: AtomicUsize::new(0),
+            inner: SpinNoIrq::new(TaskControlBlockInner {
                 trap_cx_ppn,
                 base_size: user_sp,
                 task_cx,
@@ -192,32 +626,44 @@ impl TaskControlBlock {
                 memory_set,
                 parent: None,
                 children: Vec::new(),
-                exit_code: 0,
+                exit_status: ExitStatus::exited(0),
+                is_stop_reported: false,
+                syscall_trace: None,
+                uipi_events: None,
+                child_notify: None,
+                child_notify_accept: None,
+                ptrace: None,
+                ready_since_us: 0,
+                dispatched_us: 0,
+                start_us: get_user_time_us(),
+                first_run_us: None,
+                cpu_group: DEFAULT_CPU_GROUP,
+                cpu_affinity: usize::MAX,
+                deadline: None,
+                pending_kill: None,
                 priority: 16,
-                fd_table: vec![
-                    // 0 -> stdin
-                    Some(Arc::new(Stdin)),
-                    // 1 -> stdout
-                    Some(Arc::new(Stdout)),
-                    // 2 -> stderr
-                    Some(Arc::new(Stdout)),
-                    // 3 -> serial 3
-                    Some(Arc::new(Serial::<2>)),
-                    // 4 -> serial 4
-                    Some(Arc::new(Serial::<3>)),
-                ],
+                fd_table: FdTable::initial(),
                 mail_box: Arc::new(MailBox::new()),
                 time_intr_count: 0,
                 total_cpu_cycle_count: 0,
                 last_cpu_cycle: 0,
+                cpu_times: CpuTimes::default(),
+                children_cpu_times: CpuTimes::default(),
+                usage: UsageCounters::default(),
+                children_usage: UsageCounters::default(),
+                time_mark: 0,
+                is_in_irq: false,
+                checked_utvec: 0,
             }),
+            alloc_owner,
         });
+        drop(scope);
         add_task_2_map(task_control_block.getpid(), task_control_block.clone());
         // prepare TrapContext in user space
         let trap_cx = task_control_block.acquire_inner_lock().get_trap_cx();
         *trap_cx = TrapContext::app_init_context(
             entry_point,
-            user_sp,
+            stack_sp,
             KERNEL_SPACE.lock().token(),
             kernel_stack_top,
             trap_handler as usize,
@@ -225,9 +671,17 @@ impl TaskControlBlock {
         task_control_block
     }
 
-    pub fn exec(&self, elf_data: &[u8]) {
+    pub fn exec(
+        &self,
+        elf_data: &'static [u8],
+        args: &[String],
+        envs: &[String],
+    ) -> Result<(), isize> {
+        let _scope = AllocScope::subsystem(SUBSYSTEM_EXEC);
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let (memory_set, user_sp, entry_point) =
+            MemorySet::from_elf(elf_data, kernel_config().aslr)?;
+        let user_sp = push_args(&memory_set, user_sp, args, envs)?;
         let trap_cx_ppn = memory_set
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
             .unwrap()
@@ -236,8 +690,11 @@ impl TaskControlBlock {
         // **** hold current PCB lock
         let mut inner = self.acquire_inner_lock();
         inner.user_trap_info = None;
+        inner.fd_table = inner.fd_table.on_exec();
         // substitute memory_set
         inner.memory_set = memory_set;
+        // the vector was checked against the old address space
+        inner.checked_utvec = 0;
         // update trap_cx ppn
         inner.trap_cx_ppn = trap_cx_ppn;
         // initialize trap_cx
@@ -250,47 +707,70 @@ impl TaskControlBlock {
             trap_handler as usize,
         );
         // **** release current PCB lock
+        Ok(())
+    }
+
+    /// Whether a task created by `self` in `pid_ns` is privileged
+    fn grants_privilege(self: &Arc<TaskControlBlock>, pid_ns: &Option<Arc<PidNamespace>>) -> bool {
+        Arc::ptr_eq(self, &INITPROC) && pid_ns.is_none()
     }
 
-    pub fn fork(self: &Arc<TaskControlBlock>) -> Arc<TaskControlBlock> {
+    /// `ENOMEM` if there are not enough frames for a copy of the user space
+    pub fn fork(self: &Arc<TaskControlBlock>) -> Result<Arc<TaskControlBlock>, isize> {
         // ---- hold parent PCB lock
         let mut parent_inner = self.acquire_inner_lock();
+        let alloc_owner = AllocOwner::new();
+        let scope = alloc_owner.scope(SUBSYSTEM_FORK);
         // copy user space(include trap context)
-        let memory_set = MemorySet::from_existed_user(&parent_inner.memory_set);
+        let memory_set = MemorySet::from_existed_user(&parent_inner.memory_set)?;
         let trap_cx_ppn = memory_set
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
             .unwrap()
             .ppn();
+        drop(scope);
         // alloc a pid and a kernel stack in kernel space
         let pid_handle = pid_alloc();
-        let kernel_stack = KernelStack::new(&pid_handle);
+        alloc_owner.set_pid(pid_handle.0);
+        let pid_ns = self.pid_ns.clone();
+        if let Some(ns) = &pid_ns {
+            ns.register(pid_handle.0);
+        }
+        let kernel_stack = {
+            let _scope = alloc_owner.scope(SUBSYSTEM_TASK);
+            KernelStack::new(&pid_handle)
+        };
         let kernel_stack_top = kernel_stack.get_top();
         // push a goto_trap_return task_cx on the top of kernel stack
-        let task_cx = TaskContext::goto_trap_return(kernel_stack_top);
+        let task_cx = TaskContext::fork_trap_return(kernel_stack_top);
         let task_cx_ptr = kernel_stack.push_on_top(task_cx.clone());
         debug!("forked task cx ptr: {:#x?}", task_cx_ptr as usize);
         // copy fd table
-        let mut new_fd_table: Vec<Option<Arc<dyn File + Send + Sync>>> = Vec::new();
-        for fd in parent_inner.fd_table.iter() {
-            if let Some(file) = fd {
-                new_fd_table.push(Some(file.clone()));
-            } else {
-                new_fd_table.push(None);
-            }
-        }
+        let new_fd_table = parent_inner.fd_table.clone();
         let mut user_trap_info: Option<UserTrapInfo> = None;
         if let Some(mut trap_info) = parent_inner.user_trap_info.clone() {
             debug!("[fork] copy parent trap info");
-            trap_info.user_trap_buffer_ppn = memory_set
-                .translate(VirtAddr::from(USER_TRAP_BUFFER).into())
-                .unwrap()
-                .ppn();
-            user_trap_info = Some(trap_info);
+            // claimed devices stay with the parent, the child only keeps their mappings
+            trap_info.devices.clear();
+            match memory_set.translate(VirtAddr::from(USER_TRAP_BUFFER).into()) {
+                Some(pte) if !trap_info.poisoned => {
+                    trap_info.user_trap_buffer_ppn = pte.ppn();
+                    user_trap_info = Some(trap_info);
+                }
+                // the parent unmapped its trap buffer, the child gets no user traps
+                _ => {
+                    parent_inner.poison_user_trap();
+                }
+            }
         }
+        // the control block is freed after the owner inside it, it belongs to the kernel
+        let scope = AllocScope::kernel();
         let task_control_block = Arc::new(TaskControlBlock {
             pid: pid_handle,
+            privileged: self.grants_privilege(&pid_ns),
+            pid_ns,
             kernel_stack,
-            inner: Mutex::new(TaskControlBlockInner {
+            in_syscall: AtomicUsize::new(0),
+            inner: SpinNoIrq::new(TaskControlBlockInner {
                 trap_cx_ppn,
                 base_size: parent_inner.base_size,
                 task_cx,
@@ -300,15 +780,38 @@ impl TaskControlBlock {
                 memory_set,
                 parent: Some(Arc::downgrade(self)),
                 children: Vec::new(),
-                exit_code: 0,
+                exit_status: ExitStatus::exited(0),
+                is_stop_reported: false,
+                syscall_trace: None,
+                uipi_events: None,
+                child_notify: None,
+                child_notify_accept: None,
+                ptrace: None,
+                ready_since_us: 0,
+                dispatched_us: 0,
+                start_us: get_user_time_us(),
+                first_run_us: None,
+                cpu_group: parent_inner.cpu_group,
+                cpu_affinity: parent_inner.cpu_affinity,
+                deadline: None,
+                pending_kill: None,
                 priority: 16,
                 fd_table: new_fd_table,
                 mail_box: Arc::new(MailBox::new()),
                 time_intr_count: 0,
                 total_cpu_cycle_count: 0,
                 last_cpu_cycle: 0,
+                cpu_times: CpuTimes::default(),
+                children_cpu_times: CpuTimes::default(),
+                usage: UsageCounters::default(),
+                children_usage: UsageCounters::default(),
+                time_mark: 0,
+                is_in_irq: false,
+                checked_utvec: 0,
             }),
+            alloc_owner,
         });
+        drop(scope);
         add_task_2_map(task_control_block.getpid(), task_control_block.clone());
         // add child
         parent_inner.children.push(task_control_block.clone());
@@ -318,39 +821,203 @@ impl TaskControlBlock {
         // **** release child PCB lock
         trap_cx.kernel_sp = kernel_stack_top;
         // return
-        task_control_block
+        Ok(task_control_block)
         // ---- release parent PCB lock
     }
+
+    /// A new child of `self` resuming where the task of `checkpoint` was parked,
+    /// without user traps, or `ENOMEM`
+    pub fn restore(
+        self: &Arc<TaskControlBlock>,
+        checkpoint: &Checkpoint,
+    ) -> Result<Arc<TaskControlBlock>, isize> {
+        let alloc_owner = AllocOwner::new();
+        let scope = alloc_owner.scope(SUBSYSTEM_FORK);
+        let memory_set = MemorySet::from_existed_user(&checkpoint.memory_set)?;
+        let trap_cx_ppn = memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT).into())
+            .unwrap()
+            .ppn();
+        drop(scope);
+        let pid_handle = pid_alloc();
+        alloc_owner.set_pid(pid_handle.0);
+        let pid_ns = self.pid_ns.clone();
+        if let Some(ns) = &pid_ns {
+            ns.register(pid_handle.0);
+        }
+        let kernel_stack = {
+            let _scope = alloc_owner.scope(SUBSYSTEM_TASK);
+            KernelStack::new(&pid_handle)
+        };
+        let kernel_stack_top = kernel_stack.get_top();
+        let task_cx = TaskContext::goto_trap_return(kernel_stack_top);
+        let task_cx_ptr = kernel_stack.push_on_top(task_cx.clone());
+        // the control block is freed after the owner inside it, it belongs to the kernel
+        let scope = AllocScope::kernel();
+        let task_control_block = Arc::new(TaskControlBlock {
+            pid: pid_handle,
+            privileged: self.grants_privilege(&pid_ns),
+            pid_ns,
+            kernel_stack,
+            in_syscall: AtomicUsize::new(0),
+            inner: SpinNoIrq::new(TaskControlBlockInner {
+                trap_cx_ppn,
+                base_size: checkpoint.base_size,
+                task_cx,
+                task_cx_ptr: task_cx_ptr as usize,
+                user_trap_info: None,
+                task_status: TaskStatus::Ready,
+                memory_set,
+                parent: Some(Arc::downgrade(self)),
+                children: Vec::new(),
+                exit_status: ExitStatus::exited(0),
+                is_stop_reported: false,
+                syscall_trace: None,
+                uipi_events: None,
+                child_notify: None,
+                child_notify_accept: None,
+                ptrace: None,
+                ready_since_us: 0,
+                dispatched_us: 0,
+                start_us: get_user_time_us(),
+                first_run_us: None,
+                cpu_group: checkpoint.cpu_group,
+                cpu_affinity: checkpoint.cpu_affinity,
+                deadline: None,
+                pending_kill: None,
+                priority: checkpoint.priority,
+                fd_table: checkpoint.fd_table.clone(),
+                mail_box: Arc::new(MailBox::new()),
+                time_intr_count: 0,
+                total_cpu_cycle_count: 0,
+                last_cpu_cycle: 0,
+                cpu_times: CpuTimes::default(),
+                children_cpu_times: CpuTimes::default(),
+                usage: UsageCounters::default(),
+                children_usage: UsageCounters::default(),
+                time_mark: 0,
+                is_in_irq: false,
+                checked_utvec: 0,
+            }),
+            alloc_owner,
+        });
+        drop(scope);
+        add_task_2_map(task_control_block.getpid(), task_control_block.clone());
+        self.acquire_inner_lock()
+            .children
+            .push(task_control_block.clone());
+        let trap_cx = task_control_block.acquire_inner_lock().get_trap_cx();
+        trap_cx.kernel_sp = kernel_stack_top;
+        Ok(task_control_block)
+    }
+
     pub fn getpid(&self) -> usize {
         self.pid.0
     }
 
+    /// The pid of `task` in the namespace of `self`, `None` if it is invisible to `self`
+    pub fn vpid_of(&self, task: &TaskControlBlock) -> Option<usize> {
+        match &self.pid_ns {
+            Some(ns) => ns.to_local(task.pid.0),
+            None => Some(task.pid.0),
+        }
+    }
+
+    /// The pid of `self` in its own namespace
+    pub fn ns_pid(&self) -> usize {
+        self.vpid_of(self).unwrap()
+    }
+
+    pub fn ns_id(&self) -> usize {
+        self.pid_ns.as_ref().map_or(0, |ns| ns.id)
+    }
+
+    /// Find a live task by its pid in the namespace of `self`
+    pub fn find_visible_task(&self, vpid: usize) -> Option<Arc<TaskControlBlock>> {
+        let pid = match &self.pid_ns {
+            Some(ns) => ns.to_global(vpid)?,
+            None => vpid,
+        };
+        find_task(pid)
+    }
+
+    pub fn task_info(&self) -> TaskInfo {
+        let inner = self.acquire_inner_lock();
+        TaskInfo {
+            pid: self.pid.0,
+            utime_us: ticks_to_us(inner.cpu_times.utime),
+            stime_us: ticks_to_us(inner.cpu_times.stime),
+            irqtime_us: ticks_to_us(inner.cpu_times.irqtime),
+            children_utime_us: ticks_to_us(inner.children_cpu_times.utime),
+            children_stime_us: ticks_to_us(inner.children_cpu_times.stime),
+            children_irqtime_us: ticks_to_us(inner.children_cpu_times.irqtime),
+            time_intr_count: inner.time_intr_count,
+            cpu_cycle_count: inner.total_cpu_cycle_count,
+            start_us: inner.start_us,
+            first_run_us: inner.first_run_us.unwrap_or(0),
+            boot_us: boot_us(),
+        }
+    }
+
+    /// `flags`: `SPAWN_RANDOMIZE` randomizes the layout even if ASLR is off in the kernel config
     pub fn spawn(
         self: &Arc<TaskControlBlock>,
         file: *const u8,
+        flags: usize,
     ) -> Result<Arc<TaskControlBlock>, isize> {
         let mut parent_inner = self.acquire_inner_lock();
         let parent_token = parent_inner.get_user_token();
-        let f = translated_str(parent_token, file);
+        let f = translated_str(parent_token, file)?;
         debug!("SPAWN exec {:?}", &f);
 
         if let Some(elf_data) = get_app_data_by_name(f.as_str()) {
-            let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+            let alloc_owner = AllocOwner::new();
+            let scope = alloc_owner.scope(SUBSYSTEM_SPAWN);
+            let (memory_set, user_sp, entry_point) = MemorySet::from_elf(
+                elf_data,
+                kernel_config().aslr || flags & SPAWN_RANDOMIZE != 0,
+            )?;
+            let stack_sp = push_args(&memory_set, user_sp, &[f.clone()], &[])?;
             let trap_cx_ppn = memory_set
                 .translate(VirtAddr::from(TRAP_CONTEXT).into())
                 .unwrap()
                 .ppn();
+            drop(scope);
             let pid_handle = pid_alloc();
-            let kernel_stack = KernelStack::new(&pid_handle);
+            alloc_owner.set_pid(pid_handle.0);
+            let pid_ns = if flags & SPAWN_NEW_PID_NS != 0 {
+                Some(PidNamespace::new(self.pid_ns.clone()))
+            } else {
+                self.pid_ns.clone()
+            };
+            if let Some(ns) = &pid_ns {
+                ns.register(pid_handle.0);
+            }
+            // a task in a new pid namespace starts with the fds of the first process, it is
+            // not handed those of the caller
+            let fd_table = if flags & SPAWN_NEW_PID_NS != 0 {
+                FdTable::initial()
+            } else {
+                parent_inner.fd_table.on_exec()
+            };
+            let kernel_stack = {
+                let _scope = alloc_owner.scope(SUBSYSTEM_TASK);
+                KernelStack::new(&pid_handle)
+            };
             let kernel_stack_top = kernel_stack.get_top();
             let task_cx = TaskContext::goto_trap_return(kernel_stack_top);
             let task_cx_ptr = kernel_stack.push_on_top(task_cx.clone());
             trace!("spawned task cx ptr: {:#x?}", task_cx_ptr as usize);
 
+            // the control block is freed after the owner inside it, it belongs to the kernel
+            let scope = AllocScope::kernel();
             let task_control_block = Arc::new(TaskControlBlock {
                 pid: pid_handle,
+                privileged: self.grants_privilege(&pid_ns),
+                pid_ns,
                 kernel_stack,
-                inner: Mutex::new(TaskControlBlockInner {
+                in_syscall: AtomicUsize::new(0),
+                inner: SpinNoIrq::new(TaskControlBlockInner {
                     trap_cx_ppn,
                     base_size: user_sp,
                     task_cx,
@@ -360,39 +1027,52 @@ impl TaskControlBlock {
                     memory_set,
                     parent: Some(Arc::downgrade(self)),
                     children: Vec::new(),
-                    exit_code: 0,
+                    exit_status: ExitStatus::exited(0),
+                    is_stop_reported: false,
+                    syscall_trace: None,
+                    uipi_events: None,
+                    child_notify: None,
+                    child_notify_accept: None,
+                    ptrace: None,
+                    ready_since_us: 0,
+                    dispatched_us: 0,
+                    start_us: get_user_time_us(),
+                    first_run_us: None,
+                    cpu_group: parent_inner.cpu_group,
+                    cpu_affinity: parent_inner.cpu_affinity,
+                    deadline: None,
+                    pending_kill: None,
                     priority: 16,
-                    fd_table: vec![
-                        // 0 -> stdin
-                        Some(Arc::new(Stdin)),
-                        // 1 -> stdout
-                        Some(Arc::new(Stdout)),
-                        // 2 -> stderr
-                        Some(Arc::new(Stdout)),
-                        // 3 -> serial 2
-                        Some(Arc::new(Serial::<2>)),
-                        // 4 -> serial 3
-                        Some(Arc::new(Serial::<3>)),
-                    ],
+                    // inherited like fork and exec, so redirections of the parent apply
+                    fd_table,
                     mail_box: Arc::new(MailBox::new()),
                     time_intr_count: 0,
                     total_cpu_cycle_count: 0,
                     last_cpu_cycle: 0,
+                    cpu_times: CpuTimes::default(),
+                    children_cpu_times: CpuTimes::default(),
+                    usage: UsageCounters::default(),
+                    children_usage: UsageCounters::default(),
+                    time_mark: 0,
+                    is_in_irq: false,
+                    checked_utvec: 0,
                 }),
+                alloc_owner,
             });
+            drop(scope);
             add_task_2_map(task_control_block.getpid(), task_control_block.clone());
             parent_inner.children.push(task_control_block.clone());
             let trap_cx = task_control_block.acquire_inner_lock().get_trap_cx();
             *trap_cx = TrapContext::app_init_context(
                 entry_point,
-                user_sp,
+                stack_sp,
                 KERNEL_SPACE.lock().token(),
                 kernel_stack_top,
                 trap_handler as usize,
             );
             return Ok(task_control_block);
         }
-        Err(-1)
+        Err(ENOENT)
     }
 
     pub fn create_socket(&self) -> Arc<Socket> {
@@ -400,6 +1080,14 @@ impl TaskControlBlock {
     }
 }
 
+impl Drop for TaskControlBlock {
+    fn drop(&mut self) {
+        if let Some(ns) = &self.pid_ns {
+            ns.unregister(self.pid.0);
+        }
+    }
+}
+
 impl PartialEq for TaskControlBlock {
     fn eq(&self, other: &Self) -> bool {
         self.pid == other.pid
@@ -420,9 +1108,100 @@ impl Ord for TaskControlBlock {
     }
 }
 
+/// Status of a child stopped by SIGSTOP, `(SIGSTOP << 8) | 0x7f` as `WIFSTOPPED` expects
+const STOPPED_STATUS: i32 = ((SIGSTOP as i32) << 8) | 0x7f;
+
+/// How a task ended, reported by `sys_waitpid` with `WEXITSTATUS_EXT`
+#[repr(u32)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ExitReason {
+    /// Called `sys_exit`
+    Exited = 0,
+    PageFault = 1,
+    IllegalInstruction = 2,
+    /// Faulted inside its own user trap handler
+    UserDoubleFault = 3,
+    /// Held the most frames when the kernel ran short of its own, see `oom_kill`
+    OutOfMemory = 4,
+    /// Not an exit, a child stopped by SIGSTOP reported with `WUNTRACED`
+    Stopped = 5,
+    /// Unmapped its trap buffer or corrupted its trap queue
+    UipiFault = 6,
+    /// Stayed in its user trap handler past the budget, without an abort entry
+    UserTrapTimeout = 7,
+    /// Would have returned to user mode with a bad pc or trap vector
+    BadReturnContext = 8,
+    /// A kernel service aborted while called on its behalf, see `service::api_abort`
+    ServiceAbort = 9,
+    /// Hit an ebreak which no tracer put there
+    Breakpoint = 10,
+}
+
+impl ExitReason {
+    /// Exit code of a task killed for this reason, as reported before there were reasons
+    fn code(&self) -> i32 {
+        match self {
+            ExitReason::Exited => 0,
+            ExitReason::PageFault => -2,
+            ExitReason::IllegalInstruction => -3,
+            ExitReason::UserDoubleFault => -4,
+            ExitReason::OutOfMemory => ENOMEM as i32,
+            ExitReason::Stopped => STOPPED_STATUS,
+            ExitReason::UipiFault => EFAULT as i32,
+            ExitReason::UserTrapTimeout => ETIME as i32,
+            ExitReason::BadReturnContext => EFAULT as i32,
+            ExitReason::ServiceAbort => EIO as i32,
+            ExitReason::Breakpoint => EIO as i32,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExitReason::Exited => "exited",
+            ExitReason::PageFault => "page_fault",
+            ExitReason::IllegalInstruction => "illegal_instruction",
+            ExitReason::UserDoubleFault => "user_double_fault",
+            ExitReason::OutOfMemory => "out_of_memory",
+            ExitReason::Stopped => "stopped",
+            ExitReason::UipiFault => "uipi_fault",
+            ExitReason::UserTrapTimeout => "user_trap_timeout",
+            ExitReason::BadReturnContext => "bad_return_context",
+            ExitReason::ServiceAbort => "service_abort",
+            ExitReason::Breakpoint => "breakpoint",
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct ExitStatus {
+    /// Given to `sys_exit`, or made up by the kernel for the other reasons
+    pub code: i32,
+    pub reason: ExitReason,
+}
+
+impl ExitStatus {
+    pub fn exited(code: i32) -> Self {
+        Self {
+            code,
+            reason: ExitReason::Exited,
+        }
+    }
+
+    /// Killed by the kernel
+    pub fn killed(reason: ExitReason) -> Self {
+        Self {
+            code: reason.code(),
+            reason,
+        }
+    }
+}
+
 #[derive(Copy, Clone, PartialEq)]
 pub enum TaskStatus {
     Ready,
     Running(usize),
+    /// Stopped by SIGSTOP, kept out of the ready queue until SIGCONT
+    Stopped,
     Zombie,
 }
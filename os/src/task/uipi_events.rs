@@ -0,0 +1,88 @@
+//! UIPI activity of a child reported to its parent, for the processes which spawned it
+//! with `SPAWN_NOTIFY_PARENT`. Each event is a line `<pid> <event> <arg>` written to
+//! the pipe the parent gave, the pid being the one the parent sees.
+//!
+//! Events are posted on the exit path among others, so they never block. An event which
+//! does not fit in the pipe, or which nobody would read, is dropped and counted, and the
+//! count is reported by a line `<pid> dropped <count>` before the next event which fits.
+
+use super::TaskControlBlock;
+use crate::fs::File;
+use crate::mm::UserBuffer;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+
+#[derive(Debug, Clone, Copy)]
+pub enum UipiEvent {
+    /// Initialized its user traps, it can receive from now on
+    TrapInit,
+    TrapRelease,
+    GroupJoin(usize),
+    GroupLeave(usize),
+    DeviceClaim(usize),
+    /// Exited still holding this many UIPI objects: its trap buffer, claimed devices,
+    /// DMA buffers and group memberships
+    Exit(usize),
+}
+
+impl UipiEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            UipiEvent::TrapInit => "trap_init",
+            UipiEvent::TrapRelease => "trap_release",
+            UipiEvent::GroupJoin(_) => "group_join",
+            UipiEvent::GroupLeave(_) => "group_leave",
+            UipiEvent::DeviceClaim(_) => "device_claim",
+            UipiEvent::Exit(_) => "exit",
+        }
+    }
+
+    fn arg(&self) -> usize {
+        match *self {
+            UipiEvent::TrapInit | UipiEvent::TrapRelease => 0,
+            UipiEvent::GroupJoin(group_id) | UipiEvent::GroupLeave(group_id) => group_id,
+            UipiEvent::DeviceClaim(device_id) => device_id,
+            UipiEvent::Exit(leaked) => leaked,
+        }
+    }
+}
+
+/// Where the events of a task go, dropped when it exits so that the parent sees the end
+pub struct UipiEventSink {
+    /// Pid of the task in the pid namespace of the parent
+    pub vpid: usize,
+    pub output: Arc<dyn File + Send + Sync>,
+    /// Events dropped since the last one written
+    pub dropped: usize,
+}
+
+fn line_buffer(line: &str) -> UserBuffer {
+    let buf = unsafe { core::slice::from_raw_parts_mut(line.as_ptr() as *mut u8, line.len()) };
+    UserBuffer::new(vec![buf])
+}
+
+/// Report `event` of `task` to its parent, if it asked for them, without blocking.
+/// The inner lock of `task` must not be held.
+pub fn post_uipi_event(task: &Arc<TaskControlBlock>, event: UipiEvent) {
+    let (vpid, output, dropped) = match &task.acquire_inner_lock().uipi_events {
+        Some(sink) => (sink.vpid, sink.output.clone(), sink.dropped),
+        None => return,
+    };
+    let mut text = String::new();
+    if dropped > 0 {
+        text += &format!("{} dropped {}\n", vpid, dropped);
+    }
+    text += &format!("{} {} {}\n", vpid, event.name(), event.arg());
+    let ret = match output.try_write(line_buffer(&text)) {
+        Some(ret) => ret,
+        None => output.write(line_buffer(&text)),
+    };
+    if let Some(sink) = &mut task.acquire_inner_lock().uipi_events {
+        match ret {
+            Ok(_) => sink.dropped -= dropped,
+            Err(_) => sink.dropped += 1,
+        }
+    }
+}
@@ -1,14 +1,17 @@
-use crate::config::{CLOCK_FREQ, CPU_NUM};
+use crate::config::{CLOCK_FREQ, CPU_NUM, DETERMINISTIC};
+use crate::deterministic;
 use crate::sbi::set_timer;
 use crate::task::hart_id;
+use crate::util::SpinNoIrq;
 use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
 use lazy_static::*;
 use riscv::register::time;
-use spin::Mutex;
 
 const TICKS_PER_SEC: usize = 100;
 const MSEC_PER_SEC: usize = 1000;
 pub const USEC_PER_SEC: usize = 1_000_000;
+pub const NSEC_PER_SEC: usize = 1_000_000_000;
 
 #[repr(C)]
 #[derive(Debug)]
@@ -24,9 +27,28 @@ impl TimeVal {
     }
 }
 
+#[repr(C)]
+#[derive(Debug)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+
+/// The clock user programs see in `time` CSR ticks, virtual in deterministic mode
+fn user_time() -> usize {
+    if DETERMINISTIC {
+        deterministic::now_us() * CLOCK_FREQ / USEC_PER_SEC
+    } else {
+        time::read()
+    }
+}
+
 #[allow(unused_variables)]
 pub fn get_time(mut ts: Vec<*mut usize>, tz: usize) -> isize {
-    let t = time::read();
+    let t = user_time();
     unsafe {
         *ts[0] = t / CLOCK_FREQ;
         *ts[1] = (t % CLOCK_FREQ) * 1000000 / CLOCK_FREQ;
@@ -36,6 +58,17 @@ pub fn get_time(mut ts: Vec<*mut usize>, tz: usize) -> isize {
     0
 }
 
+pub fn get_time_ns() -> usize {
+    let t = time::read();
+    t / CLOCK_FREQ * NSEC_PER_SEC + t % CLOCK_FREQ * NSEC_PER_SEC / CLOCK_FREQ
+}
+
+/// Monotonic time for user programs, see `user_time`
+pub fn get_user_time_ns() -> usize {
+    let t = user_time();
+    t / CLOCK_FREQ * NSEC_PER_SEC + t % CLOCK_FREQ * NSEC_PER_SEC / CLOCK_FREQ
+}
+
 #[allow(dead_code)]
 pub fn get_time_ms() -> usize {
     time::read() / (CLOCK_FREQ / MSEC_PER_SEC)
@@ -46,13 +79,42 @@ pub fn get_time_us() -> usize {
     time::read() * USEC_PER_SEC / CLOCK_FREQ
 }
 
+/// `get_user_time_ns` in microseconds, the clock of the timestamps in `TaskInfo`
+pub fn get_user_time_us() -> usize {
+    ticks_to_us(user_time())
+}
+
+/// The user clock when the kernel started its first task
+static BOOT_US: AtomicUsize = AtomicUsize::new(0);
+
+pub fn mark_booted() {
+    BOOT_US.store(get_user_time_us(), Relaxed);
+}
+
+pub fn boot_us() -> usize {
+    BOOT_US.load(Relaxed)
+}
+
+/// Converts `time` CSR ticks to microseconds
+pub fn ticks_to_us(ticks: usize) -> usize {
+    ticks / CLOCK_FREQ * USEC_PER_SEC + ticks % CLOCK_FREQ * USEC_PER_SEC / CLOCK_FREQ
+}
+
+/// Converts microseconds to `time` CSR ticks
+pub fn us_to_ticks(us: usize) -> usize {
+    us / USEC_PER_SEC * CLOCK_FREQ + us % USEC_PER_SEC * CLOCK_FREQ / USEC_PER_SEC
+}
+
 pub fn set_next_trigger() {
+    crate::random::add_entropy(hart_id());
     // set_timer(time::read() + CLOCK_FREQ / TICKS_PER_SEC);
     set_virtual_timer(time::read() + CLOCK_FREQ / TICKS_PER_SEC, 0);
 }
 
 lazy_static! {
-    pub static ref TIMER_MAP: [Arc<Mutex<BTreeMap<usize, usize>>>; CPU_NUM] = Default::default();
+    /// Also taken by the timer interrupt
+    pub static ref TIMER_MAP: [Arc<SpinNoIrq<BTreeMap<usize, usize>>>; CPU_NUM] =
+        Default::default();
 }
 
 pub fn set_virtual_timer(mut time: usize, pid: usize) {
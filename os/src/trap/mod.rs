@@ -1,20 +1,28 @@
 mod context;
+mod stack;
 mod usertrap;
 
-use crate::config::{TRAMPOLINE, TRAP_CONTEXT};
+use crate::config::{DETERMINISTIC, STRICT_USER_ACCESS, TRAMPOLINE, TRAP_CONTEXT};
+use crate::ipi;
+use crate::mm;
 use crate::plic;
 use crate::sbi::set_timer;
-use crate::syscall::syscall;
+use crate::service;
+use crate::syscall::{is_restartable, syscall, EINTR, ERESTART, SYSCALL_EXEC};
 use crate::task::{
-    current_task, current_trap_cx, current_user_token, exit_current_and_run_next, hart_id,
-    suspend_current_and_run_next,
+    current_deadline_exhausted, current_task, current_trap_cx, current_user_token,
+    exit_current_and_run_next, handle_ptrace_breakpoint, hart_id, set_hart_state,
+    suspend_current_and_run_next, ExitReason, ExitStatus, HartState, DEADLINE_TIMER,
 };
 use crate::timer::{get_time_us, set_next_trigger, TIMER_MAP};
+use crate::util::{irq_enter, irq_exit, rcu};
+use crate::watchdog;
 use core::arch::{asm, global_asm};
+use core::sync::atomic::Ordering::Relaxed;
 use riscv::register::{
     mtvec::TrapMode,
     scause::{self, Exception, Interrupt, Trap},
-    sepc, sideleg, sie, sip, sstatus, stval, stvec,
+    sepc, sideleg, sie, sip, sstatus, stval, stvec, uepc,
 };
 
 global_asm!(include_str!("trap.asm"));
@@ -28,6 +36,7 @@ pub fn init() {
         sideleg::set_uext();
         sideleg::set_utimer();
     }
+    stack::init(hart_id());
     set_kernel_trap_entry();
 }
 
@@ -38,6 +47,7 @@ fn set_kernel_trap_entry() {
         }
         stvec::write(kernelvec as usize, TrapMode::Direct);
     }
+    stack::arm(hart_id());
 }
 
 fn set_user_trap_entry() {
@@ -49,8 +59,25 @@ fn set_user_trap_entry() {
 #[no_mangle]
 pub fn trap_handler() -> ! {
     set_kernel_trap_entry();
+    mm::clear_sum_on_trap_entry();
     let scause = scause::read();
+    set_hart_state(if scause.is_interrupt() {
+        HartState::InTrap
+    } else {
+        HartState::InKernel
+    });
+    current_task()
+        .unwrap()
+        .acquire_inner_lock()
+        .account_user_time(scause.is_interrupt());
     let stval = stval::read();
+    watchdog::record_trap(
+        hart_id(),
+        scause.bits(),
+        sepc::read(),
+        stval,
+        current_task().unwrap().pid.0,
+    );
     // trace!(
     //     "trap from user, cause: {:?}, stval: {}, trap frame: {:x?}",
     //     scause.cause(),
@@ -63,11 +90,24 @@ pub fn trap_handler() -> ! {
             let mut cx = current_trap_cx();
             cx.sepc += 4;
             let id = cx.x[17];
+            // the task is not held across the syscall, which may not return
+            current_task().unwrap().in_syscall.store(id + 1, Relaxed);
             // get system call return value
-            let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]]);
+            let result = syscall(
+                cx.x[17],
+                [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]],
+            );
+            current_task().unwrap().in_syscall.store(0, Relaxed);
             // cx is changed during sys_exec, so we have to call it again
             // cx = current_trap_cx();
-            if id != 221 || result != 0 {
+            if result == ERESTART {
+                // the pending user trap is delivered on return, its handler returns to the ecall
+                if is_restartable(id) {
+                    cx.sepc -= 4;
+                } else {
+                    cx.x[10] = EINTR as usize;
+                }
+            } else if id != SYSCALL_EXEC || result != 0 {
                 cx.x[10] = result as usize;
             }
         }
@@ -77,19 +117,30 @@ pub fn trap_handler() -> ! {
         | Trap::Exception(Exception::InstructionPageFault)
         | Trap::Exception(Exception::LoadFault)
         | Trap::Exception(Exception::LoadPageFault) => {
+            exit_on_user_double_fault(&scause, stval);
             error!(
                 "[kernel] {:?} in application, bad addr = {:#x}, bad instruction = {:#x}, core dumped.",
                 scause.cause(),
                 stval,
                 current_trap_cx().sepc,
             );
-            // page fault exit code
-            exit_current_and_run_next(-2);
+            exit_current_and_run_next(ExitStatus::killed(ExitReason::PageFault));
+        }
+        Trap::Exception(Exception::Breakpoint) => {
+            if !handle_ptrace_breakpoint() {
+                // the ebreak would trap again on return, there is no SIGTRAP handler to run
+                exit_on_user_double_fault(&scause, stval);
+                error!(
+                    "[kernel] Breakpoint in application at {:#x}, core dumped.",
+                    current_trap_cx().sepc
+                );
+                exit_current_and_run_next(ExitStatus::killed(ExitReason::Breakpoint));
+            }
         }
         Trap::Exception(Exception::IllegalInstruction) => {
+            exit_on_user_double_fault(&scause, stval);
             error!("[kernel] IllegalInstruction in application, core dumped.");
-            // illegal instruction exit code
-            exit_current_and_run_next(-3);
+            exit_current_and_run_next(ExitStatus::killed(ExitReason::IllegalInstruction));
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
             // let current_time = time::read();
@@ -101,6 +152,10 @@ pub fn trap_handler() -> ! {
                 drop(timer_map);
                 if pid == 0 {
                     set_next_trigger();
+                    watchdog::heartbeat(hart_id());
+                    watchdog::check(hart_id());
+                    enforce_handler_budget();
+                    service::sched_tick(hart_id(), current_task().unwrap().getpid());
                     // static mut CNT: usize = 0;
                     // unsafe {
                     //     CNT += 1;
@@ -109,13 +164,26 @@ pub fn trap_handler() -> ! {
                     //         CNT = 0;
                     //     }
                     // }
-                    suspend_current_and_run_next();
-                } else if pid == current_task().unwrap().pid.0 {
+                    // in deterministic mode tasks only switch at syscalls
+                    if !DETERMINISTIC {
+                        suspend_current_and_run_next();
+                    }
+                } else if pid == DEADLINE_TIMER {
+                    // armed when a deadline task was switched in, which may have yielded since
+                    if current_deadline_exhausted() {
+                        suspend_current_and_run_next();
+                    }
+                } else if pid == current_task().unwrap().pid.0 && {
+                    let inner = current_task().unwrap().acquire_inner_lock();
+                    // a task which released its user traps has no handler to take it
+                    inner.user_trap_info.is_some() && !inner.is_user_trap_masked()
+                } {
                     debug!("set UTIP for pid {}", pid);
                     unsafe {
                         sip::set_utimer();
                     }
                 } else {
+                    // tasks off this hart or masked by `sys_uintr_mask` get a record instead
                     let _ = push_trap_record(
                         pid,
                         UserTrapRecord {
@@ -127,14 +195,16 @@ pub fn trap_handler() -> ! {
                 break;
             }
         }
+        Trap::Interrupt(Interrupt::SupervisorSoft) => {
+            // IPIs carry kernel requests or kick this hart to deliver user trap records,
+            // which trap_return does
+            unsafe { sip::clear_ssoft() }
+            ipi::handle_ipis(hart_id());
+        }
         Trap::Interrupt(Interrupt::SupervisorExternal) => {
             // debug!("Supervisor External");
             plic::handle_external_interrupt(hart_id());
         }
-        Trap::Interrupt(Interrupt::SupervisorSoft) => {
-            // debug!("Supervisor Soft");
-            unsafe { sip::clear_ssoft() }
-        }
         _ => {
             error!(
                 "Unsupported trap {:?}, stval = {:#x}!",
@@ -146,15 +216,107 @@ pub fn trap_handler() -> ! {
     trap_return();
 }
 
+/// A fault in the user trap handler cannot be handed to the handler again,
+/// the task is killed with its own exit code instead of looking like a plain fault
+fn exit_on_user_double_fault(scause: &scause::Scause, stval: usize) {
+    if !current_task()
+        .unwrap()
+        .acquire_inner_lock()
+        .is_in_user_trap_handler()
+    {
+        return;
+    }
+    error!(
+        "[kernel] double fault in user handler at {:#x}, {:?} at {:#x}, bad addr = {:#x}, core dumped.",
+        uepc::read(),
+        scause.cause(),
+        current_trap_cx().sepc,
+        stval,
+    );
+    exit_current_and_run_next(ExitStatus::killed(ExitReason::UserDoubleFault));
+}
+
+/// A handler which runs past its budget blocks every later delivery, it is sent to
+/// its abort entry or the task is killed. Handlers are only seen at timer ticks,
+/// timed from the first tick they are seen at, and told apart by the entries the
+/// trap entry counts, see `USER_TRAP_HANDLER_ENTRIES`.
+fn enforce_handler_budget() {
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    let info = match inner.user_trap_info.as_mut() {
+        Some(info) => info,
+        None => return,
+    };
+    let (depth, entries) = info.handler_state();
+    if depth == 0 || info.handler_budget_us == 0 {
+        info.handler_seen = None;
+        return;
+    }
+    let now = get_time_us();
+    let since = match info.handler_seen {
+        Some((since, seen_entries)) if seen_entries == entries => since,
+        _ => {
+            info.handler_seen = Some((now, entries));
+            return;
+        }
+    };
+    if now - since < info.handler_budget_us {
+        return;
+    }
+    info.handler_seen = None;
+    let abort_entry = info.handler_abort_entry;
+    if abort_entry != 0 {
+        warn!(
+            "[kernel] pid {} over its user trap handler budget, forced to return",
+            task.getpid()
+        );
+        inner.get_trap_cx().sepc = abort_entry;
+        return;
+    }
+    error!(
+        "[kernel] pid {} stayed in its user trap handler for {} us, killed",
+        task.getpid(),
+        now - since
+    );
+    drop(inner);
+    drop(task);
+    exit_current_and_run_next(ExitStatus::killed(ExitReason::UserTrapTimeout));
+}
+
 #[no_mangle]
 pub fn trap_return() -> ! {
     unsafe {
         sstatus::clear_sie();
     }
-    current_task()
+    let pending_kill = current_task()
         .unwrap()
         .acquire_inner_lock()
-        .restore_user_trap_info();
+        .pending_kill
+        .take();
+    if let Some(reason) = pending_kill {
+        exit_current_and_run_next(ExitStatus::killed(reason));
+    }
+    let return_context = current_task()
+        .unwrap()
+        .acquire_inner_lock()
+        .check_return_context();
+    if let Err((register, value)) = return_context {
+        error!(
+            "[kernel] pid {} would return to user mode with {} = {:#x}, killed",
+            current_task().unwrap().getpid(),
+            register,
+            value
+        );
+        exit_current_and_run_next(ExitStatus::killed(ExitReason::BadReturnContext));
+    }
+    {
+        let task = current_task().unwrap();
+        let mut inner = task.acquire_inner_lock();
+        inner.restore_user_trap_info();
+        inner.account_kernel_time();
+    }
+    rcu::quiescent(hart_id());
+    set_hart_state(HartState::InUser);
     set_user_trap_entry();
     let trap_cx_ptr = TRAP_CONTEXT;
     let user_satp = current_user_token();
@@ -179,6 +341,11 @@ pub extern "C" fn trap_from_kernel(cx: &mut TrapContext) {
     let stval = stval::read();
     let sepc = sepc::read();
     let sstatus = sstatus::read();
+    // plain locks must not be taken by any interrupt handler, see `assert_not_in_irq`
+    let is_interrupt = scause.is_interrupt();
+    if is_interrupt {
+        irq_enter();
+    }
     match scause.cause() {
         // Trap::Interrupt(Interrupt::SupervisorTimer) => {
         //     set_next_trigger();
@@ -195,7 +362,25 @@ pub extern "C" fn trap_from_kernel(cx: &mut TrapContext) {
         //     plic::handle_external_interrupt();
         // }
         Trap::Interrupt(Interrupt::SupervisorSoft) => {
+            let state = set_hart_state(HartState::InTrap);
             debug!("SupervisorSoft");
+            unsafe { sip::clear_ssoft() }
+            ipi::handle_ipis(hart_id());
+            set_hart_state(state);
+        }
+        Trap::Exception(Exception::LoadPageFault)
+        | Trap::Exception(Exception::StorePageFault)
+        | Trap::Exception(Exception::InstructionPageFault)
+            if STRICT_USER_ACCESS =>
+        {
+            // user pages are never mapped in kernel space
+            panic!(
+                "kernel touched unmapped address {:#x} at sepc {:#x}, SUM = {}, \
+                 user memory must be accessed through mm::translated_*",
+                stval,
+                sepc,
+                sstatus.sum()
+            );
         }
         _ => {
             error!(
@@ -209,9 +394,18 @@ pub extern "C" fn trap_from_kernel(cx: &mut TrapContext) {
             panic!("a trap {:?} from kernel!", scause::read().cause());
         }
     }
+    if is_interrupt {
+        irq_exit();
+    }
 }
 
 pub use context::TrapContext;
+pub use stack::high_water as trap_stack_high_water;
 pub use usertrap::{
-    push_trap_record, UserTrapError, UserTrapInfo, UserTrapQueue, UserTrapRecord, USER_EXT_INT_MAP,
+    bind_uipi_name, join_msg_group, leave_all_msg_groups, leave_msg_group, msg_group_count,
+    push_group_trap_record, push_trap_record, repair_uipi_state, resolve_uipi_name,
+    unbind_all_uipi_names, unbind_uipi_name, UserTrapDescriptor, UserTrapError, UserTrapInfo,
+    UserTrapQueue, UserTrapRecord, DEFAULT_HANDLER_BUDGET_US, MAX_UIPI_NAME_LEN, USER_EXT_INT_MAP,
+    USER_TRAP_CTL_RELEASE, USER_TRAP_CTL_SET_COALESCE, USER_TRAP_CTL_SET_HANDLER_BUDGET,
+    USER_TRAP_CTL_SET_SEND_RATE, USER_TRAP_REENTRANT, USER_TRAP_STACK_SLOT,
 };
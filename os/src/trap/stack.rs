@@ -0,0 +1,41 @@
+//! Per-hart stacks for the traps taken in supervisor mode, so that an interrupt arriving deep
+//! in a call chain does not push its frames onto the kernel stack of the task. While in the
+//! kernel, `sscratch` holds the top of the trap stack of the hart, and `kernelvec` swaps it
+//! with `sp`. It is 0 while the hart is on its trap stack, so that nested traps stay there.
+//!
+//! The stacks are filled with a pattern at boot, the bytes overwritten since give the
+//! high-water mark.
+
+use crate::config::{CPU_NUM, TRAP_STACK_SIZE};
+use riscv::register::sscratch;
+
+const STACK_PATTERN: u8 = 0x5a;
+
+#[repr(C, align(16))]
+struct TrapStacks([[u8; TRAP_STACK_SIZE]; CPU_NUM]);
+
+static mut TRAP_STACKS: TrapStacks = TrapStacks([[0; TRAP_STACK_SIZE]; CPU_NUM]);
+
+fn stack_of(hart_id: usize) -> *mut u8 {
+    unsafe { TRAP_STACKS.0[hart_id].as_mut_ptr() }
+}
+
+/// Fill the trap stack of the hart with the pattern, before its first trap
+pub fn init(hart_id: usize) {
+    unsafe { core::ptr::write_bytes(stack_of(hart_id), STACK_PATTERN, TRAP_STACK_SIZE) }
+}
+
+/// Switch to the trap stack on the next trap from the kernel,
+/// `sscratch` holding whatever the user trap entry left there
+pub fn arm(hart_id: usize) {
+    sscratch::write(stack_of(hart_id) as usize + TRAP_STACK_SIZE);
+}
+
+/// Most bytes of its trap stack the hart has used
+pub fn high_water(hart_id: usize) -> usize {
+    let stack = stack_of(hart_id);
+    let untouched = (0..TRAP_STACK_SIZE)
+        .take_while(|&i| unsafe { stack.add(i).read_volatile() } == STACK_PATTERN)
+        .count();
+    TRAP_STACK_SIZE - untouched
+}
@@ -83,14 +83,31 @@ __restore:
 .globl kernelvec
 .align 4
 kernelvec:
+        // switch to the trap stack of the hart, sscratch is 0 if already on it
+        csrrw sp, sscratch, sp
+        bnez sp, 1f
+        csrrw sp, sscratch, sp
         // make room to save registers.
         addi sp, sp, -256
+        sd t0, 32(sp)
+        addi t0, sp, 256
+        sd t0, 8(sp)
+        // sscratch stays 0 on return from a nested trap
+        sd zero, 248(sp)
+        j 2f
+1:
+        addi sp, sp, -256
+        sd t0, 32(sp)
+        addi t0, sp, 256
+        sd t0, 248(sp)
+        // the interrupted sp, and mark the trap stack in use
+        csrrw t0, sscratch, zero
+        sd t0, 8(sp)
+2:
         // save the registers.
         sd ra, 0(sp)
-        sd sp, 8(sp)
         sd gp, 16(sp)
         sd tp, 24(sp)
-        sd t0, 32(sp)
         sd t1, 40(sp)
         sd t2, 48(sp)
         sd s0, 56(sp)
@@ -127,8 +144,9 @@ kernelvec:
 kernelret:
         // restore registers.
         addi sp, sp, 8
+        ld t0, 248(sp)
+        csrw sscratch, t0
         ld ra, 0(sp)
-        ld sp, 8(sp)
         ld gp, 16(sp)
         // not this, in case we moved CPUs: ld tp, 24(sp)
         ld t0, 32(sp)
@@ -159,7 +177,8 @@ kernelret:
         ld t5, 232(sp)
         ld t6, 240(sp)
 
-        addi sp, sp, 256
+        // back to the interrupted stack
+        ld sp, 8(sp)
 
         // return to whatever we were doing in the kernel.
         sret
\ No newline at end of file
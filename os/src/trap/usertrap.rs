@@ -1,22 +1,207 @@
 const MAX_USER_TRAP_NUM: usize = 128;
 
-use crate::config::CPU_NUM;
+/// Commands of `sys_user_trap_ctl`
+pub const USER_TRAP_CTL_SET_COALESCE: usize = 0;
+pub const USER_TRAP_CTL_SET_SEND_RATE: usize = 1;
+pub const USER_TRAP_CTL_RELEASE: usize = 2;
+pub const USER_TRAP_CTL_SET_HANDLER_BUDGET: usize = 3;
+
+/// Longest a handler runs before the task is killed, by default, 0 for no limit: a task
+/// opts in with `USER_TRAP_CTL_SET_HANDLER_BUDGET`
+pub const DEFAULT_HANDLER_BUDGET_US: usize = 0;
+
+use crate::config::{IrqTrigger, CPU_NUM, PAGE_SIZE, USER_TRAP_BUFFER};
+use crate::ipi::{self, HartMask, IpiMessage};
 use crate::plic::Plic;
-use crate::sbi::send_ipi;
+use crate::syscall::{EEXIST, ENOBUFS, ENOTCONN, EPIPE, ESRCH};
 use crate::task::hart_id;
 use crate::task::TaskStatus::Running;
-use crate::{mm::PhysPageNum, plic::get_context};
-use alloc::{collections::BTreeMap, vec::Vec};
+use crate::timer::{get_time_us, USEC_PER_SEC};
+use crate::{
+    mm::{DmaTracker, MemorySet, PhysPageNum, VirtAddr},
+    plic::{self, get_context},
+    util::SpinNoIrq,
+};
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
 use core::arch::asm;
-use heapless::spsc::Queue;
+use core::mem::size_of;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
 use lazy_static::*;
 use spin::Mutex;
 
-pub type UserTrapQueue = Queue<UserTrapRecord, MAX_USER_TRAP_NUM>;
+/// Trap records queued in the trap buffer, with the layout the user library expects.
+/// The kernel produces at `tail` and the task consumes at `head`, so that at most
+/// `MAX_USER_TRAP_NUM - 1` records are queued. Both indices live in user memory and can
+/// change at any time: each is read once and checked before it is used.
+#[repr(C)]
+pub struct UserTrapQueue {
+    head: usize,
+    tail: usize,
+    buffer: [UserTrapRecord; MAX_USER_TRAP_NUM],
+}
+
+impl UserTrapQueue {
+    pub const fn new() -> Self {
+        Self {
+            head: 0,
+            tail: 0,
+            buffer: [UserTrapRecord {
+                cause: 0,
+                message: 0,
+            }; MAX_USER_TRAP_NUM],
+        }
+    }
+
+    /// `(head, tail)` as read once, `None` if either is out of range
+    fn indices(&self) -> Option<(usize, usize)> {
+        let head = unsafe { read_volatile(&self.head) };
+        let tail = unsafe { read_volatile(&self.tail) };
+        if head < MAX_USER_TRAP_NUM && tail < MAX_USER_TRAP_NUM {
+            Some((head, tail))
+        } else {
+            None
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.indices().map_or(0, |(head, tail)| {
+            (tail + MAX_USER_TRAP_NUM - head) % MAX_USER_TRAP_NUM
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write `record` at the checked copy of the tail, then publish the new tail
+    pub fn enqueue(&mut self, record: UserTrapRecord) -> Result<(), UserTrapError> {
+        let (head, tail) = self.indices().ok_or(UserTrapError::Poisoned)?;
+        let next = (tail + 1) % MAX_USER_TRAP_NUM;
+        if next == head {
+            return Err(UserTrapError::TrapBufferFull);
+        }
+        unsafe { write_volatile(&mut self.buffer[tail], record) };
+        fence(Ordering::Release);
+        unsafe { write_volatile(&mut self.tail, next) };
+        Ok(())
+    }
+}
+
+/// `UserTrapDescriptor` flag: the handler may re-enable user interrupts and nest
+pub const USER_TRAP_REENTRANT: usize = 1;
+/// The trap stack top is published here for the trap entry, after the queue in the trap buffer
+pub const USER_TRAP_STACK_SLOT: usize = USER_TRAP_BUFFER + PAGE_SIZE - size_of::<usize>();
+/// Handlers running, raised by the trap entry of the user library once the interrupted
+/// context is saved and lowered right before its uret. Runtimes bringing their own trap
+/// entry must keep it too, or the kernel never sees them in a handler.
+pub const USER_TRAP_HANDLER_DEPTH: usize = USER_TRAP_STACK_SLOT - size_of::<usize>();
+/// Handlers entered so far, raised with `USER_TRAP_HANDLER_DEPTH`, which tells one run of
+/// a handler from the next
+pub const USER_TRAP_HANDLER_ENTRIES: usize = USER_TRAP_HANDLER_DEPTH - size_of::<usize>();
+
+/// Passed to `sys_init_user_trap` by runtimes which bring their own trap entry. It is
+/// applied to utvec and `USER_TRAP_STACK_SLOT` once, the flags are for the runtime.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UserTrapDescriptor {
+    /// Written to utvec, in direct or vectored mode
+    pub entry: usize,
+    /// Top of a dedicated trap stack, 0 to stay on the interrupted stack
+    pub stack_top: usize,
+    pub flags: usize,
+}
+
 #[derive(Clone)]
 pub struct UserTrapInfo {
     pub user_trap_buffer_ppn: PhysPageNum,
     pub devices: Vec<(u16, bool)>,
+    /// (start, len) of device registers mapped by `sys_mmio_map`
+    pub mmio_regions: Vec<(usize, usize)>,
+    /// DMA buffers allocated by `sys_dma_alloc`, identically mapped in user space
+    pub dma_buffers: Vec<Arc<DmaTracker>>,
+    /// Max user soft interrupts raised in one time slice, 0 for unlimited
+    pub max_notify_per_slice: usize,
+    /// Stop raising interrupts once this many records are queued, 0 for never
+    pub poll_threshold: usize,
+    pub slice_notify_count: usize,
+    /// Records are left for the task to poll until the queue is drained
+    pub is_polling: bool,
+    /// Set by `sys_uintr_mask`, records are queued without raising interrupts
+    pub is_masked: bool,
+    /// Set by `sys_uintr_wait`, the task is not run until a record is queued
+    pub is_waiting: bool,
+    /// Set by `poison_user_trap`, the trap buffer is not touched any more
+    pub poisoned: bool,
+    pub send_quota: SendQuota,
+    /// Longest a handler may run, 0 for no limit
+    pub handler_budget_us: usize,
+    /// Where a handler over its budget is sent to return from the trap, 0 to kill the task
+    pub handler_abort_entry: usize,
+    /// (first tick it was seen at, `USER_TRAP_HANDLER_ENTRIES`) of the handler running at
+    /// the last timer tick
+    pub handler_seen: Option<(usize, usize)>,
+}
+
+/// Token bucket policing the messages sent by a task
+#[derive(Clone, Default)]
+pub struct SendQuota {
+    /// Messages per second, 0 for unlimited
+    pub rate: usize,
+    pub burst: usize,
+    pub tokens: usize,
+    pub last_refill_us: usize,
+}
+
+impl SendQuota {
+    pub fn set_rate(&mut self, rate: usize, burst: usize) {
+        self.rate = rate;
+        self.burst = burst.max(1);
+        self.tokens = self.burst;
+        self.last_refill_us = get_time_us();
+    }
+
+    fn refill(&mut self) {
+        let now = get_time_us();
+        // rate and burst come from user space, saturate instead of overflowing
+        let new_tokens = now
+            .saturating_sub(self.last_refill_us)
+            .saturating_mul(self.rate)
+            / USEC_PER_SEC;
+        if new_tokens >= self.burst - self.tokens {
+            self.tokens = self.burst;
+            self.last_refill_us = now;
+        } else if new_tokens > 0 {
+            self.tokens += new_tokens;
+            // keep the remainder for the next refill
+            self.last_refill_us += new_tokens.saturating_mul(USEC_PER_SEC) / self.rate;
+        }
+    }
+
+    pub fn try_acquire(&mut self) -> bool {
+        if self.rate == 0 {
+            return true;
+        }
+        self.refill();
+        if self.tokens > 0 {
+            self.tokens -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Give back the token of a message which was not delivered
+    pub fn refund(&mut self) {
+        if self.rate > 0 {
+            self.tokens = (self.tokens + 1).min(self.burst);
+        }
+    }
 }
 
 #[repr(C)]
@@ -30,18 +215,50 @@ pub enum UserTrapError {
     TaskNotFound,
     TrapUninitialized,
     TrapBufferFull,
+    /// The receiver broke its trap buffer and is being killed
+    Poisoned,
+}
+
+impl UserTrapError {
+    /// Error number returned to user space
+    pub fn errno(&self) -> isize {
+        match self {
+            UserTrapError::TaskNotFound => ESRCH,
+            UserTrapError::TrapUninitialized => ENOTCONN,
+            UserTrapError::TrapBufferFull => ENOBUFS,
+            UserTrapError::Poisoned => EPIPE,
+        }
+    }
 }
 
 impl UserTrapInfo {
+    /// The trap queue lives in user memory. Its page must still be the one mapped at
+    /// `USER_TRAP_BUFFER`, and its indices in range.
+    pub fn check_trap_queue(&self, memory_set: &MemorySet) -> Result<(), UserTrapError> {
+        if self.poisoned {
+            return Err(UserTrapError::Poisoned);
+        }
+        let is_mapped = memory_set
+            .translate(VirtAddr::from(USER_TRAP_BUFFER).into())
+            .map_or(false, |pte| {
+                pte.ppn() == self.user_trap_buffer_ppn && pte.writable()
+            });
+        if !is_mapped {
+            return Err(UserTrapError::Poisoned);
+        }
+        match self.get_trap_queue().indices() {
+            Some(_) => Ok(()),
+            None => Err(UserTrapError::Poisoned),
+        }
+    }
+
+    /// The indices may have changed since `check_trap_queue`, the enqueue checks them again
     pub fn push_trap_record(&mut self, trap_record: UserTrapRecord) -> Result<(), UserTrapError> {
         let res = self.get_trap_queue_mut().enqueue(trap_record);
-        match res {
-            Ok(()) => Ok(()),
-            Err(_) => {
-                warn!("[push trap record] User TrapBufferFull!");
-                Err(UserTrapError::TrapBufferFull)
-            }
+        if let Err(UserTrapError::TrapBufferFull) = res {
+            warn!("[push trap record] User TrapBufferFull!");
         }
+        res
     }
 
     pub fn enable_user_ext_int(&self) {
@@ -100,12 +317,20 @@ impl UserTrapInfo {
         // }
     }
 
-    pub fn remove_user_ext_int_map(&self) {
+    /// Give the devices claimed by `pid` back to the kernel, a device claimed since by
+    /// another task is left alone
+    pub fn remove_user_ext_int_map(&self, pid: usize) {
         let mut int_map = USER_EXT_INT_MAP.lock();
+        let owned: Vec<u16> = self
+            .devices
+            .iter()
+            .map(|(device_id, _)| *device_id)
+            .filter(|device_id| int_map.get(device_id) == Some(&pid))
+            .collect();
         for hart_id in 0..CPU_NUM {
             let s_context = get_context(hart_id, 'S');
             let u_context = get_context(hart_id, 'U');
-            for (device_id, _) in &self.devices {
+            for device_id in &owned {
                 // Plic::enable(u_context, *device_id);
                 // Plic::claim(u_context);
                 // Plic::complete(u_context, *device_id);
@@ -115,6 +340,59 @@ impl UserTrapInfo {
                 int_map.remove(device_id);
             }
         }
+        for device_id in owned {
+            plic::route_to_kernel(device_id);
+        }
+    }
+
+    pub fn start_time_slice(&mut self) {
+        self.slice_notify_count = 0;
+    }
+
+    /// Decide whether a user soft interrupt should be raised for queued records,
+    /// following the coalescing policy of the task
+    pub fn should_notify(&mut self) -> bool {
+        let record_num = self.user_trap_record_num();
+        if record_num == 0 {
+            self.is_polling = false;
+            return false;
+        }
+        if self.is_masked {
+            return false;
+        }
+        if self.poll_threshold > 0 && record_num >= self.poll_threshold {
+            self.is_polling = true;
+        }
+        if self.is_polling
+            || (self.max_notify_per_slice > 0
+                && self.slice_notify_count >= self.max_notify_per_slice)
+        {
+            return false;
+        }
+        self.slice_notify_count += 1;
+        true
+    }
+
+    /// Whether `should_notify` would raise an interrupt, without counting it
+    pub fn will_notify(&self) -> bool {
+        let record_num = self.user_trap_record_num();
+        record_num > 0
+            && !self.is_masked
+            && !self.is_polling
+            && !(self.poll_threshold > 0 && record_num >= self.poll_threshold)
+            && !(self.max_notify_per_slice > 0
+                && self.slice_notify_count >= self.max_notify_per_slice)
+    }
+
+    /// Only registers of devices claimed by this task can be mapped
+    pub fn is_mmio_allowed(&self, start: usize, len: usize) -> bool {
+        self.devices.iter().any(|(device_id, _)| {
+            if let Some((base, size)) = plic::device_mmio_range(*device_id) {
+                base <= start && start + len <= base + size
+            } else {
+                false
+            }
+        })
     }
 
     pub fn get_trap_queue(&self) -> &UserTrapQueue {
@@ -125,13 +403,192 @@ impl UserTrapInfo {
         self.user_trap_buffer_ppn.get_mut::<UserTrapQueue>()
     }
 
+    /// (`USER_TRAP_HANDLER_DEPTH`, `USER_TRAP_HANDLER_ENTRIES`) as last written by the task
+    pub fn handler_state(&self) -> (usize, usize) {
+        let words = self
+            .user_trap_buffer_ppn
+            .get_mut::<[usize; PAGE_SIZE / 8]>();
+        let word = |addr: usize| (addr - USER_TRAP_BUFFER) / size_of::<usize>();
+        unsafe {
+            (
+                read_volatile(&words[word(USER_TRAP_HANDLER_DEPTH)]),
+                read_volatile(&words[word(USER_TRAP_HANDLER_ENTRIES)]),
+            )
+        }
+    }
+
     pub fn user_trap_record_num(&self) -> usize {
         self.get_trap_queue().len()
     }
 }
 
 lazy_static! {
-    pub static ref USER_EXT_INT_MAP: Mutex<BTreeMap<u16, usize>> = Mutex::new(BTreeMap::new());
+    pub static ref USER_EXT_INT_MAP: SpinNoIrq<BTreeMap<u16, usize>> = SpinNoIrq::new(BTreeMap::new());
+    /// (pid namespace id, multicast group id) -> pids of members,
+    /// so that each pid namespace has its own groups
+    pub static ref USER_MSG_GROUPS: Mutex<BTreeMap<(usize, usize), BTreeSet<usize>>> =
+        Mutex::new(BTreeMap::new());
+    /// (pid namespace id, name) -> pid of the receiver bound to it by `sys_uipi_bind`,
+    /// so that each pid namespace has its own names
+    pub static ref UIPI_NAMES: Mutex<BTreeMap<(usize, String), usize>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// Longest name a receiver can be bound to
+pub const MAX_UIPI_NAME_LEN: usize = 64;
+
+/// Bind `name` to `pid`, which may bind it again, but not take the one of another task
+pub fn bind_uipi_name(ns_id: usize, name: String, pid: usize) -> Result<(), isize> {
+    let mut names = UIPI_NAMES.lock();
+    match names.get(&(ns_id, name.clone())) {
+        Some(&owner) if owner != pid => Err(EEXIST),
+        _ => {
+            names.insert((ns_id, name), pid);
+            Ok(())
+        }
+    }
+}
+
+/// Only the task bound to `name` can unbind it
+pub fn unbind_uipi_name(ns_id: usize, name: String, pid: usize) -> bool {
+    let mut names = UIPI_NAMES.lock();
+    let key = (ns_id, name);
+    if names.get(&key) == Some(&pid) {
+        names.remove(&key);
+        true
+    } else {
+        false
+    }
+}
+
+pub fn resolve_uipi_name(ns_id: usize, name: String) -> Option<usize> {
+    UIPI_NAMES.lock().get(&(ns_id, name)).cloned()
+}
+
+/// Called when a task exits or releases its user traps
+pub fn unbind_all_uipi_names(pid: usize) {
+    UIPI_NAMES.lock().retain(|_, owner| *owner != pid);
+}
+
+pub fn join_msg_group(ns_id: usize, group_id: usize, pid: usize) {
+    USER_MSG_GROUPS
+        .lock()
+        .entry((ns_id, group_id))
+        .or_insert_with(BTreeSet::new)
+        .insert(pid);
+}
+
+pub fn leave_msg_group(ns_id: usize, group_id: usize, pid: usize) -> bool {
+    let mut groups = USER_MSG_GROUPS.lock();
+    if let Some(members) = groups.get_mut(&(ns_id, group_id)) {
+        let res = members.remove(&pid);
+        if members.is_empty() {
+            groups.remove(&(ns_id, group_id));
+        }
+        res
+    } else {
+        false
+    }
+}
+
+/// Number of groups `pid` is a member of
+pub fn msg_group_count(pid: usize) -> usize {
+    USER_MSG_GROUPS
+        .lock()
+        .values()
+        .filter(|members| members.contains(&pid))
+        .count()
+}
+
+/// Called when a task exits
+pub fn leave_all_msg_groups(pid: usize) {
+    let mut groups = USER_MSG_GROUPS.lock();
+    groups.retain(|_, members| {
+        members.remove(&pid);
+        !members.is_empty()
+    });
+}
+
+/// After a task was killed for its broken user trap state: forget the device claims,
+/// group memberships and names of tasks which are gone, giving the devices back to the kernel
+pub fn repair_uipi_state() {
+    let claims: Vec<(u16, usize)> = USER_EXT_INT_MAP
+        .lock()
+        .iter()
+        .map(|(&device_id, &pid)| (device_id, pid))
+        .collect();
+    for (device_id, pid) in claims {
+        let is_held = crate::task::find_task(pid).map_or(false, |task| {
+            task.acquire_inner_lock()
+                .user_trap_info
+                .as_ref()
+                .map_or(false, |info| {
+                    info.devices.iter().any(|(id, _)| *id == device_id)
+                })
+        });
+        let mut int_map = USER_EXT_INT_MAP.lock();
+        if !is_held && int_map.get(&device_id) == Some(&pid) {
+            warn!("[uipi repair] device {} was left to pid {}", device_id, pid);
+            int_map.remove(&device_id);
+            let is_level = plic::irq_trigger(device_id) == IrqTrigger::Level;
+            for hart_id in 0..CPU_NUM {
+                let s_context = get_context(hart_id, 'S');
+                Plic::disable(get_context(hart_id, 'U'), device_id);
+                Plic::enable(s_context, device_id);
+                // a level source claimed for the dead owner is held back until completed
+                if is_level {
+                    Plic::complete(s_context, device_id);
+                }
+            }
+            plic::route_to_kernel(device_id);
+        }
+    }
+    let members: BTreeSet<usize> = USER_MSG_GROUPS
+        .lock()
+        .values()
+        .flat_map(|members| members.iter().cloned())
+        .collect();
+    for pid in members {
+        if crate::task::find_task(pid).is_none() {
+            warn!("[uipi repair] pid {} was left in message groups", pid);
+            leave_all_msg_groups(pid);
+        }
+    }
+    let owners: BTreeSet<usize> = UIPI_NAMES.lock().values().cloned().collect();
+    for pid in owners {
+        if crate::task::find_task(pid).is_none() {
+            warn!("[uipi repair] pid {} was left bound to names", pid);
+            unbind_all_uipi_names(pid);
+        }
+    }
+}
+
+/// Send a message to every member of a group, return the number of members reached.
+/// `sender_pid` is the pid of the sender in namespace `ns_id`, the one of the group.
+pub fn push_group_trap_record(
+    sender_pid: usize,
+    ns_id: usize,
+    group_id: usize,
+    msg: usize,
+) -> usize {
+    // members are collected first, pushing takes the lock of every receiver
+    let members: Vec<usize> = match USER_MSG_GROUPS.lock().get(&(ns_id, group_id)) {
+        Some(members) => members.iter().cloned().collect(),
+        None => return 0,
+    };
+    members
+        .into_iter()
+        .filter(|pid| {
+            push_trap_record(
+                *pid,
+                UserTrapRecord {
+                    cause: sender_pid << 4,
+                    message: msg,
+                },
+            )
+            .is_ok()
+        })
+        .count()
 }
 
 pub fn push_trap_record(pid: usize, trap_record: UserTrapRecord) -> Result<(), UserTrapError> {
@@ -145,14 +602,40 @@ pub fn push_trap_record(pid: usize, trap_record: UserTrapRecord) -> Result<(), U
             // warn!("[push trap record] User trap disabled!");
             // return Err(UserTrapError::TrapDisabled);
         }
+        let checked = tcb_inner
+            .user_trap_info
+            .as_ref()
+            .map(|trap_info| trap_info.check_trap_queue(&tcb_inner.memory_set));
+        if let Some(Err(e)) = checked {
+            // only the receiver pays for its broken queue
+            if tcb_inner.poison_user_trap() {
+                warn!("[push trap record] pid {} broke its trap queue", pid);
+                // a task in user mode on another hart is killed once it takes the interrupt
+                if let Running(task_hart_id) = tcb_inner.task_status {
+                    if task_hart_id != hart_id() {
+                        ipi::send(HartMask::single(task_hart_id), IpiMessage::UserTrap);
+                    }
+                }
+            }
+            return Err(e);
+        }
         if let Some(trap_info) = &mut tcb_inner.user_trap_info {
             let res = trap_info.push_trap_record(trap_record);
-            // if let Running(task_hart_id) = tcb_inner.task_status {
-            //     if task_hart_id != hart_id() {
-            //         let mask: usize = 1 << task_hart_id;
-            //         send_ipi(&mask as *const _ as usize);
-            //     }
-            // }
+            // the task broke its indices after they were checked
+            if let Err(UserTrapError::Poisoned) = res {
+                tcb_inner.poison_user_trap();
+                return res;
+            }
+            // a task running on this hart gets the record on trap return,
+            // a task running on another hart is kicked, and gets it on that trap return
+            if res.is_ok() {
+                tcb_inner.usage.uipi_received += 1;
+                if let Running(task_hart_id) = tcb_inner.task_status {
+                    if task_hart_id != hart_id() {
+                        ipi::send(HartMask::single(task_hart_id), IpiMessage::UserTrap);
+                    }
+                }
+            }
             res
         } else {
             warn!("[push trap record] User trap uninitialized!");
@@ -1,54 +1,104 @@
+use crate::config::kernel_config;
+use crate::util::SpinNoIrq;
 use alloc::collections::VecDeque;
+use alloc::vec::Vec;
 use core::convert::Infallible;
 use embedded_hal::serial::{Read, Write};
 use lazy_static::*;
-use spin::Mutex;
 
 pub const DEFAULT_TX_BUFFER_SIZE: usize = 1_000;
 pub const DEFAULT_RX_BUFFER_SIZE: usize = 1_000;
+/// Ask the peer to stop sending above this many buffered rx bytes, resume below the low mark
+const RX_HIGH_WATERMARK: usize = DEFAULT_RX_BUFFER_SIZE * 3 / 4;
+const RX_LOW_WATERMARK: usize = DEFAULT_RX_BUFFER_SIZE / 4;
+const UART_CLOCK: usize = 100_000_000;
+
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+
+pub const PARITY_NONE: u8 = 0;
+pub const PARITY_ODD: u8 = 1;
+pub const PARITY_EVEN: u8 = 2;
+
+pub const FLOW_CONTROL_NONE: u8 = 0;
+/// RTS/CTS lines, with auto flow control on UARTs which have it
+pub const FLOW_CONTROL_RTS_CTS: u8 = 1;
+/// XON/XOFF in band, for ports without the modem lines wired
+pub const FLOW_CONTROL_XON_XOFF: u8 = 2;
+
+/// Line settings of a serial port, shared with user space through `ioctl`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SerialConfig {
+    pub baud_rate: u32,
+    pub parity: u8,
+    /// 1 or 2
+    pub stop_bits: u8,
+    /// 5 to 8
+    pub word_length: u8,
+    pub flow_control: u8,
+}
+
+impl SerialConfig {
+    pub const fn new(baud_rate: u32) -> Self {
+        Self {
+            baud_rate,
+            parity: PARITY_NONE,
+            stop_bits: 1,
+            word_length: 8,
+            flow_control: FLOW_CONTROL_NONE,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.baud_rate > 0
+            && (1..=0xffff).contains(&(UART_CLOCK / (16 * self.baud_rate as usize)))
+            && self.parity <= PARITY_EVEN
+            && (1..=2).contains(&self.stop_bits)
+            && (5..=8).contains(&self.word_length)
+            && self.flow_control <= FLOW_CONTROL_XON_XOFF
+    }
+
+    fn lcr(&self) -> SerialRegister {
+        let parity = match self.parity {
+            PARITY_ODD => 0b001,
+            PARITY_EVEN => 0b011,
+            _ => 0,
+        };
+        (parity << 3 | (self.stop_bits - 1) << 2 | (self.word_length - 5)) as SerialRegister
+    }
+}
+
+const MCR_RTS: SerialRegister = 1 << 1;
+/// Auto flow control of 16750 compatible UARTs, ignored by plain 16550s
+const MCR_AFE: SerialRegister = 1 << 5;
+const MSR_CTS: SerialRegister = 1 << 4;
 
 #[cfg(feature = "board_qemu")]
 mod serial_config {
     pub use uart8250::{InterruptType, MmioUart8250};
     pub type SerialHardware = MmioUart8250<'static>;
+    pub type SerialRegister = u8;
     pub const FIFO_DEPTH: usize = 16;
-    pub const SERIAL_NUM: usize = 4;
-    pub const SERIAL_BASE_ADDRESS: usize = 0x1000_2000;
-    pub const SERIAL_ADDRESS_STRIDE: usize = 0x1000;
-    pub fn irq_to_serial_id(irq: u16) -> usize {
-        match irq {
-            12 => 0,
-            13 => 1,
-            14 => 2,
-            15 => 3,
-            _ => 0,
-        }
-    }
 }
 
 #[cfg(feature = "board_lrv")]
 mod serial_config {
     pub use uart_xilinx::uart_16550::{InterruptType, MmioUartAxi16550};
     pub type SerialHardware = MmioUartAxi16550<'static>;
+    pub type SerialRegister = u32;
     pub const FIFO_DEPTH: usize = 16;
-    pub const SERIAL_NUM: usize = 4;
-    pub const SERIAL_BASE_ADDRESS: usize = 0x6000_1000;
-    pub const SERIAL_ADDRESS_STRIDE: usize = 0x1000;
-    pub fn irq_to_serial_id(irq: u16) -> usize {
-        match irq {
-            4 => 0,
-            5 => 1,
-            6 => 2,
-            7 => 3,
-            _ => 0,
-        }
-    }
 }
 
 pub use serial_config::*;
 
-pub fn get_base_addr_from_irq(irq: u16) -> usize {
-    SERIAL_BASE_ADDRESS + irq_to_serial_id(irq) * SERIAL_ADDRESS_STRIDE
+pub fn serial_base_addr(serial_id: usize) -> usize {
+    kernel_config().serial_ports()[serial_id].base
+}
+
+/// Number of serial ports found at boot
+pub fn serial_num() -> usize {
+    kernel_config().serial_ports().len()
 }
 pub struct BufferedSerial {
     pub hardware: SerialHardware,
@@ -62,6 +112,13 @@ pub struct BufferedSerial {
     pub tx_fifo_count: usize,
     rx_intr_enabled: bool,
     tx_intr_enabled: bool,
+    config: SerialConfig,
+    /// The peer asked us to stop with XOFF or by dropping CTS
+    tx_stopped: bool,
+    /// We asked the peer to stop, with XOFF or by dropping RTS
+    rx_throttled: bool,
+    /// XON or XOFF to send ahead of `tx_buffer`
+    tx_control: Option<u8>,
 }
 
 impl BufferedSerial {
@@ -78,6 +135,10 @@ impl BufferedSerial {
             tx_fifo_count: 0,
             rx_intr_enabled: false,
             tx_intr_enabled: false,
+            config: SerialConfig::new(115200),
+            tx_stopped: false,
+            rx_throttled: false,
+            tx_control: None,
         }
     }
 
@@ -87,55 +148,192 @@ impl BufferedSerial {
         let _ = hardware.read_msr();
         let _ = hardware.read_lsr();
         hardware.write_mcr(0);
-        hardware.init(100_000_000, baud_rate);
+        hardware.init(UART_CLOCK, baud_rate);
         hardware.enable_received_data_available_interrupt();
         self.rx_intr_enabled = true;
         // Rx FIFO trigger level=14, reset Rx & Tx FIFO, enable FIFO
         hardware.write_fcr(0b11_000_11_1);
+        self.config = SerialConfig::new(baud_rate as u32);
+    }
+
+    pub fn config(&self) -> SerialConfig {
+        self.config
+    }
+
+    /// Reprogram the line, the caller checks `config.is_valid()`
+    pub fn set_config(&mut self, config: SerialConfig) {
+        let hardware = &self.hardware;
+        hardware.set_divisor(UART_CLOCK, config.baud_rate as usize);
+        hardware.write_lcr(config.lcr());
+        if config.flow_control == FLOW_CONTROL_RTS_CTS {
+            hardware.write_mcr(MCR_RTS | MCR_AFE);
+            hardware.enable_modem_status_interrupt();
+        } else {
+            hardware.write_mcr(0);
+            hardware.disable_modem_status_interrupt();
+        }
+        self.config = config;
+        self.rx_throttled = false;
+        self.tx_control = None;
+        self.tx_stopped = config.flow_control == FLOW_CONTROL_RTS_CTS && !self.clear_to_send();
+        self.kick_tx();
+    }
+
+    fn clear_to_send(&self) -> bool {
+        self.hardware.read_msr() & MSR_CTS != 0
+    }
+
+    /// Make sure the THR empty interrupt fires if there is anything to send
+    fn kick_tx(&mut self) {
+        let pending = self.tx_control.is_some() || !self.tx_stopped && !self.tx_buffer.is_empty();
+        if pending && !self.tx_intr_enabled {
+            self.hardware
+                .enable_transmitter_holding_register_empty_interrupt();
+            self.tx_intr_enabled = true;
+        }
+    }
+
+    /// Queue as much of `bytes` as fits in one go, return how many were taken.
+    /// An idle transmitter gets its FIFO filled right away instead of waiting for the interrupt.
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> usize {
+        let len = bytes
+            .len()
+            .min(DEFAULT_TX_BUFFER_SIZE - self.tx_buffer.len());
+        self.tx_buffer.extend(&bytes[..len]);
+        if !self.tx_intr_enabled && self.hardware.is_transmitter_holding_register_empty() {
+            self.fill_tx_fifo();
+        }
+        self.kick_tx();
+        len
+    }
+
+    /// Wait for the FIFO to drain and refill it from the tx buffer,
+    /// return false if flow control holds the line so that waiting is pointless
+    fn poll_tx(&mut self) -> bool {
+        if self.tx_stopped || self.tx_control.is_some() {
+            return false;
+        }
+        while !self.hardware.is_transmitter_holding_register_empty() {}
+        self.fill_tx_fifo();
+        true
+    }
+
+    fn fill_tx_fifo(&mut self) {
+        if self.tx_stopped || self.tx_control.is_some() {
+            return;
+        }
+        for _ in 0..FIFO_DEPTH {
+            match self.tx_buffer.pop_front() {
+                Some(ch) => {
+                    self.hardware.write_byte(ch);
+                    self.tx_count += 1;
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn throttle_rx(&mut self) {
+        if self.rx_throttled {
+            return;
+        }
+        match self.config.flow_control {
+            FLOW_CONTROL_RTS_CTS => self.hardware.write_mcr(MCR_AFE),
+            FLOW_CONTROL_XON_XOFF => {
+                self.tx_control = Some(XOFF);
+                self.kick_tx();
+            }
+            _ => return,
+        }
+        self.rx_throttled = true;
+    }
+
+    fn unthrottle_rx(&mut self) {
+        if !self.rx_throttled {
+            return;
+        }
+        match self.config.flow_control {
+            FLOW_CONTROL_RTS_CTS => self.hardware.write_mcr(MCR_RTS | MCR_AFE),
+            FLOW_CONTROL_XON_XOFF => {
+                self.tx_control = Some(XON);
+                self.kick_tx();
+            }
+            _ => {}
+        }
+        self.rx_throttled = false;
     }
 
     #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
     pub fn interrupt_handler(&mut self) {
-        let hardware = &self.hardware;
-        while let Some(int_type) = hardware.read_interrupt_type() {
+        while let Some(int_type) = self.hardware.read_interrupt_type() {
             self.intr_count += 1;
             match int_type {
                 InterruptType::ReceivedDataAvailable | InterruptType::Timeout => {
                     // trace!("Received data available");
                     self.rx_intr_count += 1;
-                    while let Some(ch) = hardware.read_byte() {
+                    while let Some(ch) = self.hardware.read_byte() {
+                        if self.config.flow_control == FLOW_CONTROL_XON_XOFF
+                            && (ch == XON || ch == XOFF)
+                        {
+                            self.tx_stopped = ch == XOFF;
+                            continue;
+                        }
                         if self.rx_buffer.len() < DEFAULT_TX_BUFFER_SIZE {
                             self.rx_buffer.push_back(ch);
                             self.rx_count += 1;
                         } else {
                             // warn!("Serial rx buffer overflow!");
-                            hardware.disable_received_data_available_interrupt();
+                            self.hardware.disable_received_data_available_interrupt();
                             self.rx_intr_enabled = false;
                             break;
                         }
                     }
+                    if self.rx_buffer.len() >= RX_HIGH_WATERMARK {
+                        self.throttle_rx();
+                    }
+                    self.kick_tx();
                 }
                 InterruptType::TransmitterHoldingRegisterEmpty => {
                     // trace!("TransmitterHoldingRegisterEmpty");
                     self.tx_intr_count += 1;
-                    for _ in 0..FIFO_DEPTH {
+                    let mut room = FIFO_DEPTH;
+                    if let Some(ch) = self.tx_control.take() {
+                        self.hardware.write_byte(ch);
+                        room -= 1;
+                    }
+                    if self.config.flow_control == FLOW_CONTROL_RTS_CTS && !self.clear_to_send() {
+                        self.tx_stopped = true;
+                    }
+                    for _ in 0..room {
+                        if self.tx_stopped {
+                            break;
+                        }
                         if let Some(ch) = self.tx_buffer.pop_front() {
-                            hardware.write_byte(ch);
+                            self.hardware.write_byte(ch);
                             self.tx_count += 1;
                         } else {
-                            hardware.disable_transmitter_holding_register_empty_interrupt();
-                            self.tx_intr_enabled = false;
                             break;
                         }
                     }
+                    if self.tx_stopped || self.tx_buffer.is_empty() {
+                        self.hardware
+                            .disable_transmitter_holding_register_empty_interrupt();
+                        self.tx_intr_enabled = false;
+                    }
                 }
                 InterruptType::ModemStatus => {
-                    debug!(
-                        "MSR: {:#x}, LSR: {:#x}, IER: {:#x}",
-                        hardware.read_msr(),
-                        hardware.read_lsr(),
-                        hardware.read_ier()
-                    );
+                    let msr = self.hardware.read_msr();
+                    if self.config.flow_control == FLOW_CONTROL_RTS_CTS {
+                        self.tx_stopped = msr & MSR_CTS == 0;
+                        self.kick_tx();
+                    } else {
+                        debug!(
+                            "MSR: {:#x}, LSR: {:#x}, IER: {:#x}",
+                            msr,
+                            self.hardware.read_lsr(),
+                            self.hardware.read_ier()
+                        );
+                    }
                 }
                 _ => {
                     warn!("[SERIAL] {:?} not supported!", int_type);
@@ -153,7 +351,7 @@ impl Write<u8> for BufferedSerial {
         let serial = &mut self.hardware;
         if self.tx_buffer.len() < DEFAULT_TX_BUFFER_SIZE {
             self.tx_buffer.push_back(word);
-            if !self.tx_intr_enabled {
+            if !self.tx_intr_enabled && !self.tx_stopped {
                 serial.enable_transmitter_holding_register_empty_interrupt();
                 self.tx_intr_enabled = true;
             }
@@ -174,6 +372,9 @@ impl Read<u8> for BufferedSerial {
 
     fn try_read(&mut self) -> nb::Result<u8, Self::Error> {
         if let Some(ch) = self.rx_buffer.pop_front() {
+            if self.rx_buffer.len() < RX_LOW_WATERMARK {
+                self.unthrottle_rx();
+            }
             Ok(ch)
         } else {
             let serial = &mut self.hardware;
@@ -199,10 +400,12 @@ impl Drop for BufferedSerial {
 
 #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
 lazy_static! {
-    pub static ref BUFFERED_SERIAL: [Mutex<BufferedSerial>; SERIAL_NUM] =
-        array_init::array_init(|i| Mutex::new(BufferedSerial::new(
-            SERIAL_BASE_ADDRESS + i * SERIAL_ADDRESS_STRIDE,
-        )));
+    /// Also taken by the external interrupt handler
+    pub static ref BUFFERED_SERIAL: Vec<SpinNoIrq<BufferedSerial>> = kernel_config()
+        .serial_ports()
+        .iter()
+        .map(|port| SpinNoIrq::new(BufferedSerial::new(port.base)))
+        .collect();
 }
 
 #[cfg(feature = "board_lrv_seriallite")]
@@ -210,17 +413,16 @@ use serial_xilinx::MmioSerialAxiLite;
 
 #[cfg(feature = "board_lrv_seriallite")]
 lazy_static! {
-    pub static ref SERIAL: Arc<Mutex<MmioSerialAxiLite<'static>>> =
-        Arc::new(Mutex::new(MmioSerialAxiLite::new(0x6000_1000)));
+    pub static ref SERIAL: Arc<SpinNoIrq<MmioSerialAxiLite<'static>>> =
+        Arc::new(SpinNoIrq::new(MmioSerialAxiLite::new(0x6000_1000)));
 }
 
 #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
 pub fn init() {
-    for serial_id in 0..2 {
-        BUFFERED_SERIAL[serial_id].lock().hardware_init(115200);
-    }
-    for serial_id in 2..SERIAL_NUM {
-        BUFFERED_SERIAL[serial_id].lock().hardware_init(6_250_000);
+    for (serial_id, serial) in BUFFERED_SERIAL.iter().enumerate() {
+        serial
+            .lock()
+            .hardware_init(if serial_id < 2 { 115200 } else { 6_250_000 });
     }
 }
 
@@ -229,10 +431,8 @@ pub fn init() {
     SERIAL.lock().enable_interrupt();
 }
 
-pub fn handle_interrupt(irq: u16) {
-    BUFFERED_SERIAL[irq_to_serial_id(irq)]
-        .lock()
-        .interrupt_handler();
+pub fn handle_interrupt(serial_id: usize) {
+    BUFFERED_SERIAL[serial_id].lock().interrupt_handler();
 }
 
 #[cfg(feature = "board_lrv_seriallite")]
@@ -268,6 +468,33 @@ pub fn serial_putchar(serial_id: usize, c: u8) -> nb::Result<(), Infallible> {
     BUFFERED_SERIAL[serial_id].lock().try_write(c)
 }
 
+/// Queue all of `bytes`, pushing out the tx buffer by polling while it is full,
+/// since the caller may have interrupts off. Only gives up, returning how much
+/// was queued, if flow control holds the line.
+pub fn serial_write(serial_id: usize, mut bytes: &[u8]) -> usize {
+    let total = bytes.len();
+    let mut serial = BUFFERED_SERIAL[serial_id].lock();
+    loop {
+        bytes = &bytes[serial.write_bytes(bytes)..];
+        if bytes.is_empty() || !serial.poll_tx() {
+            return total - bytes.len();
+        }
+    }
+}
+
+pub fn serial_config(serial_id: usize) -> SerialConfig {
+    BUFFERED_SERIAL[serial_id].lock().config()
+}
+
+/// Return false if `config` is not valid
+pub fn serial_set_config(serial_id: usize, config: SerialConfig) -> bool {
+    if !config.is_valid() {
+        return false;
+    }
+    BUFFERED_SERIAL[serial_id].lock().set_config(config);
+    true
+}
+
 #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
 pub fn serial_getchar(serial_id: usize) -> nb::Result<u8, Infallible> {
     BUFFERED_SERIAL[serial_id].lock().try_read()
@@ -0,0 +1,92 @@
+//! Integer id allocator shared by pids and frames.
+//!
+//! The allocator keeps a bitmap of live ids, so `dealloc` can tell a
+//! double free or a stray id in O(1).
+
+use alloc::vec::Vec;
+
+const WORD_BITS: usize = 64;
+
+/// One bit per id, grown on demand
+#[derive(Default)]
+struct IdBitmap {
+    words: Vec<u64>,
+}
+
+impl IdBitmap {
+    fn get(&self, bit: usize) -> bool {
+        self.words
+            .get(bit / WORD_BITS)
+            .map_or(false, |word| word & (1 << (bit % WORD_BITS)) != 0)
+    }
+    fn set(&mut self, bit: usize) {
+        let idx = bit / WORD_BITS;
+        if idx >= self.words.len() {
+            self.words.resize(idx + 1, 0);
+        }
+        self.words[idx] |= 1 << (bit % WORD_BITS);
+    }
+    fn clear(&mut self, bit: usize) {
+        if let Some(word) = self.words.get_mut(bit / WORD_BITS) {
+            *word &= !(1 << (bit % WORD_BITS));
+        }
+    }
+}
+
+/// Hands out ids in `[start, end)` from a bump pointer and recycles them in LIFO order
+pub struct StackIdAllocator {
+    start: usize,
+    current: usize,
+    end: usize,
+    recycled: Vec<usize>,
+    is_reusing: bool,
+    allocated: IdBitmap,
+}
+
+impl StackIdAllocator {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self {
+            start,
+            current: start,
+            end,
+            recycled: Vec::new(),
+            is_reusing: true,
+            allocated: IdBitmap::default(),
+        }
+    }
+    /// Freed ids are never handed out again
+    pub fn without_reuse(mut self) -> Self {
+        self.is_reusing = false;
+        self
+    }
+    pub fn alloc(&mut self) -> Option<usize> {
+        let id = match self.recycled.pop() {
+            Some(id) => id,
+            None if self.current < self.end => {
+                self.current += 1;
+                self.current - 1
+            }
+            None => return None,
+        };
+        self.allocated.set(id - self.start);
+        Some(id)
+    }
+    /// Return `false` if `id` is not allocated
+    pub fn dealloc(&mut self, id: usize) -> bool {
+        if !self.is_allocated(id) {
+            return false;
+        }
+        self.allocated.clear(id - self.start);
+        if self.is_reusing {
+            self.recycled.push(id);
+        }
+        true
+    }
+    pub fn is_allocated(&self, id: usize) -> bool {
+        id >= self.start && id < self.current && self.allocated.get(id - self.start)
+    }
+    /// Number of ids which can still be allocated
+    pub fn available(&self) -> usize {
+        self.end - self.current + self.recycled.len()
+    }
+}
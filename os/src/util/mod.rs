@@ -0,0 +1,9 @@
+mod id_alloc;
+pub mod rcu;
+mod spin_no_irq;
+
+pub use id_alloc::StackIdAllocator;
+pub use rcu::Rcu;
+pub use spin_no_irq::{
+    assert_not_in_irq, held_locks, in_irq, irq_enter, irq_exit, SpinNoIrq, SpinNoIrqGuard,
+};
@@ -0,0 +1,108 @@
+//! RCU-lite for read-mostly kernel tables.
+//!
+//! Readers of an `Rcu<T>` take no lock and write no shared cache line, they
+//! only load the pointer to the current version. Writers serialize on a lock,
+//! publish an updated copy and retire the old one, which is freed once every
+//! hart has passed a quiescent state since.
+//!
+//! The kernel is not preemptible, so a hart is quiescent whenever it is not
+//! inside a `read`: `quiescent` is reported on the way back to user space and
+//! in the idle loop. A read section must not block, switch tasks or update an
+//! `Rcu`, and nothing borrowed from it may escape the closure.
+
+use crate::config::{kernel_config, CPU_NUM};
+use crate::task::hart_id;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::sync::atomic::{fence, AtomicPtr, AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// Bumped every time a version is retired
+static EPOCH: AtomicUsize = AtomicUsize::new(1);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const SEEN_INIT: AtomicUsize = AtomicUsize::new(0);
+/// The epoch each hart saw at its last quiescent state
+static SEEN: [AtomicUsize; CPU_NUM] = [SEEN_INIT; CPU_NUM];
+
+/// Old versions with the epoch every hart has to see before they are freed
+static RETIRED: Mutex<Vec<(usize, Box<dyn Send>)>> = Mutex::new(Vec::new());
+
+/// The current hart holds no reference from any `Rcu::read`
+pub fn quiescent(hart_id: usize) {
+    // the loads of the read sections before must not be ordered after it
+    fence(Ordering::SeqCst);
+    SEEN[hart_id].store(EPOCH.load(Ordering::SeqCst), Ordering::Release);
+}
+
+fn retire(old: Box<dyn Send>) {
+    let epoch = EPOCH.fetch_add(1, Ordering::SeqCst) + 1;
+    RETIRED.lock().push((epoch, old));
+}
+
+/// Free the versions no hart can be reading any more
+fn reclaim() {
+    let current = hart_id();
+    // the writer itself is not in a read section
+    let safe_epoch = (0..kernel_config().hart_num)
+        .filter(|&hart| hart != current)
+        .map(|hart| SEEN[hart].load(Ordering::Acquire))
+        .min()
+        .unwrap_or(usize::MAX);
+    let mut retired = RETIRED.lock();
+    let mut freed = Vec::new();
+    let mut idx = 0;
+    while idx < retired.len() {
+        if retired[idx].0 <= safe_epoch {
+            freed.push(retired.swap_remove(idx));
+        } else {
+            idx += 1;
+        }
+    }
+    drop(retired);
+    // dropped outside the lock, as dropping may update another `Rcu`
+    drop(freed);
+}
+
+pub struct Rcu<T: Send + 'static> {
+    current: AtomicPtr<T>,
+    writer: Mutex<()>,
+}
+
+unsafe impl<T: Send + Sync + 'static> Sync for Rcu<T> {}
+
+impl<T: Send + 'static> Rcu<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            current: AtomicPtr::new(Box::into_raw(Box::new(value))),
+            writer: Mutex::new(()),
+        }
+    }
+
+    pub fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        let ptr = self.current.load(Ordering::Acquire);
+        // only freed after this hart reports a quiescent state
+        f(unsafe { &*ptr })
+    }
+}
+
+impl<T: Clone + Send + 'static> Rcu<T> {
+    /// Update a copy of the current version and publish it
+    pub fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let writer = self.writer.lock();
+        let old = self.current.load(Ordering::Acquire);
+        let mut new = Box::new(unsafe { (*old).clone() });
+        let ret = f(&mut new);
+        self.current.store(Box::into_raw(new), Ordering::Release);
+        drop(writer);
+        retire(unsafe { Box::from_raw(old) });
+        reclaim();
+        ret
+    }
+}
+
+impl<T: Send + 'static> Drop for Rcu<T> {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(*self.current.get_mut()) });
+    }
+}
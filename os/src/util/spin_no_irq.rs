@@ -0,0 +1,153 @@
+//! Locks which may also be taken by interrupt handlers.
+//!
+//! A plain `spin::Mutex` held by a hart that takes an interrupt whose handler
+//! locks it again spins forever. `SpinNoIrq` keeps supervisor interrupts off
+//! while held. Kernel interrupt handlers run between `irq_enter` and
+//! `irq_exit`, and `assert_not_in_irq` catches plain locks taken there.
+//!
+//! Each hart records the locks it holds and the one it spins on, by address, so that
+//! the watchdog can tell where a hung hart is stuck.
+
+use crate::config::CPU_NUM;
+use crate::task::hart_id;
+use core::fmt::{self, Debug, Formatter};
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use riscv::register::sstatus;
+use spin::{Mutex, MutexGuard};
+
+#[allow(clippy::declare_interior_mutable_const)]
+const IRQ_DEPTH_INIT: AtomicUsize = AtomicUsize::new(0);
+static IRQ_DEPTH: [AtomicUsize; CPU_NUM] = [IRQ_DEPTH_INIT; CPU_NUM];
+
+/// Locks a hart may be seen holding at once, more are not recorded
+pub const MAX_HELD_LOCKS: usize = 8;
+#[allow(clippy::declare_interior_mutable_const)]
+const HELD_LOCKS_INIT: [AtomicUsize; MAX_HELD_LOCKS] = [IRQ_DEPTH_INIT; MAX_HELD_LOCKS];
+/// Addresses of the locks held by each hart, 0 for a free slot
+static HELD_LOCKS: [[AtomicUsize; MAX_HELD_LOCKS]; CPU_NUM] = [HELD_LOCKS_INIT; CPU_NUM];
+/// Address of the lock each hart spins on, 0 for none
+static WAITING_LOCK: [AtomicUsize; CPU_NUM] = [IRQ_DEPTH_INIT; CPU_NUM];
+
+pub fn irq_enter() {
+    IRQ_DEPTH[hart_id()].fetch_add(1, Relaxed);
+}
+
+pub fn irq_exit() {
+    IRQ_DEPTH[hart_id()].fetch_sub(1, Relaxed);
+}
+
+pub fn in_irq() -> bool {
+    IRQ_DEPTH[hart_id()].load(Relaxed) > 0
+}
+
+/// The locks `hart_id` holds and the one it spins on, 0 for none
+pub fn held_locks(hart_id: usize) -> ([usize; MAX_HELD_LOCKS], usize) {
+    let mut held = [0; MAX_HELD_LOCKS];
+    for (addr, slot) in held.iter_mut().zip(HELD_LOCKS[hart_id].iter()) {
+        *addr = slot.load(Relaxed);
+    }
+    (held, WAITING_LOCK[hart_id].load(Relaxed))
+}
+
+fn record_held(addr: usize) {
+    // `tp` is not a hart id yet early in boot
+    if let Some(slots) = HELD_LOCKS.get(hart_id()) {
+        if let Some(slot) = slots.iter().find(|slot| slot.load(Relaxed) == 0) {
+            slot.store(addr, Relaxed);
+        }
+    }
+}
+
+/// Interrupts stay off while a lock is held, so it is released on the hart which locked it
+fn record_released(addr: usize) {
+    if let Some(slots) = HELD_LOCKS.get(hart_id()) {
+        if let Some(slot) = slots.iter().find(|slot| slot.load(Relaxed) == addr) {
+            slot.store(0, Relaxed);
+        }
+    }
+}
+
+/// Called before taking a plain `spin::Mutex` which is also taken with interrupts on
+#[inline]
+pub fn assert_not_in_irq() {
+    debug_assert!(!in_irq(), "plain spin lock taken in interrupt context");
+}
+
+#[derive(Default)]
+pub struct SpinNoIrq<T: ?Sized> {
+    inner: Mutex<T>,
+}
+
+pub struct SpinNoIrqGuard<'a, T: ?Sized + 'a> {
+    /// `None` only while dropping
+    guard: Option<MutexGuard<'a, T>>,
+    sie: bool,
+    /// Of the lock, as recorded in `HELD_LOCKS`
+    addr: usize,
+}
+
+impl<T> SpinNoIrq<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            inner: Mutex::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> SpinNoIrq<T> {
+    pub fn lock(&self) -> SpinNoIrqGuard<'_, T> {
+        let sie = sstatus::read().sie();
+        unsafe {
+            sstatus::clear_sie();
+        }
+        let addr = self as *const Self as *const u8 as usize;
+        let waiting = WAITING_LOCK.get(hart_id());
+        if let Some(waiting) = waiting {
+            waiting.store(addr, Relaxed);
+        }
+        let guard = self.inner.lock();
+        if let Some(waiting) = waiting {
+            waiting.store(0, Relaxed);
+        }
+        record_held(addr);
+        SpinNoIrqGuard {
+            guard: Some(guard),
+            sie,
+            addr,
+        }
+    }
+}
+
+impl<T: ?Sized + Debug> Debug for SpinNoIrq<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl<T: ?Sized> Deref for SpinNoIrqGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.guard.as_ref().unwrap()
+    }
+}
+
+impl<T: ?Sized> DerefMut for SpinNoIrqGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.as_mut().unwrap()
+    }
+}
+
+impl<T: ?Sized> Drop for SpinNoIrqGuard<'_, T> {
+    fn drop(&mut self) {
+        // while still held, so that no two harts are ever seen holding it
+        record_released(self.addr);
+        // unlock before interrupts are back on
+        self.guard.take();
+        if self.sie {
+            unsafe {
+                sstatus::set_sie();
+            }
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use crate::config::CPU_NUM;
+use crate::timer::get_time_ms;
+use crate::util::held_locks;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+
+/// Interval of heartbeat checking, done by whichever hart ticks first
+const CHECK_INTERVAL_MS: usize = 1000;
+/// A hart without heartbeat for this long is considered hung
+const HUNG_TIMEOUT_MS: usize = 2000;
+
+/// Last-known state of a hart, recorded on every trap from user
+struct HartState {
+    heartbeat: AtomicUsize,
+    scause: AtomicUsize,
+    sepc: AtomicUsize,
+    stval: AtomicUsize,
+    pid: AtomicUsize,
+    is_reported: AtomicBool,
+}
+
+impl HartState {
+    const fn new() -> Self {
+        Self {
+            heartbeat: AtomicUsize::new(0),
+            scause: AtomicUsize::new(0),
+            sepc: AtomicUsize::new(0),
+            stval: AtomicUsize::new(0),
+            pid: AtomicUsize::new(0),
+            is_reported: AtomicBool::new(false),
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const HART_STATE_INIT: HartState = HartState::new();
+static HART_STATES: [HartState; CPU_NUM] = [HART_STATE_INIT; CPU_NUM];
+static LAST_CHECK: AtomicUsize = AtomicUsize::new(0);
+
+/// Called from the timer interrupt and the idle loop of each hart
+pub fn heartbeat(hart_id: usize) {
+    let state = &HART_STATES[hart_id];
+    state.heartbeat.store(get_time_ms(), Relaxed);
+    if state.is_reported.swap(false, Relaxed) {
+        warn!("[watchdog] hart {} recovered", hart_id);
+    }
+}
+
+pub fn record_trap(hart_id: usize, scause: usize, sepc: usize, stval: usize, pid: usize) {
+    let state = &HART_STATES[hart_id];
+    state.scause.store(scause, Relaxed);
+    state.sepc.store(sepc, Relaxed);
+    state.stval.store(stval, Relaxed);
+    state.pid.store(pid, Relaxed);
+}
+
+/// Check heartbeats of the other harts, run from the tick of every hart so that any hart
+/// left can tell, and done by one of them once per interval
+pub fn check(hart_id: usize) {
+    let now = get_time_ms();
+    let last_check = LAST_CHECK.load(Relaxed);
+    if now < last_check + CHECK_INTERVAL_MS
+        || LAST_CHECK
+            .compare_exchange(last_check, now, Relaxed, Relaxed)
+            .is_err()
+    {
+        return;
+    }
+    for (id, state) in HART_STATES.iter().enumerate() {
+        if id == hart_id {
+            continue;
+        }
+        let heartbeat = state.heartbeat.load(Relaxed);
+        // harts which have not started yet are skipped
+        if heartbeat == 0 || now < heartbeat + HUNG_TIMEOUT_MS {
+            continue;
+        }
+        if state.is_reported.swap(true, Relaxed) {
+            continue;
+        }
+        error!(
+            "[watchdog] hart {} hung for {} ms, last trap: scause = {:#x}, sepc = {:#x}, stval = {:#x}, pid = {}",
+            id,
+            now - heartbeat,
+            state.scause.load(Relaxed),
+            state.sepc.load(Relaxed),
+            state.stval.load(Relaxed),
+            state.pid.load(Relaxed),
+        );
+        let (held, waiting) = held_locks(id);
+        // no allocation here, the hung hart may hold the heap lock
+        error!(
+            "[watchdog] hart {} holds locks {:x?}, spins on {:#x}",
+            id, held, waiting
+        );
+    }
+}
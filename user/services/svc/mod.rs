@@ -0,0 +1,86 @@
+//! What every loadable service shares: the header the kernel looks for at
+//! offset 0, the API table the kernel hands to `svc_init` and a panic handler.
+//!
+//! Services are flat binaries copied to wherever the kernel has room, with no
+//! relocation done. Code is pc-relative, but data holding an address is fixed
+//! at link time, so services must not keep such statics, which rules out
+//! `core::fmt` and trait objects. Logging goes through `ServiceApi::log`.
+//!
+//! A service defines, with `#[no_mangle] extern "C"`:
+//! - `svc_init(api: &'static ServiceApi) -> isize`, 0 to stay loaded
+//! - `svc_exit()`, before it is unloaded
+//! - `svc_on_uipi_send(sender_pid, receiver_pid, msg) -> isize`, non-zero drops the message
+//! - `svc_on_sched_tick(hart_id, pid)`, on every scheduler tick of a hart running `pid`
+
+#![allow(dead_code)]
+
+use core::arch::global_asm;
+use core::panic::PanicInfo;
+
+pub const SERVICE_API_VERSION: u32 = 1;
+
+pub const LOG_ERROR: usize = 1;
+pub const LOG_WARN: usize = 2;
+pub const LOG_INFO: usize = 3;
+pub const LOG_DEBUG: usize = 4;
+
+/// Kernel functions callable by services, must match `ServiceApi` in the kernel
+#[repr(C)]
+pub struct ServiceApi {
+    pub version: u32,
+    /// Log `msg` followed by `value` in hex
+    pub log: extern "C" fn(level: usize, msg: *const u8, len: usize, value: usize),
+    pub hart_id: extern "C" fn() -> usize,
+    pub time_us: extern "C" fn() -> usize,
+    /// Send a user interrupt to `pid` on behalf of the kernel, sender pid 0
+    pub send_msg: extern "C" fn(pid: usize, msg: usize) -> isize,
+    /// Give up, the kernel logs `msg`, kills the task the service was called for and never
+    /// calls the service again
+    pub abort: extern "C" fn(msg: *const u8, len: usize) -> !,
+}
+
+impl ServiceApi {
+    pub fn log(&self, level: usize, msg: &str, value: usize) {
+        (self.log)(level, msg.as_ptr(), msg.len(), value)
+    }
+    pub fn abort(&self, msg: &str) -> ! {
+        (self.abort)(msg.as_ptr(), msg.len())
+    }
+}
+
+static mut API: Option<&'static ServiceApi> = None;
+
+/// Keep the API table for `api()`, to be called first thing in `svc_init`
+pub fn set_api(api: &'static ServiceApi) {
+    unsafe { API = Some(api) }
+}
+
+pub fn api() -> &'static ServiceApi {
+    unsafe { API.unwrap() }
+}
+
+global_asm!(
+    r#"
+    .section .service.header, "a"
+    .globl __service_header
+    .align 3
+__service_header:
+    .ascii "RSVC"
+    .word 1
+    .word __service_text_end - __service_header
+    .word __service_end - __service_header
+    .word svc_init - __service_header
+    .word svc_exit - __service_header
+    .word svc_on_uipi_send - __service_header
+    .word svc_on_sched_tick - __service_header
+"#
+);
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    // the location strings are addresses fixed at link time, not readable here
+    match unsafe { API } {
+        Some(api) => api.abort("service panicked"),
+        None => loop {},
+    }
+}
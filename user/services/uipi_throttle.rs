@@ -0,0 +1,64 @@
+//! Example service: a UIPI policy dropping messages of senders exceeding
+//! `LIMIT` per `WINDOW_US`, load it with `service_ctl load uipi_throttle`
+
+#![no_std]
+#![no_main]
+
+mod svc;
+
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use svc::*;
+
+const LIMIT: usize = 64;
+const WINDOW_US: usize = 10_000;
+const SLOTS: usize = 16;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+/// Senders are hashed to a slot by pid, colliding senders share the limit
+static SENT: [AtomicUsize; SLOTS] = [ZERO; SLOTS];
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+static WINDOW_START: AtomicUsize = AtomicUsize::new(0);
+
+#[no_mangle]
+pub extern "C" fn svc_init(api: &'static ServiceApi) -> isize {
+    if api.version != SERVICE_API_VERSION {
+        return -1;
+    }
+    set_api(api);
+    WINDOW_START.store((api.time_us)(), Relaxed);
+    api.log(LOG_INFO, "uipi_throttle: messages per window", LIMIT);
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn svc_exit() {
+    api().log(
+        LOG_INFO,
+        "uipi_throttle: dropped in total",
+        DROPPED.load(Relaxed),
+    );
+}
+
+#[no_mangle]
+pub extern "C" fn svc_on_uipi_send(sender_pid: usize, _receiver_pid: usize, _msg: usize) -> isize {
+    if SENT[sender_pid % SLOTS].fetch_add(1, Relaxed) < LIMIT {
+        0
+    } else {
+        DROPPED.fetch_add(1, Relaxed);
+        1
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn svc_on_sched_tick(_hart_id: usize, _pid: usize) {
+    let now = (api().time_us)();
+    let start = WINDOW_START.load(Relaxed);
+    if now.saturating_sub(start) >= WINDOW_US
+        && WINDOW_START
+            .compare_exchange(start, now, Relaxed, Relaxed)
+            .is_ok()
+    {
+        SENT.iter().for_each(|sent| sent.store(0, Relaxed));
+    }
+}
@@ -0,0 +1,130 @@
+//! Frame pointer backtraces, resolved with the symbol map `symmap.sh` embeds
+//! into the `.symmap` section of every app after linking.
+//!
+//! The map costs every app `SYMMAP_SIZE` of read-only memory, 4 pages. It holds
+//! the functions and the call sites of the app and `user_lib` first, those of
+//! `core` and `alloc` fill what is left, so a cut map only loses library frames.
+
+use core::arch::asm;
+
+/// Must match `SYMMAP_SIZE` in the Makefile
+const SYMMAP_SIZE: usize = 16384;
+const MAX_DEPTH: usize = 32;
+/// A caller frame further away than this is not a frame
+const MAX_FRAME_SIZE: usize = 0x10000;
+
+#[used]
+#[link_section = ".symmap"]
+static SYMMAP_PLACEHOLDER: [u8; SYMMAP_SIZE] = [0; SYMMAP_SIZE];
+
+fn symmap() -> &'static str {
+    extern "C" {
+        fn __symmap_start();
+        fn __symmap_end();
+    }
+    // read through the linker symbols, reads of the placeholder itself may be folded to zeros
+    let start = __symmap_start as usize;
+    let map =
+        unsafe { core::slice::from_raw_parts(start as *const u8, __symmap_end as usize - start) };
+    let len = map.iter().position(|&b| b == 0).unwrap_or(map.len());
+    let map = core::str::from_utf8(&map[..len]).unwrap_or("");
+    match map.strip_prefix("SYMMAP\n") {
+        // the last line may have been cut
+        Some(map) => &map[..map.rfind('\n').map_or(0, |end| end + 1)],
+        None => "",
+    }
+}
+
+pub struct Symbol {
+    pub name: &'static str,
+    pub offset: usize,
+    /// The line of the call at `pc` if it is one, else where the function begins
+    pub location: Option<&'static str>,
+}
+
+/// The location of the call instruction covering `pc`, calls are at most 4 bytes
+fn call_site(pc: usize) -> Option<&'static str> {
+    symmap()
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.strip_prefix('@')?.splitn(2, ' ');
+            let addr = usize::from_str_radix(fields.next()?, 16).ok()?;
+            Some((addr, fields.next()?))
+        })
+        .filter(|&(addr, _)| (addr..addr + 4).contains(&pc))
+        .max_by_key(|&(addr, _)| addr)
+        .map(|(_, location)| location)
+}
+
+/// The function containing `pc`, if the app has a symbol map. Pass a return
+/// address minus one to get the line of the call.
+pub fn resolve(pc: usize) -> Option<Symbol> {
+    let symbol = symmap().lines().find_map(|line| {
+        let mut fields = line.splitn(4, ' ');
+        let addr = usize::from_str_radix(fields.next()?, 16).ok()?;
+        let size = usize::from_str_radix(fields.next()?, 16).ok()?;
+        let location = fields.next()?;
+        let name = fields.next()?;
+        (addr..addr + size.max(1)).contains(&pc).then(|| Symbol {
+            name,
+            offset: pc - addr,
+            location: Some(location),
+        })
+    })?;
+    let location = call_site(pc)
+        .or(symbol.location)
+        .filter(|location| !location.starts_with("??"));
+    Some(Symbol { location, ..symbol })
+}
+
+/// Walk the frame pointers from the caller, `f` gets the return address of every frame
+pub fn trace(mut f: impl FnMut(usize)) {
+    let mut fp: usize;
+    unsafe { asm!("mv {}, s0", out(reg) fp) }
+    for _ in 0..MAX_DEPTH {
+        if fp == 0 || fp % 8 != 0 {
+            break;
+        }
+        // the return address and the caller's frame pointer are saved below the frame
+        let (ra, prev_fp) = unsafe { (*(fp as *const usize).sub(1), *(fp as *const usize).sub(2)) };
+        if ra == 0 {
+            break;
+        }
+        f(ra);
+        if prev_fp <= fp || prev_fp - fp > MAX_FRAME_SIZE {
+            break;
+        }
+        fp = prev_fp;
+    }
+}
+
+pub fn print_backtrace() {
+    println!("Backtrace:");
+    let mut depth = 0;
+    trace(|ra| {
+        // the call is the instruction before the return address
+        match resolve(ra - 1) {
+            Some(Symbol {
+                name,
+                offset,
+                location: Some(location),
+            }) => {
+                println!(
+                    "  #{} {:#x} {}+{:#x} at {}",
+                    depth,
+                    ra,
+                    name,
+                    offset + 1,
+                    location
+                );
+            }
+            Some(Symbol { name, offset, .. }) => {
+                println!("  #{} {:#x} {}+{:#x}", depth, ra, name, offset + 1);
+            }
+            None => {
+                println!("  #{} {:#x}", depth, ra);
+            }
+        }
+        depth += 1;
+    });
+}
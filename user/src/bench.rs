@@ -0,0 +1,68 @@
+//! Measurement loop of the benchmarks: warmup rounds are thrown away, then
+//! rounds are measured until the last few agree, which is taken as the steady
+//! state, or until there have been too many of them.
+
+pub struct BenchConfig {
+    pub warmup_rounds: usize,
+    /// Rounds which must agree for the steady state
+    pub window: usize,
+    pub max_rounds: usize,
+    /// How far from their mean the rounds of the window may be, in percent
+    pub tolerance_pct: usize,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            warmup_rounds: 2,
+            window: 3,
+            max_rounds: 12,
+            tolerance_pct: 5,
+        }
+    }
+}
+
+/// Over the last window of measured rounds
+#[derive(Debug, Clone, Copy)]
+pub struct Measurement {
+    /// Measured rounds, warmup excluded
+    pub rounds: usize,
+    pub mean: usize,
+    pub min: usize,
+    pub max: usize,
+    /// Whether the window agreed before `max_rounds`
+    pub is_steady: bool,
+}
+
+const MAX_WINDOW: usize = 16;
+
+impl BenchConfig {
+    /// `round` runs one round and returns what it measured, like its duration
+    pub fn run(&self, mut round: impl FnMut() -> usize) -> Measurement {
+        let window = self.window.max(1).min(MAX_WINDOW);
+        for _ in 0..self.warmup_rounds {
+            round();
+        }
+        let mut samples = [0; MAX_WINDOW];
+        let mut rounds = 0;
+        loop {
+            samples[rounds % window] = round();
+            rounds += 1;
+            let last = &samples[..window.min(rounds)];
+            let mean = last.iter().sum::<usize>() / last.len();
+            let min = *last.iter().min().unwrap();
+            let max = *last.iter().max().unwrap();
+            let slack = mean * self.tolerance_pct / 100;
+            let is_steady = rounds >= window && max - mean <= slack && mean - min <= slack;
+            if is_steady || rounds >= self.max_rounds.max(window) {
+                return Measurement {
+                    rounds,
+                    mean,
+                    min,
+                    max,
+                    is_steady,
+                };
+            }
+        }
+    }
+}
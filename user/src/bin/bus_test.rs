@@ -0,0 +1,77 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{bus, exit, fork, waitpid};
+
+const SUBSCRIBERS: usize = 3;
+const DATA: usize = 1;
+const READY: usize = 2;
+const VALUES: usize = 10;
+
+/// Subscribers only get the topic they asked for, from a publisher they do not know.
+/// Needs the bus daemon, run it from the shell of initproc.
+#[no_mangle]
+pub fn main() -> i32 {
+    if let Err(err) = bus::subscribe(READY) {
+        println!("[bus] subscribe failed: {:?}, is busd running?", err);
+        return -1;
+    }
+    let mut pids = [0; SUBSCRIBERS];
+    for (i, pid) in pids.iter_mut().enumerate() {
+        *pid = fork();
+        if *pid == 0 {
+            // the subscription to READY is the parent's, the daemon knows tasks by pid
+            if bus::subscribe(DATA)
+                .and_then(|_| bus::publish(READY, i))
+                .is_err()
+            {
+                exit(-2);
+            }
+            let mut sum = 0;
+            for _ in 0..VALUES {
+                match bus::recv() {
+                    Ok((DATA, value)) => sum += value,
+                    _ => exit(-3),
+                }
+            }
+            bus::disconnect();
+            if sum != VALUES * (VALUES - 1) / 2 {
+                exit(-4);
+            }
+            exit(0);
+        } else if *pid < 0 {
+            println!("[bus] fork failed!");
+            return -1;
+        }
+    }
+    for _ in 0..SUBSCRIBERS {
+        if !matches!(bus::recv(), Ok((READY, _))) {
+            println!("[bus] a subscriber did not get ready");
+            return -1;
+        }
+    }
+    for value in 0..VALUES {
+        if let Err(err) = bus::publish(DATA, value) {
+            println!("[bus] publish failed: {:?}", err);
+            return -1;
+        }
+    }
+    for pid in pids {
+        let mut exit_code = 0;
+        if waitpid(pid as usize, &mut exit_code) != pid || exit_code != 0 {
+            println!("[bus] subscriber {} exited with {}", pid, exit_code);
+            return -1;
+        }
+    }
+    // nothing was published on the topics of the parent but READY
+    if bus::try_recv().is_ok() {
+        println!("[bus] delivered a topic which was not subscribed");
+        return -1;
+    }
+    bus::disconnect();
+    println!("[bus] passed");
+    0
+}
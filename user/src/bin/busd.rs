@@ -0,0 +1,109 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use user_lib::bus::{BusMessage, BusOp};
+use user_lib::ipi::{receive_all, send, IpiError, Message, CHANNEL_CLOSED};
+use user_lib::{init_user_trap, uintr_wait};
+
+const BATCH: usize = 32;
+
+/// topic -> pids of the subscribers
+type Topics = BTreeMap<usize, Vec<usize>>;
+
+fn leave(topics: &mut Topics, pid: usize) {
+    topics.retain(|_, subscribers| {
+        subscribers.retain(|&subscriber| subscriber != pid);
+        !subscribers.is_empty()
+    });
+}
+
+fn publish(topics: &mut Topics, topic: usize, value: usize, dropped: &mut usize) {
+    let msg = BusMessage {
+        op: BusOp::Deliver,
+        topic,
+        value,
+    }
+    .encode();
+    let mut gone = Vec::new();
+    for &pid in topics.get(&topic).into_iter().flatten() {
+        match send(pid, msg) {
+            Ok(()) => {}
+            Err(IpiError::OutOfSlots) => *dropped += 1,
+            // exited, or released its user traps
+            Err(_) => gone.push(pid),
+        }
+    }
+    for pid in gone {
+        leave(topics, pid);
+    }
+}
+
+/// Message bus daemon started by initproc, see `user_lib::bus`
+#[no_mangle]
+pub fn main() -> i32 {
+    if init_user_trap() < 0 {
+        println!("[busd] init_user_trap failed!");
+        return -1;
+    }
+    let mut topics = Topics::new();
+    let mut dropped = 0;
+    let mut messages = [Message::default(); BATCH];
+    loop {
+        let count = receive_all(&mut messages);
+        if count == 0 {
+            uintr_wait();
+            continue;
+        }
+        for &Message { sender, msg } in &messages[..count] {
+            if msg == CHANNEL_CLOSED {
+                leave(&mut topics, sender);
+                continue;
+            }
+            match BusMessage::decode(msg) {
+                Some(BusMessage {
+                    op: BusOp::Subscribe,
+                    topic,
+                    ..
+                }) => {
+                    let subscribers = topics.entry(topic).or_default();
+                    if !subscribers.contains(&sender) {
+                        subscribers.push(sender);
+                    }
+                }
+                Some(BusMessage {
+                    op: BusOp::Unsubscribe,
+                    topic,
+                    ..
+                }) => {
+                    if let Some(subscribers) = topics.get_mut(&topic) {
+                        subscribers.retain(|&pid| pid != sender);
+                        if subscribers.is_empty() {
+                            topics.remove(&topic);
+                        }
+                    }
+                }
+                Some(BusMessage {
+                    op: BusOp::Publish,
+                    topic,
+                    value,
+                }) => {
+                    let before = dropped;
+                    publish(&mut topics, topic, value, &mut dropped);
+                    if dropped != before && dropped.is_power_of_two() {
+                        println!("[busd] {} deliveries dropped so far", dropped);
+                    }
+                }
+                _ => {
+                    println!("[busd] bad message {:#x} from {}", msg, sender);
+                }
+            }
+        }
+    }
+}
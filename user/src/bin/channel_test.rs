@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::ipi::{Channel, IpiError, CHANNEL_CLOSED};
+use user_lib::{exit, fork, waitpid};
+
+const ROUNDS: usize = 16;
+
+/// Ping-pong with a forked child over a channel, then check both ways of closing it
+#[no_mangle]
+pub fn main() -> i32 {
+    let pair = match Channel::pair() {
+        Ok(pair) => pair,
+        Err(err) => {
+            println!("[channel] pair failed: {:?}", err);
+            return -1;
+        }
+    };
+    let pid = fork();
+    let mut channel = match pair.split(pid) {
+        Ok(channel) => channel,
+        Err(err) => {
+            println!("[channel] fork failed: {:?}", err);
+            return -1;
+        }
+    };
+    if pid == 0 {
+        // echo every message plus one, until the parent drops its end
+        loop {
+            match channel.recv() {
+                Ok(msg) => {
+                    if channel.send(msg + 1).is_err() {
+                        exit(-2);
+                    }
+                }
+                Err(IpiError::Closed) => exit(0),
+                Err(_) => exit(-3),
+            }
+        }
+    }
+
+    for round in 0..ROUNDS {
+        let msg = round * 2;
+        let reply = channel.send(msg).and_then(|_| channel.recv());
+        if reply != Ok(msg + 1) {
+            println!("[channel] round {}: got {:?}", round, reply);
+            return -1;
+        }
+    }
+    if channel.try_recv() != Err(IpiError::Empty) {
+        println!("[channel] a message too many");
+        return -1;
+    }
+    if channel.send(CHANNEL_CLOSED) != Err(IpiError::InvalidMessage) {
+        println!("[channel] the close message was sent");
+        return -1;
+    }
+    // the child exits once its end is closed
+    drop(channel);
+    let mut exit_code = 0;
+    if waitpid(pid as usize, &mut exit_code) != pid || exit_code != 0 {
+        println!("[channel] child exited with {}", exit_code);
+        return -1;
+    }
+
+    let mut local = match Channel::pair() {
+        Ok(pair) => pair.local(),
+        Err(err) => {
+            println!("[channel] pair failed: {:?}", err);
+            return -1;
+        }
+    };
+    if local.send(7).and_then(|_| local.recv()) != Ok(7) {
+        println!("[channel] loopback failed");
+        return -1;
+    }
+    if Channel::connect("channel_test.nobody").err() != Some(IpiError::NoSuchReceiver) {
+        println!("[channel] connect to an unbound name succeeded");
+        return -1;
+    }
+    println!("[channel] passed");
+    0
+}
@@ -0,0 +1,96 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    checkpoint_drop, checkpoint_restore, checkpoint_save, close, exit, fork, getpid, pipe, read,
+    sleep, waitpid, write, yield_,
+};
+
+/// Long enough for the parent to take a checkpoint before the worker is done
+const ROUNDS: usize = 10_000;
+const RESTORES: usize = 2;
+
+/// Build some state, spin a while, then report on the pipe and exit with the state
+fn worker(report: usize) -> ! {
+    let sum: usize = (0..100).sum();
+    let mut rounds = 0;
+    while rounds < ROUNDS {
+        rounds += 1;
+        yield_();
+    }
+    // the pipe end is shared by every restored copy
+    if write(report, b"x") != 1 {
+        exit(-2);
+    }
+    exit((sum + rounds) as i32);
+}
+
+/// Checkpoint a running child, restore it twice and check every copy ends the same way
+#[no_mangle]
+pub fn main() -> i32 {
+    let expected = (0..100).sum::<usize>() + ROUNDS;
+    let mut pipe_fd = [0usize; 2];
+    if pipe(&mut pipe_fd) < 0 {
+        println!("[checkpoint] pipe failed!");
+        return -1;
+    }
+    let pid = fork();
+    if pid == 0 {
+        close(pipe_fd[0]);
+        worker(pipe_fd[1]);
+    } else if pid < 0 {
+        println!("[checkpoint] fork failed!");
+        return -1;
+    }
+    close(pipe_fd[1]);
+    if checkpoint_save(getpid() as usize) != -22 {
+        println!("[checkpoint] checkpointed itself");
+        return -1;
+    }
+    sleep(10);
+    let id = checkpoint_save(pid as usize);
+    if id <= 0 {
+        println!("[checkpoint] save failed: {}", id);
+        return -1;
+    }
+    let mut pids = [pid; RESTORES + 1];
+    for restored in pids.iter_mut().skip(1) {
+        *restored = checkpoint_restore(id as usize);
+        if *restored <= 0 {
+            println!("[checkpoint] restore failed: {}", restored);
+            return -1;
+        }
+    }
+    for &pid in pids.iter() {
+        let mut exit_code = 0;
+        if waitpid(pid as usize, &mut exit_code) != pid || exit_code != expected as i32 {
+            println!("[checkpoint] task {} exited with {}", pid, exit_code);
+            return -1;
+        }
+    }
+    // the checkpoint keeps the write end open, so read no more than expected
+    let mut buf = [0u8; RESTORES + 1];
+    let mut reports = 0;
+    while reports < buf.len() {
+        match read(pipe_fd[0], &mut buf[reports..]) {
+            n if n > 0 => reports += n as usize,
+            _ => break,
+        }
+    }
+    if reports != buf.len() {
+        println!("[checkpoint] got {} reports", reports);
+        return -1;
+    }
+    if checkpoint_drop(id as usize) != 0
+        || checkpoint_drop(id as usize) != -2
+        || checkpoint_restore(id as usize) != -2
+    {
+        println!("[checkpoint] checkpoint {} outlived its drop", id);
+        return -1;
+    }
+    println!("[checkpoint] passed!");
+    0
+}
@@ -0,0 +1,106 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+use riscv::register::uie;
+use user_lib::{
+    child_notify, child_notify_stop, exit, fork, getpid, init_user_trap, waitpid, yield_,
+};
+
+const CHILDREN: usize = 3;
+const MAX_POLLS: usize = 1000;
+
+static EXITED: AtomicUsize = AtomicUsize::new(0);
+/// Sum of the pids and of the exit codes told, to check them against the forked children
+static PID_SUM: AtomicUsize = AtomicUsize::new(0);
+static CODE_SUM: AtomicUsize = AtomicUsize::new(0);
+
+/// Get told of the exits of the children by a user interrupt, then reap them
+#[no_mangle]
+pub fn main() -> i32 {
+    if child_notify(getpid() as usize) != -107 || child_notify(usize::MAX >> 1) != -3 {
+        println!("[child notify] registered a bad receiver");
+        return -1;
+    }
+    if init_user_trap() < 0 {
+        println!("[child notify] init user trap failed!");
+        return -1;
+    }
+    unsafe {
+        uie::set_usoft();
+    }
+    if child_notify(getpid() as usize) != 0 {
+        println!("[child notify] register failed");
+        return -1;
+    }
+    let parent = getpid() as usize;
+    let mut pids = [0usize; CHILDREN];
+    for (i, pid) in pids.iter_mut().enumerate() {
+        let ret = fork();
+        if ret == 0 {
+            // the parent never accepted to be told of the children of a child
+            if child_notify(parent) != -1 {
+                exit(-1);
+            }
+            exit(10 + i as i32);
+        } else if ret < 0 {
+            println!("[child notify] fork failed!");
+            return -1;
+        }
+        *pid = ret as usize;
+    }
+    // no waitpid until all of them were told
+    let mut polls = 0;
+    while EXITED.load(SeqCst) < CHILDREN && polls < MAX_POLLS {
+        yield_();
+        polls += 1;
+    }
+    let pid_sum: usize = pids.iter().sum();
+    let code_sum: usize = (0..CHILDREN).map(|i| 10 + i).sum();
+    if EXITED.load(SeqCst) != CHILDREN
+        || PID_SUM.load(SeqCst) != pid_sum
+        || CODE_SUM.load(SeqCst) != code_sum
+    {
+        println!(
+            "[child notify] told of {} exits, pid sum {}, code sum {}",
+            EXITED.load(SeqCst),
+            PID_SUM.load(SeqCst),
+            CODE_SUM.load(SeqCst)
+        );
+        return -1;
+    }
+    for pid in pids.iter() {
+        let mut exit_code = 0;
+        if waitpid(*pid, &mut exit_code) != *pid as isize {
+            println!("[child notify] child {} not reaped", pid);
+            return -1;
+        }
+    }
+    // no more records once stopped
+    child_notify_stop();
+    let pid = fork();
+    if pid == 0 {
+        exit(0);
+    }
+    let mut exit_code = 0;
+    waitpid(pid as usize, &mut exit_code);
+    for _ in 0..10 {
+        yield_();
+    }
+    if EXITED.load(SeqCst) != CHILDREN {
+        println!("[child notify] told after stopping");
+        return -1;
+    }
+    println!("[child notify] passed!");
+    0
+}
+
+#[no_mangle]
+pub fn child_exit_handler(pid: usize, exit_code: i32) {
+    PID_SUM.fetch_add(pid, SeqCst);
+    CODE_SUM.fetch_add(exit_code as usize, SeqCst);
+    EXITED.fetch_add(1, SeqCst);
+}
@@ -0,0 +1,42 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{env, get_time_us, write};
+
+const STDOUT: usize = 1;
+const DEFAULT_LINES: usize = 64;
+const LINE: &[u8] = b"0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcde\n";
+
+/// Console throughput of one write per line against one write per byte,
+/// e.g. `console_bench 256` writes 256 lines each way
+#[no_mangle]
+pub fn main() -> i32 {
+    let lines = env::arg(1).unwrap_or(DEFAULT_LINES);
+    let start = get_time_us();
+    for _ in 0..lines {
+        write(STDOUT, LINE);
+    }
+    let batched_us = get_time_us() - start;
+
+    let start = get_time_us();
+    for _ in 0..lines {
+        for c in LINE.chunks(1) {
+            write(STDOUT, c);
+        }
+    }
+    let bytewise_us = get_time_us() - start;
+
+    let bytes = (lines * LINE.len()) as isize;
+    println!(
+        "[console bench] {} bytes: line writes {} us ({} KB/s), byte writes {} us ({} KB/s)",
+        bytes,
+        batched_us,
+        bytes * 1000 / batched_us.max(1),
+        bytewise_us,
+        bytes * 1000 / bytewise_us.max(1)
+    );
+    0
+}
@@ -0,0 +1,53 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    cpu_group_attach, cpu_group_set_quota, cpu_group_stats, exit, fork, get_time, waitpid,
+    CpuGroupStats,
+};
+
+const GROUP: usize = 1;
+const QUOTA_US: usize = 20_000;
+const PERIOD_US: usize = 100_000;
+/// Overrun allowed in every period, the timer tick is 10 ms
+const SLACK_US: usize = 10_000;
+const TEST_TIME_MS: isize = 2000;
+
+/// Limit a busy child to 20% of a CPU and check how much time it gets
+#[no_mangle]
+pub fn main() -> i32 {
+    if cpu_group_set_quota(GROUP, QUOTA_US, PERIOD_US) < 0 {
+        println!("[cpu bandwidth test] set quota failed!");
+        return -1;
+    }
+    let start = get_time();
+    let pid = fork();
+    if pid == 0 {
+        while get_time() < start + TEST_TIME_MS {}
+        exit(0);
+    } else if pid < 0 {
+        println!("[cpu bandwidth test] fork failed!");
+        return -1;
+    }
+    cpu_group_attach(GROUP, pid as usize);
+    let mut exit_code: i32 = 0;
+    waitpid(pid as usize, &mut exit_code);
+    let elapsed_us = (get_time() - start) as usize * 1000;
+    let mut stats = CpuGroupStats::default();
+    cpu_group_stats(GROUP, &mut stats);
+    println!(
+        "[cpu bandwidth test] child ran {} us in {} us, throttled in {} periods",
+        stats.total_us, elapsed_us, stats.throttled_periods
+    );
+    let limit = (elapsed_us / PERIOD_US + 1) * (QUOTA_US + SLACK_US);
+    if stats.total_us <= limit {
+        println!("[cpu bandwidth test] passed!");
+        0
+    } else {
+        println!("[cpu bandwidth test] failed, limit {} us", limit);
+        -1
+    }
+}
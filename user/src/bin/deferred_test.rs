@@ -0,0 +1,56 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use riscv::register::uie;
+use user_lib::{defer, getpid, init_user_trap, send_msg, trap_nesting_depth};
+
+const MSG_NUM: usize = 8;
+
+static HANDLED: AtomicUsize = AtomicUsize::new(0);
+static DEFERRED_DONE: AtomicUsize = AtomicUsize::new(0);
+static ORDER_ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let init_res = init_user_trap();
+    println!("[deferred] init result: {:#x}", init_res);
+    if init_res < 0 {
+        return -1;
+    }
+    unsafe {
+        uie::set_usoft();
+    }
+    let pid = getpid() as usize;
+    for i in 0..MSG_NUM {
+        send_msg(pid, i);
+    }
+    while DEFERRED_DONE.load(Relaxed) < MSG_NUM {}
+    if ORDER_ERRORS.load(Relaxed) != 0 {
+        println!("[deferred] deferred work ran before its handler returned");
+        return -1;
+    }
+    println!(
+        "[deferred] {} messages handled in bottom halves, passed",
+        MSG_NUM
+    );
+    0
+}
+
+#[no_mangle]
+pub fn soft_intr_handler(_pid: usize, msg: usize) {
+    HANDLED.fetch_add(1, Relaxed);
+    let res = defer(move || {
+        // the handler of this message has returned by now
+        if HANDLED.load(Relaxed) <= msg || trap_nesting_depth() > 1 {
+            ORDER_ERRORS.fetch_add(1, Relaxed);
+        }
+        DEFERRED_DONE.fetch_add(1, Relaxed);
+    });
+    if res.is_err() {
+        println!("[deferred] queue full");
+    }
+}
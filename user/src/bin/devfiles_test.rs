@@ -0,0 +1,52 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, open, read, write, OpenFlags};
+
+fn open_device(path: &str) -> usize {
+    let fd = open(path, OpenFlags::RDWR);
+    if fd < 0 {
+        println!("[devfiles] open {} failed!", path);
+        return usize::MAX;
+    }
+    fd as usize
+}
+
+/// Read nothing from `/dev/null`, zeros from `/dev/zero` and something else every time
+/// from `/dev/random`, all of them taking any write
+#[no_mangle]
+pub fn main() -> i32 {
+    let null = open_device("/dev/null\0");
+    let zero = open_device("/dev/zero\0");
+    let random = open_device("/dev/random\0");
+    if null == usize::MAX || zero == usize::MAX || random == usize::MAX {
+        return -1;
+    }
+    let mut buf = [0xffu8; 100];
+    if read(null, &mut buf) != 0 || write(null, &buf) != buf.len() as isize {
+        println!("[devfiles] /dev/null is not empty");
+        return -1;
+    }
+    if read(zero, &mut buf) != buf.len() as isize || buf.iter().any(|&byte| byte != 0) {
+        println!("[devfiles] /dev/zero gave something else");
+        return -1;
+    }
+    let mut other = [0u8; 100];
+    if write(random, b"stirred in") != 10
+        || read(random, &mut buf) != buf.len() as isize
+        || read(random, &mut other) != other.len() as isize
+        || buf == other
+        || buf.iter().all(|&byte| byte == 0)
+    {
+        println!("[devfiles] /dev/random is not random");
+        return -1;
+    }
+    close(null);
+    close(zero);
+    close(random);
+    println!("[devfiles] passed!");
+    0
+}
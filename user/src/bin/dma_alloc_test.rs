@@ -0,0 +1,91 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{check_ret, claim_ext_int, close, dma_alloc, init_user_trap, open, read, OpenFlags};
+
+#[cfg(feature = "board_qemu")]
+const UART_IRQN: usize = 14;
+#[cfg(feature = "board_lrv")]
+const UART_IRQN: usize = 6;
+
+const TEST: &str = "dma alloc";
+const PAGE_SIZE: usize = 4096;
+/// Not page aligned, the buffer is rounded up to whole pages
+const LEN: usize = 2 * PAGE_SIZE + 100;
+
+const ENODEV: isize = -19;
+
+/// `region <start> <end>` on the first line of `/proc/dma`
+fn dma_region() -> Option<(usize, usize)> {
+    let fd = open("/proc/dma\0", OpenFlags::RDONLY);
+    if fd < 0 {
+        return None;
+    }
+    let mut buf = [0u8; 1024];
+    let len = read(fd as usize, &mut buf).max(0) as usize;
+    close(fd as usize);
+    let line = core::str::from_utf8(&buf[..len]).ok()?.lines().next()?;
+    let mut words = line.split(' ');
+    if words.next()? != "region" {
+        return None;
+    }
+    let mut addr = || usize::from_str_radix(words.next()?.strip_prefix("0x")?, 16).ok();
+    Some((addr()?, addr()?))
+}
+
+/// A DMA buffer needs a claimed device, lies in the DMA region, is mapped at its
+/// physical address and holds what is written to it
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut paddr = 0;
+    init_user_trap();
+    if !check_ret(TEST, "no device", dma_alloc(LEN, &mut paddr), ENODEV) {
+        return -1;
+    }
+    let base = claim_ext_int(UART_IRQN);
+    if base < 0 {
+        println!("[dma alloc] claim failed: {}", base);
+        return -1;
+    }
+    let vaddr = dma_alloc(LEN, &mut paddr);
+    if vaddr < 0 {
+        println!("[dma alloc] alloc failed: {}", vaddr);
+        return -1;
+    }
+    let vaddr = vaddr as usize;
+    if vaddr != paddr {
+        println!("[dma alloc] mapped at {:#x}, not at {:#x}", vaddr, paddr);
+        return -1;
+    }
+    match dma_region() {
+        Some((start, end)) if start <= paddr && paddr + LEN <= end => {}
+        region => {
+            println!(
+                "[dma alloc] {:#x} is not in the region {:x?}",
+                paddr, region
+            );
+            return -1;
+        }
+    }
+    let buffer = unsafe { core::slice::from_raw_parts_mut(vaddr as *mut u8, LEN) };
+    if buffer.iter().any(|b| *b != 0) {
+        println!("[dma alloc] the buffer is not zeroed");
+        return -1;
+    }
+    for (i, b) in buffer.iter_mut().enumerate() {
+        unsafe { (b as *mut u8).write_volatile(i as u8) };
+    }
+    if let Some(i) = buffer
+        .iter()
+        .enumerate()
+        .position(|(i, b)| unsafe { (b as *const u8).read_volatile() } != i as u8)
+    {
+        println!("[dma alloc] byte {} did not read back", i);
+        return -1;
+    }
+    println!("[dma alloc] passed!");
+    0
+}
@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, get_time, sched_set_deadline, sched_set_normal, task_info, waitpid, TaskInfo,
+};
+
+const BUDGET_US: usize = 2_000;
+const PERIOD_US: usize = 10_000;
+/// Overrun allowed in every period, for the switch after the budget timer fires
+const SLACK_US: usize = 1_000;
+const TEST_TIME_MS: isize = 1000;
+
+/// Put a busy child into the deadline class with 20% of a CPU and check how much time it gets
+#[no_mangle]
+pub fn main() -> i32 {
+    if sched_set_deadline(0, PERIOD_US * 2, 0, PERIOD_US) != -22 {
+        println!("[edf test] budget longer than the period accepted!");
+        return -1;
+    }
+    let pid = fork();
+    if pid == 0 {
+        if sched_set_deadline(0, BUDGET_US, 0, PERIOD_US) < 0 {
+            println!("[edf test] sched_setattr failed!");
+            exit(-1);
+        }
+        let start = get_time();
+        while get_time() < start + TEST_TIME_MS {}
+        let elapsed_us = (get_time() - start) as usize * 1000;
+        let mut info = TaskInfo::default();
+        task_info(0, &mut info);
+        sched_set_normal(0);
+        let used_us = info.utime_us + info.stime_us;
+        println!("[edf test] child ran {} us in {} us", used_us, elapsed_us);
+        let limit = (elapsed_us / PERIOD_US + 1) * (BUDGET_US + SLACK_US);
+        if used_us > limit {
+            println!("[edf test] failed, limit {} us", limit);
+            exit(-1);
+        }
+        exit(0);
+    } else if pid < 0 {
+        println!("[edf test] fork failed!");
+        return -1;
+    }
+    let mut exit_code: i32 = 0;
+    waitpid(pid as usize, &mut exit_code);
+    if exit_code == 0 {
+        println!("[edf test] passed!");
+    }
+    exit_code
+}
@@ -0,0 +1,75 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{env, exec, exec_with_env, exit, fork, waitpid};
+
+/// Exec itself with arguments and an environment, which the next exec passes on
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    match argv.get(1).copied() {
+        None => parent(),
+        Some("child") => {
+            if argc != 3 || env::arg::<usize>(2) != Some(42) || env::var("FOO") != Some("bar") {
+                println!("[env test] child got {:?}", argv);
+                exit(-2);
+            }
+            // the environment is passed on by a plain exec
+            exec(
+                "env_test\0",
+                &[
+                    "env_test\0".as_ptr(),
+                    "inherit\0".as_ptr(),
+                    core::ptr::null(),
+                ],
+            );
+            exit(-3);
+        }
+        Some("inherit") => {
+            let vars = env::vars().count();
+            if env::var("FOO") != Some("bar") || env::var("EMPTY") != Some("") || vars != 2 {
+                println!("[env test] environment not passed on, {} vars", vars);
+                exit(-4);
+            }
+            0
+        }
+        Some(arg) => {
+            println!("[env test] unknown argument {}", arg);
+            -1
+        }
+    }
+}
+
+fn parent() -> i32 {
+    if env::args().first() != Some(&"env_test") {
+        println!("[env test] argv[0] is {:?}", env::args().first());
+        return -1;
+    }
+    let pid = fork();
+    if pid == 0 {
+        exec_with_env(
+            "env_test\0",
+            &[
+                "env_test\0".as_ptr(),
+                "child\0".as_ptr(),
+                "42\0".as_ptr(),
+                core::ptr::null(),
+            ],
+            &["FOO=bar\0".as_ptr(), "EMPTY=\0".as_ptr(), core::ptr::null()],
+        );
+        exit(-5);
+    } else if pid < 0 {
+        println!("[env test] fork failed!");
+        return -1;
+    }
+    let mut exit_code = 0;
+    waitpid(pid as usize, &mut exit_code);
+    if exit_code == 0 {
+        println!("[env test] passed!");
+    } else {
+        println!("[env test] failed, exit code: {}", exit_code);
+    }
+    exit_code
+}
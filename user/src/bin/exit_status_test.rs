@@ -0,0 +1,75 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::arch::asm;
+use user_lib::{
+    exit, fork, mmap, waitpid_status, ExitStatus, EXIT_REASON_EXITED,
+    EXIT_REASON_ILLEGAL_INSTRUCTION, EXIT_REASON_PAGE_FAULT,
+};
+
+const ENOMEM: isize = -12;
+
+const BIG_CODE: i32 = i32::MIN + 7;
+
+fn big_exit() {
+    exit(BIG_CODE);
+}
+
+fn page_fault() {
+    unsafe {
+        (0x10 as *mut usize).write_volatile(0);
+    }
+}
+
+fn illegal_instruction() {
+    unsafe {
+        asm!("unimp");
+    }
+}
+
+fn out_of_memory() {
+    // far more than any board has, refused rather than killed for
+    if mmap(0, 1 << 30, 0b11) != ENOMEM {
+        exit(-1);
+    }
+}
+
+/// Each child ends in a different way, which the parent must be able to tell
+#[no_mangle]
+pub fn main() -> i32 {
+    let cases: [(&str, fn(), u32, Option<i32>); 4] = [
+        ("exit", big_exit, EXIT_REASON_EXITED, Some(BIG_CODE)),
+        ("page fault", page_fault, EXIT_REASON_PAGE_FAULT, None),
+        (
+            "illegal instruction",
+            illegal_instruction,
+            EXIT_REASON_ILLEGAL_INSTRUCTION,
+            None,
+        ),
+        ("oom", out_of_memory, EXIT_REASON_EXITED, Some(0)),
+    ];
+    for (name, run, reason, code) in cases {
+        let pid = fork();
+        if pid == 0 {
+            run();
+            exit(0);
+        }
+        let mut status = ExitStatus::default();
+        waitpid_status(pid as usize, &mut status, 0);
+        if status.reason != reason || code.map_or(false, |code| code != status.code) {
+            println!("[exit status] {}: got {:?}", name, status);
+            return -1;
+        }
+        println!(
+            "[exit status] {}: {}, code {}",
+            name,
+            status.reason_name(),
+            status.code
+        );
+    }
+    println!("[exit status] passed");
+    0
+}
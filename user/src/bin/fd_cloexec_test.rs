@@ -0,0 +1,89 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::format;
+use user_lib::{
+    close, dup, env, exec, exit, fcntl, fork, open, pipe, pipe2, waitpid, OpenFlags, FD_CLOEXEC,
+    F_GETFD, F_SETFD,
+};
+
+/// Set and clear `FD_CLOEXEC`, then exec itself to see the marked pipe end closed and the
+/// other one kept
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argv.get(1) == Some(&"child") {
+        let (closed, kept) = match (env::arg::<usize>(2), env::arg::<usize>(3)) {
+            (Some(closed), Some(kept)) if argc == 4 => (closed, kept),
+            _ => return -2,
+        };
+        if fcntl(closed, F_GETFD, 0) != -9 || fcntl(kept, F_GETFD, 0) != 0 {
+            println!(
+                "[fd cloexec] after exec, fds {} and {} are wrong",
+                closed, kept
+            );
+            return -3;
+        }
+        return 0;
+    }
+    let mut marked = [0usize; 2];
+    let mut plain = [0usize; 2];
+    if pipe2(&mut marked, OpenFlags::CLOEXEC) < 0 || pipe(&mut plain) < 0 {
+        println!("[fd cloexec] pipe failed!");
+        return -1;
+    }
+    if fcntl(marked[0], F_GETFD, 0) != FD_CLOEXEC as isize || fcntl(plain[0], F_GETFD, 0) != 0 {
+        println!("[fd cloexec] wrong flags from pipe2");
+        return -1;
+    }
+    // the flag belongs to the fd, not to the file
+    let copy = dup(marked[0]);
+    if copy < 0 || fcntl(copy as usize, F_GETFD, 0) != 0 {
+        println!("[fd cloexec] dup kept the flag");
+        return -1;
+    }
+    close(copy as usize);
+    let null = open("/dev/null\0", OpenFlags::RDONLY | OpenFlags::CLOEXEC);
+    if null < 0 || fcntl(null as usize, F_GETFD, 0) != FD_CLOEXEC as isize {
+        println!("[fd cloexec] open ignored O_CLOEXEC");
+        return -1;
+    }
+    close(null as usize);
+    if fcntl(plain[1], F_SETFD, FD_CLOEXEC) != 0
+        || fcntl(plain[1], F_GETFD, 0) != FD_CLOEXEC as isize
+        || fcntl(plain[1], F_SETFD, 0) != 0
+        || fcntl(100, F_GETFD, 0) != -9
+    {
+        println!("[fd cloexec] F_SETFD failed");
+        return -1;
+    }
+    let pid = fork();
+    if pid == 0 {
+        let closed = format!("{}\0", marked[1]);
+        let kept = format!("{}\0", plain[1]);
+        exec(
+            "fd_cloexec_test\0",
+            &[
+                "fd_cloexec_test\0".as_ptr(),
+                "child\0".as_ptr(),
+                closed.as_ptr(),
+                kept.as_ptr(),
+                core::ptr::null(),
+            ],
+        );
+        exit(-4);
+    } else if pid < 0 {
+        println!("[fd cloexec] fork failed!");
+        return -1;
+    }
+    let mut exit_code = 0;
+    if waitpid(pid as usize, &mut exit_code) != pid || exit_code != 0 {
+        println!("[fd cloexec] child exited with {}", exit_code);
+        return -1;
+    }
+    println!("[fd cloexec] passed!");
+    0
+}
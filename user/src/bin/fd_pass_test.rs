@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, exit, fork, pipe, read, recv_fd, send_fd, waitpid, write};
+
+const GREETING: &[u8] = b"hello";
+const PASSED: &[u8] = b"through the passed pipe";
+
+/// Read the greeting, which ends at the passed file, then take the file and read it out
+fn receiver(channel: usize) -> ! {
+    let mut buf = [0u8; 64];
+    if recv_fd(channel) != -42 {
+        exit(-2);
+    }
+    if read(channel, &mut buf) != GREETING.len() as isize || &buf[..GREETING.len()] != GREETING {
+        exit(-3);
+    }
+    let fd = recv_fd(channel);
+    if fd < 0 {
+        exit(-4);
+    }
+    let mut got = 0;
+    loop {
+        match read(fd as usize, &mut buf[got..]) {
+            0 => break,
+            n if n > 0 => got += n as usize,
+            _ => exit(-5),
+        }
+    }
+    if &buf[..got] != PASSED {
+        exit(-6);
+    }
+    exit(0);
+}
+
+/// Hand the read end of a pipe to a child which closed its own copies of it
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut channel = [0usize; 2];
+    let mut passed = [0usize; 2];
+    if pipe(&mut channel) < 0 || pipe(&mut passed) < 0 {
+        println!("[fd pass] pipe failed!");
+        return -1;
+    }
+    let pid = fork();
+    if pid == 0 {
+        close(channel[1]);
+        close(passed[0]);
+        close(passed[1]);
+        receiver(channel[0]);
+    } else if pid < 0 {
+        println!("[fd pass] fork failed!");
+        return -1;
+    }
+    if send_fd(channel[1], channel[1]) != -22
+        || send_fd(channel[1], channel[0]) != -22
+        || send_fd(passed[0], passed[0]) != -9
+    {
+        println!("[fd pass] passed a file it should not");
+        return -1;
+    }
+    close(channel[0]);
+    if write(channel[1], GREETING) != GREETING.len() as isize || send_fd(channel[1], passed[0]) != 0
+    {
+        println!("[fd pass] send failed!");
+        return -1;
+    }
+    // the child holds the only read end left
+    close(passed[0]);
+    if write(passed[1], PASSED) != PASSED.len() as isize {
+        println!("[fd pass] write to the passed pipe failed!");
+        return -1;
+    }
+    close(passed[1]);
+    close(channel[1]);
+    let mut exit_code = 0;
+    if waitpid(pid as usize, &mut exit_code) != pid || exit_code != 0 {
+        println!("[fd pass] receiver exited with {}", exit_code);
+        return -1;
+    }
+    println!("[fd pass] passed!");
+    0
+}
@@ -0,0 +1,25 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{env, heap_profile::HEAP_PROFILE_DUMP, send_msg};
+
+/// `heap_dump <pid>`: make a task built with the heap profiler print its profile,
+/// it has to take user traps
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid: usize = match env::arg(1) {
+        Some(pid) => pid,
+        None => {
+            println!("usage: heap_dump <pid>");
+            return -1;
+        }
+    };
+    let ret = send_msg(pid, HEAP_PROFILE_DUMP);
+    if ret < 0 {
+        println!("[heap dump] send_msg to {} failed: {}", pid, ret);
+    }
+    ret as i32
+}
@@ -0,0 +1,116 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{close, exit, fork, mmap, munmap, pipe, read, task_info, waitpid, TaskInfo, ESRCH};
+
+const CHILDREN: usize = 4;
+const PAGE_SIZE: usize = 4096;
+const CHUNK: usize = 64 * PAGE_SIZE;
+/// More than any board has, the test fails if memory has not run out by then
+const MAX_CHUNKS: usize = 4096;
+const ENOMEM: isize = -12;
+
+/// Children which stay alive until the write end of the pipe is closed
+fn fork_blocked(pipe_fd: &[usize; 2], pids: &mut [isize]) {
+    for pid in pids.iter_mut() {
+        *pid = fork();
+        if *pid == 0 {
+            close(pipe_fd[1]);
+            let mut buf = [0u8; 1];
+            read(pipe_fd[0], &mut buf);
+            exit(0);
+        }
+    }
+}
+
+/// Live pids are distinct, freed ones are gone and never handed out again
+fn test_pids() -> bool {
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    let mut pids = [0isize; CHILDREN];
+    fork_blocked(&pipe_fd, &mut pids);
+    close(pipe_fd[0]);
+    for (i, pid) in pids.iter().enumerate() {
+        if *pid <= 0 || pids[..i].contains(pid) {
+            println!("[id alloc] pids {:?} are not distinct", pids);
+            return false;
+        }
+    }
+    close(pipe_fd[1]);
+    let mut exit_code = 0;
+    for pid in pids {
+        waitpid(pid as usize, &mut exit_code);
+    }
+    let mut info = TaskInfo::default();
+    if let Some(pid) = pids
+        .iter()
+        .find(|pid| task_info(**pid as usize, &mut info) != ESRCH)
+    {
+        println!("[id alloc] pid {} is still found after it was reaped", pid);
+        return false;
+    }
+    let pid = fork();
+    if pid == 0 {
+        exit(0);
+    }
+    waitpid(pid as usize, &mut exit_code);
+    if pids.contains(&pid) {
+        println!("[id alloc] pid {} was handed out again", pid);
+        return false;
+    }
+    true
+}
+
+/// Frames run out, and one freed chunk can be mapped again
+fn test_frames() -> bool {
+    let mut chunks = Vec::new();
+    let ret = loop {
+        if chunks.len() == MAX_CHUNKS {
+            break 0;
+        }
+        let start = mmap(0, CHUNK, 0b11);
+        if start < 0 {
+            break start;
+        }
+        chunks.push(start as usize);
+    };
+    let ok = if ret != ENOMEM {
+        println!(
+            "[id alloc] got {} after {} chunks, expected ENOMEM",
+            ret,
+            chunks.len()
+        );
+        false
+    } else if let Some(last) = chunks.pop() {
+        munmap(last, CHUNK);
+        let start = mmap(0, CHUNK, 0b11);
+        if start >= 0 {
+            chunks.push(start as usize);
+        } else {
+            println!("[id alloc] freed frames were not reused: {}", start);
+        }
+        start >= 0
+    } else {
+        println!("[id alloc] no chunk could be mapped");
+        false
+    };
+    for start in chunks {
+        munmap(start, CHUNK);
+    }
+    ok
+}
+
+/// The pid and frame allocators: allocation, deallocation, reuse and exhaustion
+#[no_mangle]
+pub fn main() -> i32 {
+    if !test_pids() || !test_frames() {
+        return -1;
+    }
+    println!("[id alloc] passed!");
+    0
+}
@@ -11,10 +11,14 @@ const CR: u8 = 0x0du8;
 const DL: u8 = 0x7fu8;
 const BS: u8 = 0x08u8;
 
+use alloc::format;
 use alloc::string::String;
 use alloc::vec::Vec;
 use user_lib::console::getchar;
-use user_lib::{close, dup, exec, fork, open, waitpid, OpenFlags};
+use user_lib::{
+    close, dup, exec, exec_with_env, exit, fork, open, waitpid_status, ExitStatus, OpenFlags,
+    EXIT_REASON_EXITED,
+};
 
 // #[no_mangle]
 // fn main() -> i32 {
@@ -52,8 +56,21 @@ use user_lib::{close, dup, exec, fork, open, waitpid, OpenFlags};
 //     0
 // }
 
+/// Start the message bus daemon, its pid is given to the commands as `BUS_PID`
+fn start_bus() -> String {
+    let pid = fork();
+    if pid == 0 {
+        exec("busd\0", &["busd\0".as_ptr(), 0 as *const u8]);
+        println!("Error when starting busd!");
+        exit(-4);
+    }
+    format!("BUS_PID={}\0", pid)
+}
+
 #[no_mangle]
 pub fn main() -> i32 {
+    let bus_env = start_bus();
+    let envs = [bus_env.as_ptr(), 0 as *const u8];
     println!("Rust user shell");
     let mut line: String = String::new();
     print!(">> ");
@@ -107,7 +124,7 @@ pub fn main() -> i32 {
                         // input redirection
                         if !input.is_empty() {
                             let input_fd = open(input.as_str(), OpenFlags::RDONLY);
-                            if input_fd == -1 {
+                            if input_fd < 0 {
                                 println!("Error when opening file {}", input);
                                 return -4;
                             }
@@ -120,7 +137,7 @@ pub fn main() -> i32 {
                         if !output.is_empty() {
                             let output_fd =
                                 open(output.as_str(), OpenFlags::CREATE | OpenFlags::WRONLY);
-                            if output_fd == -1 {
+                            if output_fd < 0 {
                                 println!("Error when opening file {}", output);
                                 return -4;
                             }
@@ -130,16 +147,25 @@ pub fn main() -> i32 {
                             close(output_fd);
                         }
                         // child process
-                        if exec(args_copy[0].as_str(), args_addr.as_slice()) == -1 {
+                        if exec_with_env(&args_copy[0], &args_addr, &envs) < 0 {
                             println!("Error when executing!");
                             return -4;
                         }
                         unreachable!();
                     } else {
-                        let mut exit_code: i32 = 0;
-                        let exit_pid = waitpid(pid as usize, &mut exit_code);
+                        let mut status = ExitStatus::default();
+                        let exit_pid = waitpid_status(pid as usize, &mut status, 0);
                         assert_eq!(pid, exit_pid);
-                        println!("Shell: Process {} exited with code {}", pid, exit_code);
+                        if status.reason == EXIT_REASON_EXITED {
+                            println!("Shell: Process {} exited with code {}", pid, status.code);
+                        } else {
+                            println!(
+                                "Shell: Process {} killed by {}, code {}",
+                                pid,
+                                status.reason_name(),
+                                status.code
+                            );
+                        }
                     }
                     line.clear();
                 }
@@ -0,0 +1,52 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, pipe, readv, writev};
+
+const FRAGMENTS: [&[u8]; 3] = [b"head", b"|", b"the rest of it"];
+
+/// Scatter and gather through a pipe, whose fragments travel as one buffer, and stdout,
+/// which takes them one by one
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut pipe_fd = [0usize; 2];
+    if pipe(&mut pipe_fd) < 0 {
+        println!("[iovec] pipe failed!");
+        return -1;
+    }
+    let total: usize = FRAGMENTS.iter().map(|fragment| fragment.len()).sum();
+    if writev(pipe_fd[1], &FRAGMENTS) != total as isize {
+        println!("[iovec] writev to the pipe failed");
+        return -1;
+    }
+    // split differently from how it was written
+    let mut first = [0u8; 6];
+    let mut second = [0u8; 3];
+    let mut rest = [0u8; 10];
+    let mut bufs: [&mut [u8]; 3] = [&mut first, &mut second, &mut rest];
+    let read = readv(pipe_fd[0], &mut bufs);
+    if read != total as isize || &first != b"head|t" || &second != b"he " || &rest != b"rest of it"
+    {
+        println!("[iovec] readv from the pipe got {}", read);
+        return -1;
+    }
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+    if writev(pipe_fd[1], &FRAGMENTS) != -9 {
+        println!("[iovec] writev to a closed fd");
+        return -1;
+    }
+    let empty: [&[u8]; 1025] = [b""; 1025];
+    if writev(1, &empty) != -22 {
+        println!("[iovec] took more fragments than IOV_MAX");
+        return -1;
+    }
+    let line: [&[u8]; 3] = [b"[iovec] ", b"passed", b"!\n"];
+    if writev(1, &line) != 16 {
+        return -1;
+    }
+    0
+}
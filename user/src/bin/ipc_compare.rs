@@ -0,0 +1,371 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::bench::{BenchConfig, Measurement};
+use user_lib::ipi::{Channel, IpiError};
+use user_lib::trap::PAGE_SIZE;
+use user_lib::{
+    close, env, exit, fork, get_time_us, mmap, munmap, pipe, read, sched_setaffinity, wait4, write,
+    yield_, Rusage, MAP_SHARED,
+};
+
+const MSG_SIZES: [usize; 4] = [8, 64, 512, 4096];
+const MAX_MSG_SIZE: usize = 4096;
+/// Round trips of a measured round
+const DEFAULT_ROUND_TRIPS: usize = 200;
+/// Polls of shared memory before yielding, for a peer on the same hart
+const SPINS_BEFORE_YIELD: usize = 256;
+
+#[derive(Clone, Copy)]
+enum Mechanism {
+    Uipi,
+    Pipe,
+    ShmPoll,
+}
+
+impl Mechanism {
+    fn name(self) -> &'static str {
+        match self {
+            Mechanism::Uipi => "uipi",
+            Mechanism::Pipe => "pipe",
+            Mechanism::ShmPoll => "shm_poll",
+        }
+    }
+
+    /// Bytes carried by a round trip of `msg_size`, a UIPI reply is a single word
+    fn round_trip_bytes(self, msg_size: usize) -> usize {
+        match self {
+            Mechanism::Uipi => (msg_size / 8).max(1) * 8 + 8,
+            Mechanism::Pipe | Mechanism::ShmPoll => 2 * msg_size,
+        }
+    }
+}
+
+/// Harts of the parent and of the child
+#[derive(Clone, Copy)]
+struct Placement {
+    name: &'static str,
+    parent: usize,
+    child: usize,
+}
+
+const PLACEMENTS: [Placement; 2] = [
+    Placement {
+        name: "same_hart",
+        parent: 1 << 0,
+        child: 1 << 0,
+    },
+    Placement {
+        name: "cross_hart",
+        parent: 1 << 0,
+        child: 1 << 1,
+    },
+];
+
+/// The request or the reply of the shared memory transport
+#[repr(C)]
+struct Mailbox {
+    /// Round trip number of the message in `data`, `usize::MAX` in `len` ends the test
+    seq: AtomicUsize,
+    len: AtomicUsize,
+    data: UnsafeCell<[u8; MAX_MSG_SIZE]>,
+}
+
+const SHM_SIZE: usize =
+    (2 * core::mem::size_of::<Mailbox>() + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+
+fn wait_for(seq: &AtomicUsize, value: usize) {
+    let mut spins = 0;
+    while seq.load(Ordering::Acquire) != value {
+        spins += 1;
+        if spins % SPINS_BEFORE_YIELD == 0 {
+            yield_();
+        }
+    }
+}
+
+/// Both sides of one transport, the parent sends requests and the child replies
+enum Transport {
+    Uipi(Channel),
+    Pipe {
+        rx: usize,
+        tx: usize,
+    },
+    ShmPoll {
+        request: &'static Mailbox,
+        reply: &'static Mailbox,
+    },
+}
+
+fn read_exact(fd: usize, buf: &mut [u8]) -> bool {
+    let mut done = 0;
+    while done < buf.len() {
+        match read(fd, &mut buf[done..]) {
+            n if n > 0 => done += n as usize,
+            _ => return false,
+        }
+    }
+    true
+}
+
+fn write_all(fd: usize, buf: &[u8]) -> bool {
+    let mut done = 0;
+    while done < buf.len() {
+        match write(fd, &buf[done..]) {
+            n if n > 0 => done += n as usize,
+            _ => return false,
+        }
+    }
+    true
+}
+
+fn send_word(channel: &Channel, word: usize) -> bool {
+    loop {
+        match channel.send(word) {
+            Ok(()) => return true,
+            // the receiver has not drained its queue yet
+            Err(IpiError::OutOfSlots) => {
+                yield_();
+            }
+            Err(_) => return false,
+        }
+    }
+}
+
+impl Transport {
+    /// Send `buf` and take the reply back into it, `seq` numbers the round trip
+    fn round_trip(&mut self, buf: &mut [u8], seq: usize) -> bool {
+        match self {
+            // a message is as many words as it takes, the reply is the last one back
+            Transport::Uipi(channel) => {
+                let words = (buf.len() / 8).max(1);
+                for i in 0..words {
+                    if !send_word(channel, seq.wrapping_add(i) & !(1 << 63)) {
+                        return false;
+                    }
+                }
+                channel.recv().is_ok()
+            }
+            Transport::Pipe { rx, tx } => write_all(*tx, buf) && read_exact(*rx, buf),
+            Transport::ShmPoll { request, reply } => {
+                let data = request.data.get() as *mut u8;
+                unsafe { data.copy_from_nonoverlapping(buf.as_ptr(), buf.len()) };
+                request.len.store(buf.len(), Ordering::Relaxed);
+                request.seq.store(seq, Ordering::Release);
+                wait_for(&reply.seq, seq);
+                let len = reply.len.load(Ordering::Relaxed).min(buf.len());
+                let data = reply.data.get() as *const u8;
+                unsafe { buf.as_mut_ptr().copy_from_nonoverlapping(data, len) };
+                true
+            }
+        }
+    }
+
+    /// Echo every message until the parent ends the test
+    fn serve(&mut self, msg_size: usize) -> ! {
+        let mut buf = [0u8; MAX_MSG_SIZE];
+        let buf = &mut buf[..msg_size];
+        match self {
+            Transport::Uipi(channel) => {
+                let words = (msg_size / 8).max(1);
+                let mut received = 0;
+                loop {
+                    match channel.recv() {
+                        Ok(word) => {
+                            received += 1;
+                            if received % words == 0 && !send_word(channel, word) {
+                                exit(-2);
+                            }
+                        }
+                        Err(_) => exit(0),
+                    }
+                }
+            }
+            Transport::Pipe { rx, tx } => loop {
+                if !read_exact(*rx, buf) {
+                    exit(0);
+                }
+                if !write_all(*tx, buf) {
+                    exit(-2);
+                }
+            },
+            Transport::ShmPoll { request, reply } => {
+                let mut seq = 0;
+                loop {
+                    seq += 1;
+                    wait_for(&request.seq, seq);
+                    let len = request.len.load(Ordering::Relaxed);
+                    if len == usize::MAX {
+                        exit(0);
+                    }
+                    let (src, dst) = (request.data.get() as *const u8, reply.data.get() as *mut u8);
+                    unsafe { dst.copy_from_nonoverlapping(src, len) };
+                    reply.len.store(len, Ordering::Relaxed);
+                    reply.seq.store(seq, Ordering::Release);
+                }
+            }
+        }
+    }
+
+    /// Make the child see the end of the test
+    fn close(self, seq: usize) {
+        match self {
+            Transport::Uipi(channel) => drop(channel),
+            Transport::Pipe { rx, tx } => {
+                close(rx);
+                close(tx);
+            }
+            Transport::ShmPoll { request, .. } => {
+                request.len.store(usize::MAX, Ordering::Relaxed);
+                request.seq.store(seq, Ordering::Release);
+                // the child keeps its mapping of the frames
+                munmap(request as *const Mailbox as usize, SHM_SIZE);
+            }
+        }
+    }
+}
+
+/// Fork the child serving `msg_size` messages on `placement`, return its pid and the
+/// parent side, `None` if the placement is not possible here
+fn start(
+    mechanism: Mechanism,
+    msg_size: usize,
+    placement: Placement,
+) -> Option<(usize, Transport)> {
+    // the child inherits the affinity of the parent until it sets its own
+    if sched_setaffinity(0, placement.child) < 0 || sched_setaffinity(0, placement.parent) < 0 {
+        println!("# {}: not enough harts, skipped", placement.name);
+        return None;
+    }
+    let mut shm = 0;
+    let mut fds = [[0usize; 2]; 2];
+    let channel = match mechanism {
+        Mechanism::Uipi => Some(Channel::pair().ok()?),
+        Mechanism::Pipe => {
+            if pipe(&mut fds[0]) < 0 || pipe(&mut fds[1]) < 0 {
+                return None;
+            }
+            None
+        }
+        Mechanism::ShmPoll => {
+            let ret = mmap(0, SHM_SIZE, 0b11 | MAP_SHARED);
+            if ret < 0 {
+                return None;
+            }
+            shm = ret as usize;
+            None
+        }
+    };
+    let pid = fork();
+    if pid < 0 {
+        return None;
+    }
+    let is_child = pid == 0;
+    let mut transport = match mechanism {
+        Mechanism::Uipi => Transport::Uipi(channel?.split(pid).ok()?),
+        // fds[0] carries requests and fds[1] replies, each side closes the ends it does not use
+        Mechanism::Pipe => {
+            let (rx, tx) = if is_child {
+                close(fds[0][1]);
+                close(fds[1][0]);
+                (fds[0][0], fds[1][1])
+            } else {
+                close(fds[0][0]);
+                close(fds[1][1]);
+                (fds[1][0], fds[0][1])
+            };
+            Transport::Pipe { rx, tx }
+        }
+        Mechanism::ShmPoll => {
+            let mailboxes = unsafe { &*(shm as *const [Mailbox; 2]) };
+            Transport::ShmPoll {
+                request: &mailboxes[0],
+                reply: &mailboxes[1],
+            }
+        }
+    };
+    if is_child {
+        if sched_setaffinity(0, placement.child) < 0 {
+            exit(-3);
+        }
+        transport.serve(msg_size);
+    }
+    Some((pid as usize, transport))
+}
+
+fn run(
+    mechanism: Mechanism,
+    msg_size: usize,
+    placement: Placement,
+    round_trips: usize,
+) -> Option<(Measurement, Rusage)> {
+    let (pid, mut transport) = start(mechanism, msg_size, placement)?;
+    let mut buf = [0u8; MAX_MSG_SIZE];
+    let mut seq = 0;
+    let mut failed = false;
+    let measurement = BenchConfig::default().run(|| {
+        let start = get_time_us();
+        for _ in 0..round_trips {
+            seq += 1;
+            failed |= !transport.round_trip(&mut buf[..msg_size], seq);
+        }
+        (get_time_us() - start) as usize
+    });
+    transport.close(seq + 1);
+    let mut exit_code = 0;
+    let mut usage = Rusage::default();
+    wait4(pid, &mut exit_code, 0, &mut usage);
+    if failed || exit_code != 0 {
+        println!(
+            "# {} {} bytes {}: failed, child exited with {}",
+            mechanism.name(),
+            msg_size,
+            placement.name,
+            exit_code
+        );
+        return None;
+    }
+    Some((measurement, usage))
+}
+
+/// Round trips of the UIPI, pipe and shared memory polling transports under the same
+/// message sizes and placements, as CSV. `ipc_compare [round trips per round]`
+#[no_mangle]
+pub fn main() -> i32 {
+    let round_trips = env::arg(1).unwrap_or(DEFAULT_ROUND_TRIPS);
+    println!("mechanism,msg_bytes,placement,rounds,steady,rtt_ns,kib_per_s,child_nvcsw,child_nivcsw,child_uipi_received,child_pipe_waits");
+    for &placement in PLACEMENTS.iter() {
+        for &msg_size in MSG_SIZES.iter() {
+            for &mechanism in [Mechanism::Uipi, Mechanism::Pipe, Mechanism::ShmPoll].iter() {
+                let (measurement, usage) = match run(mechanism, msg_size, placement, round_trips) {
+                    Some(result) => result,
+                    None => continue,
+                };
+                let mean_us = measurement.mean.max(1);
+                println!(
+                    "{},{},{},{},{},{},{},{},{},{},{}",
+                    mechanism.name(),
+                    msg_size,
+                    placement.name,
+                    measurement.rounds,
+                    measurement.is_steady,
+                    mean_us * 1000 / round_trips,
+                    (mechanism.round_trip_bytes(msg_size) * round_trips) as u64 * 1_000_000
+                        / 1024
+                        / mean_us as u64,
+                    usage.nvcsw,
+                    usage.nivcsw,
+                    usage.uipi_received,
+                    usage.pipe_waits
+                );
+            }
+        }
+    }
+    sched_setaffinity(0, usize::MAX);
+    0
+}
@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::slice;
+use user_lib::trap::{PAGE_SIZE, TRAMPOLINE};
+use user_lib::{
+    clock_gettime, close, exec, open, pipe, read, write, OpenFlags, TimeSpec, CLOCK_MONOTONIC,
+};
+
+/// Mapped in every user page table, for the kernel only
+const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
+/// End of the lower half of Sv39, no syscall takes a pointer above
+const USER_SPACE_END: usize = 0x40_0000_0000;
+const EFAULT: isize = -14;
+
+/// Pointers to the trap context and the trampoline, which the page table of the task maps,
+/// are refused like unmapped ones, and the kernel neither reads nor writes there for it
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut fds = [0usize; 2];
+    if pipe(&mut fds) < 0 {
+        println!("[kernel pointer] pipe failed!");
+        return -1;
+    }
+    let trap_context = unsafe { slice::from_raw_parts_mut(TRAP_CONTEXT as *mut u8, 8) };
+    let trampoline = unsafe { slice::from_raw_parts(TRAMPOLINE as *const u8, 8) };
+    if write(fds[1], trampoline) != EFAULT || write(fds[1], trap_context) != EFAULT {
+        println!("[kernel pointer] wrote kernel memory to a pipe");
+        return -1;
+    }
+    // would overwrite the saved registers of this task
+    if write(fds[1], b"clobber!") != 8 || read(fds[0], trap_context) != EFAULT {
+        println!("[kernel pointer] read into the trap context");
+        return -1;
+    }
+    let mut buf = [0u8; 8];
+    if read(fds[0], &mut buf) != 8 || &buf != b"clobber!" {
+        println!("[kernel pointer] a refused read consumed the pipe");
+        return -1;
+    }
+    close(fds[0]);
+    close(fds[1]);
+    let time = unsafe { &mut *(TRAP_CONTEXT as *mut TimeSpec) };
+    if clock_gettime(CLOCK_MONOTONIC, time) != EFAULT {
+        println!("[kernel pointer] clock_gettime wrote the trap context");
+        return -1;
+    }
+    // a range leaving user space is refused as a whole
+    let straddling = unsafe { slice::from_raw_parts((USER_SPACE_END - 4) as *const u8, 8) };
+    if write(1, straddling) != EFAULT {
+        println!("[kernel pointer] wrote a range leaving user space");
+        return -1;
+    }
+    // strings are read a page at a time, these used to panic the kernel
+    let path = unsafe {
+        core::str::from_utf8_unchecked(slice::from_raw_parts(TRAMPOLINE as *const u8, 1))
+    };
+    if open(path, OpenFlags::RDONLY) != EFAULT || exec(path, &[core::ptr::null()]) != EFAULT {
+        println!("[kernel pointer] took a path from the trampoline");
+        return -1;
+    }
+    println!("[kernel pointer] passed!");
+    0
+}
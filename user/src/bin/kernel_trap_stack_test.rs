@@ -0,0 +1,56 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, open, read, OpenFlags};
+
+/// `trap_stack <used>/<size>` on the line of a hart in `/proc/sched`
+fn trap_stack_usage(line: &str) -> Option<(usize, usize)> {
+    let usage = line
+        .split(' ')
+        .skip_while(|word| *word != "trap_stack")
+        .nth(1)?;
+    let (used, size) = usage.split_once('/')?;
+    Some((used.parse().ok()?, size.parse().ok()?))
+}
+
+/// Every hart reports how deep its trap stack went, within the stack
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("/proc/sched\0", OpenFlags::RDONLY);
+    if fd < 0 {
+        println!("[kernel trap stack] open /proc/sched failed: {}", fd);
+        return -1;
+    }
+    let mut buf = [0u8; 1024];
+    let mut len = 0;
+    loop {
+        match read(fd as usize, &mut buf[len..]) {
+            n if n > 0 => len += n as usize,
+            _ => break,
+        }
+    }
+    close(fd as usize);
+    let report = match core::str::from_utf8(&buf[..len]) {
+        Ok(report) => report,
+        Err(_) => return -1,
+    };
+    let mut harts = 0;
+    for line in report.lines() {
+        match trap_stack_usage(line) {
+            Some((used, size)) if size > 0 && used <= size => harts += 1,
+            _ => {
+                println!("[kernel trap stack] bad line: {}", line);
+                return -1;
+            }
+        }
+    }
+    if harts == 0 {
+        println!("[kernel trap stack] no hart reported");
+        return -1;
+    }
+    println!("[kernel trap stack] passed!");
+    0
+}
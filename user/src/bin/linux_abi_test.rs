@@ -0,0 +1,104 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::arch::asm;
+use user_lib::TimeSpec;
+
+// numbers and flags as found in Linux headers, not in user_lib
+const NR_SET_TID_ADDRESS: usize = 96;
+const NR_NANOSLEEP: usize = 101;
+const NR_CLOCK_GETTIME: usize = 113;
+const NR_BRK: usize = 214;
+const NR_MMAP: usize = 222;
+const NR_RT_SIGACTION: usize = 134;
+
+const CLOCK_MONOTONIC: usize = 1;
+const PROT_READ: usize = 0x1;
+const PROT_WRITE: usize = 0x2;
+const MAP_SHARED: usize = 0x01;
+const MAP_PRIVATE: usize = 0x02;
+const MAP_ANONYMOUS: usize = 0x20;
+const EFAULT: isize = -14;
+const ENOSYS: isize = -38;
+
+/// The way libc-free Linux programs issue syscalls, with all six argument registers
+fn linux_syscall(id: usize, args: [usize; 6]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        asm!("ecall", inout("a0") args[0] => ret, in("a1") args[1],
+             in("a2") args[2], in("a3") args[3], in("a4") args[4],
+             in("a5") args[5], in("a7") id)
+    }
+    ret
+}
+
+fn monotonic_ns() -> usize {
+    let mut tp = TimeSpec::default();
+    let tp_addr = &mut tp as *mut _ as usize;
+    let ret = linux_syscall(NR_CLOCK_GETTIME, [CLOCK_MONOTONIC, tp_addr, 0, 0, 0, 0]);
+    assert_eq!(ret, 0);
+    tp.sec * 1_000_000_000 + tp.nsec
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let tid = linux_syscall(NR_SET_TID_ADDRESS, [0; 6]);
+    assert!(tid > 0);
+    println!("[linux abi] set_tid_address: {}", tid);
+
+    assert_eq!(linux_syscall(NR_BRK, [0; 6]), ENOSYS);
+    println!("[linux abi] brk: ENOSYS");
+
+    let len = 3 * 4096;
+    let addr = linux_syscall(
+        NR_MMAP,
+        [
+            0,
+            len,
+            PROT_READ | PROT_WRITE,
+            MAP_PRIVATE | MAP_ANONYMOUS,
+            usize::MAX,
+            0,
+        ],
+    );
+    assert!(addr > 0);
+    let area = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, len) };
+    area.fill(0x5a);
+    assert!(area.iter().all(|&b| b == 0x5a));
+    println!("[linux abi] mmap: {:#x}", addr);
+    let flags = MAP_SHARED | MAP_ANONYMOUS;
+    let shared = linux_syscall(NR_MMAP, [0, len, PROT_READ, flags, usize::MAX, 0]);
+    assert_eq!(shared, ENOSYS);
+
+    let start = monotonic_ns();
+    let req = TimeSpec {
+        sec: 0,
+        nsec: 20_000_000,
+    };
+    let mut rem = TimeSpec::default();
+    let ret = linux_syscall(
+        NR_NANOSLEEP,
+        [
+            &req as *const _ as usize,
+            &mut rem as *mut _ as usize,
+            0,
+            0,
+            0,
+            0,
+        ],
+    );
+    assert_eq!(ret, 0);
+    let slept = monotonic_ns() - start;
+    assert!(slept >= req.nsec);
+    println!("[linux abi] nanosleep: {} us", slept / 1000);
+    let bad_req = linux_syscall(NR_NANOSLEEP, [usize::MAX, 0, 0, 0, 0, 0]);
+    assert_eq!(bad_req, EFAULT);
+
+    // anything else fails instead of killing the caller
+    assert_eq!(linux_syscall(NR_RT_SIGACTION, [0; 6]), ENOSYS);
+    println!("[linux abi] passed");
+    0
+}
@@ -0,0 +1,116 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+use riscv::register::uie;
+use user_lib::{
+    close, init_user_trap, ioctl, mem_pressure, mem_pressure_stop, mmap, munmap, read, yield_,
+    MEM_PRESSURE_FD, MEM_PRESSURE_PENDING, MEM_PRESSURE_UIPI,
+};
+
+const PAGE_SIZE: usize = 4096;
+/// Frames taken to go below the watermark, well past it and the margin to rearm
+const PRESSURE_PAGES: usize = 256;
+const WATERMARK_BELOW_FREE: usize = 64;
+const MAX_POLLS: usize = 1000;
+
+static NOTIFIED: AtomicUsize = AtomicUsize::new(0);
+static FREE_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+/// Poll the event fd until a notification comes
+fn wait_pending(fd: usize) -> bool {
+    for _ in 0..MAX_POLLS {
+        if ioctl(fd, MEM_PRESSURE_PENDING, 0) > 0 {
+            return true;
+        }
+        yield_();
+    }
+    false
+}
+
+/// Take frames until below the watermark, check both notifications came, then give them back
+fn squeeze(fd: usize, round: usize) -> bool {
+    let start = mmap(0, PRESSURE_PAGES * PAGE_SIZE, 0b11);
+    if start < 0 {
+        println!("[mem pressure] round {}: mmap failed", round);
+        return false;
+    }
+    let pending = wait_pending(fd);
+    let mut count = [0u8; 8];
+    let notified = pending && read(fd, &mut count) == 8 && u64::from_ne_bytes(count) == 1;
+    munmap(start as usize, PRESSURE_PAGES * PAGE_SIZE);
+    // through the scheduler loop once, which sees the frames back and rearms
+    yield_();
+    if !notified {
+        println!("[mem pressure] round {}: no event", round);
+        return false;
+    }
+    for _ in 0..MAX_POLLS {
+        if NOTIFIED.load(SeqCst) > round {
+            return true;
+        }
+        yield_();
+    }
+    println!("[mem pressure] round {}: no user interrupt", round);
+    false
+}
+
+/// Get told of memory pressure by an event fd and a user interrupt, once per dip
+#[no_mangle]
+pub fn main() -> i32 {
+    if mem_pressure(1, MEM_PRESSURE_UIPI) != -107 || mem_pressure(1, 0) != -22 {
+        println!("[mem pressure] registered with bad arguments");
+        return -1;
+    }
+    if init_user_trap() < 0 {
+        println!("[mem pressure] init user trap failed!");
+        return -1;
+    }
+    unsafe {
+        uie::set_usoft();
+    }
+    // always under pressure, to learn the free frames
+    let fd = mem_pressure(usize::MAX, MEM_PRESSURE_UIPI | MEM_PRESSURE_FD);
+    let mut count = [0u8; 8];
+    if fd <= 0 || read(fd as usize, &mut count) != 8 || u64::from_ne_bytes(count) != 1 {
+        println!("[mem pressure] no event below an unreachable watermark");
+        return -1;
+    }
+    while NOTIFIED.load(SeqCst) == 0 {
+        yield_();
+    }
+    close(fd as usize);
+    let free = FREE_FRAMES.load(SeqCst);
+    if free < PRESSURE_PAGES * 2 {
+        println!("[mem pressure] only {} frames free", free);
+        return -1;
+    }
+    let fd = mem_pressure(
+        free - WATERMARK_BELOW_FREE,
+        MEM_PRESSURE_UIPI | MEM_PRESSURE_FD,
+    );
+    if fd <= 0 {
+        println!("[mem pressure] register failed: {}", fd);
+        return -1;
+    }
+    let fd = fd as usize;
+    // the second dip is only told once the first one is over
+    for round in 1..3 {
+        if !squeeze(fd, round) {
+            return -1;
+        }
+    }
+    mem_pressure_stop();
+    close(fd);
+    println!("[mem pressure] passed!");
+    0
+}
+
+#[no_mangle]
+pub fn mem_pressure_handler(free_frames: usize) {
+    FREE_FRAMES.store(free_frames, SeqCst);
+    NOTIFIED.fetch_add(1, SeqCst);
+}
@@ -0,0 +1,35 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, exit, fork, open, read, waitpid, OpenFlags};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // leave a few children behind first, so that their teardown is checked
+    for _ in 0..4 {
+        let pid = fork();
+        if pid == 0 {
+            exit(0);
+        }
+        let mut exit_code = 0;
+        waitpid(pid as usize, &mut exit_code);
+    }
+    let fd = open("/proc/memleak\0", OpenFlags::RDONLY);
+    if fd < 0 {
+        println!("[memleak] open /proc/memleak failed: {}", fd);
+        return -1;
+    }
+    let mut buf = [0u8; 256];
+    loop {
+        let len = read(fd as usize, &mut buf);
+        if len <= 0 {
+            break;
+        }
+        print!("{}", core::str::from_utf8(&buf[..len as usize]).unwrap());
+    }
+    close(fd as usize);
+    0
+}
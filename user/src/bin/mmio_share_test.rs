@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    claim_ext_int, init_user_trap, mmio_map, user_uart::SERIAL_ADDRESS_STRIDE, vm_info, VmAreaInfo,
+    EEXIST, VM_KIND_MMIO,
+};
+
+#[cfg(feature = "board_qemu")]
+const UART_IRQN: usize = 14;
+#[cfg(feature = "board_lrv")]
+const UART_IRQN: usize = 6;
+
+fn mmio_refs(start: usize) -> usize {
+    let mut areas = [VmAreaInfo::default(); 32];
+    let area_num = vm_info(&mut areas).max(0) as usize;
+    areas
+        .iter()
+        .take(area_num)
+        .find(|area| area.kind == VM_KIND_MMIO && area.start == start)
+        .map_or(0, |area| area.refs)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let base = claim_ext_int(UART_IRQN);
+    if base < 0 {
+        println!("[mmio share] claim failed: {}", base);
+        return -1;
+    }
+    let base = base as usize;
+    // two drivers of the same device in one process share the registers
+    for _ in 0..2 {
+        let ret = mmio_map(base, SERIAL_ADDRESS_STRIDE, 0b11);
+        if ret != SERIAL_ADDRESS_STRIDE as isize {
+            println!("[mmio share] map failed: {}", ret);
+            return -1;
+        }
+    }
+    if mmio_refs(base) != 2 {
+        println!("[mmio share] expected 2 refs, got {}", mmio_refs(base));
+        return -1;
+    }
+    let ret = mmio_map(base, SERIAL_ADDRESS_STRIDE, 0b01);
+    if ret != EEXIST {
+        println!("[mmio share] read-only remap returned {}", ret);
+        return -1;
+    }
+    println!("[mmio share] passed");
+    0
+}
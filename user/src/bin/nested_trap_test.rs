@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+use riscv::register::uie;
+use user_lib::{
+    get_time, getpid, init_user_trap, send_msg, set_timer, set_trap_nesting, trap_nesting_depth,
+    TrapNesting,
+};
+
+static TIMER_DEPTH: AtomicUsize = AtomicUsize::new(0);
+static IS_DONE: AtomicBool = AtomicBool::new(false);
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let init_res = init_user_trap();
+    println!("[nested trap] init result: {:#x}", init_res);
+    if init_res < 0 {
+        return -1;
+    }
+    set_trap_nesting(TrapNesting::MaskLower);
+    unsafe {
+        uie::set_usoft();
+        uie::set_utimer();
+    }
+    // the timer fires while the handler of the message spins
+    send_msg(getpid() as usize, 0);
+    while !IS_DONE.load(Relaxed) {}
+    let depth = TIMER_DEPTH.load(Relaxed);
+    if depth != 2 {
+        println!(
+            "[nested trap] timer handler ran at depth {}, expected 2",
+            depth
+        );
+        return -1;
+    }
+    println!("[nested trap] timer preempted the soft handler, passed");
+    0
+}
+
+#[no_mangle]
+pub fn soft_intr_handler(_pid: usize, _msg: usize) {
+    set_timer(get_time() * 1000 + 50_000);
+    while TIMER_DEPTH.load(Relaxed) == 0 {}
+    IS_DONE.store(true, Relaxed);
+}
+
+#[no_mangle]
+pub fn timer_intr_handler(_time_us: usize) {
+    TIMER_DEPTH.store(trap_nesting_depth(), Relaxed);
+}
@@ -0,0 +1,30 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+/// Panic a few calls deep, the panic handler prints where from, e.g.
+/// `#3 0x... panic_backtrace::level_1+0x1e at bin/panic_backtrace.rs:28`, the line of the call
+#[no_mangle]
+pub fn main() -> i32 {
+    println!("[panic backtrace] panicking 3 calls deep");
+    level_1(3)
+}
+
+#[inline(never)]
+fn level_3(depth: usize) -> i32 {
+    let values = [1, 2, 3];
+    // out of bounds, through core
+    values[depth] + depth as i32
+}
+
+#[inline(never)]
+fn level_2(depth: usize) -> i32 {
+    level_3(depth) + 1
+}
+
+#[inline(never)]
+fn level_1(depth: usize) -> i32 {
+    level_2(depth) + 1
+}
@@ -0,0 +1,36 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, exit, fork, getpid, kill, waitpid, SIGCONT};
+
+/// Run by `pid_ns_test` as the init of a new pid namespace
+#[no_mangle]
+pub fn main() -> i32 {
+    if getpid() != 1 {
+        println!("[pid ns child] getpid() = {}, expected 1", getpid());
+        return 1;
+    }
+    // fds 0 to 4 are those of the first process, nothing of the parent is inherited
+    if let Some(fd) = (5..16).find(|&fd| close(fd) == 0) {
+        println!("[pid ns child] fd {} was inherited", fd);
+        return 4;
+    }
+    let pid = fork();
+    if pid == 0 {
+        exit(if getpid() == 2 { 0 } else { 2 });
+    }
+    let mut exit_code: i32 = 0;
+    if pid != 2 || waitpid(2, &mut exit_code) != 2 || exit_code != 0 {
+        println!("[pid ns child] forked child is pid {}, expected 2", pid);
+        return 2;
+    }
+    // pids outside the namespace, e.g. initproc, are invisible
+    if kill(0, SIGCONT) >= 0 {
+        println!("[pid ns child] pid 0 of the root namespace is visible");
+        return 3;
+    }
+    0
+}
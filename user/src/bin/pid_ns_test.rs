@@ -0,0 +1,33 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, pipe, spawn_with_flags, waitpid, SPAWN_NEW_PID_NS};
+
+/// Spawn `pid_ns_child` into a new pid namespace and check what it sees from inside
+#[no_mangle]
+pub fn main() -> i32 {
+    // not handed to the child, which starts with the standard fds only
+    let mut fds = [0usize; 2];
+    if pipe(&mut fds) < 0 {
+        println!("[pid ns test] pipe failed!");
+        return -1;
+    }
+    let pid = spawn_with_flags("pid_ns_child\0", SPAWN_NEW_PID_NS);
+    close(fds[0]);
+    close(fds[1]);
+    if pid < 0 {
+        println!("[pid ns test] spawn failed!");
+        return -1;
+    }
+    let mut exit_code: i32 = 0;
+    waitpid(pid as usize, &mut exit_code);
+    if exit_code == 0 {
+        println!("[pid ns test] passed!");
+    } else {
+        println!("[pid ns test] failed, exit code: {}", exit_code);
+    }
+    exit_code
+}
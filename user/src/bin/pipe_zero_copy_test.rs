@@ -0,0 +1,85 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, exit, fork, mmap, pipe, read, waitpid, write};
+
+const PAGE_SIZE: usize = 4096;
+const PAGES: usize = 8;
+/// Copied after the pages, which are lent to the pipe
+const TAIL: usize = 100;
+const LEN: usize = PAGES * PAGE_SIZE + TAIL;
+
+fn pattern(round: usize, i: usize) -> u8 {
+    (i * 7 + i / PAGE_SIZE + round) as u8
+}
+
+fn buffer() -> &'static mut [u8] {
+    let start = mmap(0, (PAGES + 1) * PAGE_SIZE, 0b11);
+    if start < 0 {
+        println!("[pipe zero copy] mmap failed!");
+        exit(-1);
+    }
+    unsafe { core::slice::from_raw_parts_mut(start as usize as *mut u8, LEN) }
+}
+
+/// Read every round in small pieces and check it
+fn reader(fd: usize, rounds: usize) -> ! {
+    let buf = buffer();
+    for round in 0..rounds {
+        let mut got = 0;
+        while got < LEN {
+            let end = (got + 1000).min(LEN);
+            match read(fd, &mut buf[got..end]) {
+                n if n > 0 => got += n as usize,
+                _ => exit(-2),
+            }
+        }
+        if (0..LEN).any(|i| buf[i] != pattern(round, i)) {
+            exit(-3);
+        }
+    }
+    exit(0);
+}
+
+/// Large page-aligned writes to a pipe keep their contents, the tail included, and the
+/// buffer can be reused as soon as `write` returns
+#[no_mangle]
+pub fn main() -> i32 {
+    const ROUNDS: usize = 3;
+    let mut pipe_fd = [0usize; 2];
+    if pipe(&mut pipe_fd) < 0 {
+        println!("[pipe zero copy] pipe failed!");
+        return -1;
+    }
+    let pid = fork();
+    if pid == 0 {
+        close(pipe_fd[1]);
+        reader(pipe_fd[0], ROUNDS);
+    } else if pid < 0 {
+        println!("[pipe zero copy] fork failed!");
+        return -1;
+    }
+    close(pipe_fd[0]);
+    let buf = buffer();
+    for round in 0..ROUNDS {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = pattern(round, i);
+        }
+        let written = write(pipe_fd[1], buf);
+        if written != LEN as isize {
+            println!("[pipe zero copy] round {}: wrote {}", round, written);
+            return -1;
+        }
+    }
+    close(pipe_fd[1]);
+    let mut exit_code = 0;
+    if waitpid(pid as usize, &mut exit_code) != pid || exit_code != 0 {
+        println!("[pipe zero copy] reader exited with {}", exit_code);
+        return -1;
+    }
+    println!("[pipe zero copy] passed!");
+    0
+}
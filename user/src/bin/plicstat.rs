@@ -0,0 +1,28 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, open, read, OpenFlags};
+
+/// Print the priorities of the PLIC sources, who they are routed to, and the thresholds
+/// of the contexts
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("/proc/plic\0", OpenFlags::RDONLY);
+    if fd < 0 {
+        println!("[plicstat] open /proc/plic failed: {}", fd);
+        return -1;
+    }
+    let mut buf = [0u8; 256];
+    loop {
+        let len = read(fd as usize, &mut buf);
+        if len <= 0 {
+            break;
+        }
+        print!("{}", core::str::from_utf8(&buf[..len as usize]).unwrap());
+    }
+    close(fd as usize);
+    0
+}
@@ -0,0 +1,53 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{debug_translate, PteInfo, DEBUG_TRANSLATE_DUMP, PTE_R, PTE_U, PTE_V, PTE_W, PTE_X};
+
+static mut DATA: usize = 0;
+
+/// Translate a few addresses of this process, then dump its whole page table
+#[no_mangle]
+pub fn main() -> i32 {
+    let local = 0usize;
+    let addrs = [
+        ("text", main as *const () as usize),
+        ("data", unsafe { core::ptr::addr_of!(DATA) as usize }),
+        ("stack", &local as *const usize as usize),
+    ];
+    for (name, vaddr) in addrs {
+        let mut info = PteInfo::default();
+        if debug_translate(vaddr, &mut info, 0) != 0 {
+            println!("[pt_dump] {} {:#x} is not mapped", name, vaddr);
+            return -1;
+        }
+        if info.flags & (PTE_V | PTE_U) != PTE_V | PTE_U || info.paddr & 0xfff != vaddr & 0xfff {
+            println!(
+                "[pt_dump] {} {:#x} has a bad translation {:x?}",
+                name, vaddr, info
+            );
+            return -1;
+        }
+        println!(
+            "[pt_dump] {:5} {:#x} -> {:#x} L{} {}{}{}",
+            name,
+            vaddr,
+            info.paddr,
+            info.level,
+            if info.flags & PTE_R != 0 { 'r' } else { '-' },
+            if info.flags & PTE_W != 0 { 'w' } else { '-' },
+            if info.flags & PTE_X != 0 { 'x' } else { '-' },
+        );
+    }
+    // the lowest page is never mapped
+    let mut info = PteInfo::default();
+    if debug_translate(0, &mut info, DEBUG_TRANSLATE_DUMP) != -2 || info.paddr != 0 {
+        println!("[pt_dump] null page is mapped");
+        return -1;
+    }
+    println!("[pt_dump] null page stops at L{}", info.level);
+    println!("[pt_dump] passed");
+    0
+}
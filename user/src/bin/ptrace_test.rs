@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+use user_lib::{
+    exit, fork, ptrace, waitpid, waitpid_with_options, yield_, PtraceRegs, PtraceWord,
+    PTRACE_ATTACH, PTRACE_DETACH, PTRACE_GETREGS, PTRACE_PEEKDATA, PTRACE_SINGLESTEP, WUNTRACED,
+};
+
+const LOOP_NUM: usize = 100;
+const STEP_NUM: usize = 10;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Attach to a child, single step it a few times and read its memory
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        for _ in 0..LOOP_NUM {
+            COUNTER.fetch_add(1, SeqCst);
+            yield_();
+        }
+        exit(0);
+    } else if pid < 0 {
+        println!("[ptrace test] fork failed!");
+        return -1;
+    }
+    let pid = pid as usize;
+    let mut status: i32 = 0;
+    if ptrace(PTRACE_ATTACH, pid, 0) < 0 {
+        println!("[ptrace test] attach failed!");
+        return -1;
+    }
+    waitpid_with_options(pid, &mut status, WUNTRACED);
+    println!("[ptrace test] child {} stopped, status: {:#x}", pid, status);
+    let mut regs = PtraceRegs::default();
+    for _ in 0..STEP_NUM {
+        ptrace(PTRACE_GETREGS, pid, &mut regs as *mut _ as usize);
+        println!("[ptrace test] pc: {:#x}, sp: {:#x}", regs.pc, regs.x[2]);
+        ptrace(PTRACE_SINGLESTEP, pid, 0);
+        waitpid_with_options(pid, &mut status, WUNTRACED);
+    }
+    let mut word = PtraceWord {
+        addr: &COUNTER as *const _ as usize,
+        data: 0,
+    };
+    ptrace(PTRACE_PEEKDATA, pid, &mut word as *mut _ as usize);
+    println!("[ptrace test] counter of child: {}", word.data);
+    ptrace(PTRACE_DETACH, pid, 0);
+    waitpid(pid, &mut status);
+    println!("[ptrace test] child exited with code {}", status);
+    status
+}
@@ -0,0 +1,49 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, dup, exit, fork, pipe, read, waitpid};
+
+const MESSAGE: &str = "stdout of the child, through a pipe";
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    let pid = fork();
+    if pid == 0 {
+        close(pipe_fd[0]);
+        close(1);
+        if dup(pipe_fd[1]) != 1 {
+            exit(-1);
+        }
+        close(pipe_fd[1]);
+        print!("{}", MESSAGE);
+        exit(0);
+    }
+    close(pipe_fd[1]);
+    let mut buf = [0u8; 64];
+    let mut len = 0;
+    loop {
+        let n = read(pipe_fd[0], &mut buf[len..]);
+        if n <= 0 {
+            break;
+        }
+        len += n as usize;
+    }
+    close(pipe_fd[0]);
+    let mut exit_code = 0;
+    waitpid(pid as usize, &mut exit_code);
+    if exit_code != 0 || &buf[..len] != MESSAGE.as_bytes() {
+        println!(
+            "[redirect] got {:?}, exit code {}",
+            core::str::from_utf8(&buf[..len]),
+            exit_code
+        );
+        return -1;
+    }
+    println!("[redirect] passed");
+    0
+}
@@ -0,0 +1,68 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::arch::asm;
+use riscv::register::{uie, utvec};
+use user_lib::{
+    exit, fork, getpid, init_user_trap, send_msg, waitpid_status, yield_, ExitStatus,
+    EXIT_REASON_BAD_RETURN_CONTEXT,
+};
+
+/// An address no user code is mapped at
+const BAD_PC: usize = 0x10;
+
+/// Leave a bad utvec or uepc behind, then enter the kernel
+fn breaker(case: &str) -> ! {
+    if init_user_trap() < 0 {
+        exit(-1);
+    }
+    match case {
+        // mode 2 is reserved
+        "utvec" => unsafe {
+            asm!("csrw utvec, {}", in(reg) utvec::read().address() | 2);
+            yield_();
+        },
+        // the handler breaks its own uepc, see `soft_intr_handler`
+        _ => unsafe {
+            uie::set_usoft();
+            send_msg(getpid() as usize, 0);
+        },
+    }
+    loop {
+        yield_();
+    }
+}
+
+/// A task which would return to user mode with a bad uepc or utvec is killed
+#[no_mangle]
+pub fn main() -> i32 {
+    for case in ["utvec", "uepc"] {
+        let pid = fork();
+        if pid == 0 {
+            breaker(case);
+        } else if pid < 0 {
+            println!("[return context] fork failed!");
+            return -1;
+        }
+        let mut status = ExitStatus::default();
+        waitpid_status(pid as usize, &mut status, 0);
+        if status.reason != EXIT_REASON_BAD_RETURN_CONTEXT {
+            println!("[return context] {}: child ended with {:?}", case, status);
+            return -1;
+        }
+    }
+    println!("[return context] passed!");
+    0
+}
+
+#[no_mangle]
+pub fn soft_intr_handler(_pid: usize, _msg: usize) {
+    unsafe {
+        asm!("csrw uepc, {}", in(reg) BAD_PC);
+    }
+    // still in the handler, user interrupts are off
+    yield_();
+}
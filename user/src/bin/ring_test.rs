@@ -0,0 +1,92 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::ipc::Ring;
+use user_lib::ipi::IpiError;
+use user_lib::{exit, fork, get_time_us, waitpid};
+
+const CAPACITY: usize = 4096;
+const TOTAL: usize = 1 << 20;
+const CHUNK: usize = 1000;
+
+fn pattern(pos: usize) -> u8 {
+    (pos % 251) as u8
+}
+
+/// Stream bytes to a forked child through a ring smaller than the stream,
+/// so both sides wait on the other, and check what arrives
+#[no_mangle]
+pub fn main() -> i32 {
+    let pair = match Ring::pair(CAPACITY) {
+        Ok(pair) => pair,
+        Err(err) => {
+            println!("[ring] pair failed: {:?}", err);
+            return -1;
+        }
+    };
+    let pid = fork();
+    if pid == 0 {
+        let mut consumer = match pair.consumer(pid) {
+            Ok(consumer) => consumer,
+            Err(_) => exit(-2),
+        };
+        let mut buf = [0u8; CHUNK];
+        let mut pos = 0;
+        loop {
+            match consumer.recv(&mut buf) {
+                Ok(len) => {
+                    for &b in &buf[..len] {
+                        if b != pattern(pos) {
+                            println!("[ring] byte {} is {}, expected {}", pos, b, pattern(pos));
+                            exit(-3);
+                        }
+                        pos += 1;
+                    }
+                }
+                Err(IpiError::Closed) => break,
+                Err(_) => exit(-4),
+            }
+        }
+        exit(if pos == TOTAL { 0 } else { -5 });
+    }
+    let mut producer = match pair.producer(pid) {
+        Ok(producer) => producer,
+        Err(err) => {
+            println!("[ring] fork failed: {:?}", err);
+            return -1;
+        }
+    };
+    let start = get_time_us();
+    let mut buf = [0u8; CHUNK];
+    let mut pos = 0;
+    while pos < TOTAL {
+        let len = CHUNK.min(TOTAL - pos);
+        for (i, b) in buf[..len].iter_mut().enumerate() {
+            *b = pattern(pos + i);
+        }
+        if let Err(err) = producer.send(&buf[..len]) {
+            println!("[ring] send failed at {}: {:?}", pos, err);
+            return -1;
+        }
+        pos += len;
+    }
+    // the consumer sees the end of the stream once the ring is drained
+    drop(producer);
+    let mut exit_code = 0;
+    if waitpid(pid as usize, &mut exit_code) != pid || exit_code != 0 {
+        println!("[ring] consumer exited with {}", exit_code);
+        return -1;
+    }
+    let elapsed_us = (get_time_us() - start).max(1) as usize;
+    println!(
+        "[ring] {} bytes in {} us, {} KiB/s",
+        TOTAL,
+        elapsed_us,
+        TOTAL as u64 * 1_000_000 / 1024 / elapsed_us as u64
+    );
+    println!("[ring] passed");
+    0
+}
@@ -0,0 +1,80 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, get_time_us, mmap, times, wait4, yield_, Rusage, Tms};
+
+const YIELDS: usize = 8;
+const PAGES: usize = 16;
+const SPIN_US: isize = 20_000;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut before = Tms::default();
+    if times(&mut before) < 0 {
+        println!("[rusage] times failed!");
+        return -1;
+    }
+    let pid = fork();
+    if pid == 0 {
+        // touch some pages, then spend both yielding and running
+        let start = 0x1000_0000;
+        if mmap(start, PAGES * 4096, 0b11) < 0 {
+            exit(-2);
+        }
+        for page in 0..PAGES {
+            unsafe { ((start + page * 4096) as *mut u8).write_volatile(page as u8) }
+        }
+        for _ in 0..YIELDS {
+            yield_();
+        }
+        let end = get_time_us() + SPIN_US;
+        while get_time_us() < end {}
+        exit(0);
+    } else if pid < 0 {
+        println!("[rusage] fork failed!");
+        return -1;
+    }
+    let mut exit_code = 0;
+    let mut usage = Rusage::default();
+    if wait4(pid as usize, &mut exit_code, 0, &mut usage) != pid {
+        println!("[rusage] wait4 failed!");
+        return -1;
+    }
+    println!(
+        "[rusage] child {} exited with {}: {:?}",
+        pid, exit_code, usage
+    );
+    if exit_code != 0 {
+        return -1;
+    }
+    if usage.nvcsw < YIELDS {
+        println!(
+            "[rusage] {} voluntary switches, expected {}",
+            usage.nvcsw, YIELDS
+        );
+        return -1;
+    }
+    if usage.max_rss_kb < PAGES * 4 {
+        println!(
+            "[rusage] max RSS {} KiB, expected {}",
+            usage.max_rss_kb,
+            PAGES * 4
+        );
+        return -1;
+    }
+    if usage.utime_us + usage.stime_us + usage.irqtime_us == 0 {
+        println!("[rusage] no CPU time accounted");
+        return -1;
+    }
+    let mut after = Tms::default();
+    times(&mut after);
+    if after.children_utime_us < before.children_utime_us + usage.utime_us {
+        println!("[rusage] the child is not charged to the children times");
+        return -1;
+    }
+    println!("[rusage] passed");
+    0
+}
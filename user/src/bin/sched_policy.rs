@@ -0,0 +1,37 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    get_sched_policy, set_sched_policy, SCHED_POLICY_EDF, SCHED_POLICY_PRIORITY, SCHED_POLICY_RR,
+};
+
+const NAMES: [&str; 3] = ["rr", "priority", "edf"];
+
+/// Show the scheduling policy, or switch it with `sched_policy rr|priority|edf`
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc == 1 {
+        let policy = get_sched_policy();
+        println!("[sched_policy] {}", NAMES[policy as usize]);
+        return 0;
+    }
+    let policy = match argv[1] {
+        "rr" => SCHED_POLICY_RR,
+        "priority" => SCHED_POLICY_PRIORITY,
+        "edf" => SCHED_POLICY_EDF,
+        name => {
+            println!("usage: sched_policy [rr|priority|edf], not {}", name);
+            return -1;
+        }
+    };
+    let old = set_sched_policy(policy);
+    if old < 0 {
+        println!("[sched_policy] switch to {} failed: {}", argv[1], old);
+        return -1;
+    }
+    println!("[sched_policy] {} -> {}", NAMES[old as usize], argv[1]);
+    0
+}
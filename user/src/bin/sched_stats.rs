@@ -0,0 +1,42 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{sched_stats, SchedStats, SCHED_LATENCY_BUCKET_BOUNDS_US};
+
+const HART_STATE_NAMES: [&str; 4] = ["idle", "in user", "in kernel", "in trap"];
+
+/// Print the scheduler statistics of every hart
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut hart_id = 0;
+    let mut stats = SchedStats::default();
+    while sched_stats(hart_id, &mut stats) == 0 {
+        let samples = stats.run_queue_samples.max(1);
+        let switches = stats.switch_count.max(1);
+        println!(
+            "[sched stats] hart {} ({}): {} switches, run queue avg {}.{:02} max {}, latency avg {} us",
+            hart_id,
+            HART_STATE_NAMES.get(stats.state).unwrap_or(&"?"),
+            stats.switch_count,
+            stats.run_queue_len_sum / samples,
+            stats.run_queue_len_sum * 100 / samples % 100,
+            stats.run_queue_len_max,
+            stats.latency_sum_us / switches
+        );
+        let mut lower = 0;
+        for (i, count) in stats.latency_hist.iter().enumerate() {
+            match SCHED_LATENCY_BUCKET_BOUNDS_US.get(i) {
+                Some(upper) => {
+                    println!("    [{}, {}) us: {}", lower, upper, count);
+                    lower = *upper;
+                }
+                None => println!("    [{}, ...) us: {}", lower, count),
+            }
+        }
+        hart_id += 1;
+    }
+    0
+}
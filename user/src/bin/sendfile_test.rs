@@ -0,0 +1,51 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, pipe, read, sendfile, splice, write};
+
+const DATA: &[u8] = b"moved without a trip through user space";
+const PASSED: &[u8] = b"[sendfile] passed!\n";
+
+/// Fill a new pipe with `data` and close its write end, return the read end
+fn filled_pipe(data: &[u8]) -> usize {
+    let mut pipe_fd = [0usize; 2];
+    if pipe(&mut pipe_fd) < 0 || write(pipe_fd[1], data) != data.len() as isize {
+        println!("[sendfile] pipe failed!");
+        return usize::MAX;
+    }
+    close(pipe_fd[1]);
+    pipe_fd[0]
+}
+
+/// Move data between pipes and from a pipe to the console
+#[no_mangle]
+pub fn main() -> i32 {
+    let input = filled_pipe(DATA);
+    let mut output = [0usize; 2];
+    if input == usize::MAX || pipe(&mut output) < 0 {
+        return -1;
+    }
+    // the input ends early, its writer is gone
+    let moved = sendfile(output[1], input, 4096);
+    let mut buf = [0u8; DATA.len()];
+    if moved != DATA.len() as isize || read(output[0], &mut buf) != moved || buf != DATA {
+        println!("[sendfile] moved {} bytes between pipes", moved);
+        return -1;
+    }
+    if sendfile(output[1], 100, 1) != -9 {
+        println!("[sendfile] read from a bad fd");
+        return -1;
+    }
+    close(input);
+    close(output[0]);
+    close(output[1]);
+    let input = filled_pipe(PASSED);
+    if input == usize::MAX || splice(input, 1, PASSED.len()) != PASSED.len() as isize {
+        return -1;
+    }
+    close(input);
+    0
+}
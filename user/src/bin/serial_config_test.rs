@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{serial_get_config, serial_set_config, SerialConfig, FLOW_CONTROL_XON_XOFF};
+
+/// fd 3 is serial 2, see the default fd table of the kernel
+const SERIAL_FD: usize = 3;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut origin = SerialConfig::default();
+    if serial_get_config(SERIAL_FD, &mut origin) != 0 {
+        println!("[serial config] get failed");
+        return -1;
+    }
+    println!("[serial config] {:?}", origin);
+
+    let mut config = origin;
+    config.stop_bits = 3;
+    if serial_set_config(SERIAL_FD, &config) >= 0 {
+        println!("[serial config] invalid config accepted");
+        return -1;
+    }
+
+    config = origin;
+    config.baud_rate = 115200;
+    config.flow_control = FLOW_CONTROL_XON_XOFF;
+    let mut read_back = SerialConfig::default();
+    if serial_set_config(SERIAL_FD, &config) != 0
+        || serial_get_config(SERIAL_FD, &mut read_back) != 0
+        || read_back != config
+    {
+        println!("[serial config] set failed, read back {:?}", read_back);
+        return -1;
+    }
+
+    serial_set_config(SERIAL_FD, &origin);
+    println!("[serial config] passed");
+    0
+}
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{service_load, service_unload};
+
+/// Load or unload a kernel service, `service_ctl load uipi_throttle` or `service_ctl unload 0`
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc != 3 {
+        println!("usage: service_ctl load <name> | service_ctl unload <slot>");
+        return -1;
+    }
+    match argv[1] {
+        "load" => {
+            let slot = service_load(argv[2]);
+            if slot < 0 {
+                println!("[service_ctl] load {} failed: {}", argv[2], slot);
+                return -1;
+            }
+            println!("[service_ctl] {} loaded into slot {}", argv[2], slot);
+        }
+        "unload" => {
+            let slot = match argv[2].parse() {
+                Ok(slot) => slot,
+                Err(_) => {
+                    println!("[service_ctl] bad slot {}", argv[2]);
+                    return -1;
+                }
+            };
+            let ret = service_unload(slot);
+            if ret < 0 {
+                println!("[service_ctl] unload {} failed: {}", slot, ret);
+                return -1;
+            }
+            println!("[service_ctl] slot {} unloaded", slot);
+        }
+        cmd => {
+            println!("[service_ctl] unknown command {}", cmd);
+            return -1;
+        }
+    }
+    0
+}
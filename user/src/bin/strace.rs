@@ -0,0 +1,56 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::string::String;
+use user_lib::{close, exec, exit, fork, getpid, pipe, read, trace_ctl, waitpid, TRACE_CLASS_ALL};
+
+const DEFAULT_APP: &str = "hello_world_simple";
+
+/// Run an app with all its syscalls traced, e.g. `strace hello_world_simple`
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let mut app = String::from(if argc > 1 { argv[1] } else { DEFAULT_APP });
+    app.push('\0');
+    let mut pipe_fd = [0usize; 2];
+    if pipe(&mut pipe_fd) < 0 {
+        println!("[strace] pipe failed!");
+        return -1;
+    }
+    let pid = fork();
+    if pid == 0 {
+        // trace from here on, the trace keeps the write end open until exit
+        if trace_ctl(getpid() as usize, TRACE_CLASS_ALL, pipe_fd[1]) < 0 {
+            println!("[strace] trace_ctl failed!");
+            exit(-1);
+        }
+        close(pipe_fd[0]);
+        close(pipe_fd[1]);
+        exec(app.as_str(), &[app.as_ptr(), core::ptr::null()]);
+        println!("[strace] exec {} failed!", app.trim_end_matches('\0'));
+        exit(-1);
+    } else if pid < 0 {
+        println!("[strace] fork failed!");
+        return -1;
+    }
+    close(pipe_fd[1]);
+    let mut buf = [0u8; 64];
+    loop {
+        let len = read(pipe_fd[0], &mut buf);
+        if len <= 0 {
+            break;
+        }
+        print!(
+            "{}",
+            core::str::from_utf8(&buf[..len as usize]).unwrap_or("?")
+        );
+    }
+    close(pipe_fd[0]);
+    let mut exit_code: i32 = 0;
+    waitpid(pid as usize, &mut exit_code);
+    println!("[strace] pid {} exited with code {}", pid, exit_code);
+    0
+}
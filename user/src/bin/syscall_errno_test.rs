@@ -0,0 +1,53 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, munmap, read, syscall_result, write, MAX_ERRNO};
+
+const PAGE_SIZE: usize = 4096;
+/// What most syscalls of this kernel fail with
+const FAILED: usize = 1;
+const EFAULT: usize = 14;
+
+/// Errors only come from the window below zero, other returns are values
+#[no_mangle]
+pub fn main() -> i32 {
+    let start = match syscall_result(mmap(0, PAGE_SIZE, 0b11)) {
+        Ok(start) if start % PAGE_SIZE == 0 => start,
+        ret => {
+            println!("[syscall errno] mmap returned {:?}", ret);
+            return -1;
+        }
+    };
+    munmap(start, PAGE_SIZE);
+    if syscall_result(write(100, b"x")) != Err(FAILED) {
+        println!("[syscall errno] wrote to a bad fd");
+        return -1;
+    }
+    let trampoline = unsafe { core::slice::from_raw_parts_mut(usize::MAX as *mut u8, 1) };
+    if syscall_result(read(0, trampoline)) != Err(EFAULT) {
+        println!("[syscall errno] read into the trampoline");
+        return -1;
+    }
+    // addresses and counts past `isize::MAX` are values
+    let edges = [
+        (-(MAX_ERRNO as isize), Err(MAX_ERRNO)),
+        (-(MAX_ERRNO as isize) - 1, Ok(usize::MAX - MAX_ERRNO)),
+        (isize::MIN, Ok(1 << 63)),
+        (0, Ok(0)),
+    ];
+    for (ret, result) in edges.iter() {
+        if syscall_result(*ret) != *result {
+            println!(
+                "[syscall errno] {} taken as {:?}",
+                ret,
+                syscall_result(*ret)
+            );
+            return -1;
+        }
+    }
+    println!("[syscall errno] passed!");
+    0
+}
@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use riscv::register::uie;
+use user_lib::{
+    close, exit, fork, get_time, init_user_trap, pipe, read, set_timer, sleep, waitpid, write,
+};
+
+const MSG: &[u8] = b"restarted";
+
+static TIMER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    let pid = fork();
+    if pid == 0 {
+        close(pipe_fd[0]);
+        // the reader is blocked when its timer fires
+        sleep(200);
+        write(pipe_fd[1], MSG);
+        close(pipe_fd[1]);
+        exit(0);
+    }
+    close(pipe_fd[1]);
+    let init_res = init_user_trap();
+    println!("[syscall restart] init result: {:#x}", init_res);
+    if init_res < 0 {
+        return -1;
+    }
+    unsafe {
+        uie::set_usoft();
+        uie::set_utimer();
+    }
+    set_timer(get_time() * 1000 + 50_000);
+    let mut buf = [0u8; MSG.len()];
+    let len = read(pipe_fd[0], &mut buf);
+    let mut exit_code = 0;
+    waitpid(pid as usize, &mut exit_code);
+    if len != MSG.len() as isize || &buf != MSG {
+        println!("[syscall restart] read returned {}", len);
+        return -1;
+    }
+    if TIMER_COUNT.load(Relaxed) != 1 {
+        println!("[syscall restart] timer handler did not run during the read");
+        return -1;
+    }
+    println!("[syscall restart] read restarted after the timer handler, passed");
+    0
+}
+
+#[no_mangle]
+pub fn timer_intr_handler(_time_us: usize) {
+    TIMER_COUNT.fetch_add(1, Relaxed);
+}
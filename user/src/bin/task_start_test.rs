@@ -0,0 +1,81 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::format;
+use user_lib::{
+    clock_gettime, env, exec, exit, fork, task_info, waitpid, TaskInfo, TimeSpec, CLOCK_MONOTONIC,
+};
+
+fn now_us() -> usize {
+    let mut time = TimeSpec::default();
+    clock_gettime(CLOCK_MONOTONIC, &mut time);
+    time.sec * 1_000_000 + time.nsec / 1000
+}
+
+/// The timestamps of the caller, which must have happened in order
+fn ordered_info() -> Option<TaskInfo> {
+    let mut info = TaskInfo::default();
+    let now = now_us();
+    if task_info(0, &mut info) < 0
+        || info.boot_us > info.start_us
+        || info.start_us > info.first_run_us
+        || info.first_run_us > now
+    {
+        println!("[task start] out of order: {:?}, now {}", info, now);
+        return None;
+    }
+    Some(info)
+}
+
+/// A forked child starts after its parent and is started again by nothing but fork
+#[no_mangle]
+pub fn main(_argc: usize, argv: &[&str]) -> i32 {
+    let info = match ordered_info() {
+        Some(info) => info,
+        None => return -1,
+    };
+    if argv.get(1) == Some(&"exec") {
+        return if env::arg::<usize>(2) == Some(info.start_us) {
+            0
+        } else {
+            println!("[task start] exec changed the start to {}", info.start_us);
+            -2
+        };
+    }
+    let pid = fork();
+    if pid == 0 {
+        let child = match ordered_info() {
+            Some(child) => child,
+            None => exit(-3),
+        };
+        if child.start_us < info.first_run_us || child.boot_us != info.boot_us {
+            println!("[task start] child started at {}", child.start_us);
+            exit(-4);
+        }
+        let start = format!("{}\0", child.start_us);
+        exec(
+            "task_start_test\0",
+            &[
+                "task_start_test\0".as_ptr(),
+                "exec\0".as_ptr(),
+                start.as_ptr(),
+                core::ptr::null(),
+            ],
+        );
+        exit(-5);
+    } else if pid < 0 {
+        println!("[task start] fork failed!");
+        return -1;
+    }
+    let mut exit_code = 0;
+    if waitpid(pid as usize, &mut exit_code) != pid || exit_code != 0 {
+        println!("[task start] child exited with {}", exit_code);
+        return -1;
+    }
+    println!("[task start] passed!");
+    0
+}
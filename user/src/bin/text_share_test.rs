@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    debug_translate, exit, fork, ptrace, waitpid, waitpid_with_options, yield_, PteInfo,
+    PtraceWord, PTRACE_ATTACH, PTRACE_DETACH, PTRACE_PEEKDATA, PTRACE_POKEDATA, WUNTRACED,
+};
+
+const LOOP_NUM: usize = 100;
+
+fn text_paddr() -> usize {
+    let mut info = PteInfo::default();
+    debug_translate(main as *const () as usize, &mut info, 0);
+    info.paddr
+}
+
+/// Never called, its text is overwritten in the child
+#[inline(never)]
+fn patched() -> usize {
+    LOOP_NUM * 3
+}
+
+/// Text pages are shared with children, and a debugger writing to them only changes the tracee
+#[no_mangle]
+pub fn main() -> i32 {
+    let paddr = text_paddr();
+    let pid = fork();
+    if pid == 0 {
+        exit(if text_paddr() == paddr { 0 } else { -1 });
+    }
+    let mut status: i32 = 0;
+    waitpid(pid as usize, &mut status);
+    if status != 0 {
+        println!("[text share] text of the child is not shared");
+        return -1;
+    }
+    println!("[text share] text shared at {:#x}", paddr);
+
+    let pid = fork();
+    if pid == 0 {
+        for _ in 0..LOOP_NUM {
+            yield_();
+        }
+        exit(0);
+    }
+    let pid = pid as usize;
+    if ptrace(PTRACE_ATTACH, pid, 0) < 0 {
+        println!("[text share] attach failed");
+        return -1;
+    }
+    waitpid_with_options(pid, &mut status, WUNTRACED);
+    let addr = patched as *const () as usize;
+    let original = unsafe { (addr as *const usize).read_unaligned() };
+    let mut word = PtraceWord {
+        addr,
+        data: !original,
+    };
+    ptrace(PTRACE_POKEDATA, pid, &mut word as *mut _ as usize);
+    word.data = 0;
+    ptrace(PTRACE_PEEKDATA, pid, &mut word as *mut _ as usize);
+    let own = unsafe { (addr as *const usize).read_unaligned() };
+    ptrace(PTRACE_DETACH, pid, 0);
+    waitpid(pid, &mut status);
+    if word.data != !original || own != original || text_paddr() != paddr {
+        println!("[text share] write to the text of the tracee leaked");
+        return -1;
+    }
+    println!("[text share] passed, {}", patched());
+    0
+}
@@ -0,0 +1,40 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::string::String;
+use user_lib::{exec, exit, fork, task_info, waitpid, TaskInfo};
+
+const DEFAULT_APP: &str = "hello_world_simple";
+
+/// Run an app and report its user, kernel and interrupt time, e.g. `time cpu_load`
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let mut app = String::from(if argc > 1 { argv[1] } else { DEFAULT_APP });
+    app.push('\0');
+    let pid = fork();
+    if pid == 0 {
+        exec(app.as_str(), &[app.as_ptr(), core::ptr::null()]);
+        println!("[time] exec {} failed!", app.trim_end_matches('\0'));
+        exit(-1);
+    } else if pid < 0 {
+        println!("[time] fork failed!");
+        return -1;
+    }
+    let mut exit_code: i32 = 0;
+    waitpid(pid as usize, &mut exit_code);
+    // the reaped child is charged to the children times of this task
+    let mut info = TaskInfo::default();
+    if task_info(0, &mut info) < 0 {
+        println!("[time] task_info failed!");
+        return -1;
+    }
+    println!(
+        "[time] pid {} exited with code {}\n    user {} us\n    sys  {} us\n    irq  {} us",
+        pid, exit_code, info.children_utime_us, info.children_stime_us, info.children_irqtime_us
+    );
+    0
+}
@@ -0,0 +1,53 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use riscv::register::uie;
+use user_lib::{get_time, init_user_trap_with, set_timer, UserTrapDescriptor};
+
+const TRAP_STACK_SIZE: usize = 0x2000;
+
+#[repr(align(16))]
+struct TrapStack([u8; TRAP_STACK_SIZE]);
+
+static mut TRAP_STACK: TrapStack = TrapStack([0; TRAP_STACK_SIZE]);
+static HANDLER_SP: AtomicUsize = AtomicUsize::new(0);
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let bottom = unsafe { TRAP_STACK.0.as_ptr() as usize };
+    let top = bottom + TRAP_STACK_SIZE;
+    let init_res = init_user_trap_with(&UserTrapDescriptor::with_stack(top));
+    println!("[trap stack] init result: {:#x}", init_res);
+    if init_res < 0 {
+        return -1;
+    }
+    unsafe {
+        uie::set_utimer();
+    }
+    set_timer(get_time() * 1000 + 100_000);
+    while HANDLER_SP.load(Relaxed) == 0 {}
+    let sp = HANDLER_SP.load(Relaxed);
+    if sp < bottom || sp >= top {
+        println!(
+            "[trap stack] handler ran on {:#x}, outside the trap stack",
+            sp
+        );
+        return -1;
+    }
+    println!("[trap stack] handler ran on the trap stack, passed");
+    0
+}
+
+#[no_mangle]
+pub fn timer_intr_handler(_time_us: usize) {
+    let sp: usize;
+    unsafe {
+        asm!("mv {}, sp", out(reg) sp);
+    }
+    HANDLER_SP.store(sp, Relaxed);
+}
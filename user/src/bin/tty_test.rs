@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::format;
+use user_lib::{close, open, write, OpenFlags};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // Port 0 is the console, always found at boot
+    let fd = open("/dev/ttyS0\0", OpenFlags::RDWR);
+    if fd < 0 {
+        println!("[tty] open /dev/ttyS0 failed: {}", fd);
+        return -1;
+    }
+    let msg = "[tty] written through /dev/ttyS0\n";
+    if write(fd as usize, msg.as_bytes()) != msg.len() as isize {
+        println!("[tty] write to /dev/ttyS0 failed");
+        return -1;
+    }
+    close(fd as usize);
+    let mut ports = 1;
+    loop {
+        let fd = open(format!("/dev/ttyS{}\0", ports).as_str(), OpenFlags::RDWR);
+        if fd < 0 {
+            break;
+        }
+        close(fd as usize);
+        ports += 1;
+    }
+    println!("[tty] {} serial ports", ports);
+    if open("/dev/ttyS-1\0", OpenFlags::RDWR) >= 0 {
+        println!("[tty] opened a port which does not exist");
+        return -1;
+    }
+    println!("[tty] passed");
+    0
+}
@@ -5,10 +5,11 @@
 extern crate user_lib;
 extern crate alloc;
 
+use alloc::vec::Vec;
 use bitflags::bitflags;
-use user_lib::{send_msg, sleep, spawn, waitpid};
+use user_lib::{env, send_msg, sleep, spawn, waitpid};
 
-const CPU_LOAD_NUM: usize = 1;
+const DEFAULT_CPU_LOAD_NUM: usize = 1;
 
 bitflags! {
     struct UartLoadConfig: u32 {
@@ -21,10 +22,13 @@ bitflags! {
     }
 }
 
+/// The argument is the number of `cpu_load` running alongside, 1 by default
 #[no_mangle]
 pub fn main() -> i32 {
-    let cpu_load_pid: [usize; CPU_LOAD_NUM] =
-        array_init::array_init(|_| spawn("cpu_load\0") as usize);
+    let cpu_load_num = env::arg(1).unwrap_or(DEFAULT_CPU_LOAD_NUM);
+    let cpu_load_pid: Vec<usize> = (0..cpu_load_num)
+        .map(|_| spawn("cpu_load\0") as usize)
+        .collect();
     let mut exit_code: i32 = 0;
     println!("[uart benchmark] Kernel mode driver benchmark begins.");
     let pid1 = spawn("uart_load\0") as usize;
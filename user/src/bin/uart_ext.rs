@@ -10,7 +10,7 @@ use alloc::sync::Arc;
 use lazy_static::*;
 use riscv::register::uie;
 use spin::Mutex;
-use user_lib::{claim_ext_int, init_user_trap, set_ext_int_enable, user_uart::*, yield_};
+use user_lib::{claim_ext_int, init_user_trap, mmio_map, set_ext_int_enable, user_uart::*, yield_};
 
 const LF: u8 = 0x0au8;
 const CR: u8 = 0x0du8;
@@ -33,11 +33,12 @@ pub fn main() -> i32 {
     println!("[uart ext] A user mode serial driver demo using UEI");
     let init_res = init_user_trap();
     let claim_res = claim_ext_int(UART_IRQN as usize);
+    let map_res = mmio_map(claim_res as usize, SERIAL_ADDRESS_STRIDE, 0b11);
     SERIAL.lock().hardware_init(115200);
     let en_res = set_ext_int_enable(UART_IRQN as usize, 1);
     println!(
-        "[uart ext] init result: {:#x}, claim result: {:#x}, enable res: {:#x}",
-        init_res as usize, claim_res, en_res
+        "[uart ext] init result: {:#x}, claim result: {:#x}, map result: {:#x}, enable res: {:#x}",
+        init_res as usize, claim_res, map_res, en_res
     );
     let mut line = String::new();
     user_println!("Hello from user UART!");
@@ -0,0 +1,207 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use lazy_static::*;
+use riscv::register::uie;
+use spin::Mutex;
+use user_lib::{
+    claim_ext_int, exit, fork, init_user_trap, mailwrite, mmio_map, send_msg, set_ext_int_enable,
+    user_uart::*, wait, yield_,
+};
+
+const LF: u8 = 0x0au8;
+const CR: u8 = 0x0du8;
+const DL: u8 = 0x7fu8;
+const BS: u8 = 0x08u8;
+#[cfg(feature = "board_qemu")]
+const UART_IRQN: u16 = 13;
+#[cfg(feature = "board_lrv")]
+const UART_IRQN: u16 = 5;
+
+static IS_EXITING: AtomicBool = AtomicBool::new(false);
+
+#[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
+lazy_static! {
+    pub static ref SERIAL: Arc<Mutex<BufferedSerial>> = Arc::new(Mutex::new(BufferedSerial::new(
+        get_base_addr_from_irq(UART_IRQN)
+    )));
+}
+
+/// A user mode serial driver which forwards every completed line to another process.
+///
+/// The driver services the UART in U mode, puts the line into the receiver's mailbox
+/// and notifies it with a user software interrupt carrying the line length.
+#[no_mangle]
+pub fn main() -> i32 {
+    println!("[uart forward] A user mode serial driver forwarding lines by user interrupts");
+    let pid = fork();
+    if pid == 0 {
+        receiver_main()
+    } else if pid > 0 {
+        driver_main(pid as usize)
+    } else {
+        println!("[uart forward] fork failed!");
+        -1
+    }
+}
+
+fn receiver_main() -> i32 {
+    let init_res = init_user_trap();
+    println!(
+        "[uart forward] receiver init result: {:#x}",
+        init_res as usize
+    );
+    unsafe {
+        uie::set_usoft();
+    }
+    while !IS_EXITING.load(Relaxed) {
+        yield_();
+    }
+    0
+}
+
+fn driver_main(receiver_pid: usize) -> i32 {
+    let init_res = init_user_trap();
+    let claim_res = claim_ext_int(UART_IRQN as usize);
+    let map_res = mmio_map(claim_res as usize, SERIAL_ADDRESS_STRIDE, 0b11);
+    SERIAL.lock().hardware_init(115200);
+    let en_res = set_ext_int_enable(UART_IRQN as usize, 1);
+    println!(
+        "[uart forward] init result: {:#x}, claim result: {:#x}, map result: {:#x}, enable res: {:#x}",
+        init_res as usize, claim_res, map_res, en_res
+    );
+    let mut line = String::new();
+    user_println!("Lines typed here are forwarded to pid {}", receiver_pid);
+    loop {
+        unsafe {
+            uie::clear_uext();
+        }
+        loop {
+            let c = user_console::stdio_getchar();
+            if c == 0 {
+                break;
+            }
+            match c {
+                LF | CR => {
+                    user_println!("");
+                    forward_line(receiver_pid, &line);
+                    if line == "exit" {
+                        let mut exit_code: i32 = 0;
+                        wait(&mut exit_code);
+                        exit(0);
+                    }
+                    line.clear();
+                }
+                BS | DL => {
+                    if !line.is_empty() {
+                        user_print!("{}", BS as char);
+                        user_print!(" ");
+                        user_print!("{}", BS as char);
+                        line.pop();
+                    }
+                }
+                _ => {
+                    user_print!("{}", c as char);
+                    line.push(c as char);
+                }
+            }
+        }
+        unsafe {
+            uie::set_uext();
+            uie::set_usoft();
+        }
+    }
+}
+
+fn forward_line(receiver_pid: usize, line: &str) {
+    if line.is_empty() {
+        return;
+    }
+    while mailwrite(receiver_pid, line.as_bytes()) < 0 {
+        yield_();
+    }
+    // the receiver may not have initialized its user trap yet
+    while send_msg(receiver_pid, line.len()) < 0 {
+        yield_();
+    }
+}
+
+#[macro_export]
+macro_rules! user_print {
+    ($fmt: literal $(, $($arg: tt)+)?) => {
+        $crate::user_console::print(format_args!($fmt $(, $($arg)+)?));
+    }
+}
+
+#[macro_export]
+macro_rules! user_println {
+    ($fmt: literal $(, $($arg: tt)+)?) => {
+        $crate::user_console::print(format_args!(concat!($fmt, "\r\n") $(, $($arg)+)?));
+    }
+}
+
+mod user_console {
+    use core::fmt::{self, Write};
+
+    #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
+    pub fn stdio_putchar(c: u8) {
+        use embedded_hal::serial::Write;
+        let _ = crate::SERIAL.lock().try_write(c);
+    }
+
+    #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
+    pub fn stdio_getchar() -> u8 {
+        use embedded_hal::serial::Read;
+        crate::SERIAL.lock().try_read().unwrap_or(0)
+    }
+    struct UserStdout;
+
+    impl Write for UserStdout {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            for c in s.chars() {
+                stdio_putchar(c as u8);
+            }
+            Ok(())
+        }
+    }
+
+    pub fn print(args: fmt::Arguments) {
+        UserStdout.write_fmt(args).unwrap();
+    }
+}
+
+mod user_trap {
+    use core::sync::atomic::Ordering::Relaxed;
+    use user_lib::mailread;
+    use user_lib::trap::{get_context, hart_id, Plic};
+
+    #[no_mangle]
+    pub fn soft_intr_handler(pid: usize, msg: usize) {
+        let mut buf = [0u8; 256];
+        let len = mailread(&mut buf[..msg.min(256)]);
+        if len < 0 {
+            println!("[uart forward] no mail from pid {}", pid);
+            return;
+        }
+        let line = core::str::from_utf8(&buf[..len as usize]).unwrap_or("<invalid utf8>");
+        println!("[uart forward] pid {} forwarded: {}", pid, line);
+        if line == "exit" {
+            crate::IS_EXITING.store(true, Relaxed);
+        }
+    }
+
+    #[no_mangle]
+    pub fn ext_intr_handler(irq: u16, _is_from_kernel: bool) {
+        if irq == crate::UART_IRQN {
+            crate::SERIAL.lock().interrupt_handler();
+            Plic::complete(get_context(hart_id(), 'U'), irq);
+        }
+    }
+}
@@ -14,7 +14,7 @@ use rand_xorshift::XorShiftRng;
 use riscv::register::uie;
 use spin::Mutex;
 use user_lib::{
-    claim_ext_int, get_time, init_user_trap, read, set_ext_int_enable, set_timer, sleep,
+    claim_ext_int, get_time, init_user_trap, mmio_map, read, set_ext_int_enable, set_timer, sleep,
     trap::{get_context, hart_id, Plic},
     user_uart::*,
     write, yield_,
@@ -142,9 +142,13 @@ fn user_polling_test() -> (usize, usize, usize) {
     let mut hasher = Hasher::new();
     let uart_irqn = UART_IRQN.load(Relaxed);
     let claim_res = claim_ext_int(uart_irqn as usize);
+    let map_res = mmio_map(claim_res as usize, SERIAL_ADDRESS_STRIDE, 0b11);
     let mut serial = PollingSerial::new(get_base_addr_from_irq(UART_IRQN.load(Relaxed)));
     serial.hardware_init(BAUD_RATE);
-    println!("[uart load] Polling mode, claim result: {:#x}", claim_res);
+    println!(
+        "[uart load] Polling mode, claim result: {:#x}, map result: {:#x}",
+        claim_res, map_res
+    );
     let mut tx_rng = TX_RNG.lock();
     let mut rx_rng = RX_RNG.lock();
     let mut error_count: usize = 0;
@@ -194,12 +198,13 @@ fn user_intr_test() -> (usize, usize, usize) {
     let mut hasher = Hasher::new();
     let uart_irqn = UART_IRQN.load(Relaxed);
     let claim_res = claim_ext_int(uart_irqn as usize);
+    let map_res = mmio_map(claim_res as usize, SERIAL_ADDRESS_STRIDE, 0b11);
     let mut serial = BufferedSerial::new(get_base_addr_from_irq(uart_irqn));
     serial.hardware_init(BAUD_RATE);
     let en_res = set_ext_int_enable(uart_irqn as usize, 1);
     println!(
-        "[uart load] Interrupt mode, claim result: {:#x}, enable res: {:#x}",
-        claim_res, en_res
+        "[uart load] Interrupt mode, claim result: {:#x}, map result: {:#x}, enable res: {:#x}",
+        claim_res, map_res, en_res
     );
     let mut error_count: usize = 0;
     let mut err_pos = -1;
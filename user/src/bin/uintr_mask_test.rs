@@ -0,0 +1,47 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use riscv::register::uie;
+use user_lib::{getpid, init_user_trap, send_msg, yield_, InterruptGuard};
+
+const MSG_NUM: usize = 4;
+
+static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let init_res = init_user_trap();
+    println!("[uintr mask] init result: {:#x}", init_res);
+    if init_res < 0 {
+        return -1;
+    }
+    unsafe {
+        uie::set_usoft();
+    }
+    let pid = getpid() as usize;
+    {
+        let _guard = InterruptGuard::new();
+        for i in 0..MSG_NUM {
+            send_msg(pid, i);
+        }
+        // trap returns and a switch away must not deliver anything
+        yield_();
+        let received = RECEIVED.load(Relaxed);
+        if received != 0 {
+            println!("[uintr mask] {} messages delivered while masked", received);
+            return -1;
+        }
+    }
+    while RECEIVED.load(Relaxed) < MSG_NUM {}
+    println!("[uintr mask] messages held back while masked, passed");
+    0
+}
+
+#[no_mangle]
+pub fn soft_intr_handler(_pid: usize, _msg: usize) {
+    RECEIVED.fetch_add(1, Relaxed);
+}
@@ -0,0 +1,56 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    check_ret, claim_ext_int, init_user_trap, release_user_trap, set_ext_int_enable, ENOTCONN,
+};
+
+const TEST: &str = "uipi claim";
+
+/// No serial port has these interrupts, 0 is reserved by the PLIC
+const EMPTY_IRQS: [usize; 2] = [0, 1000];
+
+const ENODEV: isize = -19;
+
+/// Claiming interrupts without a device behind them, or without user traps,
+/// fails without leaving a claim behind
+#[no_mangle]
+pub fn main() -> i32 {
+    if !check_ret(
+        TEST,
+        "claim before init",
+        claim_ext_int(EMPTY_IRQS[1]),
+        ENOTCONN,
+    ) {
+        return -1;
+    }
+    if init_user_trap() < 0 {
+        println!("[uipi claim] init failed!");
+        return -1;
+    }
+    for irq in EMPTY_IRQS {
+        // twice, the first must not have claimed anything
+        if !check_ret(TEST, "claim empty", claim_ext_int(irq), ENODEV)
+            || !check_ret(TEST, "claim empty again", claim_ext_int(irq), ENODEV)
+            || !check_ret(TEST, "enable empty", set_ext_int_enable(irq, 1), ENODEV)
+        {
+            return -1;
+        }
+    }
+    if !check_ret(TEST, "release", release_user_trap(), 0) {
+        return -1;
+    }
+    if !check_ret(
+        TEST,
+        "claim after release",
+        claim_ext_int(EMPTY_IRQS[1]),
+        ENOTCONN,
+    ) {
+        return -1;
+    }
+    println!("[uipi claim] passed!");
+    0
+}
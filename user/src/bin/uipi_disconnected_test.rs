@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    check_ret, exit, fork, init_user_trap, release_user_trap, send_msg, sleep, waitpid, ENOTCONN,
+    ESRCH,
+};
+
+const TEST: &str = "uipi disconnected";
+
+/// Child which is alive for the whole test, `mode` says what it does with its user traps
+fn spawn_receiver(mode: usize) -> isize {
+    let pid = fork();
+    if pid == 0 {
+        match mode {
+            // never initialized
+            0 => {}
+            // released
+            _ => {
+                init_user_trap();
+                release_user_trap();
+            }
+        }
+        sleep(200);
+        exit(0);
+    }
+    pid
+}
+
+/// Messages to receivers without user traps, exited or reaped fail with an error
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut exit_code: i32 = 0;
+    let uninit = spawn_receiver(0);
+    let released = spawn_receiver(1);
+    let exited = fork();
+    if exited == 0 {
+        init_user_trap();
+        exit(0);
+    }
+    if uninit < 0 || released < 0 || exited < 0 {
+        println!("[uipi disconnected] fork failed!");
+        return -1;
+    }
+    // let the children get there
+    sleep(50);
+    let ok = check_ret(TEST, "never initialized", send_msg(uninit as usize, 0), ENOTCONN)
+        && check_ret(TEST, "released", send_msg(released as usize, 0), ENOTCONN)
+        // a zombie until reaped below
+        && check_ret(TEST, "exited", send_msg(exited as usize, 0), ESRCH);
+    waitpid(uninit as usize, &mut exit_code);
+    waitpid(released as usize, &mut exit_code);
+    waitpid(exited as usize, &mut exit_code);
+    if !ok || !check_ret(TEST, "reaped", send_msg(exited as usize, 0), ESRCH) {
+        return -1;
+    }
+    println!("[uipi disconnected] passed!");
+    0
+}
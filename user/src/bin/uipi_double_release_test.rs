@@ -0,0 +1,51 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+use riscv::register::uie;
+use user_lib::{check_ret, getpid, init_user_trap, release_user_trap, send_msg, yield_, ENOTCONN};
+
+const TEST: &str = "uipi double release";
+
+const ROUNDS: usize = 3;
+
+static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+/// Releasing twice, or without ever initializing, is refused without harm,
+/// and user traps can be initialized again after every release
+#[no_mangle]
+pub fn main() -> i32 {
+    if !check_ret(TEST, "release before init", release_user_trap(), ENOTCONN) {
+        return -1;
+    }
+    let pid = getpid() as usize;
+    for round in 0..ROUNDS {
+        if init_user_trap() < 0 {
+            println!("[uipi double release] init {} failed!", round);
+            return -1;
+        }
+        unsafe {
+            uie::set_usoft();
+        }
+        send_msg(pid, round);
+        while RECEIVED.load(SeqCst) <= round {
+            yield_();
+        }
+        if !check_ret(TEST, "release", release_user_trap(), 0)
+            || !check_ret(TEST, "second release", release_user_trap(), ENOTCONN)
+            || !check_ret(TEST, "send to self", send_msg(pid, round), ENOTCONN)
+        {
+            return -1;
+        }
+    }
+    println!("[uipi double release] passed!");
+    0
+}
+
+#[no_mangle]
+pub fn soft_intr_handler(_pid: usize, _msg: usize) {
+    RECEIVED.fetch_add(1, SeqCst);
+}
@@ -0,0 +1,59 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::string::String;
+use user_lib::{exec, exit, fork, waitpid_status, ExitStatus, EXIT_REASON_EXITED};
+
+const TESTS: [&str; 10] = [
+    "uipi_release_listening_test",
+    "uipi_disconnected_test",
+    "uipi_slots_test",
+    "uipi_fork_storm_test",
+    "uipi_double_release_test",
+    "uipi_claim_test",
+    "uipi_poison_test",
+    "uipi_send_quota_test",
+    "user_trap_budget_test",
+    "return_context_test",
+];
+
+/// Run every UIPI edge case test, each must exit by itself with code 0
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut failed = 0;
+    for test in TESTS {
+        let mut app = String::from(test);
+        app.push('\0');
+        let pid = fork();
+        if pid == 0 {
+            exec(app.as_str(), &[app.as_ptr(), core::ptr::null()]);
+            println!("[uipi edge tests] exec {} failed!", test);
+            exit(-1);
+        } else if pid < 0 {
+            println!("[uipi edge tests] fork failed!");
+            return -1;
+        }
+        let mut status = ExitStatus::default();
+        waitpid_status(pid as usize, &mut status, 0);
+        if status.reason == EXIT_REASON_EXITED && status.code == 0 {
+            println!("[uipi edge tests] {} ok", test);
+        } else {
+            println!("[uipi edge tests] {} FAILED: {:?}", test, status);
+            failed += 1;
+        }
+    }
+    println!(
+        "[uipi edge tests] {} passed, {} failed",
+        TESTS.len() - failed,
+        failed
+    );
+    if failed == 0 {
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,24 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+use user_lib::{getpid, init_user_trap, join_msg_group, leave_msg_group, sleep};
+
+/// Spawned by `uipi_events_test`: leaves its trap buffer and one group behind.
+/// The events are not waited for by the kernel, so the parent gets time to read each.
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = getpid() as usize;
+    if init_user_trap() < 0 {
+        return -1;
+    }
+    sleep(10);
+    join_msg_group(5, pid);
+    sleep(10);
+    leave_msg_group(5, pid);
+    sleep(10);
+    join_msg_group(7, pid);
+    sleep(10);
+    0
+}
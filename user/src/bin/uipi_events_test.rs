@@ -0,0 +1,64 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use user_lib::{close, pipe, read, spawn_with_events, waitpid};
+
+/// Lines up to and with the `exit` event of the child
+fn read_events(fd: usize) -> Vec<String> {
+    let mut events = Vec::new();
+    let mut line = String::new();
+    let mut byte = [0u8; 1];
+    while read(fd, &mut byte) == 1 {
+        if byte[0] != b'\n' {
+            line.push(byte[0] as char);
+            continue;
+        }
+        let is_exit = line.split(' ').nth(1) == Some("exit");
+        events.push(core::mem::take(&mut line));
+        if is_exit {
+            break;
+        }
+    }
+    events
+}
+
+/// A child spawned with `SPAWN_NOTIFY_PARENT` reports its UIPI actions and what it leaked
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut fds = [0usize; 2];
+    if pipe(&mut fds) < 0 {
+        return -1;
+    }
+    let pid = spawn_with_events("uipi_events_child\0", 0, fds[1]);
+    if pid < 0 {
+        println!("[uipi events] spawn failed!");
+        return -1;
+    }
+    // the kernel keeps its own reference to the write end
+    close(fds[1]);
+    let events = read_events(fds[0]);
+    close(fds[0]);
+    let mut exit_code = 0;
+    waitpid(pid as usize, &mut exit_code);
+    let expected = [
+        format!("{} trap_init 0", pid),
+        format!("{} group_join 5", pid),
+        format!("{} group_leave 5", pid),
+        format!("{} group_join 7", pid),
+        // its trap buffer and group 7
+        format!("{} exit 2", pid),
+    ];
+    if events != expected || exit_code != 0 {
+        println!("[uipi events] got {:?}, exit code {}", events, exit_code);
+        return -1;
+    }
+    println!("[uipi events] passed!");
+    0
+}
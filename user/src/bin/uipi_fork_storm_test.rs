@@ -0,0 +1,98 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+use riscv::register::uie;
+use user_lib::{exit, fork, get_time, getpid, init_user_trap, send_msg, waitpid, yield_};
+
+const CHILD_NUM: usize = 32;
+const TIMEOUT_MS: isize = 5000;
+
+static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+static FROM_CHILDREN: AtomicUsize = AtomicUsize::new(0);
+static MY_PID: AtomicUsize = AtomicUsize::new(0);
+
+/// Every child inherits the user traps of the parent: it sends to the parent,
+/// receives its own message and exits, which must leave the parent untouched
+fn child(parent: usize, index: usize) -> i32 {
+    MY_PID.store(getpid() as usize, SeqCst);
+    let before = RECEIVED.load(SeqCst);
+    if send_msg(parent, index) != 0 || send_msg(MY_PID.load(SeqCst), index) != 0 {
+        return -1;
+    }
+    let start = get_time();
+    while RECEIVED.load(SeqCst) == before {
+        if get_time() > start + TIMEOUT_MS {
+            return -2;
+        }
+        yield_();
+    }
+    0
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    if init_user_trap() < 0 {
+        println!("[uipi fork storm] init failed!");
+        return -1;
+    }
+    unsafe {
+        uie::set_usoft();
+    }
+    let parent = getpid() as usize;
+    MY_PID.store(parent, SeqCst);
+    let mut pids = [0; CHILD_NUM];
+    for (i, pid) in pids.iter_mut().enumerate() {
+        let ret = fork();
+        if ret == 0 {
+            exit(child(parent, i));
+        } else if ret < 0 {
+            println!("[uipi fork storm] fork {} failed!", i);
+            return -1;
+        }
+        *pid = ret as usize;
+    }
+    let mut failed = 0;
+    for pid in pids {
+        let mut exit_code: i32 = 0;
+        waitpid(pid, &mut exit_code);
+        if exit_code != 0 {
+            println!("[uipi fork storm] child {} failed: {}", pid, exit_code);
+            failed += 1;
+        }
+    }
+    let start = get_time();
+    while FROM_CHILDREN.load(SeqCst) < CHILD_NUM {
+        if get_time() > start + TIMEOUT_MS {
+            println!(
+                "[uipi fork storm] only {} of {} messages arrived",
+                FROM_CHILDREN.load(SeqCst),
+                CHILD_NUM
+            );
+            return -1;
+        }
+        yield_();
+    }
+    // still able to receive after every child is gone
+    let before = RECEIVED.load(SeqCst);
+    send_msg(parent, CHILD_NUM);
+    while RECEIVED.load(SeqCst) == before {
+        yield_();
+    }
+    if failed > 0 {
+        return -1;
+    }
+    println!("[uipi fork storm] passed!");
+    0
+}
+
+#[no_mangle]
+pub fn soft_intr_handler(pid: usize, _msg: usize) {
+    if pid != MY_PID.load(SeqCst) {
+        FROM_CHILDREN.fetch_add(1, SeqCst);
+    }
+    RECEIVED.fetch_add(1, SeqCst);
+}
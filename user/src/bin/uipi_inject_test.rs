@@ -0,0 +1,76 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+use riscv::register::uie;
+use user_lib::{
+    close, exit, fork, getpid, init_user_trap, pipe, read, uipi_inject, waitpid, yield_, ESRCH,
+};
+
+const INJECT_NUM: usize = 10;
+/// No task has this pid
+const NO_SUCH_PID: usize = 0x7ff;
+
+static SENDER: AtomicUsize = AtomicUsize::new(0);
+static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+static ERROR_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Drive the receive path with interrupts forged by the kernel on behalf of a child which
+/// never sends, needs a kernel built with the `uipi_inject` feature
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    unsafe {
+        uie::set_usoft();
+    }
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    let sender = fork();
+    if sender == 0 {
+        // stays alive until the write end is closed
+        close(pipe_fd[1]);
+        let mut buf = [0u8; 1];
+        read(pipe_fd[0], &mut buf);
+        exit(0);
+    }
+    close(pipe_fd[0]);
+    SENDER.store(sender as usize, SeqCst);
+    let pid = getpid() as usize;
+    if uipi_inject(pid, NO_SUCH_PID) != ESRCH {
+        println!("[uipi inject test] forged a sender which does not exist");
+        return -1;
+    }
+    for i in 0..INJECT_NUM {
+        if uipi_inject(pid, sender as usize) < 0 {
+            println!(
+                "[uipi inject test] inject {} failed, is the feature enabled?",
+                i
+            );
+            return -1;
+        }
+    }
+    while RECEIVED.load(SeqCst) < INJECT_NUM {
+        yield_();
+    }
+    close(pipe_fd[1]);
+    let mut exit_code = 0;
+    waitpid(sender as usize, &mut exit_code);
+    let error_count = ERROR_COUNT.load(SeqCst);
+    if error_count == 0 {
+        println!("[uipi inject test] passed!");
+    } else {
+        println!("[uipi inject test] {} from a wrong sender", error_count);
+    }
+    error_count as i32
+}
+
+#[no_mangle]
+pub fn soft_intr_handler(pid: usize, _msg: usize) {
+    if pid != SENDER.load(SeqCst) {
+        ERROR_COUNT.fetch_add(1, SeqCst);
+    }
+    RECEIVED.fetch_add(1, SeqCst);
+}
@@ -0,0 +1,104 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::ipi::{Channel, IpiError};
+use user_lib::{exit, fork, getpid, uipi_bind, uipi_resolve, uipi_unbind, waitpid, yield_};
+
+const NAME: &str = "test.echo";
+const ROUNDS: usize = 8;
+
+/// Echo every message plus one under `NAME`, until the parent drops its end
+fn echo(parent: usize) -> ! {
+    let channel = match Channel::with_pid(parent) {
+        Ok(channel) => channel,
+        Err(_) => exit(-2),
+    };
+    if uipi_bind(NAME) != 0 {
+        exit(-3);
+    }
+    loop {
+        match channel.recv() {
+            Ok(msg) => {
+                if channel.send(msg + 1).is_err() {
+                    exit(-4);
+                }
+            }
+            // the name goes away with the task
+            Err(IpiError::Closed) => exit(0),
+            Err(_) => exit(-5),
+        }
+    }
+}
+
+/// Bind a name in a child, reach it by that name and check the name is gone once it exits
+#[no_mangle]
+pub fn main() -> i32 {
+    if uipi_bind(NAME) != -107 {
+        println!("[uipi name] bound without user traps");
+        return -1;
+    }
+    let parent = getpid() as usize;
+    let pid = fork();
+    if pid == 0 {
+        echo(parent);
+    } else if pid < 0 {
+        println!("[uipi name] fork failed!");
+        return -1;
+    }
+    let mut resolved = uipi_resolve(NAME);
+    while resolved == -2 {
+        yield_();
+        resolved = uipi_resolve(NAME);
+    }
+    if resolved != pid {
+        println!("[uipi name] {} resolved to {}, not {}", NAME, resolved, pid);
+        return -1;
+    }
+    let channel = match Channel::connect(NAME) {
+        Ok(channel) => channel,
+        Err(err) => {
+            println!("[uipi name] connect failed: {:?}", err);
+            return -1;
+        }
+    };
+    for round in 0..ROUNDS {
+        let reply = channel.send(round).and_then(|_| channel.recv());
+        if reply != Ok(round + 1) {
+            println!("[uipi name] round {}: got {:?}", round, reply);
+            return -1;
+        }
+    }
+    if uipi_bind(NAME) != -17 || uipi_unbind(NAME) != -2 {
+        println!("[uipi name] took over the name of the child");
+        return -1;
+    }
+    for bad in ["", "no spaces", "slash/name"] {
+        if uipi_bind(bad) != -22 {
+            println!("[uipi name] bound {:?}", bad);
+            return -1;
+        }
+    }
+    if uipi_bind("test.parent") != 0
+        || uipi_resolve("test.parent") != parent as isize
+        || uipi_unbind("test.parent") != 0
+        || uipi_resolve("test.parent") != -2
+    {
+        println!("[uipi name] bind and unbind of the parent failed");
+        return -1;
+    }
+    drop(channel);
+    let mut exit_code = 0;
+    if waitpid(pid as usize, &mut exit_code) != pid || exit_code != 0 {
+        println!("[uipi name] child exited with {}", exit_code);
+        return -1;
+    }
+    if Channel::connect(NAME).err() != Some(IpiError::NoSuchReceiver) {
+        println!("[uipi name] {} outlived its task", NAME);
+        return -1;
+    }
+    println!("[uipi name] passed!");
+    0
+}
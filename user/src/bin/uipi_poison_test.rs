@@ -0,0 +1,85 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+use riscv::register::uie;
+use user_lib::{
+    exit, fork, getpid, init_user_trap, munmap, send_msg, sleep, waitpid_status, yield_,
+    ExitStatus, EPIPE, ESRCH, EXIT_REASON_UIPI_FAULT, USER_TRAP_BUFFER,
+};
+
+const PAGE_SIZE: usize = 0x1000;
+
+static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+/// Break the trap buffer in one way or the other, then wait to be killed
+fn breaker(unmap: bool) -> ! {
+    if init_user_trap() < 0 {
+        exit(-1);
+    }
+    if unmap {
+        munmap(USER_TRAP_BUFFER, PAGE_SIZE);
+    } else {
+        // the indices of the queue, whichever is where
+        let words = USER_TRAP_BUFFER as *mut usize;
+        unsafe {
+            words.write_volatile(usize::MAX);
+            words.add(1).write_volatile(usize::MAX);
+        }
+    }
+    loop {
+        yield_();
+    }
+}
+
+/// A task which broke its trap buffer is killed when a message is sent to it,
+/// the sender gets an error and the kernel keeps working for everyone else
+#[no_mangle]
+pub fn main() -> i32 {
+    for (name, unmap) in [("unmapped", true), ("scribbled", false)] {
+        let pid = fork();
+        if pid == 0 {
+            breaker(unmap);
+        } else if pid < 0 {
+            println!("[uipi poison] fork failed!");
+            return -1;
+        }
+        sleep(50);
+        let ret = send_msg(pid as usize, 0);
+        if ret != EPIPE {
+            println!("[uipi poison] {}: send returned {}", name, ret);
+            return -1;
+        }
+        let mut status = ExitStatus::default();
+        waitpid_status(pid as usize, &mut status, 0);
+        if status.reason != EXIT_REASON_UIPI_FAULT {
+            println!("[uipi poison] {}: child ended with {:?}", name, status);
+            return -1;
+        }
+        if send_msg(pid as usize, 0) != ESRCH {
+            return -1;
+        }
+    }
+    // user traps of everyone else still work
+    if init_user_trap() < 0 {
+        println!("[uipi poison] init failed!");
+        return -1;
+    }
+    unsafe {
+        uie::set_usoft();
+    }
+    send_msg(getpid() as usize, 0);
+    while RECEIVED.load(SeqCst) == 0 {
+        yield_();
+    }
+    println!("[uipi poison] passed!");
+    0
+}
+
+#[no_mangle]
+pub fn soft_intr_handler(_pid: usize, _msg: usize) {
+    RECEIVED.fetch_add(1, SeqCst);
+}
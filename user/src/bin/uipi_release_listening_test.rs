@@ -0,0 +1,97 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+use riscv::register::uie;
+use user_lib::{
+    exit, fork, getpid, init_user_trap, release_user_trap, send_msg, waitpid, yield_, ENOBUFS,
+    ENOTCONN, ESRCH,
+};
+
+/// Messages taken before the receiver releases its user traps
+const LISTEN_NUM: usize = 8;
+/// Sent by the receiver to itself once initialized again
+const SELF_MSG: usize = 0x5e1f;
+
+static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+static SELF_RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+fn receiver() -> i32 {
+    if init_user_trap() < 0 {
+        println!("[uipi release listening] receiver init failed!");
+        return -1;
+    }
+    unsafe {
+        uie::set_usoft();
+    }
+    while RECEIVED.load(SeqCst) < LISTEN_NUM {
+        yield_();
+    }
+    // the sender keeps going, its messages now have nowhere to go
+    let ret = release_user_trap();
+    if ret != 0 {
+        println!("[uipi release listening] release failed: {}", ret);
+        return -1;
+    }
+    for _ in 0..10 {
+        yield_();
+    }
+    if init_user_trap() < 0 {
+        println!("[uipi release listening] init after release failed!");
+        return -1;
+    }
+    unsafe {
+        uie::set_usoft();
+    }
+    send_msg(getpid() as usize, SELF_MSG);
+    while SELF_RECEIVED.load(SeqCst) == 0 {
+        yield_();
+    }
+    0
+}
+
+/// A receiver releases its user traps while a sender is still sending to it
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        exit(receiver());
+    } else if pid < 0 {
+        println!("[uipi release listening] fork failed!");
+        return -1;
+    }
+    let mut msg = 0;
+    loop {
+        match send_msg(pid as usize, msg) {
+            // the receiver may not be initialized yet, or initialized again
+            0 | ENOBUFS | ENOTCONN => {}
+            // gone
+            ESRCH => break,
+            ret => {
+                println!("[uipi release listening] send returned {}", ret);
+                return -1;
+            }
+        }
+        msg += 1;
+        yield_();
+    }
+    let mut exit_code: i32 = 0;
+    waitpid(pid as usize, &mut exit_code);
+    if exit_code != 0 {
+        return -1;
+    }
+    println!("[uipi release listening] {} messages sent, passed!", msg);
+    0
+}
+
+#[no_mangle]
+pub fn soft_intr_handler(_pid: usize, msg: usize) {
+    if msg == SELF_MSG {
+        SELF_RECEIVED.fetch_add(1, SeqCst);
+    } else {
+        RECEIVED.fetch_add(1, SeqCst);
+    }
+}
@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{check_ret, getpid, init_user_trap, send_msg, set_send_rate, sleep, EAGAIN};
+
+const TEST: &str = "uipi send quota";
+const BURST: usize = 3;
+
+/// Messages to itself beyond the burst are rejected, and a rate too large to multiply
+/// refills the bucket instead of overflowing
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let pid = getpid() as usize;
+    // one token per second, none comes back during the test
+    if !check_ret(TEST, "set rate", set_send_rate(1, BURST), 0) {
+        return -1;
+    }
+    for i in 0..BURST {
+        if send_msg(pid, i) != 0 {
+            println!("[uipi send quota] message {} within the burst failed", i);
+            return -1;
+        }
+    }
+    if !check_ret(TEST, "over the burst", send_msg(pid, BURST), EAGAIN) {
+        return -1;
+    }
+    set_send_rate(usize::MAX, 1);
+    send_msg(pid, 0);
+    sleep(1);
+    if !check_ret(TEST, "huge rate", send_msg(pid, 0), 0)
+        || !check_ret(TEST, "unlimited", set_send_rate(0, 0), 0)
+    {
+        return -1;
+    }
+    println!("[uipi send quota] passed!");
+    0
+}
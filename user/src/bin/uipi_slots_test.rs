@@ -0,0 +1,70 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+use riscv::register::uie;
+use user_lib::{
+    getpid, init_user_trap, send_msg, set_send_rate, uintr_mask, yield_, EAGAIN, ENOBUFS,
+};
+
+const BURST: usize = 4;
+/// More than any trap queue holds
+const MAX_SEND: usize = 1024;
+
+static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+/// Use up the send quota, then every slot of the trap queue of the receiver,
+/// and check that only the expected errors come back and nothing is lost
+#[no_mangle]
+pub fn main() -> i32 {
+    if init_user_trap() < 0 {
+        println!("[uipi slots] init failed!");
+        return -1;
+    }
+    unsafe {
+        uie::set_usoft();
+    }
+    let pid = getpid() as usize;
+    // held back, so that the queue fills up
+    uintr_mask(true);
+    // one message a second, none refilled during the test
+    set_send_rate(1, BURST);
+    for i in 0..BURST {
+        if send_msg(pid, i) != 0 {
+            println!("[uipi slots] send {} within the burst failed!", i);
+            return -1;
+        }
+    }
+    let ret = send_msg(pid, BURST);
+    if ret != EAGAIN {
+        println!("[uipi slots] send past the burst returned {}", ret);
+        return -1;
+    }
+    set_send_rate(0, 0);
+    let mut queued = BURST;
+    let ret = loop {
+        let ret = send_msg(pid, queued);
+        if ret != 0 || queued == MAX_SEND {
+            break ret;
+        }
+        queued += 1;
+    };
+    if ret != ENOBUFS {
+        println!("[uipi slots] send to a full queue returned {}", ret);
+        return -1;
+    }
+    uintr_mask(false);
+    while RECEIVED.load(SeqCst) < queued {
+        yield_();
+    }
+    println!("[uipi slots] {} messages queued, passed!", queued);
+    0
+}
+
+#[no_mangle]
+pub fn soft_intr_handler(_pid: usize, _msg: usize) {
+    RECEIVED.fetch_add(1, SeqCst);
+}
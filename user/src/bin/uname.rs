@@ -0,0 +1,26 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{uname, utsname_str, Utsname};
+
+/// Print the kernel identification, `uname -a` style
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut utsname = Utsname::default();
+    if uname(&mut utsname) != 0 {
+        println!("[uname] failed!");
+        return -1;
+    }
+    println!(
+        "{} {} {} {} {}",
+        utsname_str(&utsname.sysname),
+        utsname_str(&utsname.nodename),
+        utsname_str(&utsname.release),
+        utsname_str(&utsname.version),
+        utsname_str(&utsname.machine)
+    );
+    0
+}
@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::slice;
+use user_lib::trap::PAGE_SIZE;
+use user_lib::{close, exit, fork, mmap, munmap, pipe, read, waitpid, write};
+
+/// An address no user memory is mapped at
+const BAD_ADDR: usize = 0x10;
+const EFAULT: isize = -14;
+
+/// Bad user pointers make syscalls fail with EFAULT and leave nothing behind,
+/// a buffer running into an unmapped page is copied up to it
+#[no_mangle]
+pub fn main() -> i32 {
+    // a mapped page followed by a hole
+    let base = mmap(0, 2 * PAGE_SIZE, 0b11);
+    if base < 0 || munmap(base as usize + PAGE_SIZE, PAGE_SIZE) < 0 {
+        println!("[user copy fault] mmap failed!");
+        return -1;
+    }
+    let tail = unsafe { slice::from_raw_parts_mut((base as usize + PAGE_SIZE - 4) as *mut u8, 8) };
+    tail[..4].copy_from_slice(b"tail");
+
+    let mut fds = [0usize; 2];
+    if pipe(&mut fds) < 0 {
+        return -1;
+    }
+    let written = write(fds[1], tail);
+    let mut buf = [0u8; 8];
+    let read_len = read(fds[0], &mut buf);
+    if written != 4 || read_len != 4 || &buf[..4] != b"tail" {
+        println!(
+            "[user copy fault] straddling write: wrote {}, read {}",
+            written, read_len
+        );
+        return -1;
+    }
+    let unmapped = unsafe { slice::from_raw_parts(BAD_ADDR as *const u8, 8) };
+    if write(fds[1], unmapped) != EFAULT {
+        println!("[user copy fault] write from an unmapped buffer did not fail");
+        return -1;
+    }
+    close(fds[0]);
+    close(fds[1]);
+
+    // the fds of a pipe which could not be reported are closed again
+    let bad_fds = unsafe { slice::from_raw_parts_mut(BAD_ADDR as *mut usize, 2) };
+    if pipe(bad_fds) != EFAULT {
+        println!("[user copy fault] pipe to an unmapped array did not fail");
+        return -1;
+    }
+    let mut again = [0usize; 2];
+    if pipe(&mut again) < 0 || again != fds {
+        println!(
+            "[user copy fault] pipe fds leaked: {:?} then {:?}",
+            fds, again
+        );
+        return -1;
+    }
+    close(again[0]);
+    close(again[1]);
+
+    // a child is not reaped when its status cannot be reported
+    let pid = fork();
+    if pid == 0 {
+        exit(7);
+    } else if pid < 0 {
+        return -1;
+    }
+    let bad_code = unsafe { &mut *(BAD_ADDR as *mut i32) };
+    if waitpid(pid as usize, bad_code) != EFAULT {
+        println!("[user copy fault] waitpid to an unmapped status did not fail");
+        return -1;
+    }
+    let mut code = 0;
+    if waitpid(pid as usize, &mut code) != pid || code != 7 {
+        println!("[user copy fault] child lost, code {}", code);
+        return -1;
+    }
+    munmap(base as usize, PAGE_SIZE);
+    println!("[user copy fault] passed!");
+    0
+}
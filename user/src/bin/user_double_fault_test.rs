@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use riscv::register::uie;
+use user_lib::{fork, getpid, init_user_trap, send_msg, waitpid};
+
+/// Exit code of a task killed by a fault in its user trap handler
+const USER_DOUBLE_FAULT_EXIT_CODE: i32 = -4;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        init_user_trap();
+        unsafe {
+            uie::set_usoft();
+        }
+        send_msg(getpid() as usize, 0);
+        loop {}
+    }
+    let mut exit_code = 0;
+    waitpid(pid as usize, &mut exit_code);
+    if exit_code != USER_DOUBLE_FAULT_EXIT_CODE {
+        println!(
+            "[double fault] child exited with {}, expected {}",
+            exit_code, USER_DOUBLE_FAULT_EXIT_CODE
+        );
+        return -1;
+    }
+    println!("[double fault] faulting handler killed, passed");
+    0
+}
+
+#[no_mangle]
+pub fn soft_intr_handler(_pid: usize, _msg: usize) {
+    unsafe {
+        // the first page is never mapped
+        (0x8 as *mut usize).write_volatile(0);
+    }
+}
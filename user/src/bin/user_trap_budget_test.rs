@@ -0,0 +1,72 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+use riscv::register::uie;
+use user_lib::{
+    exit, fork, getpid, init_user_trap, send_msg, set_user_trap_handler_budget, waitpid_status,
+    yield_, ExitStatus, EXIT_REASON_EXITED, EXIT_REASON_USER_TRAP_TIMEOUT,
+};
+
+const BUDGET_US: usize = 100_000;
+/// The handler never returns from this message
+const HANG: usize = 1;
+
+static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+/// Hang in a handler, then check user traps still work if it was made to return
+fn hanger(force_return: bool) -> ! {
+    if init_user_trap() < 0 || set_user_trap_handler_budget(BUDGET_US, force_return) < 0 {
+        exit(-1);
+    }
+    unsafe {
+        uie::set_usoft();
+    }
+    let pid = getpid() as usize;
+    send_msg(pid, HANG);
+    // only reached after an abort
+    send_msg(pid, 0);
+    while RECEIVED.load(SeqCst) == 0 {
+        yield_();
+    }
+    exit(0);
+}
+
+/// A handler over its budget is made to return or its task is killed
+#[no_mangle]
+pub fn main() -> i32 {
+    for (force_return, reason, code) in [
+        (true, EXIT_REASON_EXITED, 0),
+        (false, EXIT_REASON_USER_TRAP_TIMEOUT, -62),
+    ] {
+        let pid = fork();
+        if pid == 0 {
+            hanger(force_return);
+        } else if pid < 0 {
+            println!("[user trap budget] fork failed!");
+            return -1;
+        }
+        let mut status = ExitStatus::default();
+        waitpid_status(pid as usize, &mut status, 0);
+        if status.reason != reason || status.code != code {
+            println!(
+                "[user trap budget] force_return {}: child ended with {:?}",
+                force_return, status
+            );
+            return -1;
+        }
+    }
+    println!("[user trap budget] passed!");
+    0
+}
+
+#[no_mangle]
+pub fn soft_intr_handler(_pid: usize, msg: usize) {
+    if msg == HANG {
+        loop {}
+    }
+    RECEIVED.fetch_add(1, SeqCst);
+}
@@ -0,0 +1,88 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+use riscv::register::uie;
+use user_lib::{env, exit, fork, getpid, init_user_trap, send_msg, waitpid, yield_};
+
+const DEFAULT_BURST_NUM: usize = 20;
+const DEFAULT_BURST_SIZE: usize = 50;
+
+fn burst_num() -> usize {
+    env::arg(1).unwrap_or(DEFAULT_BURST_NUM)
+}
+
+fn burst_size() -> usize {
+    env::arg(2).unwrap_or(DEFAULT_BURST_SIZE)
+}
+
+static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+static ERROR_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Send bursts of user soft interrupts to a receiver which keeps yielding,
+/// so that most of them arrive while it is descheduled.
+/// Every message must be delivered exactly once and in order.
+/// `user_trap_burst_test [bursts] [burst size]`
+#[no_mangle]
+pub fn main() -> i32 {
+    println!("[burst test] parent pid: {}", getpid());
+    let pid = fork();
+    if pid == 0 {
+        receiver_main()
+    } else if pid > 0 {
+        sender_main(pid as usize)
+    } else {
+        println!("[burst test] fork failed!");
+        -1
+    }
+}
+
+fn receiver_main() -> ! {
+    init_user_trap();
+    unsafe {
+        uie::set_usoft();
+    }
+    let msg_num = burst_num() * burst_size();
+    while RECEIVED.load(SeqCst) < msg_num {
+        yield_();
+    }
+    let error_count = ERROR_COUNT.load(SeqCst);
+    println!(
+        "[burst test] receiver got {} messages, {} out of order",
+        RECEIVED.load(SeqCst),
+        error_count
+    );
+    exit(error_count as i32);
+}
+
+fn sender_main(receiver_pid: usize) -> i32 {
+    let burst_size = burst_size();
+    for burst in 0..burst_num() {
+        for i in 0..burst_size {
+            // the receiver may not be ready, or its trap buffer may be full
+            while send_msg(receiver_pid, burst * burst_size + i) < 0 {
+                yield_();
+            }
+        }
+        yield_();
+    }
+    let mut exit_code: i32 = 0;
+    waitpid(receiver_pid, &mut exit_code);
+    if exit_code == 0 {
+        println!("[burst test] passed!");
+    } else {
+        println!("[burst test] failed, exit code: {}", exit_code);
+    }
+    exit_code
+}
+
+#[no_mangle]
+pub fn soft_intr_handler(_pid: usize, msg: usize) {
+    let expected = RECEIVED.fetch_add(1, SeqCst);
+    if msg != expected {
+        ERROR_COUNT.fetch_add(1, SeqCst);
+    }
+}
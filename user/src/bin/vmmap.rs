@@ -0,0 +1,74 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+
+use user_lib::{
+    getpid, vm_info, VmAreaInfo, VM_KIND_ELF, VM_KIND_KERNEL, VM_KIND_MMAP, VM_KIND_MMIO,
+    VM_KIND_SHARED, VM_KIND_STACK, VM_KIND_TRAP_CONTEXT, VM_PERM_R, VM_PERM_U, VM_PERM_W,
+    VM_PERM_X,
+};
+
+const MAX_AREA_NUM: usize = 32;
+
+/// Print the mapped areas of this process
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut areas = [VmAreaInfo::default(); MAX_AREA_NUM];
+    let area_num = vm_info(&mut areas);
+    if area_num < 0 {
+        println!("[vmmap] vm_info failed: {}", area_num);
+        return -1;
+    }
+    println!("[vmmap] pid {}, {} areas", getpid(), area_num);
+    for area in areas.iter().take(area_num as usize) {
+        println!(
+            "{:#018x}-{:#018x} {}{}{}{} {}{}",
+            area.start,
+            area.end,
+            perm_char(area.perm, VM_PERM_R, 'r'),
+            perm_char(area.perm, VM_PERM_W, 'w'),
+            perm_char(area.perm, VM_PERM_X, 'x'),
+            perm_char(area.perm, VM_PERM_U, 'u'),
+            kind_name(area.kind),
+            if area.refs > 1 {
+                format!(" x{}", area.refs)
+            } else {
+                String::new()
+            }
+        );
+    }
+    if area_num as usize > MAX_AREA_NUM {
+        println!(
+            "[vmmap] {} areas not shown",
+            area_num as usize - MAX_AREA_NUM
+        );
+    }
+    0
+}
+
+fn perm_char(perm: usize, bit: usize, c: char) -> char {
+    if perm & bit != 0 {
+        c
+    } else {
+        '-'
+    }
+}
+
+fn kind_name(kind: usize) -> &'static str {
+    match kind {
+        VM_KIND_KERNEL => "kernel",
+        VM_KIND_ELF => "elf",
+        VM_KIND_STACK => "stack",
+        VM_KIND_TRAP_CONTEXT => "trap context",
+        VM_KIND_MMAP => "mmap",
+        VM_KIND_MMIO => "mmio",
+        VM_KIND_SHARED => "shared",
+        _ => "unknown",
+    }
+}
@@ -0,0 +1,128 @@
+//! Topic-based message bus, served by the `busd` daemon which initproc starts.
+//!
+//! Clients find the daemon by the `BUS_PID` variable initproc gives the commands
+//! it runs, and talk to it over a `Channel`, so a task must not use another
+//! channel with the daemon. A message is a value of up to 48 bits on a topic,
+//! delivered to every task subscribed to the topic, the publisher included.
+//! Deliveries to a subscriber whose trap queue is full are dropped.
+
+use crate::env;
+use crate::ipi::{Channel, IpiError};
+
+const TOPIC_BITS: usize = 12;
+const VALUE_BITS: usize = 48;
+pub const MAX_TOPIC: usize = (1 << TOPIC_BITS) - 1;
+pub const MAX_VALUE: usize = (1 << VALUE_BITS) - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusOp {
+    Subscribe = 1,
+    Unsubscribe = 2,
+    Publish = 3,
+    /// From the daemon, a message published on a subscribed topic
+    Deliver = 4,
+}
+
+/// What one UIPI message carries, the op in the top 4 bits, then the topic and the value.
+/// An op of 0xF is never used, leaving `CHANNEL_CLOSED` apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusMessage {
+    pub op: BusOp,
+    pub topic: usize,
+    pub value: usize,
+}
+
+impl BusMessage {
+    pub fn encode(&self) -> usize {
+        (self.op as usize) << (TOPIC_BITS + VALUE_BITS) | self.topic << VALUE_BITS | self.value
+    }
+
+    pub fn decode(msg: usize) -> Option<Self> {
+        let op = match msg >> (TOPIC_BITS + VALUE_BITS) {
+            1 => BusOp::Subscribe,
+            2 => BusOp::Unsubscribe,
+            3 => BusOp::Publish,
+            4 => BusOp::Deliver,
+            _ => return None,
+        };
+        Some(Self {
+            op,
+            topic: msg >> VALUE_BITS & MAX_TOPIC,
+            value: msg & MAX_VALUE,
+        })
+    }
+}
+
+/// Connected on first use, tasks are single-threaded
+static mut BUS: Option<Channel> = None;
+
+fn bus() -> Result<&'static mut Channel, IpiError> {
+    unsafe {
+        if BUS.is_none() {
+            let pid = env::var("BUS_PID")
+                .and_then(|pid| pid.parse().ok())
+                .ok_or(IpiError::NoSuchReceiver)?;
+            BUS = Some(Channel::with_pid(pid)?);
+        }
+        Ok(BUS.as_mut().unwrap())
+    }
+}
+
+fn request(op: BusOp, topic: usize, value: usize) -> Result<(), IpiError> {
+    if topic > MAX_TOPIC || value > MAX_VALUE {
+        return Err(IpiError::InvalidMessage);
+    }
+    bus()?.send(BusMessage { op, topic, value }.encode())
+}
+
+pub fn publish(topic: usize, value: usize) -> Result<(), IpiError> {
+    request(BusOp::Publish, topic, value)
+}
+
+/// Receive what is published on `topic` from now on
+pub fn subscribe(topic: usize) -> Result<(), IpiError> {
+    request(BusOp::Subscribe, topic, 0)
+}
+
+/// Messages of `topic` delivered before may still be received
+pub fn unsubscribe(topic: usize) -> Result<(), IpiError> {
+    request(BusOp::Unsubscribe, topic, 0)
+}
+
+fn delivered(msg: usize) -> Option<(usize, usize)> {
+    match BusMessage::decode(msg)? {
+        BusMessage {
+            op: BusOp::Deliver,
+            topic,
+            value,
+        } => Some((topic, value)),
+        _ => None,
+    }
+}
+
+/// The next `(topic, value)` delivered, waiting for it
+pub fn recv() -> Result<(usize, usize), IpiError> {
+    let bus = bus()?;
+    loop {
+        if let Some(message) = delivered(bus.recv()?) {
+            return Ok(message);
+        }
+    }
+}
+
+/// The next `(topic, value)` delivered, `Empty` if there is none
+pub fn try_recv() -> Result<(usize, usize), IpiError> {
+    let bus = bus()?;
+    loop {
+        if let Some(message) = delivered(bus.try_recv()?) {
+            return Ok(message);
+        }
+    }
+}
+
+/// Leave the bus, the daemon drops every subscription of the task
+pub fn disconnect() {
+    unsafe {
+        BUS = None;
+    }
+}
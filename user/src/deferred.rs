@@ -0,0 +1,112 @@
+//! Deferred user trap work, run once the outermost trap handler is done.
+//!
+//! A handler keeps the time spent with user interrupts off short by moving
+//! the long part of its work here. The work runs with user interrupts on,
+//! so it may be preempted by any trap, and on its own thread of control, so
+//! it is never preempted by other deferred work.
+
+use crate::trap::{trap_nesting_depth, USER_TRAP_STACK_SLOT};
+use core::mem::{align_of, size_of, MaybeUninit};
+use core::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use core::task::Waker;
+use heapless::spsc::Queue;
+use riscv::register::ustatus;
+use spin::Mutex;
+
+const MAX_DEFERRED_WORK: usize = 32;
+/// Closures are stored in place, so a handler never calls the allocator
+const WORK_WORDS: usize = 4;
+
+struct Work {
+    data: MaybeUninit<[usize; WORK_WORDS]>,
+    call: unsafe fn(*mut usize),
+}
+
+unsafe fn call_once<F: FnOnce()>(data: *mut usize) {
+    (data as *mut F).read()()
+}
+
+static DEFERRED: Mutex<Queue<Work, MAX_DEFERRED_WORK>> = Mutex::new(Queue::new());
+static IS_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Run `f` with user interrupts off, so a nested handler never spins on the queue lock
+fn without_user_trap<R>(f: impl FnOnce() -> R) -> R {
+    let was_enabled = ustatus::read().uie();
+    unsafe {
+        ustatus::clear_uie();
+    }
+    let ret = f();
+    if was_enabled {
+        unsafe {
+            ustatus::set_uie();
+        }
+    }
+    ret
+}
+
+/// Queue `f` to run after the current trap handler, or in the next `run_deferred`
+/// when called outside of one. `f` is given back if the queue is full.
+///
+/// The closure must fit in four words, box larger state outside of the handler.
+pub fn defer<F: FnOnce() + Send + 'static>(f: F) -> Result<(), F> {
+    assert!(
+        size_of::<F>() <= size_of::<[usize; WORK_WORDS]>()
+            && align_of::<F>() <= align_of::<usize>(),
+        "deferred work too large"
+    );
+    let mut work = Work {
+        data: MaybeUninit::uninit(),
+        call: call_once::<F>,
+    };
+    unsafe {
+        (work.data.as_mut_ptr() as *mut F).write(f);
+    }
+    without_user_trap(|| DEFERRED.lock().enqueue(work))
+        .map_err(|work| unsafe { (work.data.as_ptr() as *const F).read() })
+}
+
+/// Wake the task of `waker` once the trap handler is done,
+/// executors need not be reentrant with respect to user traps
+pub fn defer_wake(waker: &Waker) -> bool {
+    defer({
+        let waker = waker.clone();
+        move || waker.wake()
+    })
+    .is_ok()
+}
+
+/// Run all deferred work, return the amount of work done.
+///
+/// Called by the trap handler on its way out, and by tasks polling for user traps.
+/// Does nothing when already running further up the stack.
+pub fn run_deferred() -> usize {
+    if IS_RUNNING.swap(true, Relaxed) {
+        return 0;
+    }
+    let mut count = 0;
+    while let Some(mut work) = without_user_trap(|| DEFERRED.lock().dequeue()) {
+        unsafe {
+            (work.call)(work.data.as_mut_ptr() as *mut usize);
+        }
+        count += 1;
+    }
+    IS_RUNNING.store(false, Relaxed);
+    count
+}
+
+/// Run the deferred work on the way out of the outermost trap handler,
+/// with user interrupts on, unless the handlers share a single trap stack
+pub(crate) fn run_deferred_on_trap_exit() {
+    let is_outermost = trap_nesting_depth() == 1;
+    let has_trap_stack = unsafe { (USER_TRAP_STACK_SLOT as *const usize).read_volatile() } != 0;
+    if !is_outermost || has_trap_stack {
+        return;
+    }
+    unsafe {
+        ustatus::set_uie();
+    }
+    run_deferred();
+    unsafe {
+        ustatus::clear_uie();
+    }
+}
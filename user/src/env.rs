@@ -0,0 +1,60 @@
+//! Arguments and environment of the process.
+//!
+//! exec lays them out on the stack, where `_start` finds argc followed by the
+//! null-terminated argv and envp arrays. Environment strings are `KEY=value`,
+//! and are passed on by `exec` unless `exec_with_env` is given others.
+
+use alloc::vec::Vec;
+use core::str::FromStr;
+
+static mut ARGS: Vec<&'static str> = Vec::new();
+static mut VARS: Vec<&'static str> = Vec::new();
+/// The environment as the null-terminated array exec takes
+static mut ENVP: Vec<*const u8> = Vec::new();
+
+unsafe fn c_str(ptr: *const u8) -> &'static str {
+    let len = (0usize..)
+        .find(|i| ptr.add(*i).read_volatile() == 0)
+        .unwrap();
+    core::str::from_utf8(core::slice::from_raw_parts(ptr, len)).unwrap()
+}
+
+/// Called once by `_start` with the initial stack pointer
+pub(crate) unsafe fn init(sp: *const usize) {
+    let argc = sp.read();
+    let argv = sp.add(1);
+    for i in 0..argc {
+        ARGS.push(c_str(argv.add(i).read() as *const u8));
+    }
+    let mut envp = argv.add(argc + 1);
+    while envp.read() != 0 {
+        let ptr = envp.read() as *const u8;
+        VARS.push(c_str(ptr));
+        ENVP.push(ptr);
+        envp = envp.add(1);
+    }
+    ENVP.push(core::ptr::null());
+}
+
+/// argv, the first one is the path the program was started by
+pub fn args() -> &'static [&'static str] {
+    unsafe { ARGS.as_slice() }
+}
+
+/// Argument `n` parsed, `None` if it is missing or malformed
+pub fn arg<T: FromStr>(n: usize) -> Option<T> {
+    args().get(n)?.parse().ok()
+}
+
+/// `(key, value)` of every variable
+pub fn vars() -> impl Iterator<Item = (&'static str, &'static str)> {
+    unsafe { VARS.iter() }.filter_map(|var| var.split_once('='))
+}
+
+pub fn var(key: &str) -> Option<&'static str> {
+    vars().find(|(k, _)| *k == key).map(|(_, value)| value)
+}
+
+pub(crate) fn envp() -> *const *const u8 {
+    unsafe { ENVP.as_ptr() }
+}
@@ -0,0 +1,216 @@
+//! Heap profiler, built in with the `heap_profile` feature (`make build HEAP_PROFILE=1`).
+//!
+//! Every allocation is charged to its site, the call stack it was made from,
+//! and carries the site in a header so that frees are charged back. The
+//! profile is printed at exit, by `dump`, or when the task receives the
+//! `HEAP_PROFILE_DUMP` message, see the `heap_dump` app.
+
+use crate::backtrace;
+use buddy_system_allocator::LockedHeap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem::size_of;
+use spin::Mutex;
+
+pub const ENABLED: bool = cfg!(feature = "heap_profile");
+/// Taken by the trap dispatcher instead of handed to `soft_intr_handler`, when profiling
+pub const HEAP_PROFILE_DUMP: usize = 0x4845_4150_4455_4d50;
+
+const MAX_SITES: usize = 64;
+/// Return addresses telling sites apart, the innermost ones are in the allocator
+const SITE_FRAMES: usize = 6;
+
+#[derive(Clone, Copy)]
+struct Site {
+    frames: [usize; SITE_FRAMES],
+    allocs: usize,
+    bytes: usize,
+    live_blocks: usize,
+    live_bytes: usize,
+}
+
+const EMPTY_SITE: Site = Site {
+    frames: [0; SITE_FRAMES],
+    allocs: 0,
+    bytes: 0,
+    live_blocks: 0,
+    live_bytes: 0,
+};
+
+struct Profile {
+    live_bytes: usize,
+    peak_bytes: usize,
+    allocs: usize,
+    frees: usize,
+    sites: [Site; MAX_SITES],
+    site_num: usize,
+    /// Allocations made once the site table was full
+    unattributed: usize,
+}
+
+static PROFILE: Mutex<Profile> = Mutex::new(Profile {
+    live_bytes: 0,
+    peak_bytes: 0,
+    allocs: 0,
+    frees: 0,
+    sites: [EMPTY_SITE; MAX_SITES],
+    site_num: 0,
+    unattributed: 0,
+});
+
+impl Profile {
+    /// Index of the site plus one, 0 if the table is full
+    fn site_of(&mut self, frames: &[usize; SITE_FRAMES]) -> usize {
+        let sites = &mut self.sites[..self.site_num];
+        if let Some(idx) = sites.iter().position(|site| site.frames == *frames) {
+            return idx + 1;
+        }
+        if self.site_num == MAX_SITES {
+            self.unattributed += 1;
+            return 0;
+        }
+        self.sites[self.site_num].frames = *frames;
+        self.site_num += 1;
+        self.site_num
+    }
+
+    fn on_alloc(&mut self, frames: &[usize; SITE_FRAMES], size: usize) -> usize {
+        self.allocs += 1;
+        self.live_bytes += size;
+        self.peak_bytes = self.peak_bytes.max(self.live_bytes);
+        let site = self.site_of(frames);
+        if site != 0 {
+            let site = &mut self.sites[site - 1];
+            site.allocs += 1;
+            site.bytes += size;
+            site.live_blocks += 1;
+            site.live_bytes += size;
+        }
+        site
+    }
+
+    fn on_dealloc(&mut self, site: usize, size: usize) {
+        self.frees += 1;
+        self.live_bytes -= size;
+        if site != 0 {
+            let site = &mut self.sites[site - 1];
+            site.live_blocks -= 1;
+            site.live_bytes -= size;
+        }
+    }
+}
+
+/// Wraps the heap of user_lib, each block is preceded by its site
+pub struct ProfiledHeap(pub &'static LockedHeap);
+
+/// Room before the block for the site, keeping the block aligned
+fn header_size(layout: &Layout) -> usize {
+    layout.align().max(size_of::<usize>())
+}
+
+fn inner_layout(layout: &Layout) -> Layout {
+    Layout::from_size_align(
+        layout.size() + header_size(layout),
+        layout.align().max(size_of::<usize>()),
+    )
+    .unwrap()
+}
+
+unsafe impl GlobalAlloc for ProfiledHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let raw = self.0.alloc(inner_layout(&layout));
+        if raw.is_null() {
+            return raw;
+        }
+        let mut frames = [0; SITE_FRAMES];
+        let mut depth = 0;
+        backtrace::trace(|ra| {
+            if depth < SITE_FRAMES {
+                frames[depth] = ra;
+                depth += 1;
+            }
+        });
+        let site = PROFILE.lock().on_alloc(&frames, layout.size());
+        let ptr = raw.add(header_size(&layout));
+        (ptr as *mut usize).sub(1).write(site);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let site = (ptr as *mut usize).sub(1).read();
+        PROFILE.lock().on_dealloc(site, layout.size());
+        self.0
+            .dealloc(ptr.sub(header_size(&layout)), inner_layout(&layout));
+    }
+}
+
+/// Of the symbols of the frames which are not where the allocation comes from
+const ALLOCATOR_PREFIXES: [&str; 7] = [
+    "alloc::",
+    "<alloc::",
+    "core::",
+    "<core::",
+    "__rust",
+    "__rg",
+    "<user_lib::heap_profile",
+];
+
+/// The innermost frame of a site outside the allocator, with its symbol if there is a map
+fn print_site_origin(frames: &[usize; SITE_FRAMES]) {
+    let is_allocator = |name: &str| {
+        ALLOCATOR_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+    };
+    let origin = frames
+        .iter()
+        .take_while(|&&ra| ra != 0)
+        .map(|&ra| (ra, backtrace::resolve(ra - 1)))
+        .find(|(_, symbol)| symbol.as_ref().map_or(false, |s| !is_allocator(s.name)));
+    match origin {
+        Some((ra, Some(symbol))) => match symbol.location {
+            Some(location) => {
+                println!("    from {:#x} {} at {}", ra, symbol.name, location);
+            }
+            None => {
+                println!("    from {:#x} {}", ra, symbol.name);
+            }
+        },
+        // no symbol map, the raw stack has to do
+        _ => {
+            println!("    from {:x?}", frames);
+        }
+    }
+}
+
+/// Print the totals and every site, skipped if the profile is being updated,
+/// as when called from a trap handler interrupting an allocation
+pub fn dump() {
+    if !ENABLED {
+        println!("[heap profile] not built with the heap_profile feature");
+        return;
+    }
+    let profile = match PROFILE.try_lock() {
+        Some(profile) => profile,
+        None => {
+            println!("[heap profile] busy, try again");
+            return;
+        }
+    };
+    println!(
+        "[heap profile] live {} bytes, peak {} bytes, {} allocs, {} frees, {} unattributed",
+        profile.live_bytes, profile.peak_bytes, profile.allocs, profile.frees, profile.unattributed
+    );
+    for (idx, site) in profile.sites[..profile.site_num].iter().enumerate() {
+        println!(
+            "  site {}: {} allocs, {} bytes, live {} blocks, {} bytes",
+            idx, site.allocs, site.bytes, site.live_blocks, site.live_bytes
+        );
+        print_site_origin(&site.frames);
+    }
+}
+
+/// Live and peak bytes
+pub fn usage() -> (usize, usize) {
+    let profile = PROFILE.lock();
+    (profile.live_bytes, profile.peak_bytes)
+}
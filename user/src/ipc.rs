@@ -0,0 +1,261 @@
+//! Byte ring in `MAP_SHARED` memory between a task and its child.
+//!
+//! Data never goes through the kernel, UIPI messages on the `Channel` of the
+//! two tasks only wake a consumer waiting on an empty ring or a producer
+//! waiting on a full one. A task has one channel per peer, so it must not
+//! use another `Channel` or `Ring` with the same peer at the same time.
+
+use crate::ipi::{Channel, ChannelPair, IpiError};
+use crate::trap::PAGE_SIZE;
+use crate::{mmap, munmap, MAP_SHARED};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Content of the wakeup messages, which are only hints
+const WAKE: usize = 0;
+
+/// In the first page of the mapping, the data follows in the next ones
+#[repr(C)]
+struct Header {
+    /// Bytes read and written since the ring was made, it holds `tail - head`
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    consumer_waiting: AtomicBool,
+    producer_waiting: AtomicBool,
+    producer_closed: AtomicBool,
+    consumer_closed: AtomicBool,
+}
+
+/// The mapping, unmapped by each end
+struct RingMap {
+    base: usize,
+    capacity: usize,
+}
+
+impl RingMap {
+    fn header(&self) -> &Header {
+        unsafe { &*(self.base as *const Header) }
+    }
+
+    fn data(&self) -> *mut u8 {
+        (self.base + PAGE_SIZE) as *mut u8
+    }
+
+    fn len(&self) -> usize {
+        let header = self.header();
+        header
+            .tail
+            .load(Ordering::SeqCst)
+            .wrapping_sub(header.head.load(Ordering::SeqCst))
+    }
+
+    /// Copy between `pos` of the ring and `buf`, wrapping around its end
+    unsafe fn copy(&self, pos: usize, len: usize, mut f: impl FnMut(*mut u8, usize, usize)) {
+        let start = pos % self.capacity;
+        let first = len.min(self.capacity - start);
+        f(self.data().add(start), 0, first);
+        if first < len {
+            f(self.data(), first, len - first);
+        }
+    }
+}
+
+impl Drop for RingMap {
+    fn drop(&mut self) {
+        munmap(self.base, PAGE_SIZE + self.capacity);
+    }
+}
+
+/// The ring before `fork`, each side then takes its end
+pub struct RingPair {
+    map: RingMap,
+    channel: ChannelPair,
+}
+
+impl RingPair {
+    /// The producing end, `fork_ret` is what `fork` returned
+    pub fn producer(self, fork_ret: isize) -> Result<Producer, IpiError> {
+        Ok(Producer {
+            channel: self.channel.split(fork_ret)?,
+            map: self.map,
+        })
+    }
+
+    /// The consuming end, `fork_ret` is what `fork` returned
+    pub fn consumer(self, fork_ret: isize) -> Result<Consumer, IpiError> {
+        Ok(Consumer {
+            channel: self.channel.split(fork_ret)?,
+            map: self.map,
+        })
+    }
+}
+
+pub struct Ring;
+
+impl Ring {
+    /// Map a ring of at least `capacity` bytes, rounded up to whole pages, before `fork`
+    pub fn pair(capacity: usize) -> Result<RingPair, IpiError> {
+        let capacity = (capacity.max(1) + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+        let base = mmap(0, PAGE_SIZE + capacity, 0b11 | MAP_SHARED);
+        if base < 0 {
+            return Err(base.into());
+        }
+        // fresh frames are zeroed, which is an empty open ring
+        let map = RingMap {
+            base: base as usize,
+            capacity,
+        };
+        Ok(RingPair {
+            channel: Channel::pair()?,
+            map,
+        })
+    }
+}
+
+pub struct Producer {
+    map: RingMap,
+    channel: Channel,
+}
+
+impl Producer {
+    pub fn capacity(&self) -> usize {
+        self.map.capacity
+    }
+
+    /// Write as much of `bytes` as fits, return how much did
+    pub fn try_send(&mut self, bytes: &[u8]) -> Result<usize, IpiError> {
+        let header = self.map.header();
+        if header.consumer_closed.load(Ordering::SeqCst) {
+            return Err(IpiError::Closed);
+        }
+        let len = bytes.len().min(self.map.capacity - self.map.len());
+        if len == 0 {
+            return Ok(0);
+        }
+        let tail = header.tail.load(Ordering::SeqCst);
+        unsafe {
+            self.map.copy(tail, len, |dst, offset, n| {
+                dst.copy_from_nonoverlapping(bytes.as_ptr().add(offset), n)
+            });
+        }
+        header.tail.store(tail.wrapping_add(len), Ordering::SeqCst);
+        // the consumer only waits on an empty ring, this is its transition to non-empty
+        if header.consumer_waiting.swap(false, Ordering::SeqCst) {
+            let _ = self.channel.send(WAKE);
+        }
+        Ok(len)
+    }
+
+    /// Write all of `bytes`, waiting for room while the ring is full
+    pub fn send(&mut self, mut bytes: &[u8]) -> Result<(), IpiError> {
+        while !bytes.is_empty() {
+            let len = self.try_send(bytes)?;
+            bytes = &bytes[len..];
+            if len == 0 {
+                self.wait()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn wait(&mut self) -> Result<(), IpiError> {
+        let header = self.map.header();
+        header.producer_waiting.store(true, Ordering::SeqCst);
+        // the consumer may have made room before seeing the flag
+        if self.map.len() < self.map.capacity {
+            header.producer_waiting.store(false, Ordering::SeqCst);
+            return Ok(());
+        }
+        match self.channel.recv() {
+            Ok(_) => Ok(()),
+            Err(_) => {
+                header.consumer_closed.store(true, Ordering::SeqCst);
+                Err(IpiError::Closed)
+            }
+        }
+    }
+}
+
+impl Drop for Producer {
+    fn drop(&mut self) {
+        let header = self.map.header();
+        header.producer_closed.store(true, Ordering::SeqCst);
+        if header.consumer_waiting.swap(false, Ordering::SeqCst) {
+            let _ = self.channel.send(WAKE);
+        }
+    }
+}
+
+pub struct Consumer {
+    map: RingMap,
+    channel: Channel,
+}
+
+impl Consumer {
+    pub fn capacity(&self) -> usize {
+        self.map.capacity
+    }
+
+    /// Read what is in the ring into `buf`, return how much was read,
+    /// `Closed` once it is empty and the producer is gone
+    pub fn try_recv(&mut self, buf: &mut [u8]) -> Result<usize, IpiError> {
+        let header = self.map.header();
+        let len = buf.len().min(self.map.len());
+        if len == 0 {
+            return if header.producer_closed.load(Ordering::SeqCst) && self.map.len() == 0 {
+                Err(IpiError::Closed)
+            } else {
+                Err(IpiError::Empty)
+            };
+        }
+        let head = header.head.load(Ordering::SeqCst);
+        unsafe {
+            self.map.copy(head, len, |src, offset, n| {
+                src.copy_to_nonoverlapping(buf.as_mut_ptr().add(offset), n)
+            });
+        }
+        header.head.store(head.wrapping_add(len), Ordering::SeqCst);
+        // the producer only waits on a full ring, this is its transition to non-full
+        if header.producer_waiting.swap(false, Ordering::SeqCst) {
+            let _ = self.channel.send(WAKE);
+        }
+        Ok(len)
+    }
+
+    /// Read at least one byte into `buf`, waiting while the ring is empty
+    pub fn recv(&mut self, buf: &mut [u8]) -> Result<usize, IpiError> {
+        loop {
+            match self.try_recv(buf) {
+                Err(IpiError::Empty) if !buf.is_empty() => self.wait()?,
+                res => return res,
+            }
+        }
+    }
+
+    fn wait(&mut self) -> Result<(), IpiError> {
+        let header = self.map.header();
+        header.consumer_waiting.store(true, Ordering::SeqCst);
+        // the producer may have written or left before seeing the flag
+        if self.map.len() > 0 || header.producer_closed.load(Ordering::SeqCst) {
+            header.consumer_waiting.store(false, Ordering::SeqCst);
+            return Ok(());
+        }
+        match self.channel.recv() {
+            Ok(_) => Ok(()),
+            // the producer exited without its end being dropped, what it wrote is still there
+            Err(_) => {
+                header.producer_closed.store(true, Ordering::SeqCst);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Drop for Consumer {
+    fn drop(&mut self) {
+        let header = self.map.header();
+        header.consumer_closed.store(true, Ordering::SeqCst);
+        if header.producer_waiting.swap(false, Ordering::SeqCst) {
+            let _ = self.channel.send(WAKE);
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use crate::syscall::{
+    sys_getpid, sys_init_user_trap, sys_send_group_msg, sys_send_msg, sys_uipi_resolve,
+};
+use crate::trap::{dispatch_trap_record, UserTrapQueue, USER_TRAP_BUFFER};
+use crate::{task_info, yield_, TaskInfo};
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use riscv::register::uie;
+use spin::Mutex;
+
+/// Messages are identified by the pid of their sender
+pub type SenderId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpiError {
+    /// The receiver has not initialized its user trap
+    NotConnected,
+    NoSuchReceiver,
+    /// The trap buffer of the receiver is full
+    OutOfSlots,
+    KernelError(isize),
+    /// The other end of the channel was dropped, or its task is gone
+    Closed,
+    /// Nothing has been received on the channel
+    Empty,
+    /// `CHANNEL_CLOSED` cannot be sent on a channel
+    InvalidMessage,
+    Unsupported,
+}
+
+impl From<isize> for IpiError {
+    fn from(errno: isize) -> Self {
+        match errno {
+            -107 => IpiError::NotConnected,
+            -3 => IpiError::NoSuchReceiver,
+            -105 => IpiError::OutOfSlots,
+            _ => IpiError::KernelError(errno),
+        }
+    }
+}
+
+/// Send a message to the receiver, failing if it cannot be delivered
+pub fn send(receiver: usize, msg: usize) -> Result<(), IpiError> {
+    match sys_send_msg(receiver, msg) {
+        0 => Ok(()),
+        errno => Err(errno.into()),
+    }
+}
+
+/// Send a message to every member of the group, return the number of members reached
+pub fn send_group(group_id: usize, msg: usize) -> Result<usize, IpiError> {
+    match sys_send_group_msg(group_id, msg) {
+        n if n >= 0 => Ok(n as usize),
+        errno => Err(errno.into()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Message {
+    pub sender: SenderId,
+    pub msg: usize,
+}
+
+/// Drain all pending messages at once, return the number of messages written to `messages`.
+///
+/// Other trap records met on the way are dispatched to their handlers.
+/// Messages which do not fit are left in the queue for the next call.
+pub fn receive_all(messages: &mut [Message]) -> usize {
+    let trap_queue = unsafe { &mut *(USER_TRAP_BUFFER as *mut UserTrapQueue) };
+    // the queue has a single consumer, keep the trap handler away while draining
+    let is_usoft_enabled = uie::read().usoft();
+    unsafe {
+        uie::clear_usoft();
+    }
+    let mut count = 0;
+    while count < messages.len() {
+        match trap_queue.dequeue() {
+            Some(trap_record) if trap_record.cause & 0xF == 0 => {
+                messages[count] = Message {
+                    sender: trap_record.cause >> 4,
+                    msg: trap_record.message,
+                };
+                count += 1;
+            }
+            Some(trap_record) => dispatch_trap_record(trap_record),
+            None => break,
+        }
+    }
+    if is_usoft_enabled {
+        unsafe {
+            uie::set_usoft();
+        }
+    }
+    count
+}
+
+/// Receiving end of the messages of this task.
+///
+/// Listening state lives in the u-mode CSRs of the current thread of control,
+/// so a receiver is neither `Send` nor `Sync`.
+pub struct Receiver {
+    _not_send_sync: PhantomData<*const ()>,
+}
+
+impl Receiver {
+    /// The user trap of the task must have been initialized by `init_user_trap`
+    pub fn new() -> Self {
+        Self {
+            _not_send_sync: PhantomData,
+        }
+    }
+
+    /// Take messages by interrupts until the guard is dropped
+    pub fn listen_on_current_thread(&self) -> ListenGuard<'_> {
+        let was_listening = uie::read().usoft();
+        unsafe {
+            uie::set_usoft();
+        }
+        ListenGuard {
+            was_listening,
+            _receiver: PhantomData,
+        }
+    }
+
+    pub fn receive_all(&self, messages: &mut [Message]) -> usize {
+        receive_all(messages)
+    }
+}
+
+impl Default for Receiver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct ListenGuard<'a> {
+    was_listening: bool,
+    _receiver: PhantomData<&'a Receiver>,
+}
+
+impl Drop for ListenGuard<'_> {
+    fn drop(&mut self) {
+        if !self.was_listening {
+            unsafe {
+                uie::clear_usoft();
+            }
+        }
+    }
+}
+
+/// Sent by the end of a channel being dropped, never as a message on it
+pub const CHANNEL_CLOSED: usize = usize::MAX;
+const DRAIN_BATCH: usize = 8;
+
+/// Messages drained by a channel for the other channels of the task, oldest first
+static STASH: Mutex<Vec<Message>> = Mutex::new(Vec::new());
+
+fn take_stashed(sender: SenderId) -> Option<usize> {
+    let mut stash = STASH.lock();
+    let idx = stash.iter().position(|m| m.sender == sender)?;
+    Some(stash.remove(idx).msg)
+}
+
+/// Both ends of a channel between a task and its child, made before `fork`
+pub struct ChannelPair {
+    parent: SenderId,
+}
+
+impl ChannelPair {
+    /// The end of the calling side, `fork_ret` is what `fork` returned
+    pub fn split(self, fork_ret: isize) -> Result<Channel, IpiError> {
+        match fork_ret {
+            0 => Ok(Channel::open(self.parent)),
+            pid if pid > 0 => Ok(Channel::open(pid as usize)),
+            errno => Err(errno.into()),
+        }
+    }
+
+    /// A channel of the calling task with itself, which receives what it sends
+    pub fn local(self) -> Channel {
+        Channel::open(self.parent)
+    }
+}
+
+/// A bidirectional channel with one peer task.
+///
+/// Messages are told apart by their sender, so a task has one channel per peer.
+/// Channels receive by polling, messages taken by the trap handler of a task
+/// listening on `Receiver` do not reach them.
+pub struct Channel {
+    peer: SenderId,
+    is_closed: bool,
+    _not_send_sync: PhantomData<*const ()>,
+}
+
+impl Channel {
+    /// Make a channel before `fork`, both sides then take their end by `ChannelPair::split`
+    pub fn pair() -> Result<ChannelPair, IpiError> {
+        // set up before forking, the child inherits it and both ends can be sent to at once
+        let ret = sys_init_user_trap(core::ptr::null());
+        if ret < 0 {
+            return Err(ret.into());
+        }
+        Ok(ChannelPair {
+            parent: sys_getpid() as usize,
+        })
+    }
+
+    /// Connect to the task which bound `name` by `uipi_bind`
+    pub fn connect(name: &str) -> Result<Self, IpiError> {
+        match sys_uipi_resolve(name) {
+            -2 => Err(IpiError::NoSuchReceiver), // ENOENT
+            ret if ret < 0 => Err(ret.into()),
+            peer => Self::with_pid(peer as usize),
+        }
+    }
+
+    /// A channel with a task known by its pid, which has to reach this one by pid as well,
+    /// as a service and its clients do
+    pub fn with_pid(peer: SenderId) -> Result<Self, IpiError> {
+        let ret = sys_init_user_trap(core::ptr::null());
+        if ret < 0 {
+            return Err(ret.into());
+        }
+        Ok(Self::open(peer))
+    }
+
+    fn open(peer: SenderId) -> Self {
+        Self {
+            peer,
+            is_closed: false,
+            _not_send_sync: PhantomData,
+        }
+    }
+
+    pub fn peer(&self) -> SenderId {
+        self.peer
+    }
+
+    pub fn send(&self, msg: usize) -> Result<(), IpiError> {
+        if msg == CHANNEL_CLOSED {
+            return Err(IpiError::InvalidMessage);
+        }
+        if self.is_closed {
+            return Err(IpiError::Closed);
+        }
+        send(self.peer, msg)
+    }
+
+    pub fn try_recv(&mut self) -> Result<usize, IpiError> {
+        if self.is_closed {
+            return Err(IpiError::Closed);
+        }
+        let msg = match take_stashed(self.peer) {
+            Some(msg) => msg,
+            None => self.drain().ok_or(IpiError::Empty)?,
+        };
+        if msg == CHANNEL_CLOSED {
+            self.is_closed = true;
+            return Err(IpiError::Closed);
+        }
+        Ok(msg)
+    }
+
+    /// Drain the queue up to the first message from the peer, stashing the others
+    fn drain(&self) -> Option<usize> {
+        let mut messages = [Message::default(); DRAIN_BATCH];
+        loop {
+            // one at a time once a message of the peer may be next, the rest stays queued
+            let count = receive_all(&mut messages);
+            let mut found = None;
+            let mut stash = STASH.lock();
+            for message in &messages[..count] {
+                if found.is_none() && message.sender == self.peer {
+                    found = Some(message.msg);
+                } else {
+                    stash.push(*message);
+                }
+            }
+            if found.is_some() || count < DRAIN_BATCH {
+                return found;
+            }
+        }
+    }
+
+    /// Wait for a message, yielding meanwhile
+    pub fn recv(&mut self) -> Result<usize, IpiError> {
+        loop {
+            match self.try_recv() {
+                Err(IpiError::Empty) => {
+                    // a peer which exited cannot close its end
+                    if task_info(self.peer, &mut TaskInfo::default()) < 0 {
+                        self.is_closed = true;
+                        return Err(IpiError::Closed);
+                    }
+                    yield_();
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        STASH.lock().retain(|m| m.sender != self.peer);
+        if !self.is_closed && self.peer != sys_getpid() as usize {
+            let _ = send(self.peer, CHANNEL_CLOSED);
+        }
+    }
+}
@@ -13,5 +13,6 @@ fn panic_handler(panic_info: &core::panic::PanicInfo) -> ! {
     } else {
         println!("Panicked: {}", err);
     }
+    crate::backtrace::print_backtrace();
     exit(-1);
 }
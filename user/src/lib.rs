@@ -5,6 +5,14 @@
 
 #[macro_use]
 pub mod console;
+pub mod backtrace;
+pub mod bench;
+pub mod bus;
+pub mod deferred;
+pub mod env;
+pub mod heap_profile;
+pub mod ipc;
+pub mod ipi;
 mod lang_items;
 mod syscall;
 pub mod trap;
@@ -14,27 +22,58 @@ extern crate alloc;
 #[macro_use]
 extern crate bitflags;
 
+use alloc::string::String;
 use alloc::vec::Vec;
 use buddy_system_allocator::LockedHeap;
 use syscall::*;
 
-pub use trap::{UserTrapContext, UserTrapQueue, UserTrapRecord};
+pub use deferred::{defer, defer_wake, run_deferred};
+pub use trap::{
+    set_trap_nesting, trap_nesting_depth, NestedTrapGuard, TrapNesting, TrapPriority,
+    UserTrapContext, UserTrapDescriptor, UserTrapQueue, UserTrapRecord, USER_TRAP_BUFFER,
+    USER_TRAP_REENTRANT,
+};
 
 const USER_HEAP_SIZE: usize = 32768;
 
+/// Syscalls fail with `-errno` from `-MAX_ERRNO` to -1 like on Linux, any other return is a
+/// value, which may be an address not fitting in an `isize`
+pub const MAX_ERRNO: usize = 4095;
+
+/// Tell the value of a syscall return from its errno
+pub fn syscall_result(ret: isize) -> Result<usize, usize> {
+    if (-(MAX_ERRNO as isize)..0).contains(&ret) {
+        Err((-ret) as usize)
+    } else {
+        Ok(ret as usize)
+    }
+}
+
 static mut HEAP_SPACE: [u8; USER_HEAP_SIZE] = [0; USER_HEAP_SIZE];
 
-#[global_allocator]
+#[cfg_attr(not(feature = "heap_profile"), global_allocator)]
 static HEAP: LockedHeap = LockedHeap::empty();
 
+#[cfg(feature = "heap_profile")]
+#[global_allocator]
+static PROFILED_HEAP: heap_profile::ProfiledHeap = heap_profile::ProfiledHeap(&HEAP);
+
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
     panic!("Heap allocation error, layout = {:?}", layout);
 }
 
+// crt0: the kernel leaves argc, argv and envp on the stack
+core::arch::global_asm!(
+    ".section .text.entry",
+    ".globl _start",
+    "_start:",
+    "    mv a0, sp",
+    "    call __start_main",
+);
+
 #[no_mangle]
-#[link_section = ".text.entry"]
-pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
+extern "C" fn __start_main(sp: *const usize) -> ! {
     use riscv::register::{mtvec::TrapMode, utvec};
 
     extern "C" {
@@ -47,22 +86,10 @@ pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
     unsafe {
         HEAP.lock()
             .init(HEAP_SPACE.as_ptr() as usize, USER_HEAP_SIZE);
+        env::init(sp);
     }
-    let mut v: Vec<&'static str> = Vec::new();
-    for i in 0..argc {
-        let str_start =
-            unsafe { ((argv + i * core::mem::size_of::<usize>()) as *const usize).read_volatile() };
-        let len = (0usize..)
-            .find(|i| unsafe { ((str_start + *i) as *const u8).read_volatile() == 0 })
-            .unwrap();
-        v.push(
-            core::str::from_utf8(unsafe {
-                core::slice::from_raw_parts(str_start as *const u8, len)
-            })
-            .unwrap(),
-        );
-    }
-    exit(main(argc, v.as_slice()));
+    let args = env::args();
+    exit(main(args.len(), args));
 }
 
 #[linkage = "weak"]
@@ -78,9 +105,18 @@ bitflags! {
         const RDWR = 1 << 1;
         const CREATE = 1 << 9;
         const TRUNC = 1 << 10;
+        /// Close the fd on a successful `exec`, and keep it from spawned processes
+        const CLOEXEC = 1 << 19;
     }
 }
 
+/// `fcntl` command: get the fd flags
+pub const F_GETFD: usize = 1;
+/// `fcntl` command: set the fd flags to `arg`
+pub const F_SETFD: usize = 2;
+/// fd flag, see `OpenFlags::CLOEXEC`
+pub const FD_CLOEXEC: usize = 1;
+
 pub fn dup(fd: usize) -> isize {
     sys_dup(fd)
 }
@@ -93,7 +129,17 @@ pub fn close(fd: usize) -> isize {
 }
 
 pub fn pipe(pipe_fd: &mut [usize]) -> isize {
-    sys_pipe(pipe_fd)
+    sys_pipe(pipe_fd, 0)
+}
+
+/// `pipe` taking `OpenFlags::CLOEXEC` for both ends
+pub fn pipe2(pipe_fd: &mut [usize], flags: OpenFlags) -> isize {
+    sys_pipe(pipe_fd, flags.bits)
+}
+
+/// Only `F_GETFD` and `F_SETFD`
+pub fn fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    sys_fcntl(fd, cmd, arg)
 }
 
 pub fn read(fd: usize, buf: &mut [u8]) -> isize {
@@ -103,7 +149,66 @@ pub fn read(fd: usize, buf: &mut [u8]) -> isize {
 pub fn write(fd: usize, buf: &[u8]) -> isize {
     sys_write(fd, buf)
 }
+
+/// One fragment of `readv` and `writev`, `struct iovec` of Linux
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct IoVec {
+    pub base: usize,
+    pub len: usize,
+}
+
+/// Read into `bufs` in turn with one syscall, return the total read.
+/// Like `read`, a short read ends it.
+pub fn readv(fd: usize, bufs: &mut [&mut [u8]]) -> isize {
+    let iov: Vec<IoVec> = bufs
+        .iter_mut()
+        .map(|buf| IoVec {
+            base: buf.as_mut_ptr() as usize,
+            len: buf.len(),
+        })
+        .collect();
+    sys_readv(fd, &iov)
+}
+
+/// Write `bufs` in turn with one syscall, return the total written
+pub fn writev(fd: usize, bufs: &[&[u8]]) -> isize {
+    let iov: Vec<IoVec> = bufs
+        .iter()
+        .map(|buf| IoVec {
+            base: buf.as_ptr() as usize,
+            len: buf.len(),
+        })
+        .collect();
+    sys_writev(fd, &iov)
+}
+
+/// Move up to `count` bytes from `in_fd` to `out_fd` in the kernel, return how many.
+/// Like `read`, it ends at a short read of `in_fd`.
+pub fn sendfile(out_fd: usize, in_fd: usize, count: usize) -> isize {
+    sys_sendfile(out_fd, in_fd, count)
+}
+
+/// `sendfile` with the arguments of `splice`, files have no offsets to give
+pub fn splice(fd_in: usize, fd_out: usize, len: usize) -> isize {
+    sys_splice(fd_in, fd_out, len)
+}
+
+/// Pass the file of `fd` to whoever reads the pipe `pipe_fd`, after the bytes written so far
+pub fn send_fd(pipe_fd: usize, fd: usize) -> isize {
+    sys_send_fd(pipe_fd, fd)
+}
+
+/// Receive the file passed next through the pipe `pipe_fd` as a new fd. It fails with
+/// -42 (`ENOMSG`) until the bytes before it are read, and a read stops at it.
+pub fn recv_fd(pipe_fd: usize) -> isize {
+    sys_recv_fd(pipe_fd)
+}
+
 pub fn exit(exit_code: i32) -> ! {
+    if heap_profile::ENABLED {
+        heap_profile::dump();
+    }
     sys_exit(exit_code);
 }
 pub fn yield_() -> isize {
@@ -122,6 +227,26 @@ impl TimeVal {
     }
 }
 
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+
+pub fn clock_gettime(clock_id: usize, tp: &mut TimeSpec) -> isize {
+    sys_clock_gettime(clock_id, tp)
+}
+
+/// Set the wall clock, only allowed for privileged tasks, -1 (EPERM) for the others.
+/// -22 (EINVAL) if `usec` is not below a second
+pub fn settimeofday(time: &TimeVal) -> isize {
+    sys_settimeofday(time, 0)
+}
+
 pub fn get_time() -> isize {
     let time = TimeVal::new();
     match sys_get_time(&time, 0) {
@@ -144,19 +269,43 @@ pub fn getpid() -> isize {
 pub fn fork() -> isize {
     sys_fork()
 }
+/// `args` is null-terminated, the environment is passed on
 pub fn exec(path: &str, args: &[*const u8]) -> isize {
-    sys_exec(path, args)
+    sys_exec(path, args, env::envp())
+}
+/// `args` and `envs` are null-terminated, `envs` are `KEY=value` strings
+pub fn exec_with_env(path: &str, args: &[*const u8], envs: &[*const u8]) -> isize {
+    sys_exec(path, args, envs.as_ptr())
 }
 pub fn spawn(path: &str) -> isize {
-    sys_spawn(path)
+    sys_spawn(path, 0, 0)
+}
+/// `spawn_with_flags` flag: randomize the stack top and mmap base of the new process
+pub const SPAWN_RANDOMIZE: usize = 1;
+/// `spawn_with_flags` flag: give the new process its own pid namespace, in which it is pid 1
+/// and only sees its own descendants. It gets the standard fds of the first process instead
+/// of those of the caller.
+pub const SPAWN_NEW_PID_NS: usize = 2;
+/// `spawn_with_flags` flag, set by `spawn_with_events`
+pub const SPAWN_NOTIFY_PARENT: usize = 4;
+pub fn spawn_with_flags(path: &str, flags: usize) -> isize {
+    sys_spawn(path, flags & !SPAWN_NOTIFY_PARENT, 0)
+}
+/// Like `spawn_with_flags`, and the kernel writes a line `<pid> <event> <arg>` to `events_fd`,
+/// usually the write end of a pipe, for each UIPI action of the child:
+/// `trap_init`, `trap_release`, `group_join <group>`, `group_leave <group>`,
+/// `device_claim <device>` and at last `exit <UIPI objects it still held>`.
+/// Events which do not fit in the pipe are dropped, and counted by a line `dropped <count>`.
+pub fn spawn_with_events(path: &str, flags: usize, events_fd: usize) -> isize {
+    sys_spawn(path, flags | SPAWN_NOTIFY_PARENT, events_fd)
 }
 pub fn wait(exit_code: &mut i32) -> isize {
     loop {
-        match sys_waitpid(-1, exit_code as *mut _) {
-            -2 => {
+        match sys_waitpid(-1, exit_code as *mut _, 0) {
+            EAGAIN => {
                 yield_();
             }
-            // -1 or a real pid
+            // ECHILD or a real pid
             exit_pid => return exit_pid,
         }
     }
@@ -164,15 +313,107 @@ pub fn wait(exit_code: &mut i32) -> isize {
 
 pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
     loop {
-        match sys_waitpid(pid as isize, exit_code as *mut _) {
-            -2 => {
+        match sys_waitpid(pid as isize, exit_code as *mut _, 0) {
+            EAGAIN => {
+                yield_();
+            }
+            // ECHILD or a real pid
+            exit_pid => return exit_pid,
+        }
+    }
+}
+/// With `WUNTRACED`, also returns when the child stops, `exit_code` is then `(SIGSTOP << 8) | 0x7f`
+pub fn waitpid_with_options(pid: usize, exit_code: &mut i32, options: usize) -> isize {
+    loop {
+        match sys_waitpid(pid as isize, exit_code as *mut _, options) {
+            EAGAIN => {
                 yield_();
             }
-            // -1 or a real pid
+            // ECHILD or a real pid
             exit_pid => return exit_pid,
         }
     }
 }
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const WUNTRACED: usize = 2;
+/// The status is an `ExitStatus` instead of an exit code
+pub const WEXITSTATUS_EXT: usize = 4;
+
+pub const EXIT_REASON_EXITED: u32 = 0;
+pub const EXIT_REASON_PAGE_FAULT: u32 = 1;
+pub const EXIT_REASON_ILLEGAL_INSTRUCTION: u32 = 2;
+pub const EXIT_REASON_USER_DOUBLE_FAULT: u32 = 3;
+pub const EXIT_REASON_OUT_OF_MEMORY: u32 = 4;
+pub const EXIT_REASON_STOPPED: u32 = 5;
+pub const EXIT_REASON_UIPI_FAULT: u32 = 6;
+pub const EXIT_REASON_USER_TRAP_TIMEOUT: u32 = 7;
+pub const EXIT_REASON_BAD_RETURN_CONTEXT: u32 = 8;
+pub const EXIT_REASON_SERVICE_ABORT: u32 = 9;
+pub const EXIT_REASON_BREAKPOINT: u32 = 10;
+
+/// How a child ended, filled by `waitpid_status`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ExitStatus {
+    /// Given to `exit`, or made up by the kernel when the child was killed
+    pub code: i32,
+    /// One of `EXIT_REASON_*`
+    pub reason: u32,
+}
+
+impl ExitStatus {
+    pub fn reason_name(&self) -> &'static str {
+        match self.reason {
+            EXIT_REASON_EXITED => "exited",
+            EXIT_REASON_PAGE_FAULT => "page fault",
+            EXIT_REASON_ILLEGAL_INSTRUCTION => "illegal instruction",
+            EXIT_REASON_USER_DOUBLE_FAULT => "user double fault",
+            EXIT_REASON_OUT_OF_MEMORY => "out of memory",
+            EXIT_REASON_STOPPED => "stopped",
+            EXIT_REASON_UIPI_FAULT => "uipi fault",
+            EXIT_REASON_USER_TRAP_TIMEOUT => "user trap timeout",
+            EXIT_REASON_BAD_RETURN_CONTEXT => "bad return context",
+            EXIT_REASON_SERVICE_ABORT => "service abort",
+            EXIT_REASON_BREAKPOINT => "breakpoint",
+            _ => "unknown",
+        }
+    }
+}
+
+/// Map anonymous memory, at the first free address if `start` is 0, which is then returned.
+/// A request which would leave the kernel short of memory fails with -12 (ENOMEM).
+/// With `MAP_SHARED` in `prot`, children forked afterwards share the memory instead of a copy.
+pub fn mmap(start: usize, len: usize, prot: usize) -> isize {
+    sys_mmap(start, len, prot)
+}
+
+pub const MAP_SHARED: usize = 1 << 3;
+
+pub fn munmap(start: usize, len: usize) -> isize {
+    sys_munmap(start, len)
+}
+
+/// Like `waitpid_with_options`, telling a clean exit from a kill by the kernel
+pub fn waitpid_status(pid: usize, status: &mut ExitStatus, options: usize) -> isize {
+    loop {
+        match sys_waitpid(
+            pid as isize,
+            status as *mut ExitStatus as *mut i32,
+            options | WEXITSTATUS_EXT,
+        ) {
+            EAGAIN => {
+                yield_();
+            }
+            // ECHILD or a real pid
+            exit_pid => return exit_pid,
+        }
+    }
+}
+
+pub fn kill(pid: usize, signal: usize) -> isize {
+    sys_kill(pid, signal)
+}
 pub fn sleep(period_ms: usize) {
     let start = get_time();
     while get_time() < start + period_ms as isize {
@@ -189,7 +430,17 @@ pub fn mailwrite(pid: usize, buf: &[u8]) -> isize {
 }
 
 pub fn init_user_trap() -> isize {
-    sys_init_user_trap()
+    sys_init_user_trap(core::ptr::null())
+}
+
+/// Like `init_user_trap`, but with the entry and trap stack given in `descriptor`,
+/// a `USER_TRAP_REENTRANT` descriptor lets higher priority traps nest by default
+pub fn init_user_trap_with(descriptor: &UserTrapDescriptor) -> isize {
+    let ret = sys_init_user_trap(descriptor);
+    if ret >= 0 && descriptor.flags & USER_TRAP_REENTRANT != 0 {
+        set_trap_nesting(TrapNesting::MaskLower);
+    }
+    ret
 }
 
 pub fn send_msg(pid: usize, msg: usize) -> isize {
@@ -200,6 +451,8 @@ pub fn set_timer(time_us: isize) -> isize {
     sys_set_timer(time_us)
 }
 
+/// Take over the interrupts of a device, -19 (ENODEV) if there is no device of its own behind
+/// them, -16 (EBUSY) if they are claimed by another task or shared with other devices
 pub fn claim_ext_int(device_id: usize) -> isize {
     sys_claim_ext_int(device_id)
 }
@@ -207,3 +460,682 @@ pub fn claim_ext_int(device_id: usize) -> isize {
 pub fn set_ext_int_enable(device_id: usize, enable: usize) -> isize {
     sys_set_ext_int_enable(device_id, enable)
 }
+
+/// Map registers of a claimed device, prot can only be R | W.
+/// Mapping the same region again with the same prot shares it and returns its length,
+/// a region overlapping another area is refused with `EEXIST`.
+pub fn mmio_map(start: usize, len: usize, prot: usize) -> isize {
+    sys_mmio_map(start, len, prot)
+}
+
+/// Returned by `mmio_map` when the region overlaps another area
+pub const EEXIST: isize = -17;
+
+pub const USER_TRAP_CTL_SET_COALESCE: usize = 0;
+pub const USER_TRAP_CTL_SET_SEND_RATE: usize = 1;
+pub const USER_TRAP_CTL_RELEASE: usize = 2;
+pub const USER_TRAP_CTL_SET_HANDLER_BUDGET: usize = 3;
+/// Returned by `send_msg` when the send quota is exhausted
+pub const EAGAIN: isize = -11;
+/// Returned by `send_msg` when the receiver is gone
+pub const ESRCH: isize = -3;
+/// Returned by `send_msg` when the trap queue of the receiver is full
+pub const ENOBUFS: isize = -105;
+/// Returned by `send_msg` when the receiver broke its trap queue and is being killed
+pub const EPIPE: isize = -32;
+/// Returned by `send_msg` when the receiver has no user traps, and by `release_user_trap`
+/// when there is nothing to release
+pub const ENOTCONN: isize = -107;
+
+/// For test programs: whether a syscall of `test` returned `expected`, printing both if not
+pub fn check_ret(test: &str, what: &str, ret: isize, expected: isize) -> bool {
+    if ret != expected {
+        println!("[{}] {}: got {}, expected {}", test, what, ret, expected);
+    }
+    ret == expected
+}
+
+/// Raise at most `max_per_slice` user soft interrupts per time slice (0 for unlimited),
+/// and stop raising them once `poll_threshold` records are queued (0 for never),
+/// leaving the records to `trap::poll_user_trap` until the queue is drained
+pub fn set_user_trap_coalesce(max_per_slice: usize, poll_threshold: usize) -> isize {
+    sys_user_trap_ctl(USER_TRAP_CTL_SET_COALESCE, max_per_slice, poll_threshold)
+}
+
+/// Limit `send_msg` of this task to `rate` messages per second with bursts up to `burst`,
+/// `rate` 0 for unlimited
+pub fn set_send_rate(rate: usize, burst: usize) -> isize {
+    sys_user_trap_ctl(USER_TRAP_CTL_SET_SEND_RATE, rate, burst)
+}
+
+/// Undo `init_user_trap`: claimed devices go back to the kernel, device mappings and the
+/// trap buffer are unmapped and queued records are dropped. It may be initialized again.
+pub fn release_user_trap() -> isize {
+    sys_user_trap_ctl(USER_TRAP_CTL_RELEASE, 0, 0)
+}
+
+/// Give a user trap handler `budget_us` (0 for no limit, the default). Past it the kernel
+/// kills the task with `EXIT_REASON_USER_TRAP_TIMEOUT`, or with `force_return` makes the
+/// handler return from the trap, dropping what it was doing.
+/// Budgets are checked at timer ticks, so they are only as precise as a time slice.
+pub fn set_user_trap_handler_budget(budget_us: usize, force_return: bool) -> isize {
+    let abort_entry = if force_return {
+        match trap::handler_abort_entry() {
+            0 => return -22, // EINVAL
+            entry => entry,
+        }
+    } else {
+        0
+    };
+    sys_user_trap_ctl(USER_TRAP_CTL_SET_HANDLER_BUDGET, budget_us, abort_entry)
+}
+
+const MSG_GROUP_JOIN: usize = 0;
+const MSG_GROUP_LEAVE: usize = 1;
+
+/// `pid` must be the caller or a task it controls, with user traps, else -1 (EPERM)
+/// or -22 (EINVAL)
+pub fn join_msg_group(group_id: usize, pid: usize) -> isize {
+    sys_msg_group_ctl(MSG_GROUP_JOIN, group_id, pid)
+}
+
+pub fn leave_msg_group(group_id: usize, pid: usize) -> isize {
+    sys_msg_group_ctl(MSG_GROUP_LEAVE, group_id, pid)
+}
+
+/// Make the user traps of this task reachable by `name`, like `service.name`, for the
+/// tasks of its pid namespace, until it unbinds it, releases its user traps or exits
+pub fn uipi_bind(name: &str) -> isize {
+    sys_uipi_bind(name)
+}
+
+pub fn uipi_unbind(name: &str) -> isize {
+    sys_uipi_unbind(name)
+}
+
+/// Return the pid bound to `name`, -2 if there is none
+pub fn uipi_resolve(name: &str) -> isize {
+    sys_uipi_resolve(name)
+}
+
+/// Send a message to every member of the group, return the number of members reached
+pub fn send_group_msg(group_id: usize, msg: usize) -> isize {
+    sys_send_group_msg(group_id, msg)
+}
+
+/// Allocate a physically contiguous buffer for a claimed device,
+/// return its virtual address and store its physical address in `paddr`
+pub fn dma_alloc(len: usize, paddr: &mut usize) -> isize {
+    sys_dma_alloc(len, paddr as *mut usize)
+}
+
+/// A mapped area reported by `vm_info`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VmAreaInfo {
+    pub start: usize,
+    pub end: usize,
+    /// `VM_PERM_*` bits
+    pub perm: usize,
+    /// One of `VM_KIND_*`
+    pub kind: usize,
+    /// Number of `mmio_map` sharing an mmio area, 1 for other areas
+    pub refs: usize,
+}
+
+pub const VM_PERM_R: usize = 1 << 1;
+pub const VM_PERM_W: usize = 1 << 2;
+pub const VM_PERM_X: usize = 1 << 3;
+pub const VM_PERM_U: usize = 1 << 4;
+
+pub const VM_KIND_KERNEL: usize = 0;
+pub const VM_KIND_ELF: usize = 1;
+pub const VM_KIND_STACK: usize = 2;
+pub const VM_KIND_TRAP_CONTEXT: usize = 3;
+pub const VM_KIND_MMAP: usize = 4;
+pub const VM_KIND_MMIO: usize = 5;
+pub const VM_KIND_SHARED: usize = 6;
+
+/// Fill `areas` with the mapped areas of this process sorted by address,
+/// return the total number of areas, which may exceed `areas.len()`
+pub fn vm_info(areas: &mut [VmAreaInfo]) -> isize {
+    let buf = unsafe {
+        core::slice::from_raw_parts_mut(
+            areas.as_mut_ptr() as *mut u8,
+            areas.len() * core::mem::size_of::<VmAreaInfo>(),
+        )
+    };
+    sys_vm_info(buf)
+}
+
+/// What the kernel found walking the page table for an address, filled by `debug_translate`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PteInfo {
+    /// 0 if the address is not mapped
+    pub paddr: usize,
+    /// `PTE_*` bits of the last entry reached
+    pub flags: usize,
+    /// Level of that entry, 0 for the root table and 2 for a 4 KiB page
+    pub level: usize,
+}
+
+pub const PTE_V: usize = 1 << 0;
+pub const PTE_R: usize = 1 << 1;
+pub const PTE_W: usize = 1 << 2;
+pub const PTE_X: usize = 1 << 3;
+pub const PTE_U: usize = 1 << 4;
+pub const PTE_G: usize = 1 << 5;
+pub const PTE_A: usize = 1 << 6;
+pub const PTE_D: usize = 1 << 7;
+/// `debug_translate` flag: print the whole page table of this process to the console
+pub const DEBUG_TRANSLATE_DUMP: usize = 1;
+
+/// Translate `vaddr` in the address space of this process,
+/// return 0 if it is mapped and -2 if not, `info` is filled either way
+pub fn debug_translate(vaddr: usize, info: &mut PteInfo, flags: usize) -> isize {
+    let buf = unsafe {
+        core::slice::from_raw_parts_mut(
+            info as *mut PteInfo as *mut u8,
+            core::mem::size_of::<PteInfo>(),
+        )
+    };
+    sys_debug_translate(vaddr, buf, flags)
+}
+
+pub const TRACE_CLASS_PROCESS: usize = 1 << 0;
+pub const TRACE_CLASS_FS: usize = 1 << 1;
+pub const TRACE_CLASS_MEMORY: usize = 1 << 2;
+pub const TRACE_CLASS_TIME: usize = 1 << 3;
+pub const TRACE_CLASS_USER_TRAP: usize = 1 << 4;
+pub const TRACE_CLASS_ALL: usize = (1 << 5) - 1;
+/// `trace_ctl` fd which sends the trace to the kernel log
+pub const TRACE_TO_LOG: usize = usize::MAX;
+
+/// Trace the syscalls of this process or a child whose class is in `mask`,
+/// one line per syscall is written to `fd`, `mask` 0 stops tracing
+pub fn trace_ctl(pid: usize, mask: usize, fd: usize) -> isize {
+    sys_trace_ctl(pid, mask, fd)
+}
+
+pub const PTRACE_PEEKDATA: usize = 2;
+pub const PTRACE_POKEDATA: usize = 5;
+pub const PTRACE_CONT: usize = 7;
+pub const PTRACE_SINGLESTEP: usize = 9;
+pub const PTRACE_GETREGS: usize = 12;
+pub const PTRACE_SETREGS: usize = 13;
+pub const PTRACE_ATTACH: usize = 16;
+pub const PTRACE_DETACH: usize = 17;
+
+/// Registers of a stopped tracee
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PtraceRegs {
+    pub x: [usize; 32],
+    pub pc: usize,
+}
+
+impl Default for PtraceRegs {
+    fn default() -> Self {
+        Self { x: [0; 32], pc: 0 }
+    }
+}
+
+/// A word of tracee memory for `PTRACE_PEEKDATA` and `PTRACE_POKEDATA`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PtraceWord {
+    pub addr: usize,
+    pub data: usize,
+}
+
+/// `arg` points to a `PtraceRegs` or `PtraceWord` depending on `request`.
+/// Registers and memory are only accessible while the tracee is stopped,
+/// which `waitpid_with_options(pid, _, WUNTRACED)` waits for.
+pub fn ptrace(request: usize, pid: usize, arg: usize) -> isize {
+    sys_ptrace(request, pid, arg)
+}
+
+/// Upper bounds (us) of the dispatch latency buckets of `SchedStats`,
+/// the last bucket takes the rest
+pub const SCHED_LATENCY_BUCKET_BOUNDS_US: [usize; 5] = [10, 100, 1000, 10_000, 100_000];
+
+/// Scheduler statistics of a hart since boot
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SchedStats {
+    pub switch_count: usize,
+    /// Run queue length sampled on every dispatch
+    pub run_queue_samples: usize,
+    pub run_queue_len_sum: usize,
+    pub run_queue_len_max: usize,
+    /// Time from becoming ready to running
+    pub latency_hist: [usize; 6],
+    pub latency_sum_us: usize,
+    /// One of `HART_IDLE`, `HART_IN_USER`, `HART_IN_KERNEL` and `HART_IN_TRAP`
+    pub state: usize,
+}
+
+pub const HART_IDLE: usize = 0;
+pub const HART_IN_USER: usize = 1;
+pub const HART_IN_KERNEL: usize = 2;
+pub const HART_IN_TRAP: usize = 3;
+
+/// Only run task `pid`, 0 for the caller, on the harts of `mask`, one bit per hart id.
+/// Return -22 (EINVAL) if none of them runs tasks, -1 (EPERM) if `pid` is not the caller
+/// or one of its descendants.
+pub fn sched_setaffinity(pid: usize, mask: usize) -> isize {
+    sys_sched_setaffinity(pid, &mask)
+}
+
+/// The harts task `pid`, 0 for the caller, may run on, or an error
+pub fn sched_getaffinity(pid: usize) -> Result<usize, isize> {
+    let mut mask = 0;
+    match sys_sched_getaffinity(pid, &mut mask) {
+        ret if ret < 0 => Err(ret),
+        _ => Ok(mask),
+    }
+}
+
+/// Return -22 (EINVAL) if there is no such hart
+pub fn sched_stats(hart_id: usize, stats: &mut SchedStats) -> isize {
+    let buf = unsafe {
+        core::slice::from_raw_parts_mut(
+            stats as *mut SchedStats as *mut u8,
+            core::mem::size_of::<SchedStats>(),
+        )
+    };
+    sys_sched_stats(hart_id, buf)
+}
+
+/// CPU time accounting of a task, times are in microseconds
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TaskInfo {
+    pub pid: usize,
+    pub utime_us: usize,
+    pub stime_us: usize,
+    pub irqtime_us: usize,
+    /// Sums over the children reaped by `waitpid`
+    pub children_utime_us: usize,
+    pub children_stime_us: usize,
+    pub children_irqtime_us: usize,
+    pub time_intr_count: usize,
+    pub cpu_cycle_count: usize,
+    /// On the clock of `CLOCK_MONOTONIC`: when the task was created by fork or spawn, when
+    /// it first ran, and when the kernel started its first task. Exec keeps them.
+    pub start_us: usize,
+    pub first_run_us: usize,
+    pub boot_us: usize,
+}
+
+/// `pid` 0 is the caller, return -3 (ESRCH) if there is no such task
+pub fn task_info(pid: usize, info: &mut TaskInfo) -> isize {
+    let buf = unsafe {
+        core::slice::from_raw_parts_mut(
+            info as *mut TaskInfo as *mut u8,
+            core::mem::size_of::<TaskInfo>(),
+        )
+    };
+    sys_task_info(pid, buf)
+}
+
+/// Resource usage of a child, filled by `wait4`, times are in microseconds
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Rusage {
+    pub utime_us: usize,
+    pub stime_us: usize,
+    pub irqtime_us: usize,
+    pub max_rss_kb: usize,
+    /// Switches out by yielding or blocking
+    pub nvcsw: usize,
+    /// Switches out on the timer
+    pub nivcsw: usize,
+    pub uipi_sent: usize,
+    pub uipi_received: usize,
+    /// Bytes read from and written to pipes
+    pub pipe_bytes: usize,
+    /// Pipe reads and writes which waited for the other end
+    pub pipe_waits: usize,
+}
+
+/// Like `waitpid_with_options`, also filling `rusage` with the usage of the child
+/// and of the children it reaped
+pub fn wait4(pid: usize, exit_code: &mut i32, options: usize, rusage: &mut Rusage) -> isize {
+    loop {
+        match sys_wait4(pid as isize, exit_code as *mut _, options, rusage as *mut _) {
+            EAGAIN => {
+                yield_();
+            }
+            // ECHILD or a real pid
+            exit_pid => return exit_pid,
+        }
+    }
+}
+
+/// Filled by `times`, times are in microseconds and include interrupt time
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Tms {
+    pub utime_us: usize,
+    pub stime_us: usize,
+    /// Sums over the children reaped by `waitpid`
+    pub children_utime_us: usize,
+    pub children_stime_us: usize,
+}
+
+/// Return the microseconds since boot, or -14 (EFAULT)
+pub fn times(tms: &mut Tms) -> isize {
+    sys_times(tms)
+}
+
+/// Make the kernel deliver a user soft interrupt from task `sender_pid` to task
+/// `receiver_pid`, which the caller must control. Return -3 (ESRCH) if either task does not
+/// exist, and -38 (ENOSYS) unless the kernel is built with the `uipi_inject` feature
+pub fn uipi_inject(receiver_pid: usize, sender_pid: usize) -> isize {
+    sys_uipi_inject(receiver_pid, sender_pid)
+}
+
+pub const CPU_GROUP_SET_QUOTA: usize = 0;
+pub const CPU_GROUP_SET_PERIOD: usize = 1;
+pub const CPU_GROUP_ATTACH: usize = 2;
+pub const CPU_GROUP_STATS: usize = 3;
+
+/// CPU bandwidth usage of a group
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CpuGroupStats {
+    pub quota_us: usize,
+    pub period_us: usize,
+    /// Used in the current period
+    pub used_us: usize,
+    pub total_us: usize,
+    /// Periods in which the group ran out of its quota
+    pub throttled_periods: usize,
+}
+
+/// Let the tasks of `group` run for at most `quota_us` in every `period_us`,
+/// a zero quota removes the limit. Only for a privileged task.
+pub fn cpu_group_set_quota(group: usize, quota_us: usize, period_us: usize) -> isize {
+    match sys_cpu_group_ctl(CPU_GROUP_SET_PERIOD, group, period_us) {
+        0 => sys_cpu_group_ctl(CPU_GROUP_SET_QUOTA, group, quota_us),
+        err => err,
+    }
+}
+
+/// Move task `pid`, the caller or one of its descendants, into `group`, group 0 has no limit.
+/// Unless privileged, the caller may only move it into its own group, else -1 (EPERM).
+pub fn cpu_group_attach(group: usize, pid: usize) -> isize {
+    sys_cpu_group_ctl(CPU_GROUP_ATTACH, group, pid)
+}
+
+pub fn cpu_group_stats(group: usize, stats: &mut CpuGroupStats) -> isize {
+    sys_cpu_group_ctl(CPU_GROUP_STATS, group, stats as *mut CpuGroupStats as usize)
+}
+
+pub const SCHED_NORMAL: u32 = 0;
+pub const SCHED_DEADLINE: u32 = 6;
+
+/// Scheduling class of a task, as `struct sched_attr` of Linux, times are in nanoseconds
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SchedAttr {
+    pub size: u32,
+    pub sched_policy: u32,
+    pub sched_flags: u64,
+    pub sched_nice: i32,
+    pub sched_priority: u32,
+    /// Budget of CPU time in every period
+    pub sched_runtime: u64,
+    /// Relative to the start of the period, 0 for the end of it
+    pub sched_deadline: u64,
+    pub sched_period: u64,
+}
+
+/// Put task `pid`, 0 for the caller, into the deadline class: it runs before all other
+/// tasks, earliest deadline first, for up to `budget_us` in every `period_us`.
+/// Return -16 (EBUSY) if the harts cannot take that much more, -1 (EPERM) if `pid` is not
+/// the caller or one of its descendants, or if an unprivileged caller asks for more than it
+/// holds.
+pub fn sched_set_deadline(
+    pid: usize,
+    budget_us: usize,
+    deadline_us: usize,
+    period_us: usize,
+) -> isize {
+    let attr = SchedAttr {
+        size: core::mem::size_of::<SchedAttr>() as u32,
+        sched_policy: SCHED_DEADLINE,
+        sched_runtime: budget_us as u64 * 1000,
+        sched_deadline: deadline_us as u64 * 1000,
+        sched_period: period_us as u64 * 1000,
+        ..Default::default()
+    };
+    sys_sched_setattr(pid, &attr)
+}
+
+/// Move task `pid`, 0 for the caller, back to the normal class
+pub fn sched_set_normal(pid: usize) -> isize {
+    let attr = SchedAttr {
+        size: core::mem::size_of::<SchedAttr>() as u32,
+        ..Default::default()
+    };
+    sys_sched_setattr(pid, &attr)
+}
+
+/// Yield with a hint to run task `pid` next. Return 0 if it ran before the caller did again,
+/// 1 if it is not ready and this was a plain yield, 2 if another task came first
+pub fn yield_to(pid: usize) -> isize {
+    sys_yield_to(pid)
+}
+
+/// Hold back user interrupts from the kernel, they are queued and delivered once unmasked.
+/// Return the previous mask, 1 if it was masked.
+pub fn uintr_mask(mask: bool) -> isize {
+    sys_uintr_mask(mask)
+}
+
+/// Sleep until a user trap record is queued, return at once if there is one
+pub fn uintr_wait() -> isize {
+    sys_uintr_wait()
+}
+
+/// Masks user interrupt delivery by the kernel until dropped,
+/// guards can be nested and only the outermost one unmasks
+pub struct InterruptGuard {
+    was_masked: bool,
+}
+
+impl InterruptGuard {
+    pub fn new() -> Self {
+        Self {
+            was_masked: sys_uintr_mask(true) == 1,
+        }
+    }
+}
+
+impl Default for InterruptGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        if !self.was_masked {
+            sys_uintr_mask(false);
+        }
+    }
+}
+
+const UTSNAME_FIELD_LEN: usize = 65;
+
+/// Identifies the running kernel, every field is nul-terminated
+#[repr(C)]
+pub struct Utsname {
+    pub sysname: [u8; UTSNAME_FIELD_LEN],
+    pub nodename: [u8; UTSNAME_FIELD_LEN],
+    pub release: [u8; UTSNAME_FIELD_LEN],
+    /// Git revision, build time and features of the kernel image
+    pub version: [u8; UTSNAME_FIELD_LEN],
+    pub machine: [u8; UTSNAME_FIELD_LEN],
+    pub domainname: [u8; UTSNAME_FIELD_LEN],
+}
+
+impl Default for Utsname {
+    fn default() -> Self {
+        Self {
+            sysname: [0; UTSNAME_FIELD_LEN],
+            nodename: [0; UTSNAME_FIELD_LEN],
+            release: [0; UTSNAME_FIELD_LEN],
+            version: [0; UTSNAME_FIELD_LEN],
+            machine: [0; UTSNAME_FIELD_LEN],
+            domainname: [0; UTSNAME_FIELD_LEN],
+        }
+    }
+}
+
+/// The text of a `Utsname` field
+pub fn utsname_str(field: &[u8]) -> &str {
+    let len = field.iter().position(|c| *c == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..len]).unwrap_or("")
+}
+
+pub fn uname(utsname: &mut Utsname) -> isize {
+    let buf = unsafe {
+        core::slice::from_raw_parts_mut(
+            utsname as *mut Utsname as *mut u8,
+            core::mem::size_of::<Utsname>(),
+        )
+    };
+    sys_uname(buf)
+}
+
+pub fn ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
+    sys_ioctl(fd, cmd, arg)
+}
+
+/// `ioctl` commands of serial ports, the argument points to a `SerialConfig`
+pub const SERIAL_GET_CONFIG: usize = 0x5400;
+pub const SERIAL_SET_CONFIG: usize = 0x5401;
+
+pub const PARITY_NONE: u8 = 0;
+pub const PARITY_ODD: u8 = 1;
+pub const PARITY_EVEN: u8 = 2;
+
+pub const FLOW_CONTROL_NONE: u8 = 0;
+pub const FLOW_CONTROL_RTS_CTS: u8 = 1;
+pub const FLOW_CONTROL_XON_XOFF: u8 = 2;
+
+/// Line settings of a serial port
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SerialConfig {
+    pub baud_rate: u32,
+    pub parity: u8,
+    /// 1 or 2
+    pub stop_bits: u8,
+    /// 5 to 8
+    pub word_length: u8,
+    pub flow_control: u8,
+}
+
+pub fn serial_get_config(fd: usize, config: &mut SerialConfig) -> isize {
+    sys_ioctl(fd, SERIAL_GET_CONFIG, config as *mut SerialConfig as usize)
+}
+
+pub fn serial_set_config(fd: usize, config: &SerialConfig) -> isize {
+    sys_ioctl(
+        fd,
+        SERIAL_SET_CONFIG,
+        config as *const SerialConfig as usize,
+    )
+}
+
+const SERVICE_LOAD: usize = 0;
+const SERVICE_UNLOAD: usize = 1;
+
+/// Load a service from `user/services` into the kernel, return its slot.
+/// Only allowed for initproc and commands run by it.
+pub fn service_load(name: &str) -> isize {
+    let mut name = String::from(name);
+    name.push('\0');
+    sys_service_ctl(SERVICE_LOAD, name.as_ptr() as usize)
+}
+
+pub fn service_unload(slot: usize) -> isize {
+    sys_service_ctl(SERVICE_UNLOAD, slot)
+}
+
+pub const SCHED_POLICY_RR: usize = 0;
+pub const SCHED_POLICY_PRIORITY: usize = 1;
+pub const SCHED_POLICY_EDF: usize = 2;
+
+/// Switch the scheduling policy of the kernel, only while no other task is ready.
+/// Return the previous policy, or -16 (EBUSY).
+pub fn set_sched_policy(policy: usize) -> isize {
+    sys_sched_policy(policy)
+}
+
+pub fn get_sched_policy() -> isize {
+    sys_sched_policy(usize::MAX)
+}
+
+const CHECKPOINT_SAVE: usize = 0;
+const CHECKPOINT_RESTORE: usize = 1;
+const CHECKPOINT_DROP: usize = 2;
+
+/// Copy the task `pid` into kernel memory, return the id of the checkpoint.
+/// The task is frozen meanwhile and goes on afterwards. Its user traps are not saved.
+/// Only allowed for initproc and commands run by it.
+pub fn checkpoint_save(pid: usize) -> isize {
+    sys_checkpoint(CHECKPOINT_SAVE, pid)
+}
+
+/// Start a new child from checkpoint `id`, return its pid. It resumes where the task was
+/// frozen and has to set up its user traps again.
+pub fn checkpoint_restore(id: usize) -> isize {
+    sys_checkpoint(CHECKPOINT_RESTORE, id)
+}
+
+pub fn checkpoint_drop(id: usize) -> isize {
+    sys_checkpoint(CHECKPOINT_DROP, id)
+}
+
+/// `mem_pressure` flag: notify by `mem_pressure_handler`, needs user traps
+pub const MEM_PRESSURE_UIPI: usize = 1;
+/// `mem_pressure` flag: notify through an event fd, whose reads block until there are
+/// notifications and return their number as a `u64`
+pub const MEM_PRESSURE_FD: usize = 2;
+/// `ioctl` of the event fd: the notifications not read yet, without blocking
+pub const MEM_PRESSURE_PENDING: usize = 0x5600;
+
+/// Be told once fewer than `watermark` frames are free, and again only after they rose
+/// back above it. Return the event fd with `MEM_PRESSURE_FD`, 0 otherwise.
+pub fn mem_pressure(watermark: usize, flags: usize) -> isize {
+    sys_mem_pressure(watermark, flags)
+}
+
+pub fn mem_pressure_stop() -> isize {
+    sys_mem_pressure(0, 0)
+}
+
+pub const CHILD_NOTIFY_ACCEPT: usize = 1;
+
+/// Have `receiver`, which set up user traps, told of the exits of the children of the
+/// caller by `child_exit_handler`, instead of waiting for them. They still have to be reaped.
+/// A receiver other than the caller must have accepted with `child_notify_accept`, else
+/// -1 (EPERM).
+pub fn child_notify(receiver: usize) -> isize {
+    sys_child_notify(receiver, 0)
+}
+
+pub fn child_notify_stop() -> isize {
+    sys_child_notify(0, 0)
+}
+
+/// Let task `pid` name the caller in `child_notify`, 0 for no task
+pub fn child_notify_accept(pid: usize) -> isize {
+    sys_child_notify(pid, CHILD_NOTIFY_ACCEPT)
+}
@@ -1,28 +1,19 @@
-use crate::TimeVal;
+use crate::{IoVec, Rusage, SchedAttr, TimeSpec, TimeVal, Tms, UserTrapDescriptor};
 use core::arch::asm;
 
-const SYSCALL_DUP: usize = 24;
-const SYSCALL_OPEN: usize = 56;
-const SYSCALL_CLOSE: usize = 57;
-const SYSCALL_PIPE: usize = 59;
-const SYSCALL_READ: usize = 63;
-const SYSCALL_WRITE: usize = 64;
-const SYSCALL_EXIT: usize = 93;
-const SYSCALL_YIELD: usize = 124;
-const SYSCALL_GET_TIME: usize = 169;
-const SYSCALL_GETPID: usize = 172;
-const SYSCALL_FORK: usize = 220;
-const SYSCALL_EXEC: usize = 221;
-const SYSCALL_WAITPID: usize = 260;
-const SYSCALL_SPAWN: usize = 400;
-const SYSCALL_MAILREAD: usize = 401;
-const SYSCALL_MAILWRITE: usize = 402;
-const SYSCALL_INIT_USER_TRAP: usize = 600;
-const SYSCALL_SEND_MSG: usize = 601;
-const SYSCALL_SET_TIMER: usize = 602;
-const SYSCALL_CLAIM_EXT_INT: usize = 603;
-const SYSCALL_SET_EXT_INT_ENABLE: usize = 604;
+/// Defines the `SYSCALL_*` numbers from the table shared with the kernel
+macro_rules! syscall_table {
+    ($($constant:ident = $id:expr, $name:expr, $args:expr;)*) => {
+        $(
+            #[allow(dead_code)]
+            const $constant: usize = $id;
+        )*
+    };
+}
+
+include!("syscall_table.rs");
 
+/// Errors come back as `-errno` from -`MAX_ERRNO` to -1, see `syscall_result`
 fn syscall(id: usize, args: [usize; 3]) -> isize {
     let mut ret: isize;
     unsafe {
@@ -32,10 +23,29 @@ fn syscall(id: usize, args: [usize; 3]) -> isize {
     ret
 }
 
+/// For syscalls with more than three arguments
+fn syscall6(id: usize, args: [usize; 6]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        asm!("ecall", inout("a0") args[0] => ret, in("a1") args[1],
+             in("a2") args[2], in("a3") args[3], in("a4") args[4],
+             in("a5") args[5], in("a7") id)
+    }
+    ret
+}
+
 pub fn sys_dup(fd: usize) -> isize {
     syscall(SYSCALL_DUP, [fd, 0, 0])
 }
 
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    syscall(SYSCALL_FCNTL, [fd, cmd, arg])
+}
+
+pub fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
+    syscall(SYSCALL_IOCTL, [fd, cmd, arg])
+}
+
 pub fn sys_open(path: &str, flags: u32) -> isize {
     syscall(SYSCALL_OPEN, [path.as_ptr() as usize, flags as usize, 0])
 }
@@ -44,8 +54,11 @@ pub fn sys_close(fd: usize) -> isize {
     syscall(SYSCALL_CLOSE, [fd, 0, 0])
 }
 
-pub fn sys_pipe(pipe: &mut [usize]) -> isize {
-    syscall(SYSCALL_PIPE, [pipe.as_mut_ptr() as usize, 0, 0])
+pub fn sys_pipe(pipe: &mut [usize], flags: u32) -> isize {
+    syscall(
+        SYSCALL_PIPE,
+        [pipe.as_mut_ptr() as usize, flags as usize, 0],
+    )
 }
 
 pub fn sys_read(fd: usize, buffer: &mut [u8]) -> isize {
@@ -59,6 +72,22 @@ pub fn sys_write(fd: usize, buffer: &[u8]) -> isize {
     syscall(SYSCALL_WRITE, [fd, buffer.as_ptr() as usize, buffer.len()])
 }
 
+pub fn sys_readv(fd: usize, iov: &[IoVec]) -> isize {
+    syscall(SYSCALL_READV, [fd, iov.as_ptr() as usize, iov.len()])
+}
+
+pub fn sys_writev(fd: usize, iov: &[IoVec]) -> isize {
+    syscall(SYSCALL_WRITEV, [fd, iov.as_ptr() as usize, iov.len()])
+}
+
+pub fn sys_sendfile(out_fd: usize, in_fd: usize, count: usize) -> isize {
+    syscall6(SYSCALL_SENDFILE, [out_fd, in_fd, 0, count, 0, 0])
+}
+
+pub fn sys_splice(fd_in: usize, fd_out: usize, len: usize) -> isize {
+    syscall6(SYSCALL_SPLICE, [fd_in, 0, fd_out, 0, len, 0])
+}
+
 pub fn sys_exit(exit_code: i32) -> ! {
     syscall(SYSCALL_EXIT, [exit_code as usize, 0, 0]);
     panic!("sys_exit never returns!");
@@ -73,6 +102,14 @@ pub fn sys_get_time(time: &TimeVal, tz: usize) -> isize {
     syscall(SYSCALL_GET_TIME, [time as *const _ as usize, tz, 0])
 }
 
+pub fn sys_clock_gettime(clock_id: usize, tp: &mut TimeSpec) -> isize {
+    syscall(SYSCALL_CLOCK_GETTIME, [clock_id, tp as *mut _ as usize, 0])
+}
+
+pub fn sys_settimeofday(time: &TimeVal, tz: usize) -> isize {
+    syscall(SYSCALL_SETTIMEOFDAY, [time as *const _ as usize, tz, 0])
+}
+
 pub fn sys_getpid() -> isize {
     syscall(SYSCALL_GETPID, [0, 0, 0])
 }
@@ -81,19 +118,53 @@ pub fn sys_fork() -> isize {
     syscall(SYSCALL_FORK, [0, 0, 0])
 }
 
-pub fn sys_exec(path: &str, args: &[*const u8]) -> isize {
+pub fn sys_exec(path: &str, args: &[*const u8], envp: *const *const u8) -> isize {
     syscall(
         SYSCALL_EXEC,
-        [path.as_ptr() as usize, args.as_ptr() as usize, 0],
+        [
+            path.as_ptr() as usize,
+            args.as_ptr() as usize,
+            envp as usize,
+        ],
     )
 }
 
-pub fn sys_spawn(path: &str) -> isize {
-    syscall(SYSCALL_SPAWN, [path.as_ptr() as usize, 0, 0])
+pub fn sys_spawn(path: &str, flags: usize, events_fd: usize) -> isize {
+    syscall(SYSCALL_SPAWN, [path.as_ptr() as usize, flags, events_fd])
 }
 
-pub fn sys_waitpid(pid: isize, exit_code: *mut i32) -> isize {
-    syscall(SYSCALL_WAITPID, [pid as usize, exit_code as usize, 0])
+pub fn sys_mmap(start: usize, len: usize, prot: usize) -> isize {
+    syscall(SYSCALL_NATIVE_MMAP, [start, len, prot])
+}
+
+pub fn sys_munmap(start: usize, len: usize) -> isize {
+    syscall(SYSCALL_MUNMAP, [start, len, 0])
+}
+
+pub fn sys_waitpid(pid: isize, exit_code: *mut i32, options: usize) -> isize {
+    sys_wait4(pid, exit_code, options, core::ptr::null_mut())
+}
+
+pub fn sys_wait4(pid: isize, exit_code: *mut i32, options: usize, rusage: *mut Rusage) -> isize {
+    syscall6(
+        SYSCALL_WAITPID,
+        [
+            pid as usize,
+            exit_code as usize,
+            options,
+            rusage as usize,
+            0,
+            0,
+        ],
+    )
+}
+
+pub fn sys_times(tms: &mut Tms) -> isize {
+    syscall(SYSCALL_TIMES, [tms as *mut Tms as usize, 0, 0])
+}
+
+pub fn sys_kill(pid: usize, signal: usize) -> isize {
+    syscall(SYSCALL_KILL, [pid, signal, 0])
 }
 
 pub fn sys_mailread(buf: &mut [u8]) -> isize {
@@ -104,8 +175,8 @@ pub fn sys_mailwrite(pid: usize, buf: &[u8]) -> isize {
     syscall(SYSCALL_MAILWRITE, [pid, buf.as_ptr() as usize, buf.len()])
 }
 
-pub fn sys_init_user_trap() -> isize {
-    syscall(SYSCALL_INIT_USER_TRAP, [0, 0, 0])
+pub fn sys_init_user_trap(descriptor: *const UserTrapDescriptor) -> isize {
+    syscall(SYSCALL_INIT_USER_TRAP, [descriptor as usize, 0, 0])
 }
 
 pub fn sys_send_msg(pid: usize, msg: usize) -> isize {
@@ -123,3 +194,143 @@ pub fn sys_claim_ext_int(device_id: usize) -> isize {
 pub fn sys_set_ext_int_enable(device_id: usize, enable: usize) -> isize {
     syscall(SYSCALL_SET_EXT_INT_ENABLE, [device_id as usize, enable, 0])
 }
+
+pub fn sys_mmio_map(start: usize, len: usize, prot: usize) -> isize {
+    syscall(SYSCALL_MMIO_MAP, [start, len, prot])
+}
+
+pub fn sys_dma_alloc(len: usize, paddr: *mut usize) -> isize {
+    syscall(SYSCALL_DMA_ALLOC, [len, paddr as usize, 0])
+}
+
+pub fn sys_user_trap_ctl(cmd: usize, arg0: usize, arg1: usize) -> isize {
+    syscall(SYSCALL_USER_TRAP_CTL, [cmd, arg0, arg1])
+}
+
+pub fn sys_msg_group_ctl(cmd: usize, group_id: usize, pid: usize) -> isize {
+    syscall(SYSCALL_MSG_GROUP_CTL, [cmd, group_id, pid])
+}
+
+pub fn sys_send_group_msg(group_id: usize, msg: usize) -> isize {
+    syscall(SYSCALL_SEND_GROUP_MSG, [group_id, msg, 0])
+}
+
+pub fn sys_vm_info(buf: &mut [u8]) -> isize {
+    syscall(SYSCALL_VM_INFO, [buf.as_mut_ptr() as usize, buf.len(), 0])
+}
+
+pub fn sys_trace_ctl(pid: usize, mask: usize, fd: usize) -> isize {
+    syscall(SYSCALL_TRACE_CTL, [pid, mask, fd])
+}
+
+pub fn sys_ptrace(request: usize, pid: usize, arg: usize) -> isize {
+    syscall(SYSCALL_PTRACE, [request, pid, arg])
+}
+
+pub fn sys_sched_setaffinity(pid: usize, mask: &usize) -> isize {
+    syscall(
+        SYSCALL_SCHED_SETAFFINITY,
+        [
+            pid,
+            core::mem::size_of::<usize>(),
+            mask as *const usize as usize,
+        ],
+    )
+}
+
+pub fn sys_sched_getaffinity(pid: usize, mask: &mut usize) -> isize {
+    syscall(
+        SYSCALL_SCHED_GETAFFINITY,
+        [
+            pid,
+            core::mem::size_of::<usize>(),
+            mask as *mut usize as usize,
+        ],
+    )
+}
+
+pub fn sys_sched_stats(hart_id: usize, buf: &mut [u8]) -> isize {
+    syscall(SYSCALL_SCHED_STATS, [hart_id, buf.as_mut_ptr() as usize, 0])
+}
+
+pub fn sys_task_info(pid: usize, buf: &mut [u8]) -> isize {
+    syscall(SYSCALL_TASK_INFO, [pid, buf.as_mut_ptr() as usize, 0])
+}
+
+pub fn sys_uipi_inject(receiver_pid: usize, sender_pid: usize) -> isize {
+    syscall(SYSCALL_UIPI_INJECT, [receiver_pid, sender_pid, 0])
+}
+
+pub fn sys_cpu_group_ctl(cmd: usize, group: usize, arg: usize) -> isize {
+    syscall(SYSCALL_CPU_GROUP_CTL, [cmd, group, arg])
+}
+
+pub fn sys_yield_to(pid: usize) -> isize {
+    syscall(SYSCALL_YIELD_TO, [pid, 0, 0])
+}
+
+pub fn sys_uintr_mask(mask: bool) -> isize {
+    syscall(SYSCALL_UINTR_MASK, [mask as usize, 0, 0])
+}
+
+pub fn sys_uintr_wait() -> isize {
+    syscall(SYSCALL_UINTR_WAIT, [0, 0, 0])
+}
+
+pub fn sys_debug_translate(vaddr: usize, info: &mut [u8], flags: usize) -> isize {
+    syscall(
+        SYSCALL_DEBUG_TRANSLATE,
+        [vaddr, info.as_mut_ptr() as usize, flags],
+    )
+}
+
+pub fn sys_uname(buf: &mut [u8]) -> isize {
+    syscall(SYSCALL_UNAME, [buf.as_mut_ptr() as usize, 0, 0])
+}
+
+pub fn sys_service_ctl(cmd: usize, arg: usize) -> isize {
+    syscall(SYSCALL_SERVICE_CTL, [cmd, arg, 0])
+}
+
+pub fn sys_sched_setattr(pid: usize, attr: &SchedAttr) -> isize {
+    syscall(SYSCALL_SCHED_SETATTR, [pid, attr as *const _ as usize, 0])
+}
+
+pub fn sys_sched_policy(policy: usize) -> isize {
+    syscall(SYSCALL_SCHED_POLICY, [policy, 0, 0])
+}
+
+pub fn sys_uipi_bind(name: &str) -> isize {
+    syscall(SYSCALL_UIPI_BIND, [name.as_ptr() as usize, name.len(), 0])
+}
+
+pub fn sys_uipi_unbind(name: &str) -> isize {
+    syscall(SYSCALL_UIPI_UNBIND, [name.as_ptr() as usize, name.len(), 0])
+}
+
+pub fn sys_uipi_resolve(name: &str) -> isize {
+    syscall(
+        SYSCALL_UIPI_RESOLVE,
+        [name.as_ptr() as usize, name.len(), 0],
+    )
+}
+
+pub fn sys_checkpoint(cmd: usize, arg: usize) -> isize {
+    syscall(SYSCALL_CHECKPOINT, [cmd, arg, 0])
+}
+
+pub fn sys_mem_pressure(watermark: usize, flags: usize) -> isize {
+    syscall(SYSCALL_MEM_PRESSURE, [watermark, flags, 0])
+}
+
+pub fn sys_send_fd(pipe_fd: usize, fd: usize) -> isize {
+    syscall(SYSCALL_SEND_FD, [pipe_fd, fd, 0])
+}
+
+pub fn sys_recv_fd(pipe_fd: usize) -> isize {
+    syscall(SYSCALL_RECV_FD, [pipe_fd, 0, 0])
+}
+
+pub fn sys_child_notify(pid: usize, flags: usize) -> isize {
+    syscall(SYSCALL_CHILD_NOTIFY, [pid, flags, 0])
+}
@@ -0,0 +1,75 @@
+// The syscalls of the kernel, shared by the kernel and the user crate, which `include!` it
+// after defining `syscall_table!` to make what they need of it: the `SYSCALL_*` numbers,
+// and in the kernel the names and argument counts shown by strace.
+//
+// `SYSCALL_<NAME> = number, "name", arguments;`, ordered by number
+syscall_table! {
+    SYSCALL_DUP = 24, "dup", 1;
+    SYSCALL_FCNTL = 25, "fcntl", 3;
+    SYSCALL_IOCTL = 29, "ioctl", 3;
+    SYSCALL_OPEN = 56, "open", 2;
+    SYSCALL_CLOSE = 57, "close", 1;
+    SYSCALL_PIPE = 59, "pipe", 2;
+    SYSCALL_READ = 63, "read", 3;
+    SYSCALL_WRITE = 64, "write", 3;
+    SYSCALL_READV = 65, "readv", 3;
+    SYSCALL_WRITEV = 66, "writev", 3;
+    SYSCALL_SENDFILE = 71, "sendfile", 4;
+    SYSCALL_SPLICE = 76, "splice", 6;
+    SYSCALL_EXIT = 93, "exit", 1;
+    SYSCALL_SET_TID_ADDRESS = 96, "set_tid_address", 1;
+    SYSCALL_NANOSLEEP = 101, "nanosleep", 2;
+    SYSCALL_CLOCK_GETTIME = 113, "clock_gettime", 2;
+    SYSCALL_PTRACE = 117, "ptrace", 3;
+    SYSCALL_SCHED_SETAFFINITY = 122, "sched_setaffinity", 3;
+    SYSCALL_SCHED_GETAFFINITY = 123, "sched_getaffinity", 3;
+    SYSCALL_YIELD = 124, "yield", 0;
+    SYSCALL_KILL = 129, "kill", 2;
+    SYSCALL_SET_PRIORITY = 140, "set_priority", 1;
+    SYSCALL_TIMES = 153, "times", 1;
+    SYSCALL_UNAME = 160, "uname", 1;
+    SYSCALL_GET_TIME = 169, "get_time", 2;
+    SYSCALL_SETTIMEOFDAY = 170, "settimeofday", 2;
+    SYSCALL_GETPID = 172, "getpid", 0;
+    SYSCALL_BRK = 214, "brk", 1;
+    SYSCALL_MUNMAP = 215, "munmap", 2;
+    SYSCALL_FORK = 220, "fork", 0;
+    SYSCALL_EXEC = 221, "exec", 3;
+    SYSCALL_MMAP = 222, "mmap", 6;
+    SYSCALL_WAITPID = 260, "waitpid", 4;
+    SYSCALL_SCHED_SETATTR = 274, "sched_setattr", 2;
+    SYSCALL_SPAWN = 400, "spawn", 3;
+    SYSCALL_MAILREAD = 401, "mailread", 2;
+    SYSCALL_MAILWRITE = 402, "mailwrite", 3;
+    SYSCALL_INIT_USER_TRAP = 600, "init_user_trap", 1;
+    SYSCALL_SEND_MSG = 601, "send_msg", 2;
+    SYSCALL_SET_TIMER = 602, "set_timer", 1;
+    SYSCALL_CLAIM_EXT_INT = 603, "claim_ext_int", 1;
+    SYSCALL_SET_EXT_INT_ENABLE = 604, "set_ext_int_enable", 2;
+    SYSCALL_MMIO_MAP = 605, "mmio_map", 3;
+    SYSCALL_DMA_ALLOC = 606, "dma_alloc", 2;
+    SYSCALL_USER_TRAP_CTL = 607, "user_trap_ctl", 3;
+    SYSCALL_MSG_GROUP_CTL = 608, "msg_group_ctl", 3;
+    SYSCALL_SEND_GROUP_MSG = 609, "send_group_msg", 2;
+    SYSCALL_VM_INFO = 610, "vm_info", 2;
+    SYSCALL_TRACE_CTL = 611, "trace_ctl", 3;
+    SYSCALL_SCHED_STATS = 612, "sched_stats", 2;
+    SYSCALL_TASK_INFO = 613, "task_info", 2;
+    SYSCALL_UIPI_INJECT = 614, "uipi_inject", 2;
+    SYSCALL_CPU_GROUP_CTL = 615, "cpu_group_ctl", 3;
+    SYSCALL_YIELD_TO = 616, "yield_to", 1;
+    SYSCALL_UINTR_MASK = 617, "uintr_mask", 1;
+    SYSCALL_DEBUG_TRANSLATE = 618, "debug_translate", 3;
+    SYSCALL_SERVICE_CTL = 619, "service_ctl", 2;
+    SYSCALL_SCHED_POLICY = 620, "sched_policy", 1;
+    SYSCALL_UIPI_BIND = 621, "uipi_bind", 2;
+    SYSCALL_UIPI_UNBIND = 622, "uipi_unbind", 2;
+    SYSCALL_UIPI_RESOLVE = 623, "uipi_resolve", 2;
+    SYSCALL_CHECKPOINT = 624, "checkpoint", 2;
+    SYSCALL_MEM_PRESSURE = 625, "mem_pressure", 2;
+    SYSCALL_SEND_FD = 626, "send_fd", 2;
+    SYSCALL_RECV_FD = 627, "recv_fd", 1;
+    SYSCALL_CHILD_NOTIFY = 628, "child_notify", 2;
+    SYSCALL_UINTR_WAIT = 629, "uintr_wait", 0;
+    SYSCALL_NATIVE_MMAP = 630, "native_mmap", 3;
+}
@@ -5,12 +5,7 @@
 .macro LOAD_GP n
     ld x\n, \n*8(sp)
 .endm
-    .section .text.usertrap
-    .globl __alltraps_u
-    .globl __restore_u
-    .align 2
-__alltraps_u:
-    # csrw uscratch, sp
+.macro SAVE_USER_CX
     addi sp, sp, -35*8; # sp = sp + -35*8
     sd x1, 1*8(sp)
     sd x3, 3*8(sp)
@@ -50,11 +45,8 @@ __alltraps_u:
     sd t2, 34*8(sp)
     csrr t3, uscratch
     sd t3, 2*8(sp)
-    mv  a0, sp # a0 = sp
-    call user_trap_handler
-
-__restore_u:
-    mv sp, a0
+.endm
+.macro RESTORE_USER_CX
     ld t0, 32*8(sp)
     ld t1, 33*8(sp)
     ld t2, 34*8(sp)
@@ -94,5 +86,77 @@ __restore_u:
     .endr
 
     addi sp, sp, 35*8
+.endm
+# USER_TRAP_HANDLER_DEPTH and USER_TRAP_HANDLER_ENTRIES, read by the kernel,
+# only after SAVE_USER_CX and before RESTORE_USER_CX as they clobber t0 and t1
+.macro ENTER_HANDLER
+    li t0, 0xfffffffffffffff0 - 2*0x1000
+    ld t1, 0(t0)
+    addi t1, t1, 1
+    sd t1, 0(t0)
+    ld t1, -8(t0)
+    addi t1, t1, 1
+    sd t1, -8(t0)
+.endm
+.macro LEAVE_HANDLER
+    li t0, 0xfffffffffffffff0 - 2*0x1000
+    ld t1, 0(t0)
+    addi t1, t1, -1
+    sd t1, 0(t0)
+.endm
+    .section .text.usertrap
+    .globl __alltraps_u
+    .globl __restore_u
+    .globl __alltraps_u_stack
+    .globl __abort_u
+    .globl __abort_u_stack
+    .align 2
+__alltraps_u:
+    # csrw uscratch, sp
+    SAVE_USER_CX
+    ENTER_HANDLER
+    mv  a0, sp # a0 = sp
+    call user_trap_handler
+
+__restore_u:
+    mv sp, a0
+    LEAVE_HANDLER
+    RESTORE_USER_CX
     # csrr sp, uscratch
-    uret
\ No newline at end of file
+    uret
+
+    .align 2
+__alltraps_u_stack:
+    # switch to the trap stack published by the kernel in USER_TRAP_STACK_SLOT,
+    # t0 is parked below the interrupted sp as the ABI has no red zone
+    sd t0, -8(sp)
+    mv t0, sp
+    li sp, 0xfffffffffffffff8 - 2*0x1000
+    ld sp, 0(sp)
+    addi sp, sp, -16
+    sd t0, 0(sp)
+    ld t0, -8(t0)
+    SAVE_USER_CX
+    ENTER_HANDLER
+    mv  a0, sp # a0 = sp
+    call user_trap_handler
+__restore_u_stack:
+    mv sp, a0
+    LEAVE_HANDLER
+    RESTORE_USER_CX
+    ld sp, 0(sp)
+    uret
+
+    # the kernel sends a handler over its budget here, its frames are dropped
+    # and the trap frame it would have returned is restored
+    .align 2
+__abort_u:
+    andi sp, sp, -16
+    call user_trap_abort
+    j __restore_u
+
+    .align 2
+__abort_u_stack:
+    andi sp, sp, -16
+    call user_trap_abort
+    j __restore_u_stack
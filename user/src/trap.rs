@@ -1,12 +1,59 @@
+use crate::deferred::run_deferred_on_trap_exit;
+use crate::heap_profile;
 use core::arch::{asm, global_asm};
-use heapless::spsc::Queue;
-use riscv::register::{ucause, uepc, uip, ustatus::Ustatus, utval};
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, AtomicUsize, Ordering, Ordering::Relaxed};
+use riscv::register::{
+    ucause, uepc, uie, uip,
+    ustatus::{self, Ustatus},
+    utval, utvec,
+};
 
 pub const PAGE_SIZE: usize = 0x1000;
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
 pub const USER_TRAP_BUFFER: usize = TRAP_CONTEXT - PAGE_SIZE;
 const MAX_USER_TRAP_NUM: usize = 128;
+/// Cause of the records of memory pressure notifications, the message being the free frames
+const MEM_PRESSURE_CAUSE: usize = 0xf;
+/// Cause of the records of child exits, the message being the pid of the child in the upper
+/// 32 bits and its exit code in the lower ones
+const CHILD_EXIT_CAUSE: usize = 0xe;
+/// Where the kernel publishes the trap stack top for `__alltraps_u_stack`
+pub const USER_TRAP_STACK_SLOT: usize =
+    USER_TRAP_BUFFER + PAGE_SIZE - core::mem::size_of::<usize>();
+/// Handlers running, raised by the trap entry once the interrupted context is saved and
+/// lowered right before its uret, so that the kernel knows when a handler runs
+pub const USER_TRAP_HANDLER_DEPTH: usize = USER_TRAP_STACK_SLOT - core::mem::size_of::<usize>();
+/// Handlers entered so far, raised with `USER_TRAP_HANDLER_DEPTH`
+pub const USER_TRAP_HANDLER_ENTRIES: usize =
+    USER_TRAP_HANDLER_DEPTH - core::mem::size_of::<usize>();
+/// `UserTrapDescriptor` flag: the handler may re-enable user interrupts and nest
+pub const USER_TRAP_REENTRANT: usize = 1;
+
+/// Trap entry and trap stack handed to `init_user_trap_with`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UserTrapDescriptor {
+    pub entry: usize,
+    /// Top of a 16-byte aligned trap stack, 0 to run handlers on the interrupted stack
+    pub stack_top: usize,
+    pub flags: usize,
+}
+
+impl UserTrapDescriptor {
+    /// Run the default handler on the stack ending at `stack_top`
+    pub fn with_stack(stack_top: usize) -> Self {
+        extern "C" {
+            fn __alltraps_u_stack();
+        }
+        Self {
+            entry: __alltraps_u_stack as usize,
+            stack_top,
+            flags: 0,
+        }
+    }
+}
 
 use rv_plic::PLIC;
 pub const PLIC_BASE: usize = 0xc00_0000;
@@ -48,17 +95,203 @@ pub struct UserTrapRecord {
     pub message: usize,
 }
 
-pub type UserTrapQueue = Queue<UserTrapRecord, MAX_USER_TRAP_NUM>;
+/// Trap records queued by the kernel in the trap buffer, laid out as the kernel writes them.
+/// The kernel produces at `tail` and the task consumes at `head`, so that at most
+/// `MAX_USER_TRAP_NUM - 1` records are queued.
+#[repr(C)]
+pub struct UserTrapQueue {
+    head: usize,
+    tail: usize,
+    buffer: [UserTrapRecord; MAX_USER_TRAP_NUM],
+}
+
+impl UserTrapQueue {
+    pub fn len(&self) -> usize {
+        let head = unsafe { read_volatile(&self.head) };
+        let tail = unsafe { read_volatile(&self.tail) };
+        (tail + MAX_USER_TRAP_NUM - head) % MAX_USER_TRAP_NUM
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn dequeue(&mut self) -> Option<UserTrapRecord> {
+        let head = unsafe { read_volatile(&self.head) };
+        let tail = unsafe { read_volatile(&self.tail) };
+        if head == tail {
+            return None;
+        }
+        // the record is written before the tail is published
+        fence(Ordering::Acquire);
+        let record = unsafe { read_volatile(&self.buffer[head % MAX_USER_TRAP_NUM]) };
+        unsafe { write_volatile(&mut self.head, (head + 1) % MAX_USER_TRAP_NUM) };
+        Some(record)
+    }
+}
 global_asm!(include_str!("trap.asm"));
 
+/// Priority classes of user interrupts, a handler can only be preempted by a higher class
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TrapPriority {
+    /// Trap records from the kernel: messages, forwarded device interrupts and timers
+    Soft = 0,
+    Timer = 1,
+    External = 2,
+}
+
+impl TrapPriority {
+    const ALL: [TrapPriority; 3] = [
+        TrapPriority::Soft,
+        TrapPriority::Timer,
+        TrapPriority::External,
+    ];
+
+    pub fn of(interrupt: ucause::Interrupt) -> Option<Self> {
+        match interrupt {
+            ucause::Interrupt::UserSoft => Some(TrapPriority::Soft),
+            ucause::Interrupt::UserTimer => Some(TrapPriority::Timer),
+            ucause::Interrupt::UserExternal => Some(TrapPriority::External),
+            _ => None,
+        }
+    }
+
+    /// The uie bit of the cause
+    fn uie_bit(self) -> usize {
+        match self {
+            TrapPriority::Soft => 1 << 0,
+            TrapPriority::Timer => 1 << 4,
+            TrapPriority::External => 1 << 8,
+        }
+    }
+}
+
+/// How user interrupts are let in while a user trap handler runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapNesting {
+    /// Handlers run with user interrupts off until they return, the default
+    Off = 0,
+    /// Only a cause of a higher priority preempts a running handler
+    MaskLower = 1,
+    /// Any other cause preempts a running handler, the same cause never nests
+    MaskSame = 2,
+}
+
+/// At most one handler of each priority class is running
+pub const MAX_TRAP_NESTING: usize = 3;
+
+static TRAP_NESTING: AtomicUsize = AtomicUsize::new(TrapNesting::Off as usize);
+static TRAP_DEPTH: AtomicUsize = AtomicUsize::new(0);
+/// Trap frame of the running handler at each depth, for `user_trap_abort`
+static HANDLER_FRAMES: [AtomicUsize; MAX_TRAP_NESTING] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+/// Nesting needs the handlers to run on the interrupted stack,
+/// so it cannot be used with a dedicated trap stack
+pub fn set_trap_nesting(nesting: TrapNesting) {
+    TRAP_NESTING.store(nesting as usize, Relaxed);
+}
+
+pub fn trap_nesting() -> TrapNesting {
+    match TRAP_NESTING.load(Relaxed) {
+        1 => TrapNesting::MaskLower,
+        2 => TrapNesting::MaskSame,
+        _ => TrapNesting::Off,
+    }
+}
+
+/// Number of user trap handlers running, 0 outside of any handler
+pub fn trap_nesting_depth() -> usize {
+    TRAP_DEPTH.load(Relaxed)
+}
+
+/// Lets higher priority user interrupts in while a handler runs, under the `TrapNesting` policy.
+///
+/// The re-enable protocol for a handler of `priority`:
+/// 1. read ucause, utval and uscratch, a nested trap overwrites them;
+/// 2. `NestedTrapGuard::enter(priority)` masks the causes which must not nest
+///    and sets ustatus.UIE;
+/// 3. dropping the guard clears ustatus.UIE and unmasks the causes,
+///    it must be dropped before the handler returns to the trap entry.
+///
+/// The trap entry keeps ustatus and uepc in the trap frame, so they need no care.
+pub struct NestedTrapGuard {
+    masked: usize,
+    enabled: bool,
+}
+
+impl NestedTrapGuard {
+    pub fn enter(priority: TrapPriority) -> Self {
+        let masked = match trap_nesting() {
+            TrapNesting::Off => {
+                return Self {
+                    masked: 0,
+                    enabled: false,
+                }
+            }
+            TrapNesting::MaskLower => TrapPriority::ALL
+                .iter()
+                .filter(|p| **p <= priority)
+                .fold(0, |bits, p| bits | p.uie_bit()),
+            TrapNesting::MaskSame => priority.uie_bit(),
+        } & uie::read().bits();
+        unsafe {
+            set_uie_bits(masked, false);
+            ustatus::set_uie();
+        }
+        Self {
+            masked,
+            enabled: true,
+        }
+    }
+}
+
+impl Drop for NestedTrapGuard {
+    fn drop(&mut self) {
+        if self.enabled {
+            unsafe {
+                ustatus::clear_uie();
+                set_uie_bits(self.masked, true);
+            }
+        }
+    }
+}
+
+unsafe fn set_uie_bits(bits: usize, enable: bool) {
+    for &priority in TrapPriority::ALL.iter() {
+        if bits & priority.uie_bit() == 0 {
+            continue;
+        }
+        match (priority, enable) {
+            (TrapPriority::Soft, true) => uie::set_usoft(),
+            (TrapPriority::Soft, false) => uie::clear_usoft(),
+            (TrapPriority::Timer, true) => uie::set_utimer(),
+            (TrapPriority::Timer, false) => uie::clear_utimer(),
+            (TrapPriority::External, true) => uie::set_uext(),
+            (TrapPriority::External, false) => uie::clear_uext(),
+        }
+    }
+}
+
 #[linkage = "weak"]
 #[no_mangle]
 pub fn user_trap_handler(cx: &mut UserTrapContext) -> &mut UserTrapContext {
     let ucause = ucause::read();
     let utval = utval::read();
+    let depth = TRAP_DEPTH.fetch_add(1, Relaxed);
+    HANDLER_FRAMES[depth.min(MAX_TRAP_NESTING - 1)].store(cx as *mut _ as usize, Relaxed);
+    let nested = match ucause.cause() {
+        ucause::Trap::Interrupt(interrupt) => {
+            TrapPriority::of(interrupt).map(NestedTrapGuard::enter)
+        }
+        _ => None,
+    };
     match ucause.cause() {
         ucause::Trap::Interrupt(ucause::Interrupt::UserSoft) => {
-            let trap_queue = unsafe { &mut *(USER_TRAP_BUFFER as *mut UserTrapQueue) };
+            let trap_queue = user_trap_queue();
             // println!(
             //     "[user trap] Received {} trap from kernel.",
             //     trap_queue.len()
@@ -66,20 +299,7 @@ pub fn user_trap_handler(cx: &mut UserTrapContext) -> &mut UserTrapContext {
             unsafe {
                 uip::clear_usoft();
             }
-            while let Some(trap_record) = trap_queue.dequeue() {
-                let cause = trap_record.cause;
-                let msg = trap_record.message;
-                if cause & 0xF == 0 {
-                    // "real" soft interrupt
-                    let pid = cause >> 4;
-                    soft_intr_handler(pid, msg);
-                } else if ucause::Interrupt::from(cause) == ucause::Interrupt::UserExternal {
-                    let irq = trap_record.message as u16;
-                    ext_intr_handler(irq, true);
-                } else if ucause::Interrupt::from(cause) == ucause::Interrupt::UserTimer {
-                    timer_intr_handler(msg);
-                }
-            }
+            handle_trap_records(trap_queue);
         }
         ucause::Trap::Interrupt(ucause::Interrupt::UserExternal) => {
             while let Some(irq) = Plic::claim(get_context(hart_id(), 'U')) {
@@ -102,9 +322,82 @@ pub fn user_trap_handler(cx: &mut UserTrapContext) -> &mut UserTrapContext {
             );
         }
     }
+    drop(nested);
+    run_deferred_on_trap_exit();
+    TRAP_DEPTH.fetch_sub(1, Relaxed);
     cx
 }
 
+/// Where the kernel sends a handler over its budget, the abort path of the trap entry
+/// in utvec, 0 if that entry is not one of this library
+pub fn handler_abort_entry() -> usize {
+    extern "C" {
+        fn __alltraps_u();
+        fn __alltraps_u_stack();
+        fn __abort_u();
+        fn __abort_u_stack();
+    }
+    match utvec::read().address() {
+        entry if entry == __alltraps_u as usize => __abort_u as usize,
+        entry if entry == __alltraps_u_stack as usize => __abort_u_stack as usize,
+        _ => 0,
+    }
+}
+
+/// Called by `__abort_u` on the stack of a handler the kernel found over its budget,
+/// returns the trap frame to restore in place of the handler returning it
+#[no_mangle]
+extern "C" fn user_trap_abort() -> usize {
+    let depth = TRAP_DEPTH.fetch_sub(1, Relaxed);
+    let frame = HANDLER_FRAMES[depth.max(1).min(MAX_TRAP_NESTING) - 1].load(Relaxed);
+    println!("[user trap] handler over its budget, aborted");
+    frame
+}
+
+fn user_trap_queue() -> &'static mut UserTrapQueue {
+    unsafe { &mut *(USER_TRAP_BUFFER as *mut UserTrapQueue) }
+}
+
+pub(crate) fn dispatch_trap_record(trap_record: UserTrapRecord) {
+    let cause = trap_record.cause;
+    let msg = trap_record.message;
+    if cause & 0xF == 0 {
+        // "real" soft interrupt
+        let pid = cause >> 4;
+        if heap_profile::ENABLED && msg == heap_profile::HEAP_PROFILE_DUMP {
+            heap_profile::dump();
+            return;
+        }
+        soft_intr_handler(pid, msg);
+    } else if cause == MEM_PRESSURE_CAUSE {
+        mem_pressure_handler(msg);
+    } else if cause == CHILD_EXIT_CAUSE {
+        child_exit_handler(msg >> 32, msg as u32 as i32);
+    } else if ucause::Interrupt::from(cause) == ucause::Interrupt::UserExternal {
+        let irq = trap_record.message as u16;
+        ext_intr_handler(irq, true);
+    } else if ucause::Interrupt::from(cause) == ucause::Interrupt::UserTimer {
+        timer_intr_handler(msg);
+    }
+}
+
+fn handle_trap_records(trap_queue: &mut UserTrapQueue) -> usize {
+    let mut count = 0;
+    while let Some(trap_record) = trap_queue.dequeue() {
+        dispatch_trap_record(trap_record);
+        count += 1;
+    }
+    count
+}
+
+/// Handle queued trap records without waiting for an interrupt,
+/// needed once the kernel switched the task to polling mode by `set_user_trap_coalesce`
+pub fn poll_user_trap() -> usize {
+    handle_trap_records(user_trap_queue())
+}
+
+/// Complete `irq` in U mode once the device is serviced, for records from the kernel as well:
+/// the kernel leaves level sources claimed for their owner
 #[linkage = "weak"]
 #[no_mangle]
 pub fn ext_intr_handler(irq: u16, is_from_kernel: bool) {
@@ -131,3 +424,23 @@ pub fn timer_intr_handler(time_us: usize) {
         time_us
     );
 }
+
+/// A child of the task registered by `child_notify` exited
+#[linkage = "weak"]
+#[no_mangle]
+pub fn child_exit_handler(pid: usize, exit_code: i32) {
+    println!(
+        "[user trap default] child exited, pid: {}, exit code: {}",
+        pid, exit_code
+    );
+}
+
+/// Fewer frames than the watermark given to `mem_pressure` are free
+#[linkage = "weak"]
+#[no_mangle]
+pub fn mem_pressure_handler(free_frames: usize) {
+    println!(
+        "[user trap default] memory pressure, free frames: {}",
+        free_frames
+    );
+}
@@ -0,0 +1,63 @@
+#!/bin/sh
+# Embed the symbol map of a user app into its .symmap section, for the
+# backtraces printed by the panic handler, then strip the debug info the
+# map was resolved from.
+#
+# usage: symmap.sh <elf> <section size>
+#
+# The map is text: a "SYMMAP" line, then one line per function,
+# "<addr> <size> <file:line> <name>", and one per call instruction,
+# "@<addr> <file:line>", with hex numbers. Lines of the app and user_lib
+# come first, those of core and alloc after, and the map is cut to the
+# section size. The panic handler ignores a truncated last line.
+set -e
+
+elf=$1
+size=$2
+map=$elf.symmap
+
+# cargo did not relink the app since it was filled in
+if [ "$(rust-objcopy --dump-section .symmap=/dev/stdout "$elf" | head -c 6)" = "SYMMAP" ]; then
+    exit 0
+fi
+
+# "<key> <line>", key 1 for code of the toolchain or of other crates
+rank() {
+    awk '{ print (($0 ~ /\/rustc\// || $0 ~ /\/\.cargo\//) ? 1 : 0), $0 }'
+}
+
+# shorten paths to their last two components
+shorten() {
+    sed -E 's#(^| )[^ ]*/([^/ ]+/[^/ ]+:[0-9?]+)#\1\2#'
+}
+
+rust-nm --defined-only --numeric-sort --print-size --demangle "$elf" \
+    | grep -E '^[0-9a-f]+ [0-9a-f]+ [tT] ' > "$map.syms" || true
+cut -d' ' -f1 "$map.syms" | sed 's/^/0x/' \
+    | rust-addr2line --exe "$elf" | sed 's/ (discriminator [0-9]*)$//' > "$map.lines"
+paste -d' ' "$map.syms" "$map.lines" \
+    | awk '{
+        loc = $NF; $NF = ""
+        name = $0; sub(/^[^ ]+ [^ ]+ [tT] /, "", name); sub(/ +$/, "", name)
+        printf "%s %s %s %s\n", $1, $2, loc, name
+    }' \
+    | sed -E 's/::h[0-9a-f]{16}$//' | rank > "$map.entries"
+
+# calls write ra: jal and jalr with ra or no destination given
+rust-objdump -d --no-show-raw-insn "$elf" \
+    | awk '($2 == "jal" || $2 == "jalr") && ($3 !~ /,$/ || $3 == "ra,") {
+        sub(/:$/, "", $1); print $1
+    }' > "$map.calls"
+sed 's/^/0x/' "$map.calls" | rust-addr2line --exe "$elf" \
+    | sed 's/ (discriminator [0-9]*)$//' > "$map.lines"
+paste -d' ' "$map.calls" "$map.lines" | sed 's/^/@/' | rank >> "$map.entries"
+
+echo SYMMAP > "$map"
+sort -s -n -k1,1 "$map.entries" | cut -d' ' -f2- | shorten >> "$map"
+rm -f "$map.syms" "$map.lines" "$map.calls" "$map.entries"
+
+if [ "$(wc -c < "$map")" -gt "$size" ]; then
+    echo "symmap: the map of $elf is cut to $size bytes, library entries are lost first" >&2
+fi
+truncate -s "$size" "$map"
+rust-objcopy --update-section .symmap="$map" --strip-debug "$elf"